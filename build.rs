@@ -0,0 +1,5 @@
+// Compiles the C shim that gives libretro cores a variadic log function.
+fn main() {
+    println!("cargo:rerun-if-changed=src/log_shim.c");
+    cc::Build::new().file("src/log_shim.c").compile("log_shim");
+}
@@ -5,70 +5,807 @@
 // The `audio` module handles audio processing and playback for the emulator.
 // It uses the `rodio` crate for audio output and integrates with the libretro API for audio data.
 
+use crate::resampler::Resampler;
+use crate::ring_buffer::{self, Consumer, Producer};
+use libc::c_uint;
+use libretro_sys::AudioCallback;
 use once_cell::sync::Lazy;
-use rodio::buffer::SamplesBuffer;
-use rodio::Sink;
-use std::sync::{Arc, Mutex};
-use crate::AUDIO_DATA_CHANNEL;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{Device, OutputStream, OutputStreamHandle, Sink, Source};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 // Constants for audio processing.
 const AUDIO_CHANNELS: usize = 2; // Stereo audio with left and right channels.
 const SAMPLE_RATE: u32 = 48_000; // Sample rate in Hertz (48 kHz).
 const BUFFER_DURATION_MS: u32 = 64; // Duration of each audio buffer in milliseconds.
 const BUFFER_LENGTH: usize = (SAMPLE_RATE as u32 * BUFFER_DURATION_MS / 1000) as usize; // Number of samples in each buffer.
-const POOL_SIZE: usize = 20; // Number of buffers in the audio buffer pool.
+const QUEUE_CAPACITY: usize = 1 << 17; // Samples the queue to the audio thread holds, over a second at 48 kHz.
+const AUDIO_CALLBACK_QUEUE_LENGTH: usize = 2; // Buffers queued before the core's audio callback stops being asked for more.
 
-// Represents an audio buffer containing raw audio samples.
-pub struct AudioBuffer {
-    data: Vec<i16>, // Vector to store the 16-bit audio samples.
+// Sample rate of the audio the core produces, in Hertz. The core can change it
+// after loading content through ENVIRONMENT_SET_SYSTEM_AV_INFO.
+static CORE_SAMPLE_RATE: AtomicU32 = AtomicU32::new(0);
+
+// Returns the sample rate of the audio the core produces.
+pub fn sample_rate() -> u32 {
+    CORE_SAMPLE_RATE.load(Ordering::SeqCst)
 }
 
-impl AudioBuffer {
-    // Constructs a new `AudioBuffer` with a specified size.
-    pub fn new(size: usize) -> Self {
-        AudioBuffer { data: vec![0; size] }
+// Sets the sample rate of the audio the core produces, telling the audio thread
+// if it changed. Called on the emulation thread, so the change is placed exactly
+// between the samples sent before and after it.
+pub fn set_sample_rate(sample_rate: f64) {
+    let sample_rate = sample_rate as u32;
+    if CORE_SAMPLE_RATE.swap(sample_rate, Ordering::SeqCst) == sample_rate {
+        return;
     }
+    QUEUE_SENDER.with_borrow(|sender| {
+        if let Some(sender) = sender {
+            let change = RateChange {
+                sample_rate,
+                position: sender.producer.position(),
+            };
+            // Only fails once the audio thread has gone, when there is nothing to tell
+            let _ = sender.rate_changes.send(change);
+        }
+    });
+}
 
-    // Clears the buffer, removing all audio samples.
-    pub fn clear(&mut self) {
-        self.data.clear();
+// A change of the core's sample rate, sent to the audio thread so it can reopen
+// its output at the new rate.
+pub struct RateChange {
+    pub sample_rate: u32,
+    // How many samples the core had sent when the rate changed. Those are played
+    // at the old rate, and the rest at the new one.
+    pub position: usize,
+}
+
+// Opens an output stream on the device named by `audio_device`, or the default
+// device if it is empty or not found, and returns it with the sample rate it was
+// opened at for the resampler to convert to.
+pub fn open_output(device_name: &str) -> Result<(OutputStream, OutputStreamHandle, u32), String> {
+    let host = rodio::cpal::default_host();
+    let mut device = None;
+    if !device_name.is_empty() {
+        let devices: Vec<Device> = host.output_devices().map_err(|e| e.to_string())?.collect();
+        let names: Vec<String> = devices
+            .iter()
+            .map(|device| device.name().unwrap_or_default())
+            .collect();
+        // An exact match first, then any device whose name contains the one given
+        let index = names
+            .iter()
+            .position(|name| name == device_name)
+            .or_else(|| {
+                let wanted = device_name.to_lowercase();
+                names
+                    .iter()
+                    .position(|name| name.to_lowercase().contains(&wanted))
+            });
+        match index {
+            Some(index) => device = devices.into_iter().nth(index),
+            None => println!(
+                "No audio device called {}, using the default. Devices: {}",
+                device_name,
+                names.join(", ")
+            ),
+        }
     }
+    let device = match device {
+        Some(device) => device,
+        None => host
+            .default_output_device()
+            .ok_or("No audio output device")?,
+    };
+    let config = device.default_output_config().map_err(|e| e.to_string())?;
+    let sample_rate = config.sample_rate().0;
+    let (stream, handle) =
+        OutputStream::try_from_device_config(&device, config.clone()).map_err(|e| e.to_string())?;
+    println!(
+        "Opened audio device {} at {} Hz, {} channels of {}",
+        device.name().unwrap_or_default(),
+        sample_rate,
+        config.channels(),
+        config.sample_format()
+    );
+    Ok((stream, handle, sample_rate))
+}
+
+// Whether audio is played at all. Fast-forward turns it off, and cores can skip
+// generating samples when they see it through ENVIRONMENT_GET_AUDIO_VIDEO_ENABLE.
+static AUDIO_ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Returns whether the audio the core produces is played.
+pub fn is_enabled() -> bool {
+    AUDIO_ENABLED.load(Ordering::SeqCst)
+}
+
+// Turns audio output on or off. Samples sent while it is off are dropped.
+pub fn set_enabled(enabled: bool) {
+    AUDIO_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// Whether the mute hotkey has silenced audio. Unlike turning audio off, muting
+// keeps it playing at zero volume, so it still paces the core with `audio_sync`.
+static MUTED: AtomicBool = AtomicBool::new(false);
+
+// Whether emulation is paused, and whether the audio thread has yet to drop the
+// audio that was queued when it was.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static PAUSE_PENDING: AtomicBool = AtomicBool::new(false);
+
+// Tells the audio thread whether emulation is paused, so it goes quiet at once
+// rather than playing out its queue and counting the silence as underruns.
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+    if paused {
+        PAUSE_PENDING.store(true, Ordering::SeqCst);
+    }
+}
+
+fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+// Returns true once after emulation is paused, for the audio thread to drop the
+// audio it has queued.
+pub fn take_pause() -> bool {
+    PAUSE_PENDING.swap(false, Ordering::SeqCst)
+}
+
+// Mutes or unmutes audio, returning whether it is now muted.
+pub fn toggle_mute() -> bool {
+    !MUTED.fetch_xor(true, Ordering::SeqCst)
+}
 
-    // Extends the buffer with audio samples from a slice.
-    pub fn extend_from_slice(&mut self, slice: &[i16]) {
-        self.data.extend_from_slice(slice);
+// Returns the volume to play at.
+pub fn volume() -> f32 {
+    if MUTED.load(Ordering::SeqCst) {
+        0.0
+    } else {
+        1.0
     }
+}
+
+// Audio callback registered with ENVIRONMENT_SET_AUDIO_CALLBACK, and whether it may be called.
+static AUDIO_CALLBACK: Lazy<Mutex<Option<AudioCallback>>> = Lazy::new(|| Mutex::new(None));
+static AUDIO_CALLBACK_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Handles ENVIRONMENT_SET_AUDIO_CALLBACK.
+pub unsafe fn set_audio_callback(callback: *const AudioCallback) -> bool {
+    let Some(callback) = callback.as_ref() else {
+        return false;
+    };
+    *AUDIO_CALLBACK.lock().unwrap() = Some(callback.clone());
+    true
+}
 
-    // Returns a pointer to the audio data.
-    pub fn as_ptr(&self) -> *const i16 {
-        self.data.as_ptr()
+// Lets the core know whether its audio callback will be called, e.g. once the
+// game is running, around a pause, and again when the frontend closes.
+pub fn set_audio_callback_state(enabled: bool) {
+    let set_state = AUDIO_CALLBACK
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|callback| callback.set_state);
+    if let Some(set_state) = set_state {
+        AUDIO_CALLBACK_ENABLED.store(enabled, Ordering::SeqCst);
+        unsafe { set_state(enabled) };
     }
+}
 
-    // Returns the length of the audio data in samples.
-    pub fn len(&self) -> usize {
-        self.data.len()
+// Asks a core that paces itself by audio for more samples when the sink is running
+// low, adding what it sends to `samples`. Called from the audio thread, which
+// plays them itself rather than sending them through the queue.
+pub fn run_audio_callback(sink: &Sink, samples: &mut Vec<i16>) {
+    if !AUDIO_CALLBACK_ENABLED.load(Ordering::SeqCst)
+        || is_paused()
+        || sink.len() >= AUDIO_CALLBACK_QUEUE_LENGTH
+    {
+        return;
     }
+    let callback = AUDIO_CALLBACK
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|callback| callback.callback);
+    if let Some(callback) = callback {
+        CALLBACK_SAMPLES.set(Some(std::mem::take(samples)));
+        unsafe { callback() };
+        flush_single_samples();
+        *samples = CALLBACK_SAMPLES.take().unwrap_or_default();
+    }
+}
+
+// The sending end of the queue carrying the core's samples to the audio thread,
+// and of the sample rate changes placed among them. The core sends audio from the
+// emulation thread, which keeps this for itself, so the queue has a single writer
+// and needs no lock.
+struct QueueSender {
+    producer: Producer,
+    rate_changes: Sender<RateChange>,
+}
+
+thread_local! {
+    static QUEUE_SENDER: RefCell<Option<QueueSender>> = const { RefCell::new(None) };
+
+    // Where the core's samples go while the audio thread runs its audio callback.
+    static CALLBACK_SAMPLES: RefCell<Option<Vec<i16>>> = const { RefCell::new(None) };
 }
 
-// Global buffer pool for managing audio buffers.
-static BUFFER_POOL: Lazy<Mutex<Vec<Arc<Mutex<Vec<i16>>>>>> = Lazy::new(|| {
-    let mut pool = Vec::new();
-    for _ in 0..POOL_SIZE {
-        pool.push(Arc::new(Mutex::new(vec![0; BUFFER_LENGTH])));
+// Creates the queue carrying the core's samples to the audio thread, keeping the
+// sending end on the calling thread, which must be the emulation thread, and
+// returns the receiving ends for the audio thread.
+pub fn open_queue() -> (Consumer, Receiver<RateChange>) {
+    let (producer, consumer) = ring_buffer::new(QUEUE_CAPACITY);
+    let (rate_changes, rate_change_receiver) = mpsc::channel();
+    QUEUE_SENDER.set(Some(QueueSender {
+        producer,
+        rate_changes,
+    }));
+    (consumer, rate_change_receiver)
+}
+
+// Frames of the core's audio waiting to be played, kept up to date by the audio thread.
+static QUEUED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+// How full the audio queue is in percent of its capacity, twice `audio_latency`,
+// as dynamic rate control sees it.
+static OCCUPANCY: AtomicU32 = AtomicU32::new(0);
+
+// Times the sink ran out of audio to play, and samples dropped because the audio
+// thread couldn't keep up with the core.
+static UNDERRUNS: AtomicU64 = AtomicU64::new(0);
+static DROPPED_SAMPLES: AtomicU64 = AtomicU64::new(0);
+
+// Shortest time between two underruns being logged, so a stall doesn't flood the log.
+const UNDERRUN_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+// Returns how many milliseconds of audio are queued, for the performance overlay.
+pub fn queued_ms() -> usize {
+    match sample_rate() {
+        0 => 0,
+        sample_rate => QUEUED_FRAMES.load(Ordering::SeqCst) * 1000 / sample_rate as usize,
     }
-    Mutex::new(pool)
+}
+
+// Returns how full the audio queue is, from 0 to 100.
+pub fn occupancy() -> u32 {
+    OCCUPANCY.load(Ordering::SeqCst)
+}
+
+// Returns how many times the sink has run dry since the start.
+pub fn underruns() -> u64 {
+    UNDERRUNS.load(Ordering::SeqCst)
+}
+
+// Returns how many samples have been dropped for lack of room since the start.
+pub fn dropped_samples() -> u64 {
+    DROPPED_SAMPLES.load(Ordering::SeqCst)
+}
+
+// Logs the underruns and overruns since the start, e.g. when the frontend closes.
+pub fn log_statistics() {
+    println!(
+        "Audio: {} underruns, {} samples dropped",
+        underruns(),
+        dropped_samples()
+    );
+}
+
+// struct retro_audio_buffer_status_callback, which libretro-sys predates.
+#[repr(C)]
+pub struct AudioBufferStatusCallback {
+    pub callback: Option<AudioBufferStatusFunction>,
+}
+
+type AudioBufferStatusFunction =
+    unsafe extern "C" fn(active: bool, occupancy: c_uint, underrun_likely: bool);
+
+static AUDIO_BUFFER_STATUS_CALLBACK: Mutex<Option<AudioBufferStatusFunction>> = Mutex::new(None);
+
+// Occupancy under which the core is told an underrun is likely, so it can e.g.
+// skip rendering a frame to catch up.
+const UNDERRUN_LIKELY_OCCUPANCY: u32 = 25;
+
+// Handles ENVIRONMENT_SET_AUDIO_BUFFER_STATUS_CALLBACK. A NULL pointer unregisters it.
+pub unsafe fn set_audio_buffer_status_callback(callback: *const AudioBufferStatusCallback) -> bool {
+    *AUDIO_BUFFER_STATUS_CALLBACK.lock().unwrap() =
+        callback.as_ref().and_then(|callback| callback.callback);
+    true
+}
+
+// Tells the core how full the audio queue is, if it asked. Must be called right
+// before retro_run.
+pub fn report_buffer_status() {
+    let Some(callback) = *AUDIO_BUFFER_STATUS_CALLBACK.lock().unwrap() else {
+        return;
+    };
+    let occupancy = occupancy();
+    // The lock is released first in case the core calls back into the environment
+    unsafe {
+        callback(
+            is_enabled(),
+            occupancy,
+            occupancy < UNDERRUN_LIKELY_OCCUPANCY,
+        )
+    };
+}
+
+// Whether frames are paced by the audio queue rather than the clock, from `audio_sync`.
+static AUDIO_SYNC: AtomicBool = AtomicBool::new(false);
+
+// Longest to wait for room in the audio queue, in case the device stops playing.
+const AUDIO_SYNC_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Returns whether frames are paced by the audio queue. They are only while audio
+// is playing at normal speed, and not while paused, when there is none.
+pub fn syncs() -> bool {
+    AUDIO_SYNC.load(Ordering::SeqCst)
+        && is_enabled()
+        && !FAST_FORWARDING.load(Ordering::SeqCst)
+        && !is_paused()
+}
+
+// What happens to audio while fast-forwarding, from `fastforward_audio`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u8)]
+enum FastForwardAudio {
+    // Nothing is played.
+    Mute,
+    // All of it is played faster, so it is higher pitched too.
+    Pitch,
+    // Only as much is played as fits in real time, in pieces at the right pitch
+    // that fade in and out so their edges don't click.
+    Cut,
+}
+
+// Frames over which each piece fades in and out when fast-forward audio is cut.
+const CUT_FADE_FRAMES: usize = 32;
+
+// Whether the game is being rewound, which is always quiet.
+static REWINDING: AtomicBool = AtomicBool::new(false);
+
+// Mutes audio while rewinding.
+pub fn set_rewinding(rewinding: bool) {
+    REWINDING.store(rewinding, Ordering::SeqCst);
+    update_enabled();
+}
+
+// The FastForwardAudio in use, as a number so the audio thread reads it without
+// taking a lock.
+static FAST_FORWARD_AUDIO: AtomicU8 = AtomicU8::new(FastForwardAudio::Mute as u8);
+
+// Returns the FastForwardAudio in use.
+fn fast_forward_audio() -> FastForwardAudio {
+    match FAST_FORWARD_AUDIO.load(Ordering::SeqCst) {
+        value if value == FastForwardAudio::Pitch as u8 => FastForwardAudio::Pitch,
+        value if value == FastForwardAudio::Cut as u8 => FastForwardAudio::Cut,
+        _ => FastForwardAudio::Mute,
+    }
+}
+
+static FAST_FORWARDING: AtomicBool = AtomicBool::new(false);
+
+// Tells the audio thread whether emulation is fast-forwarding, and mutes audio
+// for it if `fastforward_audio` says to.
+pub fn set_fast_forwarding(fast_forwarding: bool) {
+    FAST_FORWARDING.store(fast_forwarding, Ordering::SeqCst);
+    update_enabled();
+}
+
+// What happens to audio in slow motion, from `slowmotion_audio`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum SlowMotionAudio {
+    // Nothing is played.
+    Mute,
+    // It is played as slowly as the game runs, so it is lower pitched too.
+    Stretch,
+}
+
+static SLOW_MOTION_AUDIO: Mutex<SlowMotionAudio> = Mutex::new(SlowMotionAudio::Stretch);
+
+// The speed emulation runs at in slow motion as the bits of an f64, or 0 while it
+// is off, so the audio thread reads it without taking a lock.
+static SLOW_MOTION: AtomicU64 = AtomicU64::new(0);
+
+// Tells the audio thread the speed of slow motion, or None when it is off, and
+// mutes audio for it if `slowmotion_audio` says to.
+pub fn set_slow_motion(speed: Option<f64>) {
+    SLOW_MOTION.store(speed.map_or(0, f64::to_bits), Ordering::SeqCst);
+    update_enabled();
+}
+
+// Returns the speed of slow motion, or None when it is off.
+fn slow_motion() -> Option<f64> {
+    match SLOW_MOTION.load(Ordering::SeqCst) {
+        0 => None,
+        bits => Some(f64::from_bits(bits)),
+    }
+}
+
+// Turns audio off while rewinding, or fast-forwarding or in slow motion with it
+// muted.
+fn update_enabled() {
+    let fast_forward_mute =
+        FAST_FORWARDING.load(Ordering::SeqCst) && fast_forward_audio() == FastForwardAudio::Mute;
+    let slow_motion_mute =
+        slow_motion().is_some() && *SLOW_MOTION_AUDIO.lock().unwrap() == SlowMotionAudio::Mute;
+    set_enabled(!(REWINDING.load(Ordering::SeqCst) || fast_forward_mute || slow_motion_mute));
+}
+
+// With `audio_sync`, blocks until the audio queue has drained to the target latency,
+// so the core runs exactly as fast as the device plays its audio whatever the
+// display's refresh rate. Called before each frame is run.
+pub fn wait_for_room() {
+    if !syncs() || sample_rate() == 0 {
+        return;
+    }
+    let latency_ms = RATE_CONTROL_SETTINGS.lock().unwrap().latency_ms as usize;
+    let target = sample_rate() as usize * latency_ms / 1000;
+    let deadline = Instant::now() + AUDIO_SYNC_TIMEOUT;
+    while QUEUED_FRAMES.load(Ordering::SeqCst) > target && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// Settings for dynamic rate control: how far the playback rate may stray from the
+// core's, from `audio_rate_control_delta`, and the latency to keep queued, from `audio_latency`.
+struct RateControlSettings {
+    delta: f64,
+    latency_ms: u32,
+}
+
+static RATE_CONTROL_SETTINGS: Mutex<RateControlSettings> = Mutex::new(RateControlSettings {
+    delta: 0.005,
+    latency_ms: 64,
 });
 
-// Plays audio using the `rodio` library.
-pub unsafe fn play_audio(sink: &Sink, audio_samples: &AudioBuffer, sample_rate: u32) {
-    let audio_slice = std::slice::from_raw_parts(audio_samples.as_ptr() as *const i16, audio_samples.len());
-    let source = SamplesBuffer::new(AUDIO_CHANNELS.try_into().unwrap(), sample_rate, audio_slice);
-    sink.append(source);
+// Reads `audio_rate_control`, `audio_rate_control_delta`, `audio_latency`,
+// `audio_sync`, `fastforward_audio` and `slowmotion_audio` from the config.
+pub fn configure(config: &HashMap<String, String>) {
+    let sync = config
+        .get("audio_sync")
+        .is_some_and(|value| value == "true");
+    AUDIO_SYNC.store(sync, Ordering::SeqCst);
+    let fast_forward_audio = match config
+        .get("fastforward_audio")
+        .map_or("mute", String::as_str)
+    {
+        "pitch" => FastForwardAudio::Pitch,
+        "resample" | "cut" => FastForwardAudio::Cut,
+        "mute" | "" => FastForwardAudio::Mute,
+        other => {
+            println!(
+                "Unknown fastforward_audio {}, expected mute, pitch or resample",
+                other
+            );
+            FastForwardAudio::Mute
+        }
+    };
+    if FAST_FORWARD_AUDIO.swap(fast_forward_audio as u8, Ordering::SeqCst)
+        != fast_forward_audio as u8
+    {
+        set_fast_forwarding(FAST_FORWARDING.load(Ordering::SeqCst));
+    }
+    let slow_motion_audio = match config
+        .get("slowmotion_audio")
+        .map_or("stretch", String::as_str)
+    {
+        "stretch" | "" => SlowMotionAudio::Stretch,
+        "mute" => SlowMotionAudio::Mute,
+        other => {
+            println!(
+                "Unknown slowmotion_audio {}, expected stretch or mute",
+                other
+            );
+            SlowMotionAudio::Stretch
+        }
+    };
+    if *SLOW_MOTION_AUDIO.lock().unwrap() != slow_motion_audio {
+        *SLOW_MOTION_AUDIO.lock().unwrap() = slow_motion_audio;
+        update_enabled();
+    }
+    let enabled = config
+        .get("audio_rate_control")
+        .is_none_or(|value| value == "true");
+    let delta = config
+        .get("audio_rate_control_delta")
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|delta| (0.0..1.0).contains(delta))
+        .unwrap_or(0.005);
+    let latency_ms = config
+        .get("audio_latency")
+        .and_then(|value| value.parse::<u32>().ok())
+        .filter(|latency| *latency > 0)
+        .unwrap_or(64);
+    *RATE_CONTROL_SETTINGS.lock().unwrap() = RateControlSettings {
+        delta: if enabled { delta } else { 0.0 },
+        latency_ms,
+    };
+}
+
+// Returns the rate to play audio the core made at `sample_rate` at, given how full
+// the queue is from 0 to 1, the way RetroArch's dynamic rate control does. Half
+// full is where it should be; a fuller queue plays faster to drain and an emptier
+// one slower to fill, by up to `delta`, so the core's rate never quite matching the
+// device's neither builds up latency nor runs the queue dry.
+fn adjusted_rate(sample_rate: u32, fill: f64, delta: f64) -> u32 {
+    let direction = 2.0 * fill.clamp(0.0, 1.0) - 1.0;
+    (sample_rate as f64 * (1.0 + delta * direction)).round() as u32
+}
+
+// Returns how many frames at `sample_rate` make the queue full, twice the latency
+// so the target is half full.
+fn queue_capacity(sample_rate: u32, latency_ms: u32) -> f64 {
+    2.0 * sample_rate as f64 * latency_ms as f64 / 1000.0
+}
+
+// How long the core's speed is measured over, from how fast its audio arrives.
+const SPEED_WINDOW: Duration = Duration::from_millis(250);
+
+// Keeps track of how much audio is queued in the sink, for dynamic rate control,
+// and how fast the core is making it, for fast-forward. Belongs to the audio
+// thread, and starts over with each new sink.
+#[derive(Default)]
+pub struct RateControl {
+    // Frames in each buffer still in the sink, oldest first, counted in how long
+    // they play for at the core's rate.
+    queued: VecDeque<usize>,
+    queued_frames: usize,
+    // When the current speed measurement started and the frames that arrived since.
+    window_start: Option<Instant>,
+    window_frames: usize,
+    // How many times faster than real time the core last made audio.
+    speed: f64,
+    // When an underrun was last logged.
+    last_underrun_log: Option<Instant>,
+}
+
+impl RateControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Forgets the buffers the sink has finished playing, counting an underrun if
+    // that was all of them, and publishes how full the queue is. Called by the
+    // audio thread whenever it checks on the sink, so the figures stay current for
+    // audio_sync, the core's buffer status callback and the performance overlay.
+    pub fn update(&mut self, sink: &Sink, sample_rate: u32) {
+        let was_playing = !self.queued.is_empty();
+        while self.queued.len() > sink.len() {
+            self.queued_frames -= self.queued.pop_front().unwrap_or(0);
+        }
+        // Running dry is expected after a frame advance while paused
+        if was_playing && self.queued.is_empty() && is_enabled() && !is_paused() {
+            let underruns = UNDERRUNS.fetch_add(1, Ordering::SeqCst) + 1;
+            let now = Instant::now();
+            if self
+                .last_underrun_log
+                .is_none_or(|last| now - last >= UNDERRUN_LOG_INTERVAL)
+            {
+                println!("Audio underrun, {} so far", underruns);
+                self.last_underrun_log = Some(now);
+            }
+        }
+        QUEUED_FRAMES.store(self.queued_frames, Ordering::SeqCst);
+        OCCUPANCY.store(
+            (self.fill(sample_rate) * 100.0).round() as u32,
+            Ordering::SeqCst,
+        );
+    }
+
+    // Returns how full the queue is, from 0 to 1.
+    fn fill(&self, sample_rate: u32) -> f64 {
+        let latency_ms = RATE_CONTROL_SETTINGS.lock().unwrap().latency_ms;
+        (self.queued_frames as f64 / queue_capacity(sample_rate, latency_ms)).min(1.0)
+    }
+
+    // Returns the rate to play the next buffer at.
+    fn rate(&mut self, sink: &Sink, sample_rate: u32) -> u32 {
+        self.update(sink, sample_rate);
+        let delta = RATE_CONTROL_SETTINGS.lock().unwrap().delta;
+        if delta == 0.0 {
+            return sample_rate;
+        }
+        adjusted_rate(sample_rate, self.fill(sample_rate), delta)
+    }
+
+    // Returns whether the queue is full, when audio made faster than real time
+    // has to be dropped rather than build up a backlog.
+    fn is_full(&self, sample_rate: u32) -> bool {
+        self.fill(sample_rate) >= 1.0
+    }
+
+    // Counts frames arriving from the core towards measuring its speed.
+    fn measure_speed(&mut self, frames: usize, sample_rate: u32) {
+        let now = Instant::now();
+        let start = *self.window_start.get_or_insert(now);
+        self.window_frames += frames;
+        let elapsed = now.duration_since(start);
+        if elapsed >= SPEED_WINDOW {
+            self.speed = self.window_frames as f64 / (elapsed.as_secs_f64() * sample_rate as f64);
+            self.window_start = Some(now);
+            self.window_frames = 0;
+        }
+    }
+
+    fn queue(&mut self, frames: usize) {
+        self.queued.push_back(frames);
+        self.queued_frames += frames;
+        QUEUED_FRAMES.store(self.queued_frames, Ordering::SeqCst);
+    }
+}
+
+// A buffer of resampled audio in the sink. Once played it goes back to the audio
+// thread to be filled again, so no buffer is allocated for each batch.
+struct OutputBuffer {
+    samples: Vec<i16>,
+    position: usize,
+    sample_rate: u32,
+    recycle: Sender<Vec<i16>>,
+}
+
+impl Iterator for OutputBuffer {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+}
+
+impl Source for OutputBuffer {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len().saturating_sub(self.position))
+    }
+
+    fn channels(&self) -> u16 {
+        AUDIO_CHANNELS as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = self.samples.len() / AUDIO_CHANNELS;
+        Some(Duration::from_secs_f64(
+            frames as f64 / self.sample_rate as f64,
+        ))
+    }
+}
+
+impl Drop for OutputBuffer {
+    fn drop(&mut self) {
+        // Fails once the audio thread has moved on to new buffers, e.g. for a new sink
+        let _ = self.recycle.send(std::mem::take(&mut self.samples));
+    }
+}
+
+// The audio thread's buffers of resampled audio that have finished playing.
+pub struct OutputBuffers {
+    recycled: Receiver<Vec<i16>>,
+    recycle: Sender<Vec<i16>>,
+}
+
+impl Default for OutputBuffers {
+    fn default() -> Self {
+        let (recycle, recycled) = mpsc::channel();
+        OutputBuffers { recycled, recycle }
+    }
+}
+
+impl OutputBuffers {
+    // Returns an empty buffer, one that has played if there is one.
+    fn take(&self) -> Vec<i16> {
+        let mut samples = self.recycled.try_recv().unwrap_or_default();
+        samples.clear();
+        samples
+    }
+}
+
+// Plays audio using the `rodio` library, resampled to the device's rate from a
+// rate nudged by dynamic rate control. While fast-forwarding, it is dealt with as
+// `fastforward_audio` says, and dropped once the queue is full, and in slow motion
+// it is slowed down to match.
+pub fn play_audio(
+    sink: &Sink,
+    audio_slice: &[i16],
+    rate_control: &mut RateControl,
+    resampler: &mut Resampler,
+    buffers: &OutputBuffers,
+) {
+    let sample_rate = resampler.input_rate();
+    rate_control.measure_speed(audio_slice.len() / AUDIO_CHANNELS, sample_rate);
+    let mut rate = rate_control.rate(sink, sample_rate) as f64;
+    let mut audio_slice = audio_slice;
+    let mut piece = Vec::new();
+    if FAST_FORWARDING.load(Ordering::SeqCst) {
+        if rate_control.is_full(sample_rate) {
+            return;
+        }
+        let speed = rate_control.speed.max(1.0);
+        match fast_forward_audio() {
+            FastForwardAudio::Pitch => rate *= speed,
+            FastForwardAudio::Cut => {
+                let frames = (audio_slice.len() / AUDIO_CHANNELS) as f64 / speed;
+                piece = buffers.take();
+                piece.extend_from_slice(&audio_slice[..frames.ceil() as usize * AUDIO_CHANNELS]);
+                fade_edges(&mut piece);
+                audio_slice = &piece;
+            }
+            FastForwardAudio::Mute => return,
+        }
+    } else if let Some(speed) = slow_motion() {
+        // Stretched over the time its frames take in slow motion
+        rate *= speed;
+    }
+    let mut resampled = buffers.take();
+    resampler.process(audio_slice, rate, &mut resampled);
+    sink.append(OutputBuffer {
+        samples: resampled,
+        position: 0,
+        sample_rate: resampler.output_rate(),
+        recycle: buffers.recycle.clone(),
+    });
+    let frames = (audio_slice.len() / AUDIO_CHANNELS) as f64 * sample_rate as f64 / rate;
+    rate_control.queue(frames.round() as usize);
+    if piece.capacity() > 0 {
+        let _ = buffers.recycle.send(piece);
+    }
+}
+
+// Fades interleaved stereo samples in over their first CUT_FADE_FRAMES frames and
+// out over their last, or over half of them each if there are fewer.
+fn fade_edges(samples: &mut [i16]) {
+    let frames = samples.len() / AUDIO_CHANNELS;
+    let fade = CUT_FADE_FRAMES.min(frames / 2);
+    for frame in 0..fade {
+        let gain = frame as f32 / fade as f32;
+        for channel in 0..AUDIO_CHANNELS {
+            for index in [frame, frames - 1 - frame] {
+                let sample = &mut samples[index * AUDIO_CHANNELS + channel];
+                *sample = (*sample as f32 * gain) as i16;
+            }
+        }
+    }
+}
+
+thread_local! {
+    // Samples from cores that send one stereo pair at a time, collected until the
+    // end of the frame so they go down the same path as batches do.
+    static SINGLE_SAMPLES: RefCell<Vec<i16>> = const { RefCell::new(Vec::new()) };
 }
 
 // Callback function for the libretro API to handle individual audio samples.
 pub unsafe extern "C" fn libretro_set_audio_sample_callback(left: i16, right: i16) {
-    println!("libretro_set_audio_sample_callback");
+    SINGLE_SAMPLES.with_borrow_mut(|samples| {
+        samples.extend_from_slice(&[left, right]);
+        // Don't let a core that never finishes a frame, e.g. one waiting on input, hold audio back
+        if samples.len() >= BUFFER_LENGTH * AUDIO_CHANNELS {
+            send_samples(samples);
+            samples.clear();
+        }
+    });
+}
+
+// Sends the samples collected from the single-sample callback to be played. Called
+// after each frame is run.
+pub fn flush_single_samples() {
+    SINGLE_SAMPLES.with_borrow_mut(|samples| {
+        if !samples.is_empty() {
+            send_samples(samples);
+            samples.clear();
+        }
+    });
 }
 
 // Callback function for the libretro API to handle batches of audio samples.
@@ -76,28 +813,61 @@ pub unsafe extern "C" fn libretro_set_audio_sample_batch_callback(
     audio_data: *const i16,
     frames: libc::size_t,
 ) -> libc::size_t {
-    let buffer_arc: Arc<Mutex<Vec<i16>>>;
-    {
-        let mut pool = BUFFER_POOL.lock().unwrap();
-        buffer_arc = pool.pop().unwrap_or_else(|| Arc::new(Mutex::new(vec![0; BUFFER_LENGTH])));
-    }
+    send_samples(std::slice::from_raw_parts(
+        audio_data,
+        frames * AUDIO_CHANNELS,
+    ));
+    frames
+}
 
-    {
-        let mut buffer = AudioBuffer::new(BUFFER_LENGTH);
-        let audio_slice = std::slice::from_raw_parts(audio_data, frames * AUDIO_CHANNELS);
-        buffer.clear();
-        buffer.extend_from_slice(audio_slice);
-        let buffer_arc = Arc::new(Mutex::new(buffer));
-        if let Err(e) = AUDIO_DATA_CHANNEL.0.send(buffer_arc.clone()) {
-            eprintln!("Failed to send audio data: {:?}", e);
-        }
+// Sends interleaved stereo samples to the audio thread: through the queue from the
+// emulation thread, or straight to it from its audio callback. If the audio thread
+// has fallen so far behind that the queue is full, the samples that don't fit are
+// dropped, as are any sent from other threads.
+fn send_samples(audio_slice: &[i16]) {
+    let pushed = QUEUE_SENDER.with_borrow_mut(|sender| match sender {
+        Some(sender) => sender.producer.push(audio_slice),
+        None => CALLBACK_SAMPLES.with_borrow_mut(|samples| match samples {
+            Some(samples) => {
+                samples.extend_from_slice(audio_slice);
+                audio_slice.len()
+            }
+            None => 0,
+        }),
+    });
+    if pushed < audio_slice.len() {
+        DROPPED_SAMPLES.fetch_add((audio_slice.len() - pushed) as u64, Ordering::SeqCst);
     }
+}
 
-    // Reuse and return buffers to the pool after processing.
-    {
-        let mut pool = BUFFER_POOL.lock().unwrap();
-        pool.push(buffer_arc);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_cut_pieces_in_and_out() {
+        let mut samples = vec![1000; 100 * AUDIO_CHANNELS];
+        fade_edges(&mut samples);
+        assert_eq!(samples[..2], [0, 0]);
+        assert_eq!(samples[samples.len() - 2..], [0, 0]);
+        assert_eq!(
+            samples[CUT_FADE_FRAMES * AUDIO_CHANNELS..][..2],
+            [1000, 1000]
+        );
+
+        // Too short to fade over the whole length, so it fades over half each way
+        let mut samples = vec![1000; 4 * AUDIO_CHANNELS];
+        fade_edges(&mut samples);
+        assert_eq!(samples, [0, 0, 500, 500, 500, 500, 0, 0]);
     }
 
-    frames
+    #[test]
+    fn nudges_the_rate_towards_a_half_full_queue() {
+        assert_eq!(adjusted_rate(32_000, 0.5, 0.005), 32_000);
+        assert_eq!(adjusted_rate(32_000, 1.0, 0.005), 32_160);
+        assert_eq!(adjusted_rate(32_000, 0.0, 0.005), 31_840);
+        // Overfull queues don't speed up any further
+        assert_eq!(adjusted_rate(32_000, 3.0, 0.005), 32_160);
+        assert_eq!(adjusted_rate(44_100, 0.75, 0.0), 44_100);
+    }
 }
@@ -0,0 +1,143 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// audio_dump.rs
+//
+// This module records the core's audio to a file from the hotkey, e.g. to rip a
+// game's music. Recordings are WAV or, with `audio_dump_format = "flac"`, FLAC,
+// saved in `screenshot_directory` with the screenshots and clips. The audio is
+// written as the core made it, before resampling, by the audio thread so the
+// emulation thread never waits on the disk.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::flac::FlacWriter;
+use crate::{audio, osd, screenshot};
+
+enum Writer {
+    Wav(hound::WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+struct Recording {
+    writer: Writer,
+    path: PathBuf,
+    // The core's sample rate when recording started.
+    sample_rate: u32,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+// Starts recording, or stops the recording in progress.
+pub fn toggle(config: &HashMap<String, String>, rom_name: &str) {
+    let mut recording = RECORDING.lock().unwrap();
+    if let Some(recording) = recording.take() {
+        finish(recording);
+        return;
+    }
+    match start(config, rom_name) {
+        Ok(started) => {
+            println!("Recording audio to {}", started.path.display());
+            osd::show_message("Recording audio");
+            *recording = Some(started);
+        }
+        Err(e) => {
+            println!("Failed to record audio: {}", e);
+            osd::show_message("Audio recording failed");
+        }
+    }
+}
+
+// Opens a new file for a recording in the configured format.
+fn start(config: &HashMap<String, String>, rom_name: &str) -> Result<Recording, String> {
+    let sample_rate = audio::sample_rate();
+    if sample_rate == 0 {
+        return Err("the core hasn't given its sample rate".to_string());
+    }
+    let format = config
+        .get("audio_dump_format")
+        .map_or("wav", String::as_str);
+    let extension = match format {
+        "wav" | "flac" => format,
+        other => {
+            return Err(format!(
+                "unknown audio_dump_format {}, expected wav or flac",
+                other
+            ))
+        }
+    };
+    let path = screenshot::new_path(config, rom_name, extension)?;
+    let file = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+    let writer = if format == "flac" {
+        Writer::Flac(FlacWriter::new(file, sample_rate)?)
+    } else {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        Writer::Wav(hound::WavWriter::new(file, spec).map_err(|e| e.to_string())?)
+    };
+    Ok(Recording {
+        writer,
+        path,
+        sample_rate,
+    })
+}
+
+// Closes a recording's file, filling in the lengths its header gives.
+fn finish(recording: Recording) {
+    let result = match recording.writer {
+        Writer::Wav(writer) => writer.finalize().map_err(|e| e.to_string()),
+        Writer::Flac(writer) => writer.finish().map(|_| ()),
+    };
+    match result {
+        Ok(()) => {
+            println!("Saved audio recording to {}", recording.path.display());
+            osd::show_message("Audio recording saved");
+        }
+        Err(e) => {
+            println!("Failed to save audio recording: {}", e);
+            osd::show_message("Audio recording failed");
+        }
+    }
+}
+
+// Writes interleaved stereo samples from the core to the recording, if there is
+// one. A recording ends if the core changes its sample rate, since the file can
+// only have one.
+pub fn write(samples: &[i16]) {
+    let mut recording = RECORDING.lock().unwrap();
+    let Some(current) = recording.as_mut() else {
+        return;
+    };
+    if current.sample_rate != audio::sample_rate() {
+        println!("The core changed its sample rate, ending the audio recording");
+        finish(recording.take().unwrap());
+        return;
+    }
+    let result = match &mut current.writer {
+        Writer::Wav(writer) => samples
+            .iter()
+            .try_for_each(|&sample| writer.write_sample(sample))
+            .map_err(|e| e.to_string()),
+        Writer::Flac(writer) => writer.write(samples),
+    };
+    if let Err(e) = result {
+        println!("Failed to write audio recording: {}", e);
+        finish(recording.take().unwrap());
+    }
+}
+
+// Ends the recording in progress, e.g. when the frontend closes.
+pub fn stop() {
+    if let Some(recording) = RECORDING.lock().unwrap().take() {
+        finish(recording);
+    }
+}
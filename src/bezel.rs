@@ -0,0 +1,267 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// bezel.rs
+//
+// This module draws a PNG bezel from `video_bezel` around the game, e.g. a
+// handheld's border or an arcade cabinet's artwork. The image is fitted to the
+// window keeping its aspect ratio, and the game is shown in its viewport: the
+// rectangle `video_bezel_viewport` gives in image pixels, or else the transparent
+// area of the image. Setting it in a core's override file gives each system its own.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Mutex;
+
+use crate::pixels;
+
+struct Bezel {
+    path: String,
+    // ARGB8888 pixels of the image.
+    image: Vec<u32>,
+    width: usize,
+    height: usize,
+    // Where the game goes in the image, as x, y, width and height.
+    viewport: (usize, usize, usize, usize),
+    // The image scaled to the last window size it was drawn in.
+    scaled: Option<((usize, usize), Vec<u32>)>,
+}
+
+static BEZEL: Mutex<Option<Bezel>> = Mutex::new(None);
+
+// Reads `video_bezel` and `video_bezel_viewport` from the config, loading the
+// image unless it is the one already shown.
+pub fn configure(config: &HashMap<String, String>) {
+    let mut bezel = BEZEL.lock().unwrap();
+    let path = config.get("video_bezel").map_or("", String::as_str);
+    if path.is_empty() {
+        *bezel = None;
+        return;
+    }
+    let viewport = config
+        .get("video_bezel_viewport")
+        .filter(|viewport| !viewport.is_empty());
+    let viewport = match viewport.map(|viewport| parse_viewport(viewport)) {
+        Some(None) => {
+            println!("video_bezel_viewport should be x,y,width,height in image pixels");
+            None
+        }
+        Some(viewport) => viewport,
+        None => None,
+    };
+    if let Some(current) = bezel.as_mut().filter(|current| current.path == *path) {
+        if let Some(viewport) = viewport {
+            current.viewport = viewport;
+        }
+        return;
+    }
+
+    *bezel = match load(path) {
+        Ok((image, width, height)) => {
+            let viewport = viewport
+                .or_else(|| transparent_area(&image, width, height))
+                .unwrap_or((0, 0, width, height));
+            println!(
+                "Loaded {}x{} bezel {} with the game at {:?}",
+                width, height, path, viewport
+            );
+            Some(Bezel {
+                path: path.to_string(),
+                image,
+                width,
+                height,
+                viewport,
+                scaled: None,
+            })
+        }
+        Err(e) => {
+            println!("Failed to load bezel {}: {}", path, e);
+            None
+        }
+    };
+}
+
+// Parses `x,y,width,height`.
+fn parse_viewport(value: &str) -> Option<(usize, usize, usize, usize)> {
+    let numbers = value
+        .split(',')
+        .map(|number| number.trim().parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+    match numbers[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Some((x, y, width, height)),
+        _ => None,
+    }
+}
+
+// Decodes a PNG into ARGB8888 pixels and its size.
+fn load(path: &str) -> Result<(Vec<u32>, usize, usize), String> {
+    let file = File::open(shellexpand::tilde(path).as_ref()).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    let data = &data[..info.buffer_size()];
+    let argb = |r: u8, g: u8, b: u8, a: u8| u32::from_be_bytes([a, r, g, b]);
+    let image = match info.color_type {
+        png::ColorType::Rgba => data
+            .chunks_exact(4)
+            .map(|p| argb(p[0], p[1], p[2], p[3]))
+            .collect(),
+        png::ColorType::Rgb => data
+            .chunks_exact(3)
+            .map(|p| argb(p[0], p[1], p[2], 0xFF))
+            .collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks_exact(2)
+            .map(|p| argb(p[0], p[0], p[0], p[1]))
+            .collect(),
+        png::ColorType::Grayscale => data.iter().map(|&p| argb(p, p, p, 0xFF)).collect(),
+        color_type => return Err(format!("Unsupported colour type {:?}", color_type)),
+    };
+    Ok((image, info.width as usize, info.height as usize))
+}
+
+// Returns the bounding box of the fully transparent pixels, where a bezel
+// leaves room for the game, or None if it has none.
+fn transparent_area(
+    image: &[u32],
+    width: usize,
+    height: usize,
+) -> Option<(usize, usize, usize, usize)> {
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (index, &pixel) in image.iter().enumerate() {
+        if pixel >> 24 == 0 {
+            let (x, y) = (index % width, index / width);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    (right > left && bottom > top).then(|| (left, top, right - left, bottom - top))
+}
+
+// Returns where an image of the given size goes in the window, fitted to it
+// keeping its aspect ratio and centered, as x, y, width and height.
+fn fit(image_size: (usize, usize), window_size: (usize, usize)) -> (usize, usize, usize, usize) {
+    let scale = (window_size.0 as f64 / image_size.0 as f64)
+        .min(window_size.1 as f64 / image_size.1 as f64);
+    let width = ((image_size.0 as f64 * scale).round() as usize).min(window_size.0);
+    let height = ((image_size.1 as f64 * scale).round() as usize).min(window_size.1);
+    (
+        (window_size.0 - width) / 2,
+        (window_size.1 - height) / 2,
+        width,
+        height,
+    )
+}
+
+// Returns the area of the window the game is shown in: the bezel's viewport if
+// there is one, or else the whole window.
+pub fn viewport(window_size: (usize, usize)) -> (usize, usize, usize, usize) {
+    let bezel = BEZEL.lock().unwrap();
+    let whole_window = (0, 0, window_size.0, window_size.1);
+    let Some(bezel) = bezel.as_ref() else {
+        return whole_window;
+    };
+    let (x, y, width, height) = fit((bezel.width, bezel.height), window_size);
+    let scale_x = width as f64 / bezel.width as f64;
+    let scale_y = height as f64 / bezel.height as f64;
+    let (viewport_x, viewport_y, viewport_width, viewport_height) = bezel.viewport;
+    let left = x + (viewport_x as f64 * scale_x).round() as usize;
+    let top = y + (viewport_y as f64 * scale_y).round() as usize;
+    let right = x + ((viewport_x + viewport_width) as f64 * scale_x).round() as usize;
+    let bottom = y + ((viewport_y + viewport_height) as f64 * scale_y).round() as usize;
+    let (right, bottom) = (right.min(window_size.0), bottom.min(window_size.1));
+    if right <= left || bottom <= top {
+        return whole_window;
+    }
+    (left, top, right - left, bottom - top)
+}
+
+// Returns the image scaled into a window of the given size, with nothing around it.
+fn scale(bezel: &Bezel, window_size: (usize, usize)) -> Vec<u32> {
+    let mut scaled = vec![0; window_size.0 * window_size.1];
+    let (x, y, width, height) = fit((bezel.width, bezel.height), window_size);
+    for row in 0..height {
+        let source_row = row * bezel.height / height;
+        let source = &bezel.image[source_row * bezel.width..][..bezel.width];
+        let start = (y + row) * window_size.0 + x;
+        for (column, pixel) in scaled[start..start + width].iter_mut().enumerate() {
+            *pixel = source[column * bezel.width / width];
+        }
+    }
+    scaled
+}
+
+// Calls `draw` with each pixel of the window and the bezel pixel over it, for
+// the pixels the bezel isn't transparent over.
+fn draw_with(window_buffer: &mut [u32], width: usize, height: usize, draw: impl Fn(&mut u32, u32)) {
+    let mut bezel = BEZEL.lock().unwrap();
+    let Some(bezel) = bezel.as_mut() else {
+        return;
+    };
+    let window_size = (width, height);
+    if bezel.scaled.as_ref().map(|(size, _)| *size) != Some(window_size) {
+        bezel.scaled = Some((window_size, scale(bezel, window_size)));
+    }
+    let (_, scaled) = bezel.scaled.as_ref().unwrap();
+    for (pixel, &bezel_pixel) in window_buffer.iter_mut().zip(scaled) {
+        if bezel_pixel >> 24 != 0 {
+            draw(pixel, bezel_pixel);
+        }
+    }
+}
+
+// Blends the bezel over a window buffer of XRGB8888 pixels.
+pub fn draw(window_buffer: &mut [u32], width: usize, height: usize) {
+    draw_with(window_buffer, width, height, |pixel, bezel_pixel| {
+        let alpha = bezel_pixel >> 24;
+        *pixel = pixels::blend(*pixel, bezel_pixel & 0xFFFFFF, alpha + (alpha >> 7));
+    });
+}
+
+// Copies the bezel with its alpha into the GPU renderer's overlay, for it to
+// blend over the frame.
+#[cfg(feature = "wgpu")]
+pub fn draw_overlay(overlay: &mut [u32], width: usize, height: usize) {
+    draw_with(overlay, width, height, |pixel, bezel_pixel| {
+        *pixel = bezel_pixel
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_transparent_area() {
+        let mut image = vec![0xFF00_0000; 8 * 6];
+        for y in 1..5 {
+            for x in 2..7 {
+                image[y * 8 + x] = 0x0000_0000;
+            }
+        }
+        assert_eq!(transparent_area(&image, 8, 6), Some((2, 1, 5, 4)));
+        assert_eq!(transparent_area(&[0xFF00_0000; 4], 2, 2), None);
+    }
+
+    #[test]
+    fn fits_the_image_to_the_window() {
+        // A 16:9 bezel in a 4:3 window is letterboxed
+        assert_eq!(fit((1920, 1080), (1024, 768)), (0, 96, 1024, 576));
+        assert_eq!(fit((1920, 1080), (3840, 2160)), (0, 0, 3840, 2160));
+    }
+
+    #[test]
+    fn parses_viewports() {
+        assert_eq!(
+            parse_viewport("240, 0, 1440, 1080"),
+            Some((240, 0, 1440, 1080))
+        );
+        assert_eq!(parse_viewport("240,0,1440"), None);
+        assert_eq!(parse_viewport("0,0,0,1080"), None);
+    }
+}
@@ -0,0 +1,215 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// camera.rs
+//
+// This module implements the libretro camera interface for cores like the Game Boy
+// Camera or the DSi camera. With the `camera` feature enabled, frames come from the
+// first webcam through nokhwa. Without it, or when no webcam can be opened, the
+// core is told the camera failed to start and carries on without it.
+
+use libc::{c_uint, size_t};
+use std::cell::RefCell;
+
+// Bit of retro_camera_callback.caps for raw XRGB8888 framebuffers.
+const CAMERA_BUFFER_RAW_FRAMEBUFFER: u64 = 1 << 1;
+
+// struct retro_camera_callback. Mirrored here because the core leaves the callbacks
+// it doesn't use NULL, which libretro-sys's non-nullable function pointers can't hold.
+#[repr(C)]
+pub struct CameraCallback {
+    pub caps: u64,
+    pub width: c_uint,
+    pub height: c_uint,
+    pub start: unsafe extern "C" fn() -> bool,
+    pub stop: unsafe extern "C" fn(),
+    pub frame_raw_framebuffer: Option<
+        unsafe extern "C" fn(buffer: *const u32, width: c_uint, height: c_uint, pitch: size_t),
+    >,
+    pub frame_opengl_texture: Option<
+        unsafe extern "C" fn(texture_id: c_uint, texture_target: c_uint, affine: *const f32),
+    >,
+    pub initialized: Option<unsafe extern "C" fn()>,
+    pub deinitialized: Option<unsafe extern "C" fn()>,
+}
+
+// What the core registered, plus the webcam while it is running.
+#[cfg_attr(not(feature = "camera"), allow(dead_code))]
+struct Camera {
+    width: u32,
+    height: u32,
+    frame_raw_framebuffer:
+        unsafe extern "C" fn(buffer: *const u32, width: c_uint, height: c_uint, pitch: size_t),
+    initialized: Option<unsafe extern "C" fn()>,
+    deinitialized: Option<unsafe extern "C" fn()>,
+    #[cfg(feature = "camera")]
+    webcam: Option<webcam::Webcam>,
+}
+
+// start, stop and the frame callbacks all run on the emulation thread.
+thread_local! {
+    static CAMERA: RefCell<Option<Camera>> = const { RefCell::new(None) };
+}
+
+// Handles ENVIRONMENT_GET_CAMERA_INTERFACE. Only raw framebuffer delivery is
+// supported, since frames are not uploaded to a GL texture.
+pub unsafe fn get_camera_interface(callback: *mut CameraCallback) -> bool {
+    let Some(callback) = callback.as_mut() else {
+        return false;
+    };
+    let frame_raw_framebuffer = match callback.frame_raw_framebuffer {
+        Some(frame) if callback.caps & CAMERA_BUFFER_RAW_FRAMEBUFFER != 0 => frame,
+        _ => {
+            println!("Core wants camera frames as GL textures, which are not supported");
+            return false;
+        }
+    };
+    callback.start = start;
+    callback.stop = stop;
+    CAMERA.with(|camera| {
+        *camera.borrow_mut() = Some(Camera {
+            width: callback.width,
+            height: callback.height,
+            frame_raw_framebuffer,
+            initialized: callback.initialized,
+            deinitialized: callback.deinitialized,
+            #[cfg(feature = "camera")]
+            webcam: None,
+        })
+    });
+    true
+}
+
+// Tells the core the camera driver is ready. Must be called after the game has been loaded.
+pub fn initialize() {
+    let initialized = CAMERA.with(|camera| camera.borrow().as_ref().and_then(|c| c.initialized));
+    if let Some(initialized) = initialized {
+        unsafe { initialized() };
+    }
+}
+
+// Tells the core the camera driver is going away and closes the webcam.
+pub fn deinitialize() {
+    let camera = CAMERA.with(|camera| camera.borrow_mut().take());
+    if let Some(deinitialized) = camera.and_then(|camera| camera.deinitialized) {
+        unsafe { deinitialized() };
+    }
+}
+
+// Hands the newest webcam frame to the core, if the camera is running.
+pub fn poll() {
+    #[cfg(feature = "camera")]
+    {
+        let frame = CAMERA.with(|camera| {
+            let camera = camera.borrow();
+            let camera = camera.as_ref()?;
+            let frame = camera.webcam.as_ref()?.take_frame()?;
+            Some((camera.frame_raw_framebuffer, frame))
+        });
+        if let Some((frame_raw_framebuffer, (pixels, width, height))) = frame {
+            unsafe {
+                frame_raw_framebuffer(pixels.as_ptr(), width, height, width as size_t * 4);
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn start() -> bool {
+    CAMERA.with(|camera| {
+        let mut camera = camera.borrow_mut();
+        let Some(camera) = camera.as_mut() else {
+            return false;
+        };
+        #[cfg(feature = "camera")]
+        {
+            match webcam::Webcam::open(camera.width, camera.height) {
+                Ok(webcam) => {
+                    camera.webcam = Some(webcam);
+                    true
+                }
+                Err(err) => {
+                    println!("Failed to start camera: {}", err);
+                    false
+                }
+            }
+        }
+        #[cfg(not(feature = "camera"))]
+        {
+            println!(
+                "Core asked for a {}x{} camera, but this build has no camera support",
+                camera.width, camera.height
+            );
+            false
+        }
+    })
+}
+
+unsafe extern "C" fn stop() {
+    #[cfg(feature = "camera")]
+    CAMERA.with(|camera| {
+        if let Some(camera) = camera.borrow_mut().as_mut() {
+            camera.webcam = None;
+        }
+    });
+}
+
+#[cfg(feature = "camera")]
+mod webcam {
+    use nokhwa::pixel_format::RgbFormat;
+    use nokhwa::utils::{
+        CameraFormat, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution,
+    };
+    use nokhwa::{Buffer, CallbackCamera};
+    use std::sync::{Arc, Mutex};
+
+    // The first webcam, capturing on its own thread.
+    pub struct Webcam {
+        camera: CallbackCamera,
+        latest: Arc<Mutex<Option<Buffer>>>,
+    }
+
+    impl Webcam {
+        // Opens the first webcam, as close to the core's requested size as it supports.
+        pub fn open(width: u32, height: u32) -> Result<Self, String> {
+            let requested = if width > 0 && height > 0 {
+                RequestedFormatType::Closest(CameraFormat::new(
+                    Resolution::new(width, height),
+                    FrameFormat::MJPEG,
+                    30,
+                ))
+            } else {
+                RequestedFormatType::AbsoluteHighestFrameRate
+            };
+            let latest = Arc::new(Mutex::new(None));
+            let captured = latest.clone();
+            let mut camera = CallbackCamera::new(
+                CameraIndex::Index(0),
+                RequestedFormat::new::<RgbFormat>(requested),
+                move |buffer| *captured.lock().unwrap() = Some(buffer),
+            )
+            .map_err(|err| err.to_string())?;
+            camera.open_stream().map_err(|err| err.to_string())?;
+            println!("Camera started: {}", camera.info().human_name());
+            Ok(Webcam { camera, latest })
+        }
+
+        // Returns the frame captured since the last call as XRGB8888, with its size.
+        pub fn take_frame(&self) -> Option<(Vec<u32>, u32, u32)> {
+            let buffer = self.latest.lock().unwrap().take()?;
+            let image = buffer.decode_image::<RgbFormat>().ok()?;
+            let pixels = image
+                .as_raw()
+                .chunks_exact(3)
+                .map(|rgb| ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32)
+                .collect();
+            Some((pixels, image.width(), image.height()))
+        }
+    }
+
+    impl Drop for Webcam {
+        fn drop(&mut self) {
+            let _ = self.camera.stop_stream();
+        }
+    }
+}
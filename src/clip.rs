@@ -0,0 +1,220 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// clip.rs
+//
+// This module keeps the last `clip_length` seconds of the core's frames, shrunk by
+// `clip_downscale`, and saves them as an animated GIF from the hotkey for sharing.
+// Frames are encoded on another thread, since quantizing them to GIF palettes
+// takes a while.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::sync::Mutex;
+use std::thread;
+
+use crate::pixels::{self, Frame};
+use crate::{osd, screenshot};
+
+// GIF frame delays are in hundredths of a second, and most viewers slow down
+// anything shorter than 2, so clips play at 50 fps at most.
+const MAX_FPS: f64 = 50.0;
+
+// A recorded frame and how long it lasts, in seconds.
+#[derive(Clone)]
+struct ClipFrame {
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    duration: f64,
+}
+
+struct Recorder {
+    // Seconds of frames to keep, from `clip_length`.
+    length: f64,
+    // How many times smaller than the core's frames recorded ones are, from `clip_downscale`.
+    downscale: usize,
+    frames: VecDeque<ClipFrame>,
+    // Seconds of frames in `frames`.
+    duration: f64,
+    // Frames the core sent since the last one recorded.
+    skipped: usize,
+}
+
+static RECORDER: Mutex<Recorder> = Mutex::new(Recorder {
+    length: 10.0,
+    downscale: 2,
+    frames: VecDeque::new(),
+    duration: 0.0,
+    skipped: 0,
+});
+
+// Reads `clip_length` and `clip_downscale` from the config. A length of 0 turns
+// recording off.
+pub fn configure(config: &HashMap<String, String>) {
+    let length = config
+        .get("clip_length")
+        .and_then(|length| length.parse::<f64>().ok())
+        .map_or(10.0, |length| length.max(0.0));
+    let downscale = config
+        .get("clip_downscale")
+        .and_then(|downscale| downscale.parse::<usize>().ok())
+        .map_or(2, |downscale| downscale.max(1));
+    let mut recorder = RECORDER.lock().unwrap();
+    if recorder.length != length || recorder.downscale != downscale {
+        recorder.length = length;
+        recorder.downscale = downscale;
+        recorder.frames.clear();
+        recorder.duration = 0.0;
+    }
+}
+
+// Returns how many of the core's frames make up one recorded frame, so clips stay
+// under MAX_FPS.
+fn frame_interval(fps: f64) -> usize {
+    if fps > MAX_FPS {
+        (fps / MAX_FPS).ceil() as usize
+    } else {
+        1
+    }
+}
+
+// Records a frame from the core, dropping the oldest ones past the clip length.
+pub fn record(frame: &Frame, fps: f64) {
+    let mut recorder = RECORDER.lock().unwrap();
+    if recorder.length == 0.0 || fps <= 0.0 {
+        return;
+    }
+    let interval = frame_interval(fps);
+    recorder.skipped += 1;
+    if recorder.skipped < interval {
+        return;
+    }
+    recorder.skipped = 0;
+
+    let width = (frame.width / recorder.downscale).max(1);
+    let height = (frame.height / recorder.downscale).max(1);
+    let mut pixels = vec![0; width * height];
+    pixels::scale_bilinear(frame, &mut pixels, width, width, height);
+    let duration = interval as f64 / fps;
+    recorder.frames.push_back(ClipFrame {
+        pixels,
+        width,
+        height,
+        duration,
+    });
+    recorder.duration += duration;
+    while recorder.duration > recorder.length {
+        let Some(oldest) = recorder.frames.pop_front() else {
+            break;
+        };
+        recorder.duration -= oldest.duration;
+    }
+}
+
+// Saves the recorded frames as a GIF from the hotkey. Recording carries on while
+// it is encoded.
+pub fn save(config: &HashMap<String, String>, rom_name: &str) {
+    // Frames change size with the core's resolution, and a GIF has only one, so
+    // the clip starts after the last change
+    let frames: Vec<ClipFrame> = {
+        let recorder = RECORDER.lock().unwrap();
+        let size = |frame: &ClipFrame| (frame.width, frame.height);
+        let last_size = recorder.frames.back().map(size);
+        let start = recorder
+            .frames
+            .iter()
+            .rposition(|frame| Some(size(frame)) != last_size)
+            .map_or(0, |index| index + 1);
+        recorder.frames.range(start..).cloned().collect()
+    };
+    if frames.is_empty() {
+        println!("No frames recorded for a clip, is clip_length 0?");
+        osd::show_message("Clip failed");
+        return;
+    }
+    let path = match screenshot::new_path(config, rom_name, "gif") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Failed to save clip: {}", e);
+            osd::show_message("Clip failed");
+            return;
+        }
+    };
+    osd::show_message("Saving clip");
+    thread::spawn(move || {
+        let result = File::create(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| encode(file, &frames));
+        match result {
+            Ok(()) => {
+                println!("Saved clip to {}", path.display());
+                osd::show_message("Clip saved");
+            }
+            Err(e) => {
+                println!("Failed to save clip: {}", e);
+                osd::show_message("Clip failed");
+            }
+        }
+    });
+}
+
+// Returns each frame's delay in hundredths of a second, rounding the time they
+// end at so the clip as a whole keeps time.
+fn delays(durations: impl IntoIterator<Item = f64>) -> Vec<u16> {
+    let mut elapsed = 0.0;
+    let mut shown = 0;
+    durations
+        .into_iter()
+        .map(|duration| {
+            elapsed += duration;
+            let end = (elapsed * 100.0).round() as u64;
+            let delay = end - shown;
+            shown = end;
+            delay as u16
+        })
+        .collect()
+}
+
+// Encodes XRGB8888 frames of the same size as a looping GIF, each with its own
+// palette.
+fn encode(file: File, frames: &[ClipFrame]) -> Result<(), String> {
+    let (width, height) = (frames[0].width as u16, frames[0].height as u16);
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(|e| e.to_string())?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+    let delays = delays(frames.iter().map(|frame| frame.duration));
+    for (frame, delay) in frames.iter().zip(delays) {
+        let mut rgba: Vec<u8> = frame
+            .pixels
+            .iter()
+            .flat_map(|&pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8, 0xFF])
+            .collect();
+        let mut gif_frame = gif::Frame::from_rgba_speed(width, height, &mut rgba, 10);
+        gif_frame.delay = delay;
+        encoder.write_frame(&gif_frame).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_clips_under_the_gif_frame_rate() {
+        assert_eq!(frame_interval(60.0988), 2);
+        assert_eq!(frame_interval(50.0), 1);
+        assert_eq!(frame_interval(30.0), 1);
+        assert_eq!(frame_interval(120.0), 3);
+    }
+
+    #[test]
+    fn spreads_rounding_over_frame_delays() {
+        // Every other frame at 60 fps lasts 3.33 hundredths of a second
+        assert_eq!(delays([2.0 / 60.0; 6]), vec![3, 4, 3, 3, 4, 3]);
+        assert_eq!(delays([0.02; 3]), vec![2, 2, 2]);
+    }
+}
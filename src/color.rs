@@ -0,0 +1,225 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// color.rs
+//
+// This module corrects the colours of frames before they are scaled, with
+// `video_gamma`, `video_brightness` and `video_saturation`, and with
+// `video_color_correction` set to "gba" or "gbc", the colours of those handhelds'
+// LCDs. Games for them were made to look right on dim, washed out screens, and
+// most cores leave reproducing that to the frontend.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::pixels::Frame;
+
+// Steps in the tables that turn corrected values back into 8 bit channels.
+const OUTPUT_STEPS: usize = 1024;
+
+// Gamma the display is assumed to have.
+const DISPLAY_GAMMA: f32 = 2.2;
+
+// How a handheld's LCD shows colours: the gamma its pixels are decoded with, how
+// much of each input channel goes into each output channel in linear light, one
+// row per output channel, and how bright it is overall. The values are those of
+// Pokefan531's gba-color and gbc-color shaders.
+struct Lcd {
+    gamma: f32,
+    matrix: [[f32; 3]; 3],
+    luminance: f32,
+}
+
+const GBA: Lcd = Lcd {
+    // The GBA's screen is darker than the display gamma alone accounts for
+    gamma: DISPLAY_GAMMA + 0.5,
+    matrix: [
+        [0.82, 0.24, -0.06],
+        [0.125, 0.665, 0.21],
+        [0.195, 0.075, 0.73],
+    ],
+    luminance: 0.94,
+};
+
+const GBC: Lcd = Lcd {
+    gamma: DISPLAY_GAMMA,
+    matrix: [
+        [0.78824, 0.12157, 0.0],
+        [0.025, 0.72941, 0.275],
+        [0.12039, 0.12157, 0.82],
+    ],
+    luminance: 0.94,
+};
+
+// An LCD's matrix scaled by its luminance, with tables from 8 bit channels to
+// linear light and from linear light back to display values.
+struct LcdTables {
+    matrix: [[f32; 3]; 3],
+    decode: [f32; 256],
+    encode: Vec<f32>,
+}
+
+// Lookup tables and factors for correcting pixels.
+struct Correction {
+    brightness: f32,
+    saturation: f32,
+    // The emulated LCD, if any.
+    lcd: Option<LcdTables>,
+    // Display values from 0 to 1 with `video_gamma` applied, as 8 bit channels.
+    gamma: Vec<u8>,
+}
+
+// The correction to apply, or None when colours are left alone.
+static CORRECTION: Mutex<Option<Correction>> = Mutex::new(None);
+
+// Reads `video_gamma`, `video_brightness`, `video_saturation` and
+// `video_color_correction` from the config.
+pub fn configure(config: &HashMap<String, String>) {
+    let number = |key: &str| {
+        config
+            .get(key)
+            .and_then(|value| value.parse::<f32>().ok())
+            .filter(|value| *value >= 0.0)
+            .unwrap_or(1.0)
+    };
+    let lcd = match config
+        .get("video_color_correction")
+        .map_or("none", String::as_str)
+    {
+        "gba" => Some(&GBA),
+        "gbc" => Some(&GBC),
+        "none" | "" => None,
+        other => {
+            println!(
+                "Unknown video_color_correction {}, expected gba, gbc or none",
+                other
+            );
+            None
+        }
+    };
+    *CORRECTION.lock().unwrap() = Correction::new(
+        number("video_gamma"),
+        number("video_brightness"),
+        number("video_saturation"),
+        lcd,
+    );
+}
+
+impl Correction {
+    // Builds the tables for a correction, or returns None if it changes nothing.
+    fn new(gamma: f32, brightness: f32, saturation: f32, lcd: Option<&Lcd>) -> Option<Self> {
+        if gamma == 1.0 && brightness == 1.0 && saturation == 1.0 && lcd.is_none() {
+            return None;
+        }
+        let gamma = if gamma > 0.0 { gamma } else { 1.0 };
+        let lcd = lcd.map(|lcd| LcdTables {
+            matrix: lcd
+                .matrix
+                .map(|row| row.map(|weight| weight * lcd.luminance)),
+            decode: std::array::from_fn(|value| (value as f32 / 255.0).powf(lcd.gamma)),
+            encode: output_table(|value| value.powf(1.0 / DISPLAY_GAMMA)),
+        });
+        let gamma = output_table(|value| value.powf(1.0 / gamma))
+            .iter()
+            .map(|value| (value * 255.0).round() as u8)
+            .collect();
+        Some(Correction {
+            brightness,
+            saturation,
+            lcd,
+            gamma,
+        })
+    }
+
+    // Corrects an XRGB8888 pixel.
+    fn apply(&self, pixel: u32) -> u32 {
+        let channels = [pixel >> 16, pixel >> 8, pixel].map(|channel| channel as u8);
+        let mut rgb = match &self.lcd {
+            Some(lcd) => {
+                let linear = channels.map(|channel| lcd.decode[channel as usize]);
+                lcd.matrix.map(|row| {
+                    let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                    lookup(&lcd.encode, value)
+                })
+            }
+            None => channels.map(|channel| channel as f32 / 255.0),
+        };
+        let luma = 0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2];
+        for value in &mut rgb {
+            *value = (luma + (*value - luma) * self.saturation) * self.brightness;
+        }
+        let [red, green, blue] = rgb.map(|value| lookup(&self.gamma, value) as u32);
+        (red << 16) | (green << 8) | blue
+    }
+}
+
+// Tabulates a function over [0, 1] in OUTPUT_STEPS steps.
+fn output_table<T>(function: impl Fn(f32) -> T) -> Vec<T> {
+    (0..OUTPUT_STEPS)
+        .map(|step| function(step as f32 / (OUTPUT_STEPS - 1) as f32))
+        .collect()
+}
+
+// Looks up a value from 0 to 1 in a table from output_table, clamping it first.
+fn lookup<T: Copy>(table: &[T], value: f32) -> T {
+    let step = (value.clamp(0.0, 1.0) * (OUTPUT_STEPS - 1) as f32).round();
+    table[step as usize]
+}
+
+// Appends the frame with its colours corrected to `corrected` as packed lines, and
+// returns true, or returns false if no correction is configured.
+pub fn correct(frame: &Frame, corrected: &mut Vec<u32>) -> bool {
+    let correction = CORRECTION.lock().unwrap();
+    let Some(correction) = correction.as_ref() else {
+        return false;
+    };
+    corrected.extend(
+        (0..frame.height)
+            .flat_map(|y| frame.line(y))
+            .map(|&pixel| correction.apply(pixel)),
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(pixel: u32) -> [u32; 3] {
+        [(pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF]
+    }
+
+    #[test]
+    fn leaves_colours_alone_by_default() {
+        assert!(Correction::new(1.0, 1.0, 1.0, None).is_none());
+    }
+
+    #[test]
+    fn adjusts_brightness_saturation_and_gamma() {
+        let darker = Correction::new(1.0, 0.5, 1.0, None).unwrap();
+        assert_eq!(darker.apply(0xFF8000), 0x804000);
+
+        let grey = Correction::new(1.0, 1.0, 0.0, None).unwrap();
+        let [red, green, blue] = channels(grey.apply(0xFF0000));
+        assert_eq!((red, green, blue), (76, 76, 76));
+
+        // A higher gamma brightens the mid tones and keeps black and white
+        let gamma = Correction::new(2.2, 1.0, 1.0, None).unwrap();
+        assert_eq!(gamma.apply(0x000000), 0x000000);
+        assert_eq!(gamma.apply(0xFFFFFF), 0xFFFFFF);
+        assert!(channels(gamma.apply(0x808080))[0] > 0xB0);
+    }
+
+    #[test]
+    fn washes_out_handheld_colours() {
+        let gbc = Correction::new(1.0, 1.0, 1.0, Some(&GBC)).unwrap();
+        let [red, green, blue] = channels(gbc.apply(0xFF0000));
+        assert!(red < 0xFF && green > 0 && blue > 0);
+
+        // White keeps its balance on the GBA, only dimmed by the screen's luminance
+        let gba = Correction::new(1.0, 1.0, 1.0, Some(&GBA)).unwrap();
+        let [red, green, blue] = channels(gba.apply(0xFFFFFF));
+        assert!(red == green && green == blue && red < 0xFF && red > 0xF0);
+    }
+}
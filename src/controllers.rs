@@ -0,0 +1,131 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// controllers.rs
+//
+// This module keeps the controller types a core offers for each port through
+// ENVIRONMENT_SET_CONTROLLER_INFO, and plugs in the ones `input_libretro_device_p1`
+// to `input_libretro_device_p5` ask for, by number as in RetroArch or by name,
+// e.g. "multitap". A multitap in port 2 of a Super Nintendo core makes players 2
+// to 5 of the frontend players 2 to 5 of the game, for Bomberman and the like.
+
+use libc::c_char;
+use libretro_sys::{ControllerInfo, CoreAPI};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Mutex;
+
+use crate::gamepad;
+
+// A controller type a core accepts on a port, e.g. "Multitap".
+#[derive(Clone, PartialEq, Debug)]
+pub struct ControllerType {
+    pub description: String,
+    pub id: u32,
+}
+
+// The types each port accepts, as the core announced them.
+static CONTROLLER_TYPES: Mutex<Vec<Vec<ControllerType>>> = Mutex::new(Vec::new());
+
+// The device plugged into each port, once one has been chosen.
+static PORT_DEVICES: Mutex<[Option<u32>; gamepad::PORTS]> = Mutex::new([None; gamepad::PORTS]);
+
+fn string_from_ptr(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr).to_string_lossy().into_owned() }
+    }
+}
+
+// Handles ENVIRONMENT_SET_CONTROLLER_INFO. There is an entry for each port, and the
+// array ends with a zeroed one.
+pub unsafe fn set_controller_info(info: *const ControllerInfo) -> bool {
+    let mut ports = Vec::new();
+    let mut current = info;
+    while !current.is_null() && !(*current).types.is_null() {
+        let info = &*current;
+        let types = std::slice::from_raw_parts(info.types, info.num_types as usize)
+            .iter()
+            .map(|controller| ControllerType {
+                description: string_from_ptr(controller.desc),
+                id: controller.id,
+            })
+            .collect::<Vec<_>>();
+        let names = types
+            .iter()
+            .map(|controller| format!("{} ({})", controller.description, controller.id))
+            .collect::<Vec<_>>();
+        println!("Port {} controllers: {}", ports.len() + 1, names.join(", "));
+        ports.push(types);
+        current = current.add(1);
+    }
+    *CONTROLLER_TYPES.lock().unwrap() = ports;
+    true
+}
+
+// Finds the device a config value names among a port's types: a number is taken
+// as the device ID, anything else as part of a type's description.
+fn find_device(types: &[ControllerType], value: &str) -> Option<u32> {
+    if let Ok(id) = value.parse::<u32>() {
+        return Some(id);
+    }
+    let value = value.to_lowercase();
+    types
+        .iter()
+        .find(|controller| controller.description.to_lowercase().contains(&value))
+        .map(|controller| controller.id)
+}
+
+// Plugs the devices the config asks for into the ports, leaving a port alone
+// when it asks for nothing or for the device it already has. Called once content
+// is loaded, as cores expect, and again when the config changes.
+pub fn apply_port_devices(core_api: &CoreAPI, config: &HashMap<String, String>) {
+    let types = CONTROLLER_TYPES.lock().unwrap();
+    let mut devices = PORT_DEVICES.lock().unwrap();
+    for (port, device) in devices.iter_mut().enumerate() {
+        let key = format!("input_libretro_device_p{}", port + 1);
+        let Some(value) = config.get(&key).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let port_types = types.get(port).map_or(&[][..], Vec::as_slice);
+        let Some(id) = find_device(port_types, value) else {
+            println!("The core has no controller {} for {}", value, key);
+            continue;
+        };
+        if *device == Some(id) {
+            continue;
+        }
+        *device = Some(id);
+        let name = port_types
+            .iter()
+            .find(|controller| controller.id == id)
+            .map_or(id.to_string(), |controller| controller.description.clone());
+        println!("Plugged {} into port {}", name, port + 1);
+        unsafe { (core_api.retro_set_controller_port_device)(port as u32, id) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_devices_by_id_or_name() {
+        let types = [
+            ControllerType {
+                description: "SNES Joypad".to_string(),
+                id: 1,
+            },
+            ControllerType {
+                description: "Multitap".to_string(),
+                id: 257,
+            },
+        ];
+        assert_eq!(find_device(&types, "multitap"), Some(257));
+        assert_eq!(find_device(&types, "Joypad"), Some(1));
+        assert_eq!(find_device(&types, "513"), Some(513));
+        assert_eq!(find_device(&types, "mouse"), None);
+    }
+}
@@ -0,0 +1,455 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// core_options.rs
+//
+// This module keeps track of the options a libretro core advertises through
+// ENVIRONMENT_SET_VARIABLES or the v2 core options API and answers
+// ENVIRONMENT_GET_VARIABLE from values found in the frontend configuration.
+
+use libc::c_char;
+use libretro_sys::Variable;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::Mutex;
+
+// Highest core options API version we understand.
+pub const CORE_OPTIONS_VERSION: u32 = 2;
+
+// Fixed size of the value array in a v2 option definition.
+const NUM_CORE_OPTION_VALUES_MAX: usize = 128;
+
+// Global store shared between the environment callback and the main loop.
+pub static CORE_OPTIONS: Lazy<Mutex<CoreOptions>> =
+    Lazy::new(|| Mutex::new(CoreOptions::default()));
+
+// struct retro_core_option_value
+#[repr(C)]
+pub struct CoreOptionValue {
+    pub value: *const c_char,
+    pub label: *const c_char,
+}
+
+// struct retro_core_option_v2_category
+#[repr(C)]
+pub struct CoreOptionV2Category {
+    pub key: *const c_char,
+    pub desc: *const c_char,
+    pub info: *const c_char,
+}
+
+// struct retro_core_option_v2_definition
+#[repr(C)]
+pub struct CoreOptionV2Definition {
+    pub key: *const c_char,
+    pub desc: *const c_char,
+    pub desc_categorized: *const c_char,
+    pub info: *const c_char,
+    pub info_categorized: *const c_char,
+    pub category_key: *const c_char,
+    pub values: [CoreOptionValue; NUM_CORE_OPTION_VALUES_MAX],
+    pub default_value: *const c_char,
+}
+
+// struct retro_core_options_v2
+#[repr(C)]
+pub struct CoreOptionsV2 {
+    pub categories: *const CoreOptionV2Category,
+    pub definitions: *const CoreOptionV2Definition,
+}
+
+// struct retro_core_options_v2_intl
+#[repr(C)]
+pub struct CoreOptionsV2Intl {
+    pub us: *const CoreOptionsV2,
+    pub local: *const CoreOptionsV2,
+}
+
+// struct retro_core_option_display
+#[repr(C)]
+pub struct CoreOptionDisplay {
+    pub key: *const c_char,
+    pub visible: bool,
+}
+
+// A category grouping related options, as declared by a v2 core.
+#[derive(Debug, Clone)]
+pub struct CoreOptionCategory {
+    pub key: String,
+    pub description: String,
+    pub info: String,
+}
+
+// A single option advertised by the core.
+#[derive(Debug, Clone)]
+pub struct CoreOption {
+    pub key: String,
+    pub description: String,
+    pub info: String,
+    pub category: Option<String>,
+    pub values: Vec<String>,
+    pub labels: Vec<String>,
+    pub default_value: String,
+    pub visible: bool,
+}
+
+// Option definitions, the values currently handed to the core and whether
+// any of them changed since the core last asked.
+#[derive(Default)]
+pub struct CoreOptions {
+    categories: Vec<CoreOptionCategory>,
+    definitions: Vec<CoreOption>,
+    values: HashMap<String, CString>,
+    configured: HashMap<String, String>,
+    updated: bool,
+    dirty: bool,
+}
+
+impl CoreOptions {
+    // Records the variables advertised by the core, which arrive as an array
+    // terminated by a { NULL, NULL } entry.
+    pub unsafe fn set_variables(&mut self, variables: *const Variable) {
+        self.categories.clear();
+        self.definitions.clear();
+        self.values.clear();
+
+        let mut variable = variables;
+        while !variable.is_null() && !(*variable).key.is_null() {
+            let key = CStr::from_ptr((*variable).key)
+                .to_string_lossy()
+                .into_owned();
+            let value = string_from_ptr((*variable).value);
+            if let Some(option) = parse_variable(&key, &value) {
+                self.define(option);
+            }
+            variable = variable.add(1);
+        }
+        self.print_options();
+    }
+
+    // Records the categories and definitions of a v2 core, preferring the
+    // localized strings in `local` where the core provides them.
+    pub unsafe fn set_options_v2(&mut self, us: *const CoreOptionsV2, local: *const CoreOptionsV2) {
+        self.categories.clear();
+        self.definitions.clear();
+        self.values.clear();
+        if us.is_null() {
+            return;
+        }
+
+        let local_categories = if local.is_null() {
+            HashMap::new()
+        } else {
+            read_categories((*local).categories)
+                .into_iter()
+                .map(|category| (category.key.clone(), category))
+                .collect()
+        };
+        let local_definitions = if local.is_null() {
+            HashMap::new()
+        } else {
+            read_definitions((*local).definitions)
+                .into_iter()
+                .map(|option| (option.key.clone(), option))
+                .collect()
+        };
+
+        for category in read_categories((*us).categories) {
+            let category = match local_categories.get(&category.key) {
+                Some(localized) => localized.clone(),
+                None => category,
+            };
+            self.categories.push(category);
+        }
+        for mut option in read_definitions((*us).definitions) {
+            if let Some(localized) = local_definitions.get(&option.key) {
+                option.description = localized.description.clone();
+                option.info = localized.info.clone();
+                if localized.labels.len() == option.labels.len() {
+                    option.labels = localized.labels.clone();
+                }
+            }
+            self.define(option);
+        }
+        self.print_options();
+    }
+
+    // Shows or hides an option, as requested through SET_CORE_OPTIONS_DISPLAY.
+    pub fn set_visible(&mut self, key: &str, visible: bool) {
+        if let Some(option) = self.definitions.iter_mut().find(|option| option.key == key) {
+            option.visible = visible;
+        }
+    }
+
+    // Adds an option definition and resolves its current value.
+    pub fn define(&mut self, option: CoreOption) {
+        let value = self.resolve_value(&option);
+        self.values
+            .insert(option.key.clone(), CString::new(value).unwrap_or_default());
+        self.definitions
+            .retain(|existing| existing.key != option.key);
+        self.definitions.push(option);
+    }
+
+    // Returns the value the core should see for `key`, if there is one.
+    pub fn get(&self, key: &str) -> Option<&CStr> {
+        self.values.get(key).map(|value| value.as_c_str())
+    }
+
+    // Returns the categories declared by the core, in declaration order.
+    pub fn categories(&self) -> &[CoreOptionCategory] {
+        &self.categories
+    }
+
+    // Returns the visible options belonging to `category`; `None` selects
+    // the options that are not part of any category.
+    pub fn options_in_category(&self, category: Option<&str>) -> Vec<&CoreOption> {
+        self.definitions
+            .iter()
+            .filter(|option| option.visible && option.category.as_deref() == category)
+            .collect()
+    }
+
+    // Returns each option key with the value the core currently sees.
+    pub fn current_values(&self) -> Vec<(String, String)> {
+        self.definitions
+            .iter()
+            .filter_map(|option| {
+                self.get(&option.key)
+                    .map(|value| (option.key.clone(), value.to_string_lossy().into_owned()))
+            })
+            .collect()
+    }
+
+    // Changes the value of an option on behalf of the user, flagging it both for
+    // GET_VARIABLE_UPDATE and for being written back to the override files.
+    pub fn set_value(&mut self, key: &str, value: &str) -> bool {
+        let changed = self.update_value(key, value);
+        self.dirty |= changed;
+        changed
+    }
+
+    // Reports whether values were changed by the user since the last save.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    // Changes the value of an option, flagging the change for GET_VARIABLE_UPDATE.
+    // Returns true only if the value the core sees actually changed.
+    fn update_value(&mut self, key: &str, value: &str) -> bool {
+        let valid = match self.definitions.iter().find(|option| option.key == key) {
+            Some(option) => option.values.iter().any(|allowed| allowed == value),
+            None => false,
+        };
+        if !valid {
+            println!("Ignoring invalid value '{}' for core option {}", value, key);
+            return false;
+        }
+        if self.get(key).map(|current| current.to_bytes()) == Some(value.as_bytes()) {
+            return false;
+        }
+        self.values
+            .insert(key.to_string(), CString::new(value).unwrap_or_default());
+        self.configured.insert(key.to_string(), value.to_string());
+        self.updated = true;
+        println!("Core option {} set to {}", key, value);
+        true
+    }
+
+    // Takes the frontend configuration into account, updating any option
+    // whose configured value differs from the one the core currently sees.
+    pub fn apply_config(&mut self, config: &HashMap<String, String>) {
+        self.configured = config.clone();
+        let keys: Vec<String> = self
+            .definitions
+            .iter()
+            .map(|option| option.key.clone())
+            .collect();
+        for key in keys {
+            if let Some(value) = config.get(&key).cloned() {
+                self.update_value(&key, &value);
+            }
+        }
+    }
+
+    // Reports whether any value changed since the last call, then resets the flag.
+    pub fn take_update(&mut self) -> bool {
+        std::mem::replace(&mut self.updated, false)
+    }
+
+    // Prints the options grouped by category along with their current values.
+    pub fn print_options(&self) {
+        println!("Core advertised {} options", self.definitions.len());
+        let uncategorized = std::iter::once((None, "General", ""));
+        let categories = self.categories().iter().map(|category| {
+            (
+                Some(category.key.as_str()),
+                category.description.as_str(),
+                category.info.as_str(),
+            )
+        });
+        for (key, description, info) in uncategorized.chain(categories) {
+            let options = self.options_in_category(key);
+            if options.is_empty() {
+                continue;
+            }
+            if info.is_empty() {
+                println!("[{}]", description);
+            } else {
+                println!("[{}] {}", description, info);
+            }
+            for option in options {
+                let current = self
+                    .get(&option.key)
+                    .map(|value| value.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                println!(
+                    "  {} = {} ({}: {})",
+                    option.key,
+                    current,
+                    option.description,
+                    option.labels.join("|")
+                );
+                if !option.info.is_empty() {
+                    println!("      {}", option.info);
+                }
+            }
+        }
+    }
+
+    fn resolve_value(&self, option: &CoreOption) -> String {
+        match self.configured.get(&option.key) {
+            Some(value) if option.values.contains(value) => value.clone(),
+            _ => option.default_value.clone(),
+        }
+    }
+}
+
+// Parses a SET_VARIABLES entry of the form "Description; first|second|third".
+// The first value is the default.
+fn parse_variable(key: &str, value: &str) -> Option<CoreOption> {
+    let (description, values) = value.split_once(';')?;
+    let values: Vec<String> = values
+        .trim_start()
+        .split('|')
+        .map(|value| value.to_string())
+        .collect();
+    let default_value = values.first()?.clone();
+    Some(CoreOption {
+        key: key.to_string(),
+        description: description.trim().to_string(),
+        info: String::new(),
+        category: None,
+        labels: values.clone(),
+        values,
+        default_value,
+        visible: true,
+    })
+}
+
+// Copies a possibly NULL C string.
+unsafe fn string_from_ptr(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy().into_owned()
+    }
+}
+
+// Reads a { NULL, ... } terminated array of v2 categories.
+unsafe fn read_categories(mut category: *const CoreOptionV2Category) -> Vec<CoreOptionCategory> {
+    let mut categories = Vec::new();
+    while !category.is_null() && !(*category).key.is_null() {
+        categories.push(CoreOptionCategory {
+            key: string_from_ptr((*category).key),
+            description: string_from_ptr((*category).desc),
+            info: string_from_ptr((*category).info),
+        });
+        category = category.add(1);
+    }
+    categories
+}
+
+// Reads a { NULL, ... } terminated array of v2 option definitions.
+unsafe fn read_definitions(mut definition: *const CoreOptionV2Definition) -> Vec<CoreOption> {
+    let mut options = Vec::new();
+    while !definition.is_null() && !(*definition).key.is_null() {
+        let mut values = Vec::new();
+        let mut labels = Vec::new();
+        for value in (*definition).values.iter() {
+            if value.value.is_null() {
+                break;
+            }
+            let name = string_from_ptr(value.value);
+            // A missing label means the value itself should be displayed.
+            labels.push(if value.label.is_null() {
+                name.clone()
+            } else {
+                string_from_ptr(value.label)
+            });
+            values.push(name);
+        }
+
+        let category = string_from_ptr((*definition).category_key);
+        let (description, info) = if category.is_empty() {
+            ((*definition).desc, (*definition).info)
+        } else {
+            (
+                (*definition).desc_categorized,
+                (*definition).info_categorized,
+            )
+        };
+        let description = match string_from_ptr(description) {
+            categorized if categorized.is_empty() => string_from_ptr((*definition).desc),
+            text => text,
+        };
+        let info = match string_from_ptr(info) {
+            categorized if categorized.is_empty() => string_from_ptr((*definition).info),
+            text => text,
+        };
+
+        let default_value = match string_from_ptr((*definition).default_value) {
+            value if values.contains(&value) => value,
+            _ => values.first().cloned().unwrap_or_default(),
+        };
+        options.push(CoreOption {
+            key: string_from_ptr((*definition).key),
+            description,
+            info,
+            category: if category.is_empty() {
+                None
+            } else {
+                Some(category)
+            },
+            values,
+            labels,
+            default_value,
+            visible: true,
+        });
+        definition = definition.add(1);
+    }
+    options
+}
+
+// Answers ENVIRONMENT_GET_VARIABLE, setting the value pointer to NULL for unknown keys.
+pub unsafe fn get_variable(variable: *mut Variable) -> bool {
+    if variable.is_null() || (*variable).key.is_null() {
+        return false;
+    }
+    let key = CStr::from_ptr((*variable).key).to_string_lossy();
+    let options = CORE_OPTIONS.lock().unwrap();
+    match options.get(&key) {
+        Some(value) => {
+            // The CString lives in the global store, so the pointer stays valid
+            // until the option is redefined.
+            (*variable).value = value.as_ptr();
+            true
+        }
+        None => {
+            (*variable).value = std::ptr::null();
+            false
+        }
+    }
+}
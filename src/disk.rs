@@ -0,0 +1,269 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// disk.rs
+//
+// This module implements the libretro disk control interface used by multi-disc
+// games (usually loaded from an M3U playlist). Discs are swapped with hotkeys, the
+// disc's label is shown on screen, and the disc in use is remembered so the game
+// starts from it again next time.
+
+use libc::{c_char, c_uint, size_t};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::osd;
+
+// Version of the disk control interface, for ENVIRONMENT_GET_DISK_CONTROL_INTERFACE_VERSION.
+pub const DISK_CONTROL_INTERFACE_VERSION: c_uint = 1;
+
+// struct retro_disk_control_ext_callback. ENVIRONMENT_SET_DISK_CONTROL_INTERFACE
+// passes only the first seven callbacks, and cores may leave the optional ones NULL.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DiskControlCallback {
+    pub set_eject_state: Option<unsafe extern "C" fn(ejected: bool) -> bool>,
+    pub get_eject_state: Option<unsafe extern "C" fn() -> bool>,
+    pub get_image_index: Option<unsafe extern "C" fn() -> c_uint>,
+    pub set_image_index: Option<unsafe extern "C" fn(index: c_uint) -> bool>,
+    pub get_num_images: Option<unsafe extern "C" fn() -> c_uint>,
+    pub replace_image_index:
+        Option<unsafe extern "C" fn(index: c_uint, info: *const libc::c_void) -> bool>,
+    pub add_image_index: Option<unsafe extern "C" fn() -> bool>,
+    pub set_initial_image: Option<unsafe extern "C" fn(index: c_uint, path: *const c_char) -> bool>,
+    pub get_image_path:
+        Option<unsafe extern "C" fn(index: c_uint, path: *mut c_char, len: size_t) -> bool>,
+    pub get_image_label:
+        Option<unsafe extern "C" fn(index: c_uint, label: *mut c_char, len: size_t) -> bool>,
+}
+
+// Interface registered by the core. Only used on the emulation thread.
+static DISK_CONTROL: Lazy<Mutex<Option<DiskControlCallback>>> = Lazy::new(|| Mutex::new(None));
+
+// Handles ENVIRONMENT_SET_DISK_CONTROL_INTERFACE. The original interface ends after
+// add_image_index, so only those fields are read.
+pub unsafe fn set_disk_control_interface(callback: *const DiskControlCallback) -> bool {
+    let Some(callback) = callback.as_ref() else {
+        return false;
+    };
+    *DISK_CONTROL.lock().unwrap() = Some(DiskControlCallback {
+        set_eject_state: callback.set_eject_state,
+        get_eject_state: callback.get_eject_state,
+        get_image_index: callback.get_image_index,
+        set_image_index: callback.set_image_index,
+        get_num_images: callback.get_num_images,
+        replace_image_index: callback.replace_image_index,
+        add_image_index: callback.add_image_index,
+        set_initial_image: None,
+        get_image_path: None,
+        get_image_label: None,
+    });
+    true
+}
+
+// Handles ENVIRONMENT_SET_DISK_CONTROL_EXT_INTERFACE.
+pub unsafe fn set_disk_control_ext_interface(callback: *const DiskControlCallback) -> bool {
+    let Some(&callback) = callback.as_ref() else {
+        return false;
+    };
+    *DISK_CONTROL.lock().unwrap() = Some(callback);
+    true
+}
+
+fn disk_control() -> Option<DiskControlCallback> {
+    *DISK_CONTROL.lock().unwrap()
+}
+
+// Reads a string from one of the core's get_image_path/get_image_label callbacks.
+unsafe fn read_image_string(
+    get: Option<unsafe extern "C" fn(c_uint, *mut c_char, size_t) -> bool>,
+    index: c_uint,
+) -> Option<String> {
+    let mut buffer = [0 as c_char; 512];
+    if !get?(index, buffer.as_mut_ptr(), buffer.len()) || buffer[0] == 0 {
+        return None;
+    }
+    Some(
+        CStr::from_ptr(buffer.as_ptr())
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+// Returns the path of a disc image, if the core reports it.
+fn image_path(disk_control: &DiskControlCallback, index: c_uint) -> Option<String> {
+    unsafe { read_image_string(disk_control.get_image_path, index) }
+}
+
+// Returns a name for a disc: the core's label, its file name, or just its number.
+fn image_label(disk_control: &DiskControlCallback, index: c_uint) -> String {
+    unsafe { read_image_string(disk_control.get_image_label, index) }
+        .or_else(|| {
+            image_path(disk_control, index).map(|path| {
+                Path::new(&path)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        })
+        .unwrap_or_else(|| format!("Disc {}", index + 1))
+}
+
+// `<savefile_directory>/<game>.ldci` remembers the disc in use, like RetroArch does.
+fn image_index_path(config: &HashMap<String, String>, rom_name: &str) -> PathBuf {
+    let game_name = Path::new(rom_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    PathBuf::from(shellexpand::tilde(&config["savefile_directory"]).into_owned())
+        .join(format!("{}.ldci", game_name))
+}
+
+// Asks the core to start from the disc that was in use when the game was last
+// closed. Must be called after retro_init and before the game is loaded.
+pub fn set_initial_image(config: &HashMap<String, String>, rom_name: &str) {
+    let Some(set_initial_image) = disk_control().and_then(|d| d.set_initial_image) else {
+        return;
+    };
+    let Ok(contents) = fs::read_to_string(image_index_path(config, rom_name)) else {
+        return;
+    };
+    let mut lines = contents.lines();
+    let index = lines
+        .next()
+        .and_then(|index| index.trim().parse::<c_uint>().ok());
+    let path = lines.next().and_then(|path| CString::new(path).ok());
+    if let (Some(index), Some(path)) = (index, path) {
+        println!(
+            "Resuming from disc {}: {}",
+            index + 1,
+            path.to_string_lossy()
+        );
+        unsafe { set_initial_image(index, path.as_ptr()) };
+    }
+}
+
+// Records the disc in use so the next launch can resume from it.
+pub fn save_image_index(config: &HashMap<String, String>, rom_name: &str) {
+    let Some(disk_control) = disk_control() else {
+        return;
+    };
+    let (Some(get_image_index), Some(get_num_images)) =
+        (disk_control.get_image_index, disk_control.get_num_images)
+    else {
+        return;
+    };
+    let (index, count) = unsafe { (get_image_index(), get_num_images()) };
+    let Some(path) = image_path(&disk_control, index).filter(|_| count > 1) else {
+        return;
+    };
+    let file_path = image_index_path(config, rom_name);
+    if let Some(directory) = file_path.parent() {
+        let _ = fs::create_dir_all(directory);
+    }
+    if let Err(err) = fs::write(&file_path, format!("{}\n{}\n", index, path)) {
+        println!(
+            "Failed to save disc index to {}: {}",
+            file_path.display(),
+            err
+        );
+    }
+}
+
+// Prints the discs the core knows about, marking the inserted one.
+pub fn print_images() {
+    let Some(disk_control) = disk_control() else {
+        return;
+    };
+    let (Some(get_image_index), Some(get_num_images)) =
+        (disk_control.get_image_index, disk_control.get_num_images)
+    else {
+        return;
+    };
+    let (current, count) = unsafe { (get_image_index(), get_num_images()) };
+    println!("Core has {} disc image(s):", count);
+    for index in 0..count {
+        let marker = if index == current { "*" } else { " " };
+        println!(
+            "{} {}: {}",
+            marker,
+            index + 1,
+            image_label(&disk_control, index)
+        );
+    }
+}
+
+// Opens or closes the virtual disc tray.
+pub fn toggle_eject() {
+    let Some(disk_control) = disk_control() else {
+        return;
+    };
+    let (Some(get_eject_state), Some(set_eject_state)) =
+        (disk_control.get_eject_state, disk_control.set_eject_state)
+    else {
+        return;
+    };
+    let ejected = unsafe { !get_eject_state() };
+    if !unsafe { set_eject_state(ejected) } {
+        println!(
+            "Core refused to {} the disc tray",
+            if ejected { "open" } else { "close" }
+        );
+        return;
+    }
+    let message = match disk_control.get_image_index {
+        Some(get_image_index) if !ejected => {
+            let index = unsafe { get_image_index() };
+            format!("Inserted {}", image_label(&disk_control, index))
+        }
+        _ if ejected => "Disc tray opened".to_string(),
+        _ => "Disc tray closed".to_string(),
+    };
+    println!("{}", message);
+    osd::show_message(&message);
+}
+
+// Selects the next or previous disc. Like a real console, the tray has to be
+// open to change discs.
+pub fn change_image(offset: i32) {
+    let Some(disk_control) = disk_control() else {
+        return;
+    };
+    let (Some(get_eject_state), Some(get_image_index), Some(set_image_index), Some(get_num_images)) = (
+        disk_control.get_eject_state,
+        disk_control.get_image_index,
+        disk_control.set_image_index,
+        disk_control.get_num_images,
+    ) else {
+        return;
+    };
+    if !unsafe { get_eject_state() } {
+        osd::show_message("Open the disc tray before changing discs");
+        return;
+    }
+    let count = unsafe { get_num_images() };
+    if count == 0 {
+        return;
+    }
+    let index =
+        (unsafe { get_image_index() } as i64 + offset as i64).rem_euclid(count as i64) as c_uint;
+    if !unsafe { set_image_index(index) } {
+        println!("Core refused to select disc {}", index + 1);
+        return;
+    }
+    let message = format!(
+        "Disc {}/{}: {}",
+        index + 1,
+        count,
+        image_label(&disk_control, index)
+    );
+    println!("{}", message);
+    osd::show_message(&message);
+}
@@ -0,0 +1,286 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// dsp.rs
+//
+// This module runs the core's audio through a chain of effects before it is
+// played, listed in order in `audio_dsp_chain`, e.g. "lowpass,eq". Setting it in a
+// core's override file gives each system its own, say a low-pass filter to soften
+// a Mega Drive's harsh output. An effect is anything implementing `Effect`; adding
+// one to EFFECTS makes it available to the chain by name. After the chain come the
+// accessibility options, `audio_swap_channels` for reversed speakers and
+// `audio_mono` for hearing on one side only, so they hold whatever the chain does.
+
+use std::collections::HashMap;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+use std::sync::Mutex;
+
+const CHANNELS: usize = 2;
+
+// An audio effect. It is made for a config with its own `audio_dsp_<name>_*` keys,
+// and lives on the audio thread, keeping whatever state it needs between calls.
+pub trait Effect: Send {
+    // Processes interleaved stereo samples from -1 to 1 in place.
+    fn process(&mut self, samples: &mut [f32], sample_rate: u32);
+}
+
+type Constructor = fn(&HashMap<String, String>) -> Box<dyn Effect>;
+
+// The effects `audio_dsp_chain` can name.
+const EFFECTS: &[(&str, Constructor)] = &[
+    ("lowpass", |config| Box::new(low_pass(config))),
+    ("eq", |config| Box::new(Equalizer::new(config))),
+    ("mono", |_| Box::new(Mono)),
+];
+
+static CHAIN: Mutex<Vec<Box<dyn Effect>>> = Mutex::new(Vec::new());
+
+// Reads a number of an effect's from the config.
+fn setting(config: &HashMap<String, String>, key: &str, default: f32) -> f32 {
+    config
+        .get(key)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Builds the chain `audio_dsp_chain` lists, followed by the accessibility options,
+// starting its effects afresh.
+pub fn configure(config: &HashMap<String, String>) {
+    let mut chain = config
+        .get("audio_dsp_chain")
+        .map_or("", String::as_str)
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let effect = EFFECTS.iter().find(|(effect, _)| *effect == name);
+            if effect.is_none() {
+                println!("Unknown audio DSP effect {}", name);
+            }
+            effect.map(|(_, constructor)| constructor(config))
+        })
+        .collect::<Vec<_>>();
+    let enabled = |key: &str| config.get(key).is_some_and(|value| value == "true");
+    if enabled("audio_swap_channels") {
+        chain.push(Box::new(Swap));
+    }
+    if enabled("audio_mono") {
+        chain.push(Box::new(Mono));
+    }
+    *CHAIN.lock().unwrap() = chain;
+}
+
+// Runs interleaved stereo samples from the core through the chain.
+pub fn process(samples: &mut [i16], sample_rate: u32) {
+    let mut chain = CHAIN.lock().unwrap();
+    if chain.is_empty() {
+        return;
+    }
+    let mut floats: Vec<f32> = samples
+        .iter()
+        .map(|&sample| sample as f32 / 32768.0)
+        .collect();
+    for effect in chain.iter_mut() {
+        effect.process(&mut floats, sample_rate);
+    }
+    for (sample, float) in samples.iter_mut().zip(floats) {
+        *sample = (float * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+    }
+}
+
+// Designs a filter's coefficients for a sample rate, as b0, b1, b2, a1 and a2
+// divided by a0.
+type Design = Box<dyn Fn(f32) -> [f32; 5] + Send>;
+
+// A second order IIR filter, after Robert Bristow-Johnson's Audio EQ Cookbook.
+struct Biquad {
+    design: Design,
+    sample_rate: u32,
+    coefficients: [f32; 5],
+    // The last two inputs and outputs of each channel.
+    history: [[f32; 4]; CHANNELS],
+}
+
+impl Biquad {
+    fn new(design: Design) -> Self {
+        Biquad {
+            design,
+            sample_rate: 0,
+            coefficients: [1.0, 0.0, 0.0, 0.0, 0.0],
+            history: [[0.0; 4]; CHANNELS],
+        }
+    }
+
+    // Returns b0 to a2 normalized by a0.
+    fn normalize(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> [f32; 5] {
+        [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0]
+    }
+}
+
+impl Effect for Biquad {
+    fn process(&mut self, samples: &mut [f32], sample_rate: u32) {
+        if self.sample_rate != sample_rate {
+            self.sample_rate = sample_rate;
+            self.coefficients = (self.design)(sample_rate as f32);
+        }
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            for (sample, history) in frame.iter_mut().zip(&mut self.history) {
+                let [x1, x2, y1, y2] = *history;
+                let x = *sample;
+                let y = b0 * x + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2;
+                *history = [x, x1, y, y1];
+                *sample = y;
+            }
+        }
+    }
+}
+
+// Returns w0 and alpha for a filter at `frequency` with the given Q.
+fn angle(frequency: f32, sample_rate: f32, q: f32) -> (f32, f32) {
+    // Keep the frequency under the Nyquist frequency, where the designs break down
+    let w0 = 2.0 * PI * frequency.min(sample_rate * 0.49) / sample_rate;
+    (w0, w0.sin() / (2.0 * q))
+}
+
+// A low-pass filter at `audio_dsp_lowpass_cutoff` Hz.
+fn low_pass(config: &HashMap<String, String>) -> Biquad {
+    let cutoff = setting(config, "audio_dsp_lowpass_cutoff", 5000.0).max(1.0);
+    Biquad::new(Box::new(move |sample_rate| {
+        let (w0, alpha) = angle(cutoff, sample_rate, FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        Biquad::normalize(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }))
+}
+
+// A shelving filter boosting or cutting by `gain` dB below `frequency`, or above
+// it for a high shelf.
+fn shelf(frequency: f32, gain: f32, high: bool) -> Biquad {
+    Biquad::new(Box::new(move |sample_rate| {
+        let a = 10f32.powf(gain / 40.0);
+        let (w0, alpha) = angle(frequency, sample_rate, FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        let beta = 2.0 * a.sqrt() * alpha;
+        // The high shelf is the low shelf with the sign of cos flipped
+        let sign = if high { -1.0 } else { 1.0 };
+        let cos = sign * cos;
+        Biquad::normalize(
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            sign * 2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            (a + 1.0) + (a - 1.0) * cos + beta,
+            sign * -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - beta,
+        )
+    }))
+}
+
+// Bass and treble controls, `audio_dsp_eq_bass` and `audio_dsp_eq_treble` in dB.
+struct Equalizer {
+    bass: Biquad,
+    treble: Biquad,
+}
+
+impl Equalizer {
+    fn new(config: &HashMap<String, String>) -> Self {
+        Equalizer {
+            bass: shelf(250.0, setting(config, "audio_dsp_eq_bass", 0.0), false),
+            treble: shelf(4000.0, setting(config, "audio_dsp_eq_treble", 0.0), true),
+        }
+    }
+}
+
+impl Effect for Equalizer {
+    fn process(&mut self, samples: &mut [f32], sample_rate: u32) {
+        self.bass.process(samples, sample_rate);
+        self.treble.process(samples, sample_rate);
+    }
+}
+
+// Mixes both channels into each.
+struct Mono;
+
+impl Effect for Mono {
+    fn process(&mut self, samples: &mut [f32], _sample_rate: u32) {
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            let mixed = (frame[0] + frame[1]) / 2.0;
+            frame.fill(mixed);
+        }
+    }
+}
+
+// Swaps the left and right channels.
+struct Swap;
+
+impl Effect for Swap {
+    fn process(&mut self, samples: &mut [f32], _sample_rate: u32) {
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            frame.swap(0, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the loudest a tone of `frequency` Hz comes out of an effect once it
+    // has settled.
+    fn peak(effect: &mut dyn Effect, frequency: f32) -> f32 {
+        let mut samples: Vec<f32> = (0..4800)
+            .flat_map(|frame| {
+                let sample = 0.5 * (2.0 * PI * frequency * frame as f32 / 48_000.0).sin();
+                [sample, sample]
+            })
+            .collect();
+        effect.process(&mut samples, 48_000);
+        samples[4800..]
+            .iter()
+            .fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn low_pass_keeps_the_bass() {
+        let config = HashMap::from([("audio_dsp_lowpass_cutoff".to_string(), "2000".to_string())]);
+        assert!((peak(&mut low_pass(&config), 100.0) - 0.5).abs() < 0.01);
+        assert!(peak(&mut low_pass(&config), 12_000.0) < 0.05);
+    }
+
+    #[test]
+    fn shelves_boost_their_own_end() {
+        let config = HashMap::from([
+            ("audio_dsp_eq_bass".to_string(), "6".to_string()),
+            ("audio_dsp_eq_treble".to_string(), "-6".to_string()),
+        ]);
+        // 6 dB is about twice as loud
+        assert!((peak(&mut Equalizer::new(&config), 40.0) - 1.0).abs() < 0.05);
+        assert!((peak(&mut Equalizer::new(&config), 1000.0) - 0.5).abs() < 0.1);
+        assert!((peak(&mut Equalizer::new(&config), 15_000.0) - 0.25).abs() < 0.05);
+        // At 0 dB it changes nothing
+        assert!((peak(&mut Equalizer::new(&HashMap::new()), 15_000.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn runs_the_configured_chain() {
+        let config = HashMap::from([("audio_dsp_chain".to_string(), "mono, reverb".to_string())]);
+        configure(&config);
+        let mut samples = [1000, -3000, 32767, 32767];
+        process(&mut samples, 48_000);
+        assert_eq!(samples, [-1000, -1000, 32767, 32767]);
+
+        let config = HashMap::from([("audio_swap_channels".to_string(), "true".to_string())]);
+        configure(&config);
+        let mut samples = [1000, -3000, 32767, 0];
+        process(&mut samples, 48_000);
+        assert_eq!(samples, [-3000, 1000, 0, 32767]);
+        configure(&HashMap::new());
+    }
+}
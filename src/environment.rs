@@ -0,0 +1,597 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// environment.rs
+//
+// This module implements the libretro environment callback. The raw command number
+// is turned into an `EnvironmentCommand` and `return_data` is wrapped in
+// `EnvironmentData`, so each command is handled by its own function that reads and
+// writes typed values instead of casting pointers inline.
+
+use crate::audio::{self, AudioBufferStatusCallback};
+use crate::camera::{self, CameraCallback};
+use crate::controllers;
+use crate::core_options::{
+    self, CoreOptionDisplay, CoreOptionsV2, CoreOptionsV2Intl, CORE_OPTIONS,
+};
+use crate::disk::{self, DiskControlCallback};
+use crate::frame_time::{self, FrameTimeCallback};
+use crate::hw_render::{self, HwRenderCallback};
+use crate::input;
+use crate::keyboard;
+use crate::led;
+use crate::libretro::{self, GetProcAddressInterface, ENVIRONMENT_SETTINGS};
+use crate::logging;
+use crate::memory;
+use crate::osd::{self, MessageExt};
+use crate::perf;
+use crate::rumble;
+use crate::sensor;
+use crate::speed::{self, FastForwardingOverride, ThrottleState};
+use crate::subsystem;
+use crate::video;
+use crate::{
+    AV_INFO_CHANNEL, GEOMETRY_CHANNEL, INPUT_DESCRIPTORS_CHANNEL, PIXEL_FORMAT_CHANNEL,
+    ROTATION_CHANNEL,
+};
+use libc::{c_int, c_uint, c_void};
+use libretro_sys::{
+    AudioCallback, ControllerInfo, Framebuffer, GameGeometry, InputDescriptor, KeyboardCallback,
+    Message, PixelFormat, SubsystemInfo, SystemAvInfo, Variable,
+};
+use std::ffi::CStr;
+use std::ptr;
+
+// Environment commands introduced after the libretro-sys bindings were generated.
+const ENVIRONMENT_GET_CORE_OPTIONS_VERSION: u32 = 52;
+const ENVIRONMENT_SET_CORE_OPTIONS_DISPLAY: u32 = 55;
+const ENVIRONMENT_SET_CORE_OPTIONS_V2: u32 = 67;
+const ENVIRONMENT_SET_CORE_OPTIONS_V2_INTL: u32 = 68;
+const ENVIRONMENT_SET_HW_RENDER_CONTEXT_NEGOTIATION_INTERFACE: u32 =
+    43 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+const ENVIRONMENT_SET_SERIALIZATION_QUIRKS: u32 = 44;
+const ENVIRONMENT_SET_SUPPORT_ACHIEVEMENTS: u32 = 42 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+const ENVIRONMENT_GET_LED_INTERFACE: u32 = 46 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+const ENVIRONMENT_GET_AUDIO_VIDEO_ENABLE: u32 = 47 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+const ENVIRONMENT_GET_FASTFORWARDING: u32 = 49 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+const ENVIRONMENT_GET_MESSAGE_INTERFACE_VERSION: u32 = 59;
+const ENVIRONMENT_GET_PREFERRED_HW_RENDER: u32 = 56;
+const ENVIRONMENT_GET_DISK_CONTROL_INTERFACE_VERSION: u32 = 57;
+const ENVIRONMENT_SET_DISK_CONTROL_EXT_INTERFACE: u32 = 58;
+const ENVIRONMENT_SET_MESSAGE_EXT: u32 = 60;
+const ENVIRONMENT_SET_AUDIO_BUFFER_STATUS_CALLBACK: u32 = 62;
+const ENVIRONMENT_SET_FASTFORWARDING_OVERRIDE: u32 = 64;
+const ENVIRONMENT_GET_THROTTLE_STATE: u32 = 71 | libretro_sys::ENVIRONMENT_EXPERIMENTAL;
+
+// Bits of the ENVIRONMENT_GET_AUDIO_VIDEO_ENABLE answer.
+const AUDIO_VIDEO_ENABLE_VIDEO: c_int = 1 << 0;
+const AUDIO_VIDEO_ENABLE_AUDIO: c_int = 1 << 1;
+
+// The environment commands the frontend handles. Anything else is kept as its raw
+// number so it can be logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvironmentCommand {
+    SetRotation,
+    GetOverscan,
+    GetCanDupe,
+    GetInputDeviceCapabilities,
+    SetMessage,
+    GetSystemDirectory,
+    SetPixelFormat,
+    SetInputDescriptors,
+    SetKeyboardCallback,
+    SetDiskControlInterface,
+    SetHwRender,
+    GetVariable,
+    SetVariables,
+    GetVariableUpdate,
+    SetSupportNoGame,
+    SetFrameTimeCallback,
+    SetAudioCallback,
+    GetRumbleInterface,
+    GetSensorInterface,
+    GetCameraInterface,
+    GetLogInterface,
+    GetPerfInterface,
+    GetCoreAssetsDirectory,
+    SetSystemAvInfo,
+    SetProcAddressCallback,
+    SetSubsystemInfo,
+    SetControllerInfo,
+    SetMemoryMaps,
+    SetGeometry,
+    GetUsername,
+    GetLanguage,
+    GetCurrentSoftwareFramebuffer,
+    GetHwRenderInterface,
+    SetSupportAchievements,
+    SetHwRenderContextNegotiationInterface,
+    SetSerializationQuirks,
+    GetLedInterface,
+    GetAudioVideoEnable,
+    GetFastforwarding,
+    GetCoreOptionsVersion,
+    SetCoreOptionsDisplay,
+    GetPreferredHwRender,
+    GetDiskControlInterfaceVersion,
+    SetDiskControlExtInterface,
+    GetMessageInterfaceVersion,
+    SetMessageExt,
+    SetAudioBufferStatusCallback,
+    SetFastforwardingOverride,
+    SetCoreOptionsV2,
+    SetCoreOptionsV2Intl,
+    GetThrottleState,
+    Unknown(u32),
+}
+
+impl EnvironmentCommand {
+    pub fn from_raw(command: u32) -> Self {
+        use EnvironmentCommand::*;
+        match command {
+            libretro_sys::ENVIRONMENT_SET_ROTATION => SetRotation,
+            libretro_sys::ENVIRONMENT_GET_OVERSCAN => GetOverscan,
+            libretro_sys::ENVIRONMENT_GET_CAN_DUPE => GetCanDupe,
+            libretro_sys::ENVIRONMENT_GET_INPUT_DEVICE_CAPABILITIES => GetInputDeviceCapabilities,
+            libretro_sys::ENVIRONMENT_SET_MESSAGE => SetMessage,
+            libretro_sys::ENVIRONMENT_GET_SYSTEM_DIRECTORY => GetSystemDirectory,
+            libretro_sys::ENVIRONMENT_SET_PIXEL_FORMAT => SetPixelFormat,
+            libretro_sys::ENVIRONMENT_SET_INPUT_DESCRIPTORS => SetInputDescriptors,
+            libretro_sys::ENVIRONMENT_SET_KEYBOARD_CALLBACK => SetKeyboardCallback,
+            libretro_sys::ENVIRONMENT_SET_DISK_CONTROL_INTERFACE => SetDiskControlInterface,
+            libretro_sys::ENVIRONMENT_SET_HW_RENDER => SetHwRender,
+            libretro_sys::ENVIRONMENT_GET_VARIABLE => GetVariable,
+            libretro_sys::ENVIRONMENT_SET_VARIABLES => SetVariables,
+            libretro_sys::ENVIRONMENT_GET_VARIABLE_UPDATE => GetVariableUpdate,
+            libretro_sys::ENVIRONMENT_SET_SUPPORT_NO_GAME => SetSupportNoGame,
+            libretro_sys::ENVIRONMENT_SET_FRAME_TIME_CALLBACK => SetFrameTimeCallback,
+            libretro_sys::ENVIRONMENT_SET_AUDIO_CALLBACK => SetAudioCallback,
+            libretro_sys::ENVIRONMENT_GET_RUMBLE_INTERFACE => GetRumbleInterface,
+            libretro_sys::ENVIRONMENT_GET_SENSOR_INTERFACE => GetSensorInterface,
+            libretro_sys::ENVIRONMENT_GET_CAMERA_INTERFACE => GetCameraInterface,
+            libretro_sys::ENVIRONMENT_GET_LOG_INTERFACE => GetLogInterface,
+            libretro_sys::ENVIRONMENT_GET_PERF_INTERFACE => GetPerfInterface,
+            libretro_sys::ENVIRONMENT_GET_CORE_ASSETS_DIRECTORY => GetCoreAssetsDirectory,
+            libretro_sys::ENVIRONMENT_SET_SYSTEM_AV_INFO => SetSystemAvInfo,
+            libretro_sys::ENVIRONMENT_SET_PROC_ADDRESS_CALLBACK => SetProcAddressCallback,
+            libretro_sys::ENVIRONMENT_SET_SUBSYSTEM_INFO => SetSubsystemInfo,
+            libretro_sys::ENVIRONMENT_SET_CONTROLLER_INFO => SetControllerInfo,
+            libretro_sys::ENVIRONMENT_SET_MEMORY_MAPS => SetMemoryMaps,
+            libretro_sys::ENVIRONMENT_SET_GEOMETRY => SetGeometry,
+            libretro_sys::ENVIRONMENT_GET_USERNAME => GetUsername,
+            libretro_sys::ENVIRONMENT_GET_LANGUAGE => GetLanguage,
+            libretro_sys::ENVIRONMENT_GET_CURRENT_SOFTWARE_FRAMEBUFFER => {
+                GetCurrentSoftwareFramebuffer
+            }
+            libretro_sys::ENVIRONMENT_GET_HW_RENDER_INTERFACE => GetHwRenderInterface,
+            ENVIRONMENT_SET_SUPPORT_ACHIEVEMENTS => SetSupportAchievements,
+            ENVIRONMENT_SET_HW_RENDER_CONTEXT_NEGOTIATION_INTERFACE => {
+                SetHwRenderContextNegotiationInterface
+            }
+            ENVIRONMENT_SET_SERIALIZATION_QUIRKS => SetSerializationQuirks,
+            ENVIRONMENT_GET_LED_INTERFACE => GetLedInterface,
+            ENVIRONMENT_GET_AUDIO_VIDEO_ENABLE => GetAudioVideoEnable,
+            ENVIRONMENT_GET_FASTFORWARDING => GetFastforwarding,
+            ENVIRONMENT_GET_CORE_OPTIONS_VERSION => GetCoreOptionsVersion,
+            ENVIRONMENT_SET_CORE_OPTIONS_DISPLAY => SetCoreOptionsDisplay,
+            ENVIRONMENT_GET_PREFERRED_HW_RENDER => GetPreferredHwRender,
+            ENVIRONMENT_GET_DISK_CONTROL_INTERFACE_VERSION => GetDiskControlInterfaceVersion,
+            ENVIRONMENT_SET_DISK_CONTROL_EXT_INTERFACE => SetDiskControlExtInterface,
+            ENVIRONMENT_GET_MESSAGE_INTERFACE_VERSION => GetMessageInterfaceVersion,
+            ENVIRONMENT_SET_MESSAGE_EXT => SetMessageExt,
+            ENVIRONMENT_SET_AUDIO_BUFFER_STATUS_CALLBACK => SetAudioBufferStatusCallback,
+            ENVIRONMENT_SET_FASTFORWARDING_OVERRIDE => SetFastforwardingOverride,
+            ENVIRONMENT_SET_CORE_OPTIONS_V2 => SetCoreOptionsV2,
+            ENVIRONMENT_SET_CORE_OPTIONS_V2_INTL => SetCoreOptionsV2Intl,
+            ENVIRONMENT_GET_THROTTLE_STATE => GetThrottleState,
+            command => Unknown(command),
+        }
+    }
+}
+
+// The `data` argument of an environment call. What it points to depends on the
+// command, so reading or writing it is unsafe: the handler for each command
+// promises to use the type libretro documents for it.
+pub struct EnvironmentData(*mut c_void);
+
+impl EnvironmentData {
+    // Safety: `data` must be NULL or point to the type libretro documents for the
+    // command it is dispatched with, valid for the duration of the call.
+    pub unsafe fn new(data: *mut c_void) -> Self {
+        EnvironmentData(data)
+    }
+
+    // Returns the value the core passed in, or None for a NULL pointer.
+    // Safety: `T` must be the type libretro documents for the command.
+    pub unsafe fn get<T>(&self) -> Option<&T> {
+        (self.0 as *const T).as_ref()
+    }
+
+    // Returns the struct the core passed in for the frontend to fill, or None for a
+    // NULL pointer.
+    // Safety: `T` must be the type libretro documents for the command.
+    pub unsafe fn get_mut<T>(&mut self) -> Option<&mut T> {
+        (self.0 as *mut T).as_mut()
+    }
+
+    // Hands a value back to the core. Returns false for a NULL pointer, which
+    // some cores pass to ask whether a command is supported at all.
+    // Safety: `T` must be the type libretro documents for the command.
+    pub unsafe fn set<T>(&mut self, value: T) -> bool {
+        if self.0.is_null() {
+            return false;
+        }
+        ptr::write(self.0 as *mut T, value);
+        true
+    }
+
+    // The raw pointer, for handlers in other modules that take the core's struct as is.
+    pub fn as_ptr<T>(&self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+// Callback function for the libretro environment.
+pub unsafe extern "C" fn environment_callback(command: u32, data: *mut c_void) -> bool {
+    dispatch(
+        EnvironmentCommand::from_raw(command),
+        &mut EnvironmentData::new(data),
+    )
+}
+
+// Runs the handler for a command. Each handler reads `data` as the type libretro
+// documents for its command, which `EnvironmentData::new` was promised.
+// Safety: `data` must have been made for this command.
+pub unsafe fn dispatch(command: EnvironmentCommand, data: &mut EnvironmentData) -> bool {
+    use EnvironmentCommand::*;
+    match command {
+        SetRotation => set_rotation(data),
+        GetOverscan => data.set(!video::crop_overscan()), // true keeps overscan
+        GetCanDupe => get_can_dupe(data),
+        GetInputDeviceCapabilities => data.set(input::device_capabilities()),
+        SetMessage => osd::set_message(data.as_ptr::<Message>()),
+        GetSystemDirectory => data.set(
+            ENVIRONMENT_SETTINGS
+                .lock()
+                .unwrap()
+                .system_directory
+                .as_ptr(),
+        ),
+        SetPixelFormat => set_pixel_format(data),
+        SetInputDescriptors => set_input_descriptors(data),
+        SetKeyboardCallback => keyboard::set_keyboard_callback(data.as_ptr::<KeyboardCallback>()),
+        SetDiskControlInterface => {
+            disk::set_disk_control_interface(data.as_ptr::<DiskControlCallback>())
+        }
+        SetHwRender => hw_render::set_hw_render(data.as_ptr::<HwRenderCallback>()),
+        GetVariable => core_options::get_variable(data.as_ptr::<Variable>()),
+        SetVariables => {
+            CORE_OPTIONS
+                .lock()
+                .unwrap()
+                .set_variables(data.as_ptr::<Variable>());
+            true
+        }
+        GetVariableUpdate => data.set(CORE_OPTIONS.lock().unwrap().take_update()),
+        SetSupportNoGame => set_support_no_game(data),
+        SetFrameTimeCallback => {
+            frame_time::set_frame_time_callback(data.as_ptr::<FrameTimeCallback>())
+        }
+        SetAudioCallback => audio::set_audio_callback(data.as_ptr::<AudioCallback>()),
+        GetRumbleInterface => data.set(rumble::rumble_interface()),
+        GetSensorInterface => data.set(sensor::sensor_interface()),
+        GetCameraInterface => camera::get_camera_interface(data.as_ptr::<CameraCallback>()),
+        GetLogInterface => data.set(logging::log_interface()),
+        GetPerfInterface => data.set(perf::perf_interface()),
+        GetCoreAssetsDirectory => data.set(
+            ENVIRONMENT_SETTINGS
+                .lock()
+                .unwrap()
+                .core_assets_directory
+                .as_ptr(),
+        ),
+        SetSystemAvInfo => set_system_av_info(data),
+        SetProcAddressCallback => {
+            libretro::set_proc_address_callback(data.as_ptr::<GetProcAddressInterface>());
+            true
+        }
+        SetSubsystemInfo => {
+            subsystem::set_subsystem_info(data.as_ptr::<SubsystemInfo>());
+            true
+        }
+        SetControllerInfo => controllers::set_controller_info(data.as_ptr::<ControllerInfo>()),
+        SetMemoryMaps => {
+            memory::set_memory_maps(data.as_ptr());
+            true
+        }
+        SetGeometry => set_geometry(data),
+        GetUsername => get_username(data),
+        GetLanguage => data.set(ENVIRONMENT_SETTINGS.lock().unwrap().language),
+        GetCurrentSoftwareFramebuffer => match data.get_mut::<Framebuffer>() {
+            Some(framebuffer) => video::get_current_software_framebuffer(framebuffer),
+            None => false,
+        },
+        GetHwRenderInterface => match hw_render::hw_render_interface() {
+            Some(interface) => data.set(interface),
+            None => false,
+        },
+        SetSupportAchievements => set_support_achievements(data),
+        SetHwRenderContextNegotiationInterface => {
+            hw_render::set_negotiation_interface(data.as_ptr::<c_void>())
+        }
+        SetSerializationQuirks => set_serialization_quirks(data),
+        GetLedInterface => data.set(led::led_interface()),
+        GetAudioVideoEnable => get_audio_video_enable(data),
+        GetFastforwarding => data.set(speed::is_fast_forwarding()),
+        GetCoreOptionsVersion => data.set(core_options::CORE_OPTIONS_VERSION),
+        SetCoreOptionsDisplay => set_core_options_display(data),
+        GetPreferredHwRender => match hw_render::preferred_context() {
+            Some(context_type) => data.set(context_type),
+            None => false,
+        },
+        GetDiskControlInterfaceVersion => data.set(disk::DISK_CONTROL_INTERFACE_VERSION),
+        SetDiskControlExtInterface => {
+            disk::set_disk_control_ext_interface(data.as_ptr::<DiskControlCallback>())
+        }
+        // Version 1 adds ENVIRONMENT_SET_MESSAGE_EXT
+        GetMessageInterfaceVersion => data.set::<c_uint>(1),
+        SetMessageExt => osd::set_message_ext(data.as_ptr::<MessageExt>()),
+        SetAudioBufferStatusCallback => {
+            audio::set_audio_buffer_status_callback(data.as_ptr::<AudioBufferStatusCallback>())
+        }
+        SetFastforwardingOverride => {
+            speed::set_fastforwarding_override(data.as_ptr::<FastForwardingOverride>())
+        }
+        SetCoreOptionsV2 => {
+            CORE_OPTIONS
+                .lock()
+                .unwrap()
+                .set_options_v2(data.as_ptr::<CoreOptionsV2>(), ptr::null());
+            true
+        }
+        SetCoreOptionsV2Intl => set_core_options_v2_intl(data),
+        GetThrottleState => speed::get_throttle_state(data.as_ptr::<ThrottleState>()),
+        Unknown(command) => {
+            println!(
+                "libretro_environment_callback Called with command: {}",
+                command
+            );
+            false
+        }
+    }
+}
+
+unsafe fn set_rotation(data: &mut EnvironmentData) -> bool {
+    let Some(&rotation) = data.get::<c_uint>() else {
+        return false;
+    };
+    ROTATION_CHANNEL
+        .0
+        .send(rotation % 4)
+        .expect("Failed to send rotation");
+    true
+}
+
+unsafe fn get_can_dupe(data: &mut EnvironmentData) -> bool {
+    println!("ENVIRONMENT_GET_CAN_DUPE");
+    data.set(true)
+}
+
+// Rejects formats libretro-sys doesn't know, instead of panicking inside the core.
+unsafe fn set_pixel_format(data: &mut EnvironmentData) -> bool {
+    let Some(pixel_format) = data
+        .get::<c_uint>()
+        .and_then(|&format| PixelFormat::from_uint(format))
+    else {
+        return false;
+    };
+    video::set_core_pixel_format(pixel_format);
+    PIXEL_FORMAT_CHANNEL
+        .0
+        .send(pixel_format)
+        .expect("Failed to send pixel format");
+    true
+}
+
+unsafe fn set_input_descriptors(data: &mut EnvironmentData) -> bool {
+    if data.get::<InputDescriptor>().is_none() {
+        return false;
+    }
+    let descriptors = input::read_input_descriptors(data.as_ptr());
+    INPUT_DESCRIPTORS_CHANNEL
+        .0
+        .send(descriptors)
+        .expect("Failed to send input descriptors");
+    true
+}
+
+unsafe fn set_support_no_game(data: &mut EnvironmentData) -> bool {
+    let Some(&supported) = data.get::<bool>() else {
+        return false;
+    };
+    libretro::set_support_no_game(supported);
+    true
+}
+
+unsafe fn set_system_av_info(data: &mut EnvironmentData) -> bool {
+    let Some(av_info) = data.get::<SystemAvInfo>() else {
+        return false;
+    };
+    AV_INFO_CHANNEL
+        .0
+        .send(av_info.clone())
+        .expect("Failed to send AV info");
+    true
+}
+
+unsafe fn set_geometry(data: &mut EnvironmentData) -> bool {
+    let Some(geometry) = data.get::<GameGeometry>() else {
+        return false;
+    };
+    GEOMETRY_CHANNEL
+        .0
+        .send(geometry.clone())
+        .expect("Failed to send geometry");
+    true
+}
+
+unsafe fn get_username(data: &mut EnvironmentData) -> bool {
+    let settings = ENVIRONMENT_SETTINGS.lock().unwrap();
+    match &settings.username {
+        Some(username) => data.set(username.as_ptr()),
+        None => false,
+    }
+}
+
+unsafe fn set_support_achievements(data: &mut EnvironmentData) -> bool {
+    let Some(&supported) = data.get::<bool>() else {
+        return false;
+    };
+    memory::set_support_achievements(supported);
+    true
+}
+
+// The core learns which quirks the frontend accepted from the flags written back.
+unsafe fn set_serialization_quirks(data: &mut EnvironmentData) -> bool {
+    let Some(&quirks) = data.get::<u64>() else {
+        return false;
+    };
+    data.set(libretro::set_serialization_quirks(quirks))
+}
+
+// Lets the core skip rendering or mixing output that would be thrown away.
+unsafe fn get_audio_video_enable(data: &mut EnvironmentData) -> bool {
+    let mut enable = 0;
+    if video::is_enabled() {
+        enable |= AUDIO_VIDEO_ENABLE_VIDEO;
+    }
+    if audio::is_enabled() {
+        enable |= AUDIO_VIDEO_ENABLE_AUDIO;
+    }
+    // A NULL pointer only asks whether the command is supported
+    data.set(enable);
+    true
+}
+
+unsafe fn set_core_options_display(data: &mut EnvironmentData) -> bool {
+    let Some(display) = data.get::<CoreOptionDisplay>() else {
+        return false;
+    };
+    if !display.key.is_null() {
+        let key = CStr::from_ptr(display.key).to_string_lossy();
+        CORE_OPTIONS
+            .lock()
+            .unwrap()
+            .set_visible(&key, display.visible);
+    }
+    true
+}
+
+unsafe fn set_core_options_v2_intl(data: &mut EnvironmentData) -> bool {
+    let Some(intl) = data.get::<CoreOptionsV2Intl>() else {
+        return false;
+    };
+    CORE_OPTIONS
+        .lock()
+        .unwrap()
+        .set_options_v2(intl.us, intl.local);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call<T>(command: u32, value: &mut T) -> bool {
+        let mut data = unsafe { EnvironmentData::new(value as *mut T as *mut c_void) };
+        unsafe { dispatch(EnvironmentCommand::from_raw(command), &mut data) }
+    }
+
+    #[test]
+    fn maps_raw_commands() {
+        assert_eq!(
+            EnvironmentCommand::from_raw(libretro_sys::ENVIRONMENT_GET_CAN_DUPE),
+            EnvironmentCommand::GetCanDupe
+        );
+        assert_eq!(
+            EnvironmentCommand::from_raw(47 | libretro_sys::ENVIRONMENT_EXPERIMENTAL),
+            EnvironmentCommand::GetAudioVideoEnable
+        );
+        // Experimental commands only match with the experimental bit set
+        assert_eq!(
+            EnvironmentCommand::from_raw(47),
+            EnvironmentCommand::Unknown(47)
+        );
+        assert_eq!(
+            EnvironmentCommand::from_raw(1000),
+            EnvironmentCommand::Unknown(1000)
+        );
+    }
+
+    #[test]
+    fn get_can_dupe_answers_true() {
+        let mut can_dupe = false;
+        assert!(call(libretro_sys::ENVIRONMENT_GET_CAN_DUPE, &mut can_dupe));
+        assert!(can_dupe);
+    }
+
+    #[test]
+    fn get_message_interface_version_is_1() {
+        let mut version: c_uint = 0;
+        assert!(call(
+            ENVIRONMENT_GET_MESSAGE_INTERFACE_VERSION,
+            &mut version
+        ));
+        assert_eq!(version, 1);
+    }
+
+    #[test]
+    fn get_language_returns_setting() {
+        ENVIRONMENT_SETTINGS.lock().unwrap().language = 3;
+        let mut language: c_uint = 0;
+        assert!(call(libretro_sys::ENVIRONMENT_GET_LANGUAGE, &mut language));
+        assert_eq!(language, 3);
+    }
+
+    #[test]
+    fn get_commands_fail_on_null() {
+        let mut data = unsafe { EnvironmentData::new(ptr::null_mut()) };
+        unsafe {
+            assert!(!dispatch(EnvironmentCommand::GetLanguage, &mut data));
+            assert!(!dispatch(EnvironmentCommand::GetCanDupe, &mut data));
+            assert!(!dispatch(EnvironmentCommand::SetRotation, &mut data));
+            assert!(!dispatch(EnvironmentCommand::SetHwRender, &mut data));
+            assert!(!dispatch(EnvironmentCommand::SetAudioCallback, &mut data));
+        }
+    }
+
+    #[test]
+    fn get_audio_video_enable_accepts_null() {
+        let mut data = unsafe { EnvironmentData::new(ptr::null_mut()) };
+        assert!(unsafe { dispatch(EnvironmentCommand::GetAudioVideoEnable, &mut data) });
+    }
+
+    #[test]
+    fn set_pixel_format_rejects_unknown_formats() {
+        let mut format: c_uint = 42;
+        assert!(!call(
+            libretro_sys::ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format
+        ));
+    }
+
+    #[test]
+    fn set_rotation_sends_quarter_turns() {
+        let mut rotation: c_uint = 5;
+        assert!(call(libretro_sys::ENVIRONMENT_SET_ROTATION, &mut rotation));
+        let sent = ROTATION_CHANNEL.1.lock().unwrap().try_iter().last();
+        assert_eq!(sent, Some(1));
+    }
+
+    #[test]
+    fn unknown_commands_are_unsupported() {
+        let mut value = 0u32;
+        assert!(!call(1000, &mut value));
+    }
+}
@@ -0,0 +1,376 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// flac.rs
+//
+// This module writes 16 bit stereo audio as FLAC, for audio dumps. It only does
+// what the format needs to compress game audio well: each channel of a block is
+// stored as a constant for silence, or else as the residual of whichever of
+// FLAC's fixed polynomial predictors fits best, Rice coded.
+
+use std::io::{Seek, SeekFrom, Write};
+
+const CHANNELS: usize = 2;
+const BITS_PER_SAMPLE: u32 = 16;
+
+// Frames per block. 4096 is what the reference encoder uses at 44.1 and 48 kHz.
+const BLOCK_SIZE: usize = 4096;
+
+// Largest Rice parameter the 4 bit residual coding method allows.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+// Packs values into bytes most significant bit first, the way FLAC stores them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    // Bits not yet making up a whole byte, and how many there are.
+    accumulator: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    // Writes the low `bits` bits of `value`, up to 32.
+    fn write(&mut self, bits: u32, value: u32) {
+        let mask = if bits == 32 {
+            u32::MAX
+        } else {
+            (1 << bits) - 1
+        };
+        self.accumulator = (self.accumulator << bits) | (value & mask) as u64;
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+    }
+
+    // Writes a signed value in two's complement.
+    fn write_signed(&mut self, bits: u32, value: i32) {
+        self.write(bits, value as u32);
+    }
+
+    // Writes `count` zero bits followed by a one.
+    fn write_unary(&mut self, count: u32) {
+        for _ in 0..count / 32 {
+            self.write(32, 0);
+        }
+        self.write(count % 32 + 1, 1);
+    }
+
+    // Pads with zero bits to the next byte.
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(8 - self.bits, 0);
+        }
+    }
+}
+
+// CRC-8 with polynomial x^8 + x^2 + x + 1, which protects frame headers.
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+// CRC-16 with polynomial x^16 + x^15 + x^2 + 1, which protects whole frames.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+// Returns the residual of FLAC's fixed predictor of the given order, 0 to 4.
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i32> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+// Maps signed residuals onto unsigned values, 0, -1, 1, -2 and so on.
+fn fold(residual: i32) -> u32 {
+    ((residual << 1) ^ (residual >> 31)) as u32
+}
+
+// Returns the Rice parameter that codes the residual in the fewest bits, and how many that is.
+fn rice_parameter(residual: &[i32]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let bits = residual
+                .iter()
+                .map(|&value| (fold(value) >> parameter) as u64 + 1 + parameter as u64)
+                .sum();
+            (parameter, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+// Writes one channel of a block as a subframe.
+fn write_subframe(writer: &mut BitWriter, samples: &[i32]) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        // A constant subframe: type 0, no wasted bits
+        writer.write(8, 0);
+        writer.write_signed(BITS_PER_SAMPLE, samples[0]);
+        return;
+    }
+    if samples.len() <= 4 {
+        // A verbatim subframe, for blocks too short to predict
+        writer.write(8, 0b0000_0010);
+        for &sample in samples {
+            writer.write_signed(BITS_PER_SAMPLE, sample);
+        }
+        return;
+    }
+    let (order, residual, parameter) = (0..=4)
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (parameter, bits) = rice_parameter(&residual);
+            (
+                bits + order as u64 * BITS_PER_SAMPLE as u64,
+                order,
+                residual,
+                parameter,
+            )
+        })
+        .min_by_key(|(bits, ..)| *bits)
+        .map(|(_, order, residual, parameter)| (order, residual, parameter))
+        .unwrap();
+    // A fixed subframe: type 8 + order, shifted past the zero padding bit
+    writer.write(8, (0b1000 | order as u32) << 1);
+    for &sample in &samples[..order] {
+        writer.write_signed(BITS_PER_SAMPLE, sample);
+    }
+    // Rice coding with 4 bit parameters, in a single partition
+    writer.write(2, 0);
+    writer.write(4, 0);
+    writer.write(4, parameter);
+    for &value in &residual {
+        let value = fold(value);
+        writer.write_unary(value >> parameter);
+        writer.write(parameter, value);
+    }
+}
+
+// Writes a number the way FLAC frame headers do, in UTF-8's variable length
+// coding extended to 36 bits.
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(8, value as u32);
+        return;
+    }
+    let continuation_bytes = (1..=6)
+        .find(|&bytes| value < 1 << (5 * bytes + 6))
+        .unwrap_or(6);
+    let lead_bits = 6 - continuation_bytes;
+    let marker = (0xFF00u32 >> (continuation_bytes + 1)) & 0xFF;
+    let lead = (value >> (6 * continuation_bytes)) as u32 & ((1 << lead_bits) - 1);
+    writer.write(8, marker | lead);
+    for byte in (0..continuation_bytes).rev() {
+        writer.write(8, 0x80 | ((value >> (6 * byte)) & 0x3F) as u32);
+    }
+}
+
+// Encodes interleaved stereo samples, a block at a time, into a FLAC stream.
+pub struct FlacWriter<W: Write + Seek> {
+    output: W,
+    sample_rate: u32,
+    // Samples waiting for a whole block, interleaved.
+    pending: Vec<i16>,
+    frames_written: u64,
+    blocks_written: u64,
+    // Smallest and largest encoded frames, in bytes, for the stream info.
+    frame_sizes: Option<(usize, usize)>,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    // Starts a stream of audio at the given sample rate.
+    pub fn new(output: W, sample_rate: u32) -> Result<Self, String> {
+        let mut writer = FlacWriter {
+            output,
+            sample_rate,
+            pending: Vec::with_capacity(BLOCK_SIZE * CHANNELS),
+            frames_written: 0,
+            blocks_written: 0,
+            frame_sizes: None,
+        };
+        writer
+            .output
+            .write_all(b"fLaC")
+            .map_err(|e| e.to_string())?;
+        writer.write_stream_info()?;
+        Ok(writer)
+    }
+
+    // Writes the STREAMINFO block, the only metadata block, which is rewritten at
+    // the end once the length and frame sizes are known.
+    fn write_stream_info(&mut self) -> Result<(), String> {
+        let mut writer = BitWriter::default();
+        // Last metadata block, of type 0 and 34 bytes
+        writer.write(1, 1);
+        writer.write(7, 0);
+        writer.write(24, 34);
+        writer.write(16, BLOCK_SIZE as u32);
+        writer.write(16, BLOCK_SIZE as u32);
+        let (min_frame_size, max_frame_size) = self.frame_sizes.unwrap_or((0, 0));
+        writer.write(24, min_frame_size as u32);
+        writer.write(24, max_frame_size as u32);
+        writer.write(20, self.sample_rate);
+        writer.write(3, CHANNELS as u32 - 1);
+        writer.write(5, BITS_PER_SAMPLE - 1);
+        writer.write(4, (self.frames_written >> 32) as u32);
+        writer.write(32, self.frames_written as u32);
+        // No MD5 signature of the audio, which decoders accept as unknown
+        for _ in 0..4 {
+            writer.write(32, 0);
+        }
+        self.output
+            .write_all(&writer.bytes)
+            .map_err(|e| e.to_string())
+    }
+
+    // Adds interleaved stereo samples to the stream.
+    pub fn write(&mut self, samples: &[i16]) -> Result<(), String> {
+        for chunk in samples.chunks(BLOCK_SIZE * CHANNELS) {
+            let room = BLOCK_SIZE * CHANNELS - self.pending.len();
+            let (now, later) = chunk.split_at(room.min(chunk.len()));
+            self.pending.extend_from_slice(now);
+            if self.pending.len() == BLOCK_SIZE * CHANNELS {
+                self.write_block()?;
+                self.pending.extend_from_slice(later);
+            }
+        }
+        Ok(())
+    }
+
+    // Encodes the pending samples as one frame.
+    fn write_block(&mut self) -> Result<(), String> {
+        let frames = self.pending.len() / CHANNELS;
+        if frames == 0 {
+            return Ok(());
+        }
+        let mut writer = BitWriter::default();
+        // Sync code, then a fixed block size given at the end of the header
+        writer.write(14, 0b11_1111_1111_1110);
+        writer.write(2, 0);
+        writer.write(4, 0b0111);
+        // The sample rate and size of the stream info, independent left and right channels
+        writer.write(4, 0);
+        writer.write(4, CHANNELS as u32 - 1);
+        writer.write(3, 0b100);
+        writer.write(1, 0);
+        write_utf8(&mut writer, self.blocks_written);
+        writer.write(16, frames as u32 - 1);
+        let crc = crc8(&writer.bytes);
+        writer.write(8, crc as u32);
+        for channel in 0..CHANNELS {
+            let samples: Vec<i32> = self.pending[channel..]
+                .iter()
+                .step_by(CHANNELS)
+                .map(|&sample| sample as i32)
+                .collect();
+            write_subframe(&mut writer, &samples);
+        }
+        writer.align();
+        let crc = crc16(&writer.bytes);
+        writer.write(16, crc as u32);
+
+        self.output
+            .write_all(&writer.bytes)
+            .map_err(|e| e.to_string())?;
+        let size = writer.bytes.len();
+        self.frame_sizes = Some(match self.frame_sizes {
+            Some((min, max)) => (min.min(size), max.max(size)),
+            None => (size, size),
+        });
+        self.frames_written += frames as u64;
+        self.blocks_written += 1;
+        self.pending.clear();
+        Ok(())
+    }
+
+    // Writes the last, shorter block and fills in the stream info, returning the output.
+    pub fn finish(mut self) -> Result<W, String> {
+        self.write_block()?;
+        self.output
+            .seek(SeekFrom::Start(4))
+            .map_err(|e| e.to_string())?;
+        self.write_stream_info()?;
+        self.output.flush().map_err(|e| e.to_string())?;
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn checks_with_flacs_crcs() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn codes_frame_numbers_like_utf8() {
+        let coded = |value| {
+            let mut writer = BitWriter::default();
+            write_utf8(&mut writer, value);
+            writer.bytes
+        };
+        assert_eq!(coded(0x24), "$".as_bytes());
+        assert_eq!(coded(0xE9), "é".as_bytes());
+        assert_eq!(coded(0x20AC), "€".as_bytes());
+        assert_eq!(coded(0x1F600), "😀".as_bytes());
+    }
+
+    #[test]
+    fn predicts_smooth_audio_in_few_bits() {
+        // A ramp is all zeros past the first order's warm up
+        let ramp: Vec<i32> = (0..64).map(|i| i * 100).collect();
+        assert!(fixed_residual(&ramp, 2).iter().all(|&value| value == 0));
+        assert_eq!(rice_parameter(&fixed_residual(&ramp, 2)), (0, 62));
+        assert_eq!([0, -1, 1, -2].map(fold), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn writes_a_stream_with_its_length() {
+        let samples: Vec<i16> = (0..5000 * CHANNELS)
+            .map(|i| ((i / CHANNELS) as f64 / 10.0).sin().mul_add(8000.0, 0.0) as i16)
+            .collect();
+        let mut flac = FlacWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+        flac.write(&samples[..3000]).unwrap();
+        flac.write(&samples[3000..]).unwrap();
+        assert_eq!(flac.blocks_written, 1);
+        let output = flac.finish().unwrap().into_inner();
+
+        assert_eq!(&output[..4], b"fLaC");
+        // 44100 Hz, stereo, 16 bit and 5000 frames, packed after the frame sizes
+        assert_eq!(&output[18..26], &[0x0A, 0xC4, 0x42, 0xF0, 0, 0, 0x13, 0x88]);
+        assert!(output.len() < samples.len());
+        // Frames follow the stream info, starting with a sync code
+        assert_eq!(&output[42..44], &[0xFF, 0xF8]);
+    }
+}
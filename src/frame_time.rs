@@ -0,0 +1,63 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// frame_time.rs
+//
+// This module implements ENVIRONMENT_SET_FRAME_TIME_CALLBACK. Cores that scale
+// their simulation by real elapsed time are told how many microseconds passed
+// since the previous retro_run, instead of assuming a fixed 60 Hz.
+
+use libretro_sys::Usec;
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// struct retro_frame_time_callback. Mirrored because a core may pass a NULL callback
+// to unregister, which libretro-sys's non-nullable function pointer can't hold.
+#[repr(C)]
+pub struct FrameTimeCallback {
+    pub callback: Option<unsafe extern "C" fn(usec: Usec)>,
+    pub reference: Usec,
+}
+
+struct FrameTime {
+    callback: unsafe extern "C" fn(usec: Usec),
+    reference: Usec,
+    last_frame: Option<Instant>,
+}
+
+static FRAME_TIME: Lazy<Mutex<Option<FrameTime>>> = Lazy::new(|| Mutex::new(None));
+
+// Handles ENVIRONMENT_SET_FRAME_TIME_CALLBACK.
+pub unsafe fn set_frame_time_callback(callback: *const FrameTimeCallback) -> bool {
+    let Some(callback) = callback.as_ref() else {
+        return false;
+    };
+    *FRAME_TIME.lock().unwrap() = callback.callback.map(|function| FrameTime {
+        callback: function,
+        reference: callback.reference,
+        last_frame: None,
+    });
+    true
+}
+
+// Tells the core how long it has been since the previous frame. Must be called
+// right before retro_run. The first frame gets the core's reference frame time.
+pub fn notify() {
+    let (callback, usec) = {
+        let mut frame_time = FRAME_TIME.lock().unwrap();
+        let Some(frame_time) = frame_time.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        let usec = match frame_time.last_frame {
+            Some(last_frame) => now.duration_since(last_frame).as_micros() as Usec,
+            None => frame_time.reference,
+        };
+        frame_time.last_frame = Some(now);
+        (frame_time.callback, usec)
+    };
+    // The lock is released first in case the core calls back into the environment
+    unsafe { callback(usec) };
+}
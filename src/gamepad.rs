@@ -0,0 +1,260 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// gamepad.rs
+//
+// This module keeps track of which gamepad plays on which port. Gamepads are given
+// the first free port as they are found, whether they were plugged in before the
+// frontend started or while it runs. A gamepad that is unplugged keeps its port
+// and gets it back when plugged in again, unless another gamepad took it over in
+// the meantime, so a loose cable doesn't shuffle the players around.
+//
+// Gamepads are mapped to a standard layout by gilrs, from the SDL game controller
+// database it comes with and any newer `gamecontrollerdb.txt` in
+// `joypad_autoconfig_dir`. A gamepad that needs more than that can have a profile
+// there named after its GUID, e.g. `030000005e0400008e02000010010000.cfg`, binding
+// its buttons with `input_a_btn = "east"` and the like; the GUID is logged when
+// the gamepad is connected.
+
+use gilrs::{Button, Event, EventType, GamepadId, Gilrs, GilrsBuilder, MappingSource};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{input, libretro, osd};
+
+// Starts gilrs with the mappings in `joypad_autoconfig_dir` added to its own.
+pub fn open(config: &HashMap<String, String>) -> Gilrs {
+    let mut builder = GilrsBuilder::new();
+    let database = autoconfig_dir(config).join("gamecontrollerdb.txt");
+    if let Ok(mappings) = fs::read_to_string(&database) {
+        println!("Loaded gamepad mappings from {}", database.display());
+        builder = builder.add_mappings(&mappings);
+    }
+    builder.build().unwrap()
+}
+
+fn autoconfig_dir(config: &HashMap<String, String>) -> PathBuf {
+    let directory = config
+        .get("joypad_autoconfig_dir")
+        .map_or("", String::as_str);
+    PathBuf::from(shellexpand::tilde(directory).into_owned())
+}
+
+// Returns a gamepad's GUID as SDL writes it, which names its profile.
+fn guid(uuid: [u8; 16]) -> String {
+    uuid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Reads the profile for a gamepad, if there is one, as a map of its buttons to
+// libretro device IDs.
+fn load_profile(directory: &Path, guid: &str) -> Option<HashMap<String, usize>> {
+    let path = directory.join(format!("{}.cfg", guid));
+    let profile = libretro::parse_retroarch_config(&path).ok()?;
+    println!("Loaded gamepad profile {}", path.display());
+    Some(input::joypad_device_map(&profile, "input_"))
+}
+
+// Ports gamepads are assigned to.
+pub const PORTS: usize = 5;
+
+// A gamepad assigned to a port, and whether it is plugged in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Assignment<T> {
+    gamepad: T,
+    connected: bool,
+}
+
+pub struct Gamepads {
+    ports: [Option<Assignment<GamepadId>>; PORTS],
+    autoconfig_dir: PathBuf,
+    // The button maps of gamepads with a profile.
+    profiles: HashMap<GamepadId, HashMap<String, usize>>,
+}
+
+impl Gamepads {
+    // Assigns the gamepads already plugged in, in the order gilrs lists them.
+    pub fn new(gilrs: &Gilrs, config: &HashMap<String, String>) -> Self {
+        let mut gamepads = Gamepads {
+            ports: [None; PORTS],
+            autoconfig_dir: autoconfig_dir(config),
+            profiles: HashMap::new(),
+        };
+        for (id, _) in gilrs.gamepads() {
+            gamepads.connect(gilrs, id);
+        }
+        gamepads
+    }
+
+    // Handles gamepads plugged in or unplugged since the last frame, and returns
+    // the buttons pressed since then with the port of the gamepad they were on.
+    pub fn update(&mut self, gilrs: &mut Gilrs) -> Vec<(usize, Button)> {
+        let mut pressed = Vec::new();
+        while let Some(Event { id, event, .. }) = gilrs.next_event() {
+            match event {
+                EventType::Connected => self.connect(gilrs, id),
+                EventType::Disconnected => self.disconnect(gilrs, id),
+                EventType::ButtonPressed(button, _) => {
+                    let port = (0..PORTS).find(|&port| self.port(port) == Some(id));
+                    pressed.extend(port.map(|port| (port, button)));
+                }
+                _ => {}
+            }
+        }
+        pressed
+    }
+
+    // Returns the gamepad playing on `port`, counting from 0, if it is plugged in.
+    pub fn port(&self, port: usize) -> Option<GamepadId> {
+        self.ports
+            .get(port)
+            .copied()
+            .flatten()
+            .filter(|assignment| assignment.connected)
+            .map(|assignment| assignment.gamepad)
+    }
+
+    // Returns the button map of the profile of the gamepad on `port`, if it has one.
+    pub fn profile(&self, port: usize) -> Option<&HashMap<String, usize>> {
+        self.port(port).and_then(|id| self.profiles.get(&id))
+    }
+
+    fn connect(&mut self, gilrs: &Gilrs, id: GamepadId) {
+        let gamepad = gilrs.gamepad(id);
+        let name = gamepad.name().to_string();
+        let guid = guid(gamepad.uuid());
+        match gamepad.mapping_source() {
+            MappingSource::SdlMappings => println!("Gamepad {} ({}) is mapped by SDL", name, guid),
+            MappingSource::Driver => {
+                println!("Gamepad {} ({}) is mapped by its driver", name, guid)
+            }
+            MappingSource::None => println!(
+                "Gamepad {} ({}) has no mapping, its buttons may be mixed up",
+                name, guid
+            ),
+        }
+        // The profile is read again on reconnecting, to pick up edits
+        match load_profile(&self.autoconfig_dir, &guid) {
+            Some(profile) => self.profiles.insert(id, profile),
+            None => self.profiles.remove(&id),
+        };
+        match connect(&mut self.ports, id) {
+            Connection::Already => {}
+            Connection::Assigned(port) => {
+                println!("Gamepad {} connected as player {}", name, port + 1);
+                osd::show_message(&format!("Controller connected: player {}", port + 1));
+            }
+            Connection::Reconnected(port) => {
+                println!("Gamepad {} reconnected as player {}", name, port + 1);
+                osd::show_message(&format!("Player {} controller reconnected", port + 1));
+            }
+            Connection::Unassigned => {
+                println!("Gamepad {} connected, but every player has one", name);
+            }
+        }
+    }
+
+    fn disconnect(&mut self, gilrs: &Gilrs, id: GamepadId) {
+        let name = gilrs.gamepad(id).name().to_string();
+        match disconnect(&mut self.ports, id) {
+            Some(port) => {
+                println!("Gamepad {} of player {} disconnected", name, port + 1);
+                osd::show_message(&format!("Player {} controller disconnected", port + 1));
+            }
+            None => println!("Gamepad {} disconnected", name),
+        }
+    }
+}
+
+// What plugging in a gamepad did.
+#[derive(PartialEq, Debug)]
+enum Connection {
+    // It already had a port and was plugged in.
+    Already,
+    // It was given a port, counting from 0.
+    Assigned(usize),
+    // It got its port back.
+    Reconnected(usize),
+    // Every port has a gamepad plugged in.
+    Unassigned,
+}
+
+// Finds a port for a gamepad that was plugged in: its own if it had one, else the
+// first free port, else the first one whose gamepad is unplugged.
+fn connect<T: Copy + PartialEq>(ports: &mut [Option<Assignment<T>>], gamepad: T) -> Connection {
+    let assigned = Assignment {
+        gamepad,
+        connected: true,
+    };
+    if let Some(port) = ports
+        .iter()
+        .position(|assignment| assignment.is_some_and(|assignment| assignment.gamepad == gamepad))
+    {
+        let was_connected = ports[port].is_some_and(|assignment| assignment.connected);
+        ports[port] = Some(assigned);
+        return if was_connected {
+            Connection::Already
+        } else {
+            Connection::Reconnected(port)
+        };
+    }
+    let free = ports.iter().position(Option::is_none).or_else(|| {
+        ports
+            .iter()
+            .position(|assignment| assignment.is_some_and(|assignment| !assignment.connected))
+    });
+    match free {
+        Some(port) => {
+            ports[port] = Some(assigned);
+            Connection::Assigned(port)
+        }
+        None => Connection::Unassigned,
+    }
+}
+
+// Marks a gamepad that was unplugged, returning its port if it had one.
+fn disconnect<T: PartialEq>(ports: &mut [Option<Assignment<T>>], gamepad: T) -> Option<usize> {
+    let port = ports.iter().position(|assignment| {
+        assignment
+            .as_ref()
+            .is_some_and(|assignment| assignment.gamepad == gamepad)
+    })?;
+    if let Some(assignment) = ports[port].as_mut() {
+        assignment.connected = false;
+    }
+    Some(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamepads_keep_their_ports_until_taken_over() {
+        let mut ports = [None; 2];
+        assert_eq!(connect(&mut ports, 'a'), Connection::Assigned(0));
+        assert_eq!(connect(&mut ports, 'a'), Connection::Already);
+        assert_eq!(connect(&mut ports, 'b'), Connection::Assigned(1));
+        assert_eq!(connect(&mut ports, 'c'), Connection::Unassigned);
+
+        // A gamepad unplugged and plugged in again gets its port back
+        assert_eq!(disconnect(&mut ports, 'a'), Some(0));
+        assert_eq!(disconnect(&mut ports, 'c'), None);
+        assert_eq!(connect(&mut ports, 'a'), Connection::Reconnected(0));
+
+        // Unless another gamepad took it in the meantime
+        disconnect(&mut ports, 'b');
+        assert_eq!(connect(&mut ports, 'c'), Connection::Assigned(1));
+        assert_eq!(connect(&mut ports, 'b'), Connection::Unassigned);
+    }
+
+    #[test]
+    fn writes_guids_as_sdl_does() {
+        let uuid = [
+            0x03, 0x00, 0x00, 0x00, 0x5e, 0x04, 0x00, 0x00, 0x8e, 0x02, 0x00, 0x00, 0x10, 0x01,
+            0x00, 0x00,
+        ];
+        assert_eq!(guid(uuid), "030000005e0400008e02000010010000");
+    }
+}
@@ -0,0 +1,855 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// gpu.rs
+//
+// This module presents frames with wgpu when the `wgpu` feature is enabled. Each
+// frame is uploaded as a texture and drawn into the target area of the minifb
+// window's surface, so scaling and bilinear filtering happen on the GPU instead of
+// in the per-pixel loops of pixels.rs. The LED and OSD are drawn on the CPU into a
+// window sized overlay that is only uploaded while something is on it. A shader
+// preset loaded with `video_shader` replaces the plain scaling with its passes.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::ptr::NonNull;
+use wgpu::naga;
+use wgpu::rwh::{self, RawDisplayHandle, RawWindowHandle};
+
+use crate::pixels::Frame;
+use crate::shader;
+use crate::window::Window;
+
+// Full window triangle clipped to the viewport, sampling the bound texture. The
+// frame is drawn opaque, the overlay with its alpha.
+const SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let position = vec2<f32>(f32(index & 1u) * 4.0 - 1.0, f32(index >> 1u) * 4.0 - 1.0);
+    var output: VertexOutput;
+    output.position = vec4<f32>(position, 0.0, 1.0);
+    output.uv = vec2<f32>(position.x + 1.0, 1.0 - position.y) * 0.5;
+    return output;
+}
+
+@group(0) @binding(0) var picture: texture_2d<f32>;
+@group(0) @binding(1) var picture_sampler: sampler;
+
+@fragment
+fn fs_frame(input: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(picture, picture_sampler, input.uv).rgb, 1.0);
+}
+
+@fragment
+fn fs_overlay(input: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(picture, picture_sampler, input.uv);
+}
+"#;
+
+// Overlay pixels nothing was drawn on. The LED and OSD draw opaque pixels with the
+// top byte clear, so this can never be a colour they use, and the bezel draws with
+// its alpha in the top byte.
+pub const OVERLAY_CLEAR: u32 = 0x0100_0000;
+
+// A texture with bind groups for sampling it with either filter.
+struct Picture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    width: u32,
+    height: u32,
+    nearest: wgpu::BindGroup,
+    linear: wgpu::BindGroup,
+}
+
+// Returns whether `picture` exists and has the given size.
+fn fits(picture: &Option<Picture>, width: u32, height: u32) -> bool {
+    picture
+        .as_ref()
+        .is_some_and(|picture| picture.width == width && picture.height == height)
+}
+
+pub struct Renderer {
+    surface: wgpu::Surface<'static>,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    bind_group_layout: wgpu::BindGroupLayout,
+    frame_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+    nearest_sampler: wgpu::Sampler,
+    linear_sampler: wgpu::Sampler,
+    frame: Option<Picture>,
+    overlay: Option<Picture>,
+    // The overlay with its alpha filled in, kept to upload it from every frame.
+    overlay_pixels: Vec<u32>,
+    shader: Option<ShaderChain>,
+}
+
+// Corners of the [0, 1] quad shader passes draw, as a position and a texture
+// coordinate each.
+const QUAD: [[f32; 6]; 4] = [
+    [0.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+    [0.0, 1.0, 0.0, 1.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0, 1.0, 1.0, 1.0],
+];
+
+// A texture a shader pass renders into.
+struct Output {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+// A pass of a shader preset, ready to draw.
+struct ShaderPass {
+    config: shader::PassConfig,
+    // Name later passes sample the output by besides PassOutputN, if any.
+    alias: Option<String>,
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    bindings: Vec<shader::Binding>,
+    // A buffer for each uniform block, in the order of `bindings`.
+    buffers: Vec<wgpu::Buffer>,
+    // Samplers for the inputs with nearest and linear filtering.
+    samplers: [wgpu::Sampler; 2],
+    format: wgpu::TextureFormat,
+    // The texture the pass renders into, unless it's the last.
+    output: Option<Output>,
+}
+
+// The passes of a loaded shader preset, with the values its parameters have.
+struct ShaderChain {
+    passes: Vec<ShaderPass>,
+    parameters: HashMap<String, f32>,
+    quad: wgpu::Buffer,
+    frame_count: u32,
+}
+
+impl ShaderChain {
+    // Renders every pass but the last into its texture with the frame as the
+    // original, and returns the bind group the last pass draws into the `viewport`
+    // sized target with.
+    fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &Picture,
+        viewport: (u32, u32),
+        smooth: bool,
+    ) -> wgpu::BindGroup {
+        let (original, original_size) = (&frame.view, (frame.width, frame.height));
+        self.frame_count = self.frame_count.wrapping_add(1);
+        let viewport = (viewport.0.max(1), viewport.1.max(1));
+        let last = self.passes.len() - 1;
+        let mut source_size = original_size;
+        for index in 0..self.passes.len() {
+            let (earlier, rest) = self.passes.split_at_mut(index);
+            let pass = &mut rest[0];
+            let output_size = if index == last {
+                viewport
+            } else {
+                shader::output_size(pass.config.scale, source_size, viewport)
+            };
+            let fits = pass.output.as_ref().is_some_and(|output| {
+                (output.texture.width(), output.texture.height()) == output_size
+            });
+            if index != last && !fits {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("shader pass output"),
+                    size: wgpu::Extent3d {
+                        width: output_size.0,
+                        height: output_size.1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: pass.format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                pass.output = Some(Output { texture, view });
+            }
+
+            // Textures and their sizes by the names shaders use for them
+            let mut views = HashMap::from([
+                ("Original".to_string(), original),
+                ("OriginalHistory0".to_string(), original),
+            ]);
+            let mut sizes = HashMap::from([
+                ("Original".to_string(), original_size),
+                ("OriginalHistory0".to_string(), original_size),
+                ("Source".to_string(), source_size),
+                ("Output".to_string(), output_size),
+                ("FinalViewport".to_string(), viewport),
+            ]);
+            for (earlier_index, earlier) in earlier.iter().enumerate() {
+                let Some(output) = &earlier.output else {
+                    continue;
+                };
+                let size = (output.texture.width(), output.texture.height());
+                let names = std::iter::once(format!("PassOutput{}", earlier_index))
+                    .chain(earlier.alias.clone());
+                for name in names {
+                    views.insert(name.clone(), &output.view);
+                    sizes.insert(name, size);
+                }
+            }
+            let source = earlier
+                .last()
+                .and_then(|pass| pass.output.as_ref())
+                .map_or(original, |output| &output.view);
+            views.insert("Source".to_string(), source);
+
+            let semantics = shader::Semantics {
+                sizes: &sizes,
+                frame_count: match pass.config.frame_count_mod {
+                    0 => self.frame_count,
+                    modulo => self.frame_count % modulo,
+                },
+                parameters: &self.parameters,
+            };
+            let sampler = &pass.samplers[pass.config.filter_linear.unwrap_or(smooth) as usize];
+            let mut buffers = pass.buffers.iter();
+            let entries: Vec<wgpu::BindGroupEntry> = pass
+                .bindings
+                .iter()
+                .map(|binding| wgpu::BindGroupEntry {
+                    binding: binding.binding(),
+                    resource: match binding {
+                        shader::Binding::Uniforms { size, members, .. } => {
+                            let buffer = buffers.next().unwrap();
+                            let data = shader::uniform_data(*size, members, &semantics);
+                            queue.write_buffer(buffer, 0, &data);
+                            buffer.as_entire_binding()
+                        }
+                        shader::Binding::Texture { name, .. } => {
+                            wgpu::BindingResource::TextureView(
+                                views.get(name).copied().unwrap_or(original),
+                            )
+                        }
+                        shader::Binding::Sampler { .. } => wgpu::BindingResource::Sampler(sampler),
+                    },
+                })
+                .collect();
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("shader pass"),
+                layout: &pass.layout,
+                entries: &entries,
+            });
+            let Some(output) = &pass.output else {
+                return bind_group;
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shader pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &output.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.set_vertex_buffer(0, self.quad.slice(..));
+            render_pass.draw(0..QUAD.len() as u32, 0..1);
+            drop(render_pass);
+            source_size = output_size;
+        }
+        unreachable!("the last pass returns its bind group")
+    }
+}
+
+// Translates minifb's window handle to the version wgpu takes.
+fn surface_target(window: &dyn Window) -> Result<wgpu::SurfaceTargetUnsafe, String> {
+    use raw_window_handle::RawWindowHandle as Minifb;
+    let (raw_display_handle, raw_window_handle) = match window.raw_window_handle() {
+        Minifb::Xlib(handle) => (
+            RawDisplayHandle::Xlib(rwh::XlibDisplayHandle::new(NonNull::new(handle.display), 0)),
+            RawWindowHandle::Xlib(rwh::XlibWindowHandle::new(handle.window)),
+        ),
+        Minifb::Wayland(handle) => (
+            RawDisplayHandle::Wayland(rwh::WaylandDisplayHandle::new(
+                NonNull::new(handle.display).ok_or("Wayland display is null")?,
+            )),
+            RawWindowHandle::Wayland(rwh::WaylandWindowHandle::new(
+                NonNull::new(handle.surface).ok_or("Wayland surface is null")?,
+            )),
+        ),
+        Minifb::Win32(handle) => {
+            let hwnd = std::num::NonZeroIsize::new(handle.hwnd as isize).ok_or("HWND is null")?;
+            let mut window_handle = rwh::Win32WindowHandle::new(hwnd);
+            window_handle.hinstance = std::num::NonZeroIsize::new(handle.hinstance as isize);
+            (
+                RawDisplayHandle::Windows(rwh::WindowsDisplayHandle::new()),
+                RawWindowHandle::Win32(window_handle),
+            )
+        }
+        Minifb::AppKit(handle) => (
+            RawDisplayHandle::AppKit(rwh::AppKitDisplayHandle::new()),
+            RawWindowHandle::AppKit(rwh::AppKitWindowHandle::new(
+                NonNull::new(handle.ns_view).ok_or("NSView is null")?,
+            )),
+        ),
+        handle => return Err(format!("Unsupported window handle {:?}", handle)),
+    };
+    Ok(wgpu::SurfaceTargetUnsafe::RawHandle {
+        raw_display_handle,
+        raw_window_handle,
+    })
+}
+
+impl Renderer {
+    // Creates a surface for the window and a device to draw to it, presenting on
+    // vertical blank with `vsync`. The window must outlive the renderer.
+    pub fn new(window: &dyn Window, vsync: bool) -> Result<Self, String> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let surface = unsafe { instance.create_surface_unsafe(surface_target(window)?) }
+            .map_err(|e| format!("Failed to create surface: {}", e))?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: Some(&surface),
+            ..Default::default()
+        }))
+        .ok_or("No GPU adapter can draw to the window")?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("rustretro"),
+                required_features: wgpu::Features::empty(),
+                required_limits:
+                    wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .map_err(|e| format!("Failed to create GPU device: {}", e))?;
+        println!("Presenting with wgpu on {}", adapter.get_info().name);
+
+        // Frames are already in display colours, so they must not be gamma encoded again
+        let capabilities = surface.get_capabilities(&adapter);
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|format| !format.is_srgb())
+            .or(capabilities.formats.first().copied())
+            .ok_or("The window surface has no formats")?;
+        let (width, height) = window.get_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: width.max(1) as u32,
+            height: height.max(1) as u32,
+            // Frames are paced by the speed module either way, vsync only stops tearing
+            present_mode: if vsync {
+                wgpu::PresentMode::AutoVsync
+            } else {
+                wgpu::PresentMode::AutoNoVsync
+            },
+            desired_maximum_frame_latency: 2,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(&device, &config);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("picture"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("picture"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("picture"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = |entry_point: &str, blend: wgpu::BlendState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            })
+        };
+        let frame_pipeline = pipeline("fs_frame", wgpu::BlendState::REPLACE);
+        let overlay_pipeline = pipeline("fs_overlay", wgpu::BlendState::ALPHA_BLENDING);
+
+        let sampler = |filter: wgpu::FilterMode| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                mag_filter: filter,
+                min_filter: filter,
+                ..Default::default()
+            })
+        };
+        let nearest_sampler = sampler(wgpu::FilterMode::Nearest);
+        let linear_sampler = sampler(wgpu::FilterMode::Linear);
+
+        Ok(Renderer {
+            surface,
+            device,
+            queue,
+            config,
+            bind_group_layout,
+            frame_pipeline,
+            overlay_pipeline,
+            nearest_sampler,
+            linear_sampler,
+            frame: None,
+            overlay: None,
+            overlay_pixels: Vec::new(),
+            shader: None,
+        })
+    }
+
+    // Loads a shader preset, or a single shader, to draw frames through from now
+    // on. Nothing changes if any of its passes fails to load.
+    pub fn load_shader(&mut self, path: &Path) -> Result<(), String> {
+        let preset = shader::load_preset(path)?;
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let chain = self.create_shader_chain(preset);
+        let error = pollster::block_on(self.device.pop_error_scope());
+        let chain = chain?;
+        if let Some(error) = error {
+            return Err(format!(
+                "Failed to load shader preset {}: {}",
+                path.display(),
+                error
+            ));
+        }
+        println!(
+            "Loaded shader preset {} with {} passes",
+            path.display(),
+            chain.passes.len()
+        );
+        self.shader = Some(chain);
+        Ok(())
+    }
+
+    // Compiles the passes of a preset into pipelines.
+    fn create_shader_chain(&self, preset: shader::Preset) -> Result<ShaderChain, String> {
+        let mut parameters = HashMap::new();
+        let mut passes: Vec<ShaderPass> = Vec::new();
+        let last = preset.passes.len() - 1;
+        for (index, config) in preset.passes.into_iter().enumerate() {
+            let source = shader::load_source(&config.shader)?;
+            let compile = |source: &str, stage| {
+                shader::compile(source, stage)
+                    .map_err(|e| format!("Failed to compile {}: {}", config.shader.display(), e))
+            };
+            let vertex = compile(&source.vertex, naga::ShaderStage::Vertex)?;
+            let fragment = compile(&source.fragment, naga::ShaderStage::Fragment)?;
+            let bindings = shader::bindings(&[&vertex, &fragment]);
+            for parameter in &source.parameters {
+                parameters
+                    .entry(parameter.name.clone())
+                    .or_insert(parameter.default);
+            }
+
+            // Passes can only sample the frame and what earlier passes rendered
+            for binding in &bindings {
+                let shader::Binding::Texture { name, .. } = binding else {
+                    continue;
+                };
+                let earlier = passes.iter().enumerate().any(|(earlier, pass)| {
+                    *name == format!("PassOutput{}", earlier) || pass.alias.as_ref() == Some(name)
+                });
+                if !earlier && !matches!(name.as_str(), "Source" | "Original" | "OriginalHistory0")
+                {
+                    return Err(format!(
+                        "{} samples {}, which isn't supported",
+                        config.shader.display(),
+                        name
+                    ));
+                }
+            }
+
+            let entries: Vec<wgpu::BindGroupLayoutEntry> = bindings
+                .iter()
+                .map(|binding| wgpu::BindGroupLayoutEntry {
+                    binding: binding.binding(),
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: match binding {
+                        shader::Binding::Uniforms { .. } => wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        shader::Binding::Texture { .. } => wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        shader::Binding::Sampler { .. } => {
+                            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+                        }
+                    },
+                    count: None,
+                })
+                .collect();
+            let layout = self
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("shader pass"),
+                    entries: &entries,
+                });
+            let buffers = bindings
+                .iter()
+                .filter_map(|binding| match binding {
+                    shader::Binding::Uniforms { size, .. } => {
+                        Some(self.device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("shader uniforms"),
+                            size: *size as u64,
+                            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }))
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            let format = if index == last {
+                self.config.format
+            } else {
+                shader::texture_format(&config, source.format.as_deref())
+            };
+            let pipeline_layout =
+                self.device
+                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                        label: Some("shader pass"),
+                        bind_group_layouts: &[&layout],
+                        push_constant_ranges: &[],
+                    });
+            let module = |module| {
+                self.device
+                    .create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: config.shader.to_str(),
+                        source: wgpu::ShaderSource::Naga(Cow::Owned(module)),
+                    })
+            };
+            let (vertex, fragment) = (module(vertex), module(fragment));
+            let pipeline = self
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: config.shader.to_str(),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &vertex,
+                        entry_point: "main",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: std::mem::size_of::<[f32; 6]>() as u64,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x2],
+                        }],
+                    },
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleStrip,
+                        ..Default::default()
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &fragment,
+                        entry_point: "main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: None,
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview: None,
+                });
+            let sampler = |filter| {
+                self.device.create_sampler(&wgpu::SamplerDescriptor {
+                    address_mode_u: config.wrap_mode,
+                    address_mode_v: config.wrap_mode,
+                    mag_filter: filter,
+                    min_filter: filter,
+                    ..Default::default()
+                })
+            };
+            let samplers = [
+                sampler(wgpu::FilterMode::Nearest),
+                sampler(wgpu::FilterMode::Linear),
+            ];
+            passes.push(ShaderPass {
+                alias: config.alias.clone().or(source.name),
+                config,
+                pipeline,
+                layout,
+                bindings,
+                buffers,
+                samplers,
+                format,
+                output: None,
+            });
+        }
+        parameters.extend(preset.parameters);
+
+        // SAFETY: any f32 is four valid bytes
+        let quad = unsafe {
+            std::slice::from_raw_parts(QUAD.as_ptr() as *const u8, std::mem::size_of_val(&QUAD))
+        };
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shader quad"),
+            size: quad.len() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        self.queue.write_buffer(&buffer, 0, quad);
+        Ok(ShaderChain {
+            passes,
+            parameters,
+            quad: buffer,
+            frame_count: 0,
+        })
+    }
+
+    // Creates a texture for pictures of the given size.
+    fn create_picture(&self, width: u32, height: u32) -> Picture {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("picture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // XRGB8888 words are stored B, G, R, X in memory
+            format: wgpu::TextureFormat::Bgra8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = |sampler: &wgpu::Sampler| {
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("picture"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            })
+        };
+        Picture {
+            nearest: bind_group(&self.nearest_sampler),
+            linear: bind_group(&self.linear_sampler),
+            texture,
+            view,
+            width,
+            height,
+        }
+    }
+
+    // Uploads pixels whose lines are `stride` pixels apart to a picture's texture.
+    fn upload(&self, picture: &Picture, pixels: &[u32], stride: usize) {
+        // SAFETY: any u32 is four valid bytes
+        let bytes = unsafe {
+            std::slice::from_raw_parts(pixels.as_ptr() as *const u8, std::mem::size_of_val(pixels))
+        };
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &picture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(stride as u32 * 4),
+                rows_per_image: Some(picture.height),
+            },
+            picture.texture.size(),
+        );
+    }
+
+    // Draws the frame scaled into the target area of a `window_size` window, with
+    // everything around it black, and the overlay on top if there is one. The
+    // overlay is a window sized buffer where OVERLAY_CLEAR marks the pixels the
+    // picture shows through.
+    pub fn draw(
+        &mut self,
+        frame: &Frame,
+        target: (usize, usize, usize, usize),
+        window_size: (usize, usize),
+        smooth: bool,
+        overlay: Option<&[u32]>,
+    ) -> Result<(), String> {
+        // Nothing can be drawn to a minimized window
+        if window_size.0 == 0 || window_size.1 == 0 {
+            return Ok(());
+        }
+        let (window_width, window_height) = (window_size.0 as u32, window_size.1 as u32);
+        if (self.config.width, self.config.height) != (window_width, window_height) {
+            self.config.width = window_width;
+            self.config.height = window_height;
+            self.surface.configure(&self.device, &self.config);
+        }
+
+        let (frame_width, frame_height) = (frame.width as u32, frame.height as u32);
+        if !fits(&self.frame, frame_width, frame_height) {
+            self.frame = Some(self.create_picture(frame_width, frame_height));
+        }
+        if overlay.is_some() && !fits(&self.overlay, window_width, window_height) {
+            self.overlay = Some(self.create_picture(window_width, window_height));
+        }
+        let frame_picture = self.frame.as_ref().unwrap();
+        self.upload(frame_picture, frame.pixels, frame.stride);
+
+        let overlay = match overlay {
+            Some(pixels) => {
+                let overlay_picture = self.overlay.as_ref().unwrap();
+                self.overlay_pixels.clear();
+                self.overlay_pixels
+                    .extend(pixels.iter().map(|&pixel| match pixel {
+                        OVERLAY_CLEAR => 0,
+                        pixel if pixel >> 24 == 0 => pixel | 0xFF00_0000,
+                        pixel => pixel,
+                    }));
+                self.upload(overlay_picture, &self.overlay_pixels, window_size.0);
+                Some(overlay_picture)
+            }
+            None => None,
+        };
+
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            // The window changed under us, try again next frame
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                self.surface.configure(&self.device, &self.config);
+                return Ok(());
+            }
+            Err(wgpu::SurfaceError::Timeout) => return Ok(()),
+            Err(e) => return Err(format!("Failed to get the next surface texture: {}", e)),
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let shader_bind_group = self.shader.as_mut().map(|chain| {
+            chain.render(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                frame_picture,
+                (target.2 as u32, target.3 as u32),
+                smooth,
+            )
+        });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("present"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            let (x, y, width, height) = target;
+            pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+            match (&self.shader, &shader_bind_group) {
+                (Some(chain), Some(bind_group)) => {
+                    pass.set_pipeline(&chain.passes[chain.passes.len() - 1].pipeline);
+                    pass.set_bind_group(0, bind_group, &[]);
+                    pass.set_vertex_buffer(0, chain.quad.slice(..));
+                    pass.draw(0..QUAD.len() as u32, 0..1);
+                }
+                _ => {
+                    pass.set_pipeline(&self.frame_pipeline);
+                    pass.set_bind_group(
+                        0,
+                        if smooth {
+                            &frame_picture.linear
+                        } else {
+                            &frame_picture.nearest
+                        },
+                        &[],
+                    );
+                    pass.draw(0..3, 0..1);
+                }
+            }
+
+            if let Some(overlay) = overlay {
+                pass.set_viewport(
+                    0.0,
+                    0.0,
+                    window_width as f32,
+                    window_height as f32,
+                    0.0,
+                    1.0,
+                );
+                pass.set_pipeline(&self.overlay_pipeline);
+                pass.set_bind_group(0, &overlay.nearest, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        self.queue.submit([encoder.finish()]);
+        output.present();
+        Ok(())
+    }
+}
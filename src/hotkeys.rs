@@ -0,0 +1,433 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// hotkeys.rs
+//
+// This module is the registry of the frontend's hotkeys. Each is bound to a key by
+// its config entry, e.g. `input_reset = "h"`, named as RetroArch names them where
+// it has the same action, and set to "nul" or left empty to unbind it. The menu
+// hotkey lists them all over the game.
+//
+// They can also be bound to gamepad combos for couch play: a gamepad button in the
+// entry with `_btn` added, e.g. `input_save_state_btn = "righttrigger"`, runs the
+// hotkey when pressed while the `input_enable_hotkey_btn` button is held. While it
+// is, as in RetroArch, the gamepad's other buttons don't reach the game, so a
+// combo doesn't also press them there.
+
+use gilrs::{Button, GamepadId, Gilrs};
+use libretro_sys::CoreAPI;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::input::JOYPAD_BUTTONS;
+use crate::libretro::{self, EmulatorState, StateAction};
+use crate::window::{self, Window};
+use crate::{
+    audio, audio_dump, clip, disk, input, movie, osd, perf_overlay, remap, rewind, screenshot,
+    speed, video,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    StateSlotIncrease,
+    StateSlotDecrease,
+    Reset,
+    Pause,
+    FrameAdvance,
+    FastForward,
+    HoldFastForward,
+    SlowMotion,
+    Rewind,
+    Screenshot,
+    SaveClip,
+    AudioDump,
+    Movie,
+    TakeOver,
+    Mute,
+    Fullscreen,
+    Smooth,
+    Crt,
+    PerfOverlay,
+    DiskEjectToggle,
+    DiskNext,
+    DiskPrev,
+    Remap,
+    Menu,
+    Quit,
+}
+
+// Each hotkey with the config entry binding it and what the menu calls it.
+const HOTKEYS: [(Hotkey, &str, &str); 27] = [
+    (Hotkey::SaveState, "input_save_state", "Save state"),
+    (Hotkey::LoadState, "input_load_state", "Load state"),
+    (
+        Hotkey::StateSlotIncrease,
+        "input_state_slot_increase",
+        "Next state slot",
+    ),
+    (
+        Hotkey::StateSlotDecrease,
+        "input_state_slot_decrease",
+        "Previous state slot",
+    ),
+    (Hotkey::Reset, "input_reset", "Reset"),
+    (Hotkey::Pause, "input_pause_toggle", "Pause"),
+    (Hotkey::FrameAdvance, "input_frame_advance", "Frame advance"),
+    (
+        Hotkey::FastForward,
+        "input_toggle_fast_forward",
+        "Fast-forward",
+    ),
+    (
+        Hotkey::HoldFastForward,
+        "input_hold_fast_forward",
+        "Fast-forward while held",
+    ),
+    (Hotkey::SlowMotion, "input_toggle_slowmotion", "Slow motion"),
+    (Hotkey::Rewind, "input_rewind", "Rewind while held"),
+    (Hotkey::Screenshot, "input_screenshot", "Screenshot"),
+    (Hotkey::SaveClip, "input_save_clip", "Save clip"),
+    (Hotkey::AudioDump, "input_toggle_audio_dump", "Record audio"),
+    (Hotkey::Movie, "input_movie_record_toggle", "Record movie"),
+    (
+        Hotkey::TakeOver,
+        "input_movie_take_over",
+        "Take over from movie",
+    ),
+    (Hotkey::Mute, "input_audio_mute", "Mute"),
+    (Hotkey::Fullscreen, "input_toggle_fullscreen", "Fullscreen"),
+    (Hotkey::Smooth, "input_toggle_smooth", "Smooth scaling"),
+    (Hotkey::Crt, "input_toggle_crt", "CRT filter"),
+    (
+        Hotkey::PerfOverlay,
+        "input_toggle_perf_overlay",
+        "Performance overlay",
+    ),
+    (
+        Hotkey::DiskEjectToggle,
+        "input_disk_eject_toggle",
+        "Eject disk",
+    ),
+    (Hotkey::DiskNext, "input_disk_next", "Next disk"),
+    (Hotkey::DiskPrev, "input_disk_prev", "Previous disk"),
+    (Hotkey::Remap, "input_remap", "Rebind buttons"),
+    (Hotkey::Menu, "input_menu_toggle", "Menu"),
+    (Hotkey::Quit, "input_exit_emulator", "Quit"),
+];
+
+// Set by the quit hotkey, and checked by the main loop.
+static QUIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Whether emulation is paused by the hotkey.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+// Set by frame advance to run one frame while paused.
+static FRAME_STEP: AtomicBool = AtomicBool::new(false);
+
+// The lines of the menu while it is open.
+static MENU: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+// Returns the key bound to a hotkey's config entry, unless it is unbound.
+fn bound_key<'a>(config: &'a HashMap<String, String>, config_key: &str) -> Option<&'a str> {
+    config
+        .get(config_key)
+        .map(String::as_str)
+        .filter(|key| !key.is_empty() && *key != "nul")
+}
+
+// Returns the hotkey a key is bound to, if any.
+pub fn find(config: &HashMap<String, String>, key: &str) -> Option<Hotkey> {
+    HOTKEYS
+        .iter()
+        .find(|(_, config_key, _)| bound_key(config, config_key) == Some(key))
+        .map(|&(hotkey, _, _)| hotkey)
+}
+
+// Returns the `input_enable_hotkey_btn` button if a gamepad holds it, when its
+// other buttons are kept from the game.
+pub fn held_enable_button(
+    config: &HashMap<String, String>,
+    gilrs: &Gilrs,
+    gamepad: GamepadId,
+) -> Option<Button> {
+    bound_key(config, "input_enable_hotkey_btn")
+        .and_then(input::parse_button)
+        .filter(|&enable| gilrs.gamepad(gamepad).is_pressed(enable))
+}
+
+// Returns the hotkey a gamepad button runs, if it was pressed while the gamepad
+// holds the `input_enable_hotkey_btn` button.
+pub fn find_combo(
+    config: &HashMap<String, String>,
+    gilrs: &Gilrs,
+    gamepad: Option<GamepadId>,
+    button: Button,
+) -> Option<Hotkey> {
+    if held_enable_button(config, gilrs, gamepad?)? == button {
+        return None;
+    }
+    let name = input::button_name(button);
+    HOTKEYS
+        .iter()
+        .find(|(_, config_key, _)| {
+            bound_key(config, &format!("{}_btn", config_key)) == Some(name.as_str())
+        })
+        .map(|&(hotkey, _, _)| hotkey)
+}
+
+// Returns "key: action" for each bound hotkey, in the registry's order, and
+// "enable+button: action" for each gamepad combo.
+fn bindings(config: &HashMap<String, String>) -> Vec<String> {
+    let keys = HOTKEYS.iter().filter_map(|(_, config_key, name)| {
+        bound_key(config, config_key).map(|key| format!("{}: {}", key, name))
+    });
+    let enable = bound_key(config, "input_enable_hotkey_btn");
+    let combos = HOTKEYS.iter().filter_map(|(_, config_key, name)| {
+        let button = bound_key(config, &format!("{}_btn", config_key))?;
+        Some(format!("{}+{}: {}", enable?, button, name))
+    });
+    keys.chain(combos).collect()
+}
+
+// Returns "a and b are both bound to key" for each key bound to more than one
+// hotkey, or to a hotkey and one of player 1's buttons or the tilt sensor, which
+// are read every frame.
+fn clashes(config: &HashMap<String, String>) -> Vec<String> {
+    let tilts = ["up", "down", "left", "right"].map(|tilt| format!("input_sensor_tilt_{}", tilt));
+    let others = JOYPAD_BUTTONS
+        .iter()
+        .map(|(name, _)| format!("input_player1_{}", name))
+        .chain(tilts)
+        .collect::<Vec<_>>();
+    let mut clashes = Vec::new();
+    for (index, (_, config_key, _)) in HOTKEYS.iter().enumerate() {
+        let Some(key) = bound_key(config, config_key) else {
+            continue;
+        };
+        let other_hotkeys = HOTKEYS[index + 1..]
+            .iter()
+            .map(|(_, other_key, _)| *other_key);
+        for other_key in other_hotkeys.chain(others.iter().map(String::as_str)) {
+            if bound_key(config, other_key) == Some(key) {
+                clashes.push(format!(
+                    "{} and {} are both bound to {}",
+                    config_key, other_key, key
+                ));
+            }
+        }
+    }
+    clashes
+}
+
+// Prints the hotkeys, warning about keys bound to more than one thing.
+pub fn print_bindings(config: &HashMap<String, String>) {
+    println!("Hotkeys:");
+    for binding in bindings(config) {
+        println!("  {}", binding);
+    }
+    for clash in clashes(config) {
+        println!("{}", clash);
+    }
+}
+
+// Returns whether the quit hotkey has been pressed.
+pub fn quit_requested() -> bool {
+    QUIT_REQUESTED.load(Ordering::SeqCst)
+}
+
+// Returns whether emulation is paused.
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+// Pauses or resumes emulation. While paused the core isn't run and audio is quiet,
+// but the window keeps showing the last frame and handling hotkeys. A core paced
+// by its audio callback is told the callback stops and starts again.
+fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::SeqCst);
+    audio::set_paused(paused);
+    audio::set_audio_callback_state(!paused);
+    let message = if paused { "Paused" } else { "Resumed" };
+    println!("{}", message);
+    osd::show_message(message);
+}
+
+// Returns whether to run the next frame: always unless paused, and once for each
+// frame advance while paused.
+pub fn runs_frame() -> bool {
+    !is_paused() || FRAME_STEP.swap(false, Ordering::SeqCst)
+}
+
+// Checks the hotkeys that act for as long as they are held down. Called every frame.
+pub fn update_held(config: &HashMap<String, String>, window: &dyn Window) {
+    let keys = window.get_keys();
+    let held = |config_key: &str| {
+        bound_key(config, config_key).is_some_and(|key| {
+            keys.iter()
+                .any(|held| format!("{:?}", held).to_ascii_lowercase() == key)
+        })
+    };
+    speed::set_fast_forward_held(held("input_hold_fast_forward"));
+    rewind::set_held(held("input_rewind"));
+}
+
+// Carries out a hotkey's action.
+pub fn run(
+    hotkey: Hotkey,
+    core_api: &CoreAPI,
+    window: &mut dyn Window,
+    current_state: &mut EmulatorState,
+    config: &HashMap<String, String>,
+) {
+    match hotkey {
+        Hotkey::SaveState => unsafe {
+            libretro::run_state_action(core_api, current_state, config, StateAction::Save);
+        },
+        Hotkey::LoadState => unsafe {
+            libretro::run_state_action(core_api, current_state, config, StateAction::Load);
+        },
+        Hotkey::StateSlotIncrease => {
+            if current_state.current_save_slot != 255 {
+                current_state.current_save_slot += 1;
+                println!(
+                    "Current save slot increased to: {}",
+                    current_state.current_save_slot
+                );
+            }
+        }
+        Hotkey::StateSlotDecrease => {
+            if current_state.current_save_slot != 0 {
+                current_state.current_save_slot -= 1;
+                println!(
+                    "Current save slot decreased to: {}",
+                    current_state.current_save_slot
+                );
+            }
+        }
+        Hotkey::Reset => {
+            unsafe { (core_api.retro_reset)() };
+            println!("Reset");
+            osd::show_message("Reset");
+        }
+        Hotkey::Pause => set_paused(!is_paused()),
+        // Pauses if running, then runs a single frame
+        Hotkey::FrameAdvance => {
+            if !is_paused() {
+                set_paused(true);
+            }
+            FRAME_STEP.store(true, Ordering::SeqCst);
+        }
+        Hotkey::FastForward => speed::toggle_fast_forward(),
+        // Held rather than pressed, which `update_held` keeps track of
+        Hotkey::HoldFastForward => {}
+        Hotkey::SlowMotion => speed::toggle_slow_motion(),
+        // Also held, but says why nothing happens when rewind is off
+        Hotkey::Rewind => {
+            if !rewind::is_enabled() {
+                println!("Rewind is off, set rewind_enable to \"true\" to use it");
+                osd::show_message("Rewind is off");
+            }
+        }
+        Hotkey::Screenshot => screenshot::take(config, &current_state.rom_name),
+        Hotkey::SaveClip => clip::save(config, &current_state.rom_name),
+        Hotkey::AudioDump => audio_dump::toggle(config, &current_state.rom_name),
+        Hotkey::Movie => movie::toggle(core_api, config, &current_state.rom_name),
+        Hotkey::TakeOver => movie::take_over(),
+        Hotkey::Mute => {
+            let message = if audio::toggle_mute() {
+                "Audio muted"
+            } else {
+                "Audio unmuted"
+            };
+            osd::show_message(message);
+        }
+        Hotkey::Fullscreen => window::toggle_fullscreen(window),
+        Hotkey::Smooth => video::toggle_smooth(),
+        Hotkey::Crt => video::toggle_crt(),
+        Hotkey::PerfOverlay => perf_overlay::toggle(),
+        Hotkey::DiskEjectToggle => disk::toggle_eject(),
+        Hotkey::DiskNext => disk::change_image(1),
+        Hotkey::DiskPrev => disk::change_image(-1),
+        Hotkey::Remap => remap::start(),
+        Hotkey::Menu => {
+            let mut menu = MENU.lock().unwrap();
+            *menu = match menu.take() {
+                Some(_) => None,
+                None => Some(bindings(config)),
+            };
+        }
+        Hotkey::Quit => QUIT_REQUESTED.store(true, Ordering::SeqCst),
+    }
+}
+
+// Draws the menu over the window buffer, if it is open.
+pub fn draw(buffer: &mut [u32], width: usize, height: usize) {
+    let menu = MENU.lock().unwrap();
+    let Some(lines) = menu.as_ref() else {
+        return;
+    };
+    let scale = osd::font_scale(height);
+    let x = scale * 2;
+    // Listed up from the bottom, clear of the performance overlay
+    let mut y = height.saturating_sub((lines.len() * osd::GLYPH_HEIGHT + 2) * scale);
+    for line in lines {
+        osd::draw_text(buffer, width, height, line, x, y, scale);
+        y += osd::GLYPH_HEIGHT * scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_hotkeys_by_their_keys() {
+        let config = HashMap::from([
+            ("input_reset".to_string(), "h".to_string()),
+            ("input_rewind".to_string(), "nul".to_string()),
+            ("input_exit_emulator".to_string(), "escape".to_string()),
+        ]);
+        assert_eq!(find(&config, "h"), Some(Hotkey::Reset));
+        assert_eq!(find(&config, "escape"), Some(Hotkey::Quit));
+        assert_eq!(find(&config, "nul"), None);
+        assert_eq!(bindings(&config), ["h: Reset", "escape: Quit"]);
+    }
+
+    #[test]
+    fn warns_about_keys_bound_twice() {
+        let config = HashMap::from([
+            ("input_reset".to_string(), "h".to_string()),
+            ("input_pause_toggle".to_string(), "h".to_string()),
+            ("input_hold_fast_forward".to_string(), "l".to_string()),
+            ("input_sensor_tilt_right".to_string(), "l".to_string()),
+            ("input_audio_mute".to_string(), "a".to_string()),
+            ("input_player1_a".to_string(), "a".to_string()),
+            ("input_player2_a".to_string(), "h".to_string()),
+        ]);
+        assert_eq!(
+            clashes(&config),
+            [
+                "input_reset and input_pause_toggle are both bound to h",
+                "input_hold_fast_forward and input_sensor_tilt_right are both bound to l",
+                "input_audio_mute and input_player1_a are both bound to a",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_gamepad_combos() {
+        let config = HashMap::from([
+            ("input_enable_hotkey_btn".to_string(), "select".to_string()),
+            (
+                "input_save_state_btn".to_string(),
+                "righttrigger".to_string(),
+            ),
+            ("input_load_state_btn".to_string(), "nul".to_string()),
+        ]);
+        assert_eq!(bindings(&config), ["select+righttrigger: Save state"]);
+    }
+}
@@ -0,0 +1,478 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// hw_render.rs
+//
+// This module services cores that render with a GPU API through
+// ENVIRONMENT_SET_HW_RENDER. With the `gl` feature enabled, an offscreen OpenGL
+// context is created and the core draws into a framebuffer object we own. Each
+// frame is read back and handed to the regular software presentation path, since
+// the minifb window has no GL surface of its own. The `vulkan` feature does the
+// same for Vulkan cores, see vulkan.rs.
+
+use libc::{c_char, c_uint, c_void, uintptr_t};
+use libretro_sys::{GameGeometry, HwContextType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(feature = "vulkan")]
+use crate::vulkan::{NegotiationInterfaceVulkan, VulkanContext};
+
+// Value passed to the video refresh callback when the frame lives in the HW framebuffer.
+pub const HW_FRAME_BUFFER_VALID: *const c_void = usize::MAX as *const c_void;
+
+// struct retro_hw_render_callback. Mirrored here because libretro-sys declares the
+// optional context_destroy callback as a non-nullable function pointer.
+#[repr(C)]
+pub struct HwRenderCallback {
+    pub context_type: c_uint,
+    pub context_reset: Option<unsafe extern "C" fn()>,
+    pub get_current_framebuffer: unsafe extern "C" fn() -> uintptr_t,
+    pub get_proc_address: unsafe extern "C" fn(sym: *const c_char) -> *const c_void,
+    pub depth: bool,
+    pub stencil: bool,
+    pub bottom_left_origin: bool,
+    pub version_major: c_uint,
+    pub version_minor: c_uint,
+    pub cache_context: bool,
+    pub context_destroy: Option<unsafe extern "C" fn()>,
+    pub debug_context: bool,
+}
+
+// What the core asked for, plus the context serving it once one exists.
+#[cfg_attr(not(feature = "gl"), allow(dead_code))]
+struct HwRender {
+    context_type: HwContextType,
+    context_reset: Option<unsafe extern "C" fn()>,
+    context_destroy: Option<unsafe extern "C" fn()>,
+    depth: bool,
+    stencil: bool,
+    bottom_left_origin: bool,
+    version: (u32, u32),
+    debug_context: bool,
+    #[cfg(feature = "gl")]
+    context: Option<gl_context::GlContext>,
+    #[cfg(feature = "vulkan")]
+    negotiation: *const NegotiationInterfaceVulkan,
+    #[cfg(feature = "vulkan")]
+    vulkan: Option<VulkanContext>,
+}
+
+// GL contexts are bound to the thread that made them current, and the core only
+// calls us from the emulation thread, so the state is kept per thread.
+thread_local! {
+    static HW_RENDER: RefCell<Option<HwRender>> = const { RefCell::new(None) };
+}
+
+// Handles ENVIRONMENT_SET_HW_RENDER, filling in our callbacks if we can provide
+// the requested context type.
+pub unsafe fn set_hw_render(callback: *mut HwRenderCallback) -> bool {
+    let Some(callback) = callback.as_mut() else {
+        return false;
+    };
+    let context_type = match HwContextType::from_uint(callback.context_type) {
+        Some(context_type) => context_type,
+        None => return false,
+    };
+    println!(
+        "Core requested hardware context {:?} {}.{}",
+        context_type, callback.version_major, callback.version_minor
+    );
+    if !supports_context(context_type) {
+        println!("Hardware context {:?} is not supported", context_type);
+        return false;
+    }
+
+    callback.get_current_framebuffer = get_current_framebuffer;
+    callback.get_proc_address = get_proc_address;
+    HW_RENDER.with(|hw_render| {
+        *hw_render.borrow_mut() = Some(HwRender {
+            context_type,
+            context_reset: callback.context_reset,
+            context_destroy: callback.context_destroy,
+            depth: callback.depth,
+            stencil: callback.stencil,
+            bottom_left_origin: callback.bottom_left_origin,
+            version: (callback.version_major, callback.version_minor),
+            debug_context: callback.debug_context,
+            #[cfg(feature = "gl")]
+            context: None,
+            #[cfg(feature = "vulkan")]
+            negotiation: std::ptr::null(),
+            #[cfg(feature = "vulkan")]
+            vulkan: None,
+        })
+    });
+    true
+}
+
+// Handles ENVIRONMENT_SET_HW_RENDER_CONTEXT_NEGOTIATION_INTERFACE. Only Vulkan cores
+// use it, to take part in creating the instance and device.
+pub fn set_negotiation_interface(interface: *const c_void) -> bool {
+    #[cfg(feature = "vulkan")]
+    return HW_RENDER.with(|hw_render| match hw_render.borrow_mut().as_mut() {
+        Some(hw_render) if hw_render.context_type == HwContextType::Vulkan => {
+            hw_render.negotiation = interface as *const NegotiationInterfaceVulkan;
+            true
+        }
+        _ => false,
+    });
+    #[cfg(not(feature = "vulkan"))]
+    {
+        let _ = interface;
+        false
+    }
+}
+
+// Handles ENVIRONMENT_GET_HW_RENDER_INTERFACE, returning the interface of the
+// current context, if it has one.
+pub fn hw_render_interface() -> Option<*const c_void> {
+    #[cfg(feature = "vulkan")]
+    return with_vulkan(|context| context.interface() as *const c_void);
+    #[cfg(not(feature = "vulkan"))]
+    None
+}
+
+// Reports whether this build can create a context of the given type.
+fn supports_context(context_type: HwContextType) -> bool {
+    let is_gl = matches!(
+        context_type,
+        HwContextType::OpenGL
+            | HwContextType::OpenGLCore
+            | HwContextType::OpenGLES2
+            | HwContextType::OpenGLES3
+            | HwContextType::OpenGLESVersion
+    );
+    let is_vulkan = context_type == HwContextType::Vulkan;
+    (is_gl && cfg!(feature = "gl")) || (is_vulkan && cfg!(feature = "vulkan"))
+}
+
+// Context type answered for ENVIRONMENT_GET_PREFERRED_HW_RENDER, or None when this
+// build can't render with any GPU API.
+static PREFERRED_CONTEXT: AtomicU32 = AtomicU32::new(HwContextType::None as u32);
+
+// Picks the context type cores that support several renderers should use. The
+// `video_driver` setting chooses between them when more than one is built in.
+pub fn configure(config: &HashMap<String, String>) {
+    let driver = config.get("video_driver").map(String::as_str);
+    let preferred = match driver {
+        Some("vulkan") if cfg!(feature = "vulkan") => HwContextType::Vulkan,
+        Some("glcore") if cfg!(feature = "gl") => HwContextType::OpenGLCore,
+        _ if cfg!(feature = "gl") => HwContextType::OpenGL,
+        _ if cfg!(feature = "vulkan") => HwContextType::Vulkan,
+        _ => HwContextType::None,
+    };
+    PREFERRED_CONTEXT.store(preferred as u32, Ordering::SeqCst);
+}
+
+// The context type answered for ENVIRONMENT_GET_PREFERRED_HW_RENDER, if any.
+pub fn preferred_context() -> Option<c_uint> {
+    let preferred = PREFERRED_CONTEXT.load(Ordering::SeqCst);
+    (preferred != HwContextType::None as u32).then_some(preferred)
+}
+
+// Returns true when the core renders through a hardware context.
+pub fn is_active() -> bool {
+    HW_RENDER.with(|hw_render| hw_render.borrow().is_some())
+}
+
+// Creates the context and framebuffer sized for the content, then lets the core
+// set up its GPU resources. Must be called after the game has been loaded.
+pub fn context_reset(geometry: &GameGeometry) {
+    let context_reset = HW_RENDER.with(|hw_render| {
+        let mut hw_render = hw_render.borrow_mut();
+        let hw_render = hw_render.as_mut()?;
+
+        let created = match hw_render.context_type {
+            #[cfg(feature = "vulkan")]
+            HwContextType::Vulkan => {
+                hw_render.vulkan = None;
+                unsafe { VulkanContext::new(hw_render.negotiation) }
+                    .map(|context| hw_render.vulkan = Some(context))
+            }
+            #[cfg(feature = "gl")]
+            _ => {
+                hw_render.context = None;
+                gl_context::GlContext::new(hw_render, geometry.max_width, geometry.max_height)
+                    .map(|context| hw_render.context = Some(context))
+            }
+            #[cfg(not(feature = "gl"))]
+            _ => Ok::<(), String>(()),
+        };
+        #[cfg(not(feature = "gl"))]
+        let _ = geometry;
+        if let Err(err) = created {
+            println!("Failed to create hardware context: {}", err);
+            return None;
+        }
+
+        hw_render.context_reset
+    });
+    if let Some(context_reset) = context_reset {
+        unsafe { context_reset() };
+    }
+}
+
+// Lets the core release its GPU resources before the context goes away.
+pub fn context_destroy() {
+    let hw_render = HW_RENDER.with(|hw_render| hw_render.borrow_mut().take());
+    if let Some(hw_render) = hw_render {
+        if let Some(context_destroy) = hw_render.context_destroy {
+            unsafe { context_destroy() };
+        }
+    }
+}
+
+// Reads the frame the core rendered into our framebuffer as XRGB8888 pixels,
+// flipped to top-left origin if needed.
+pub fn read_frame(width: u32, height: u32) -> Option<Vec<u32>> {
+    HW_RENDER.with(|hw_render| {
+        let mut hw_render = hw_render.borrow_mut();
+        let hw_render = hw_render.as_mut()?;
+        #[cfg(feature = "vulkan")]
+        if let Some(context) = hw_render.vulkan.as_mut() {
+            return context
+                .read_frame(width, height)
+                .map_err(|err| println!("Failed to read Vulkan frame: {}", err))
+                .ok();
+        }
+        #[cfg(feature = "gl")]
+        {
+            let context = hw_render.context.as_ref()?;
+            Some(context.read_frame(width, height, hw_render.bottom_left_origin))
+        }
+        #[cfg(not(feature = "gl"))]
+        {
+            let _ = (hw_render, width, height);
+            None
+        }
+    })
+}
+
+// Runs `f` against the Vulkan context, for the callbacks of the Vulkan interface.
+#[cfg(feature = "vulkan")]
+pub fn with_vulkan<T>(f: impl FnOnce(&mut VulkanContext) -> T) -> Option<T> {
+    HW_RENDER.with(|hw_render| {
+        let mut hw_render = hw_render.borrow_mut();
+        hw_render.as_mut()?.vulkan.as_mut().map(f)
+    })
+}
+
+unsafe extern "C" fn get_current_framebuffer() -> uintptr_t {
+    HW_RENDER.with(|hw_render| {
+        #[cfg(feature = "gl")]
+        if let Some(context) = hw_render
+            .borrow()
+            .as_ref()
+            .and_then(|hw| hw.context.as_ref())
+        {
+            return context.framebuffer_id();
+        }
+        let _ = hw_render;
+        0
+    })
+}
+
+unsafe extern "C" fn get_proc_address(symbol: *const c_char) -> *const c_void {
+    if symbol.is_null() {
+        return std::ptr::null();
+    }
+    let symbol = CStr::from_ptr(symbol);
+    HW_RENDER.with(|hw_render| {
+        #[cfg(feature = "gl")]
+        if let Some(context) = hw_render
+            .borrow()
+            .as_ref()
+            .and_then(|hw| hw.context.as_ref())
+        {
+            return context.proc_address(symbol);
+        }
+        let _ = (hw_render, symbol);
+        std::ptr::null()
+    })
+}
+
+#[cfg(feature = "gl")]
+mod gl_context {
+    use super::HwRender;
+    use glow::HasContext;
+    use glutin::api::egl::{context::PossiblyCurrentContext, device::Device, display::Display};
+    use glutin::config::{ConfigSurfaceTypes, ConfigTemplateBuilder};
+    use glutin::context::{ContextApi, ContextAttributesBuilder, GlProfile, Version};
+    use glutin::prelude::*;
+    use libretro_sys::HwContextType;
+    use std::ffi::{c_void, CStr};
+
+    // An offscreen EGL context with the framebuffer object the core renders into.
+    pub struct GlContext {
+        display: Display,
+        _context: PossiblyCurrentContext,
+        gl: glow::Context,
+        framebuffer: glow::Framebuffer,
+        _texture: glow::Texture,
+        _depth_stencil: Option<glow::Renderbuffer>,
+    }
+
+    impl GlContext {
+        pub fn new(hw_render: &HwRender, width: u32, height: u32) -> Result<Self, String> {
+            unsafe {
+                let device = Device::query_devices()
+                    .map_err(|err| err.to_string())?
+                    .next()
+                    .ok_or("No EGL device available")?;
+                let display = Display::with_device(&device, None).map_err(|err| err.to_string())?;
+
+                let template = ConfigTemplateBuilder::new()
+                    .with_surface_type(ConfigSurfaceTypes::empty())
+                    .build();
+                let config = display
+                    .find_configs(template)
+                    .map_err(|err| err.to_string())?
+                    .next()
+                    .ok_or("No suitable EGL config")?;
+
+                let (major, minor) = hw_render.version;
+                let (api, profile) = match hw_render.context_type {
+                    HwContextType::OpenGLCore => (
+                        ContextApi::OpenGl(Some(Version::new(major as u8, minor as u8))),
+                        Some(GlProfile::Core),
+                    ),
+                    HwContextType::OpenGLES2 => (ContextApi::Gles(Some(Version::new(2, 0))), None),
+                    HwContextType::OpenGLES3 => (ContextApi::Gles(Some(Version::new(3, 0))), None),
+                    HwContextType::OpenGLESVersion => (
+                        ContextApi::Gles(Some(Version::new(major as u8, minor as u8))),
+                        None,
+                    ),
+                    _ => (ContextApi::OpenGl(None), Some(GlProfile::Compatibility)),
+                };
+                let mut attributes = ContextAttributesBuilder::new()
+                    .with_context_api(api)
+                    .with_debug(hw_render.debug_context);
+                if let Some(profile) = profile {
+                    attributes = attributes.with_profile(profile);
+                }
+                let context = display
+                    .create_context(&config, &attributes.build(None))
+                    .map_err(|err| err.to_string())?
+                    .make_current_surfaceless()
+                    .map_err(|err| err.to_string())?;
+
+                let gl = glow::Context::from_loader_function_cstr(|symbol| {
+                    display.get_proc_address(symbol)
+                });
+
+                let texture = gl.create_texture()?;
+                gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+                gl.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    glow::RGBA8 as i32,
+                    width as i32,
+                    height as i32,
+                    0,
+                    glow::RGBA,
+                    glow::UNSIGNED_BYTE,
+                    None,
+                );
+                let framebuffer = gl.create_framebuffer()?;
+                gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+                gl.framebuffer_texture_2d(
+                    glow::FRAMEBUFFER,
+                    glow::COLOR_ATTACHMENT0,
+                    glow::TEXTURE_2D,
+                    Some(texture),
+                    0,
+                );
+
+                let depth_stencil = if hw_render.depth {
+                    let renderbuffer = gl.create_renderbuffer()?;
+                    let (format, attachment) = if hw_render.stencil {
+                        (glow::DEPTH24_STENCIL8, glow::DEPTH_STENCIL_ATTACHMENT)
+                    } else {
+                        (glow::DEPTH_COMPONENT24, glow::DEPTH_ATTACHMENT)
+                    };
+                    gl.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
+                    gl.renderbuffer_storage(
+                        glow::RENDERBUFFER,
+                        format,
+                        width as i32,
+                        height as i32,
+                    );
+                    gl.framebuffer_renderbuffer(
+                        glow::FRAMEBUFFER,
+                        attachment,
+                        glow::RENDERBUFFER,
+                        Some(renderbuffer),
+                    );
+                    Some(renderbuffer)
+                } else {
+                    None
+                };
+
+                let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+                if status != glow::FRAMEBUFFER_COMPLETE {
+                    return Err(format!("Framebuffer incomplete: 0x{:x}", status));
+                }
+                gl.clear_color(0.0, 0.0, 0.0, 1.0);
+                gl.clear(
+                    glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT | glow::STENCIL_BUFFER_BIT,
+                );
+                println!(
+                    "Created {:?} hardware context with a {}x{} framebuffer",
+                    hw_render.context_type, width, height
+                );
+
+                Ok(GlContext {
+                    display,
+                    _context: context,
+                    gl,
+                    framebuffer,
+                    _texture: texture,
+                    _depth_stencil: depth_stencil,
+                })
+            }
+        }
+
+        pub fn framebuffer_id(&self) -> usize {
+            self.framebuffer.0.get() as usize
+        }
+
+        pub fn proc_address(&self, symbol: &CStr) -> *const c_void {
+            self.display.get_proc_address(symbol)
+        }
+
+        pub fn read_frame(&self, width: u32, height: u32, bottom_left_origin: bool) -> Vec<u32> {
+            let mut pixels = vec![0u32; (width * height) as usize];
+            unsafe {
+                self.gl
+                    .bind_framebuffer(glow::READ_FRAMEBUFFER, Some(self.framebuffer));
+                // BGRA bytes read into little-endian u32s give XRGB8888 directly.
+                self.gl.read_pixels(
+                    0,
+                    0,
+                    width as i32,
+                    height as i32,
+                    glow::BGRA,
+                    glow::UNSIGNED_BYTE,
+                    glow::PixelPackData::Slice(std::slice::from_raw_parts_mut(
+                        pixels.as_mut_ptr() as *mut u8,
+                        pixels.len() * 4,
+                    )),
+                );
+            }
+            if bottom_left_origin {
+                let row_length = width as usize;
+                let rows = height as usize;
+                for y in 0..rows / 2 {
+                    let (top, bottom) = pixels.split_at_mut((rows - 1 - y) * row_length);
+                    top[y * row_length..(y + 1) * row_length]
+                        .swap_with_slice(&mut bottom[..row_length]);
+                }
+            }
+            pixels
+        }
+    }
+}
@@ -8,169 +8,216 @@
 // keyboard and gamepad inputs. It utilizes the gilrs library for gamepad
 // support and minifb for keyboard inputs.
 
-use gilrs::{Button, GamepadId, Gilrs};
+use gilrs::{Axis, Button, GamepadId, Gilrs};
 use libretro_sys::{
-    CoreAPI, DEVICE_ID_JOYPAD_A, DEVICE_ID_JOYPAD_B, DEVICE_ID_JOYPAD_DOWN, DEVICE_ID_JOYPAD_L,
-    DEVICE_ID_JOYPAD_LEFT, DEVICE_ID_JOYPAD_R, DEVICE_ID_JOYPAD_RIGHT, DEVICE_ID_JOYPAD_SELECT,
-    DEVICE_ID_JOYPAD_START, DEVICE_ID_JOYPAD_UP, DEVICE_ID_JOYPAD_X, DEVICE_ID_JOYPAD_Y,
+    CoreAPI, DEVICE_ANALOG, DEVICE_ID_ANALOG_X, DEVICE_ID_ANALOG_Y, DEVICE_ID_JOYPAD_A,
+    DEVICE_ID_JOYPAD_B, DEVICE_ID_JOYPAD_DOWN, DEVICE_ID_JOYPAD_L, DEVICE_ID_JOYPAD_L2,
+    DEVICE_ID_JOYPAD_L3, DEVICE_ID_JOYPAD_LEFT, DEVICE_ID_JOYPAD_R, DEVICE_ID_JOYPAD_R2,
+    DEVICE_ID_JOYPAD_R3, DEVICE_ID_JOYPAD_RIGHT, DEVICE_ID_JOYPAD_SELECT, DEVICE_ID_JOYPAD_START,
+    DEVICE_ID_JOYPAD_UP, DEVICE_ID_JOYPAD_X, DEVICE_ID_JOYPAD_Y, DEVICE_INDEX_ANALOG_LEFT,
+    DEVICE_INDEX_ANALOG_RIGHT, DEVICE_JOYPAD,
 };
-use minifb::{KeyRepeat, Window};
+use minifb::{Key, KeyRepeat};
 use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::Mutex;
 
 use crate::{
-    libretro::{self, EmulatorState},
-    BUTTONS_PRESSED,
+    gamepad, hotkeys,
+    libretro::EmulatorState,
+    movie, remap,
+    window::{self, Window},
+    BUTTONS_PRESSED, INPUT_DESCRIPTORS_CHANNEL,
 };
 
-/// Maps keyboard key names to libretro device IDs based on the provided configuration.
-pub fn key_device_map(config: &HashMap<String, String>) -> HashMap<String, usize> {
-    HashMap::from([
-        (
-            config["input_player1_a"].clone(),
-            DEVICE_ID_JOYPAD_A as usize,
-        ),
-        (
-            config["input_player1_b"].clone(),
-            DEVICE_ID_JOYPAD_B as usize,
-        ),
-        (
-            config["input_player1_x"].clone(),
-            DEVICE_ID_JOYPAD_X as usize,
-        ),
-        (
-            config["input_player1_y"].clone(),
-            DEVICE_ID_JOYPAD_Y as usize,
-        ),
-        (
-            config["input_player1_l"].clone(),
-            DEVICE_ID_JOYPAD_L as usize,
-        ),
-        (
-            config["input_player1_r"].clone(),
-            DEVICE_ID_JOYPAD_R as usize,
-        ),
-        (
-            config["input_player1_down"].clone(),
-            DEVICE_ID_JOYPAD_DOWN as usize,
-        ),
-        (
-            config["input_player1_up"].clone(),
-            DEVICE_ID_JOYPAD_UP as usize,
-        ),
-        (
-            config["input_player1_right"].clone(),
-            DEVICE_ID_JOYPAD_RIGHT as usize,
-        ),
-        (
-            config["input_player1_left"].clone(),
-            DEVICE_ID_JOYPAD_LEFT as usize,
-        ),
-        (
-            config["input_player1_start"].clone(),
-            DEVICE_ID_JOYPAD_START as usize,
-        ),
-        (
-            config["input_player1_select"].clone(),
-            DEVICE_ID_JOYPAD_SELECT as usize,
-        ),
-    ])
+/// A human-readable description of one of the core's inputs, as sent with
+/// ENVIRONMENT_SET_INPUT_DESCRIPTORS (e.g. "Fire" for the B button).
+#[derive(Clone, Debug)]
+pub struct InputDescriptor {
+    pub port: u32,
+    pub device: u32,
+    pub index: u32,
+    pub id: u32,
+    pub description: String,
 }
 
-/// Sets up the mapping between gamepad buttons and libretro device IDs.
-pub fn setup_joypad_device_map(config: &HashMap<String, String>) -> HashMap<String, usize> {
-    HashMap::from([
-        (
-            config
-                .get("input_player1_a_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_A.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_A as usize,
-        ),
-        (
-            config
-                .get("input_player1_b_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_B.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_B as usize,
-        ),
-        (
-            config
-                .get("input_player1_x_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_X.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_X as usize,
-        ),
-        (
-            config
-                .get("input_player1_y_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_Y.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_Y as usize,
-        ),
-        (
-            config
-                .get("input_player1_l_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_L.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_L as usize,
-        ),
-        (
-            config
-                .get("input_player1_r_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_R.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_R as usize,
-        ),
-        (
-            config
-                .get("input_player1_down_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_DOWN.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_DOWN as usize,
-        ),
-        (
-            config
-                .get("input_player1_up_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_UP.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_UP as usize,
-        ),
-        (
-            config
-                .get("input_player1_right_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_RIGHT.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_RIGHT as usize,
-        ),
-        (
-            config
-                .get("input_player1_left_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_LEFT.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_LEFT as usize,
-        ),
-        (
-            config
-                .get("input_player1_start_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_START.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_START as usize,
-        ),
-        (
-            config
-                .get("input_player1_select_btn")
-                .unwrap_or(&DEVICE_ID_JOYPAD_SELECT.to_string())
-                .clone(),
-            DEVICE_ID_JOYPAD_SELECT as usize,
-        ),
-    ])
+/// Copies the descriptor array the core passed in. The array ends with an entry
+/// whose description is NULL.
+pub unsafe fn read_input_descriptors(
+    descriptors: *const libretro_sys::InputDescriptor,
+) -> Vec<InputDescriptor> {
+    let mut result = Vec::new();
+    let mut current = descriptors;
+    while !current.is_null() && !(*current).description.is_null() {
+        let descriptor = &*current;
+        result.push(InputDescriptor {
+            port: descriptor.port,
+            device: descriptor.device,
+            index: descriptor.index,
+            id: descriptor.id,
+            description: CStr::from_ptr(descriptor.description)
+                .to_string_lossy()
+                .into_owned(),
+        });
+        current = current.add(1);
+    }
+    result
+}
+
+/// Stores input descriptors received from the core in the emulator state and prints them.
+pub fn update_input_descriptors(current_state: &mut EmulatorState) {
+    let receiver = INPUT_DESCRIPTORS_CHANNEL.1.lock().unwrap();
+    for descriptors in receiver.try_iter() {
+        print_input_descriptors(&descriptors);
+        current_state.input_descriptors = descriptors;
+    }
+}
+
+/// Returns the core's description of a joypad button on the given port, if it sent one.
+pub fn button_description(current_state: &EmulatorState, port: u32, id: u32) -> Option<&str> {
+    current_state
+        .input_descriptors
+        .iter()
+        .find(|descriptor| {
+            descriptor.port == port && descriptor.device == DEVICE_JOYPAD && descriptor.id == id
+        })
+        .map(|descriptor| descriptor.description.as_str())
+}
+
+/// Prints the core's input descriptors as "Port 1: B = Fire".
+pub fn print_input_descriptors(descriptors: &[InputDescriptor]) {
+    println!("Input descriptors:");
+    for descriptor in descriptors {
+        println!(
+            "  Port {}: {} = {}",
+            descriptor.port + 1,
+            input_name(descriptor),
+            descriptor.description
+        );
+    }
+}
+
+/// Prints which keyboard key drives each joypad button, with the core's name for it.
+pub fn print_key_bindings(
+    key_device_map: &HashMap<String, (usize, usize)>,
+    current_state: &EmulatorState,
+) {
+    let mut bindings: Vec<(&String, &(usize, usize))> = key_device_map.iter().collect();
+    bindings.sort_by_key(|(_, port_and_id)| **port_and_id);
+    println!("Keyboard bindings:");
+    for (key, &(port, id)) in bindings {
+        let name = input_name(&InputDescriptor {
+            port: port as u32,
+            device: DEVICE_JOYPAD,
+            index: 0,
+            id: id as u32,
+            description: String::new(),
+        });
+        match button_description(current_state, port as u32, id as u32) {
+            Some(description) => {
+                println!("  {} -> Port {} {} ({})", key, port + 1, name, description)
+            }
+            None => println!("  {} -> Port {} {}", key, port + 1, name),
+        }
+    }
 }
 
-/// Callback function for polling input states. Used primarily for logging in this context.
-pub unsafe extern "C" fn libretro_set_input_poll_callback() {
-    println!("libretro_set_input_poll_callback")
+/// Returns the name of the joypad button or analog axis a descriptor refers to.
+fn input_name(descriptor: &InputDescriptor) -> String {
+    if descriptor.device == DEVICE_ANALOG {
+        let stick = if descriptor.index == DEVICE_INDEX_ANALOG_LEFT {
+            "Left Analog"
+        } else {
+            "Right Analog"
+        };
+        let axis = if descriptor.id == 0 { "X" } else { "Y" };
+        return format!("{} {}", stick, axis);
+    }
+    let name = match descriptor.id {
+        DEVICE_ID_JOYPAD_B => "B",
+        DEVICE_ID_JOYPAD_Y => "Y",
+        DEVICE_ID_JOYPAD_SELECT => "Select",
+        DEVICE_ID_JOYPAD_START => "Start",
+        DEVICE_ID_JOYPAD_UP => "Up",
+        DEVICE_ID_JOYPAD_DOWN => "Down",
+        DEVICE_ID_JOYPAD_LEFT => "Left",
+        DEVICE_ID_JOYPAD_RIGHT => "Right",
+        DEVICE_ID_JOYPAD_A => "A",
+        DEVICE_ID_JOYPAD_X => "X",
+        DEVICE_ID_JOYPAD_L => "L",
+        DEVICE_ID_JOYPAD_R => "R",
+        DEVICE_ID_JOYPAD_L2 => "L2",
+        DEVICE_ID_JOYPAD_R2 => "R2",
+        DEVICE_ID_JOYPAD_L3 => "L3",
+        DEVICE_ID_JOYPAD_R3 => "R3",
+        id => return format!("Input {}", id),
+    };
+    name.to_string()
+}
+
+/// The joypad buttons players can bind, by the name their config keys use, e.g.
+/// `input_player2_start` for a key and `input_player2_start_btn` for a gamepad button.
+pub const JOYPAD_BUTTONS: [(&str, u32); 16] = [
+    ("a", DEVICE_ID_JOYPAD_A),
+    ("b", DEVICE_ID_JOYPAD_B),
+    ("x", DEVICE_ID_JOYPAD_X),
+    ("y", DEVICE_ID_JOYPAD_Y),
+    ("l", DEVICE_ID_JOYPAD_L),
+    ("r", DEVICE_ID_JOYPAD_R),
+    ("down", DEVICE_ID_JOYPAD_DOWN),
+    ("up", DEVICE_ID_JOYPAD_UP),
+    ("right", DEVICE_ID_JOYPAD_RIGHT),
+    ("left", DEVICE_ID_JOYPAD_LEFT),
+    ("start", DEVICE_ID_JOYPAD_START),
+    ("select", DEVICE_ID_JOYPAD_SELECT),
+    ("l2", DEVICE_ID_JOYPAD_L2),
+    ("r2", DEVICE_ID_JOYPAD_R2),
+    ("l3", DEVICE_ID_JOYPAD_L3),
+    ("r3", DEVICE_ID_JOYPAD_R3),
+];
+
+/// Maps keyboard key names to the port and libretro device ID they press, from
+/// `input_player1_a` through `input_player5_select`. Keys left empty or set to
+/// "nul", as RetroArch does for players without a keyboard, are skipped.
+pub fn key_device_map(config: &HashMap<String, String>) -> HashMap<String, (usize, usize)> {
+    let mut map = HashMap::new();
+    for port in 0..gamepad::PORTS {
+        for (name, id) in JOYPAD_BUTTONS {
+            let key = config.get(&format!("input_player{}_{}", port + 1, name));
+            if let Some(key) = key.filter(|key| !key.is_empty() && *key != "nul") {
+                map.insert(key.clone(), (port, id as usize));
+            }
+        }
+    }
+    map
+}
+
+/// Sets up the mapping between gamepad buttons and libretro device IDs for each port.
+pub fn setup_joypad_device_map(config: &HashMap<String, String>) -> Vec<HashMap<String, usize>> {
+    (0..gamepad::PORTS)
+        .map(|port| joypad_device_map(config, &format!("input_player{}_", port + 1)))
+        .collect()
+}
+
+/// Maps gamepad buttons to libretro device IDs from the `<prefix><button>_btn` keys
+/// of a config or gamepad profile, using each ID's usual button where there is none.
+pub fn joypad_device_map(config: &HashMap<String, String>, prefix: &str) -> HashMap<String, usize> {
+    JOYPAD_BUTTONS
+        .iter()
+        .map(|&(name, id)| {
+            let button = config
+                .get(&format!("{}{}_btn", prefix, name))
+                .cloned()
+                .unwrap_or_else(|| id.to_string());
+            (button, id as usize)
+        })
+        .collect()
 }
 
+/// Callback function for polling input states. The main loop reads the devices right
+/// before each call to retro_run, so there is nothing left to poll here.
+pub unsafe extern "C" fn libretro_set_input_poll_callback() {}
+
 /// Retrieves the state of a specific input identified by libretro device IDs.
 pub unsafe extern "C" fn libretro_set_input_state_callback(
     port: libc::c_uint,
@@ -178,8 +225,334 @@ pub unsafe extern "C" fn libretro_set_input_state_callback(
     index: libc::c_uint,
     id: libc::c_uint,
 ) -> i16 {
+    // A movie being replayed answers in place of the devices
+    let state = match movie::play_input() {
+        Some(state) => state,
+        None if device == DEVICE_ANALOG => analog_state(port as usize, index, id),
+        None => button_state(port as usize, id),
+    };
+    movie::record(state);
+    state
+}
+
+/// Returns whether a joypad button is held, as 1 or 0, counting a stick that drives
+/// the D-pad and axes bound to the button.
+fn button_state(port: usize, id: u32) -> i16 {
+    let mappings = *DPAD_MAPPINGS.lock().unwrap();
+    let axis_pressed = AXIS_BINDINGS
+        .lock()
+        .unwrap()
+        .get(port)
+        .is_some_and(|bindings| {
+            let analog = ANALOG_STATE.lock().unwrap()[port];
+            bindings.iter().any(|&(bound, binding)| {
+                bound == id && axis_presses(&analog, binding, mappings.threshold)
+            })
+        });
+    let stick_pressed = mappings
+        .ports
+        .get(port)
+        .and_then(|mapping| mapping.stick_to_dpad)
+        .is_some_and(|stick| {
+            let sticks = ANALOG_STATE.lock().unwrap()[port].sticks;
+            stick_presses(sticks[stick], id, mappings.threshold)
+        });
+    held(port, id).max((stick_pressed || axis_pressed) as i16)
+}
+
+/// Returns whether a joypad button is held on the keyboard or gamepad, as 1 or 0.
+fn held(port: usize, id: u32) -> i16 {
     let buttons = BUTTONS_PRESSED.lock().unwrap();
-    buttons.0.get(id as usize).copied().unwrap_or(0)
+    buttons
+        .get(port)
+        .and_then(|buttons| buttons.get(id as usize))
+        .copied()
+        .unwrap_or(0)
+}
+
+/// RETRO_DEVICE_INDEX_ANALOG_BUTTON, which libretro-sys predates. Cores read how far
+/// a button is pressed through it, from 0 to 0x7fff.
+const DEVICE_INDEX_ANALOG_BUTTON: u32 = 2;
+
+/// The analog inputs of one port: the left and right sticks as x then y, from
+/// -0x8000 to 0x7fff with y growing downwards, and the L2 and R2 triggers from 0
+/// to 0x7fff.
+#[derive(Clone, Copy)]
+struct AnalogState {
+    sticks: [[i16; 2]; 2],
+    triggers: [i16; 2],
+}
+
+/// Sticks at rest and triggers released, as on a port without a gamepad.
+const ANALOG_AT_REST: AnalogState = AnalogState {
+    sticks: [[0; 2]; 2],
+    triggers: [0; 2],
+};
+
+static ANALOG_STATE: Mutex<[AnalogState; gamepad::PORTS]> =
+    Mutex::new([ANALOG_AT_REST; gamepad::PORTS]);
+
+/// Returns the bitmask of devices answered for ENVIRONMENT_GET_INPUT_DEVICE_CAPABILITIES.
+pub fn device_capabilities() -> u64 {
+    (1 << DEVICE_JOYPAD) | (1 << DEVICE_ANALOG)
+}
+
+/// Returns a stick axis or how far a button is pressed, for RETRO_DEVICE_ANALOG.
+fn analog_state(port: usize, index: u32, id: u32) -> i16 {
+    let Some(mut analog) = ANALOG_STATE.lock().unwrap().get(port).copied() else {
+        return 0;
+    };
+    if DPAD_MAPPINGS.lock().unwrap().ports[port].dpad_to_stick {
+        analog.sticks[0] = dpad_stick(port, analog.sticks[0]);
+    }
+    match (index, id) {
+        (
+            DEVICE_INDEX_ANALOG_LEFT | DEVICE_INDEX_ANALOG_RIGHT,
+            DEVICE_ID_ANALOG_X | DEVICE_ID_ANALOG_Y,
+        ) => analog.sticks[index as usize][id as usize],
+        (DEVICE_INDEX_ANALOG_BUTTON, DEVICE_ID_JOYPAD_L2) => analog.triggers[0],
+        (DEVICE_INDEX_ANALOG_BUTTON, DEVICE_ID_JOYPAD_R2) => analog.triggers[1],
+        // Other buttons are digital, so fully pressed or not at all
+        (DEVICE_INDEX_ANALOG_BUTTON, id) => button_state(port, id) * 0x7fff,
+        _ => 0,
+    }
+}
+
+/// How the D-pad and a stick stand in for each other on one port, for cores that
+/// only read one of them.
+#[derive(Clone, Copy)]
+struct DpadMapping {
+    /// The stick, 0 for left or 1 for right, that also presses the D-pad.
+    stick_to_dpad: Option<usize>,
+    /// Whether the D-pad also pushes the left stick.
+    dpad_to_stick: bool,
+}
+
+#[derive(Clone, Copy)]
+struct DpadMappings {
+    ports: [DpadMapping; gamepad::PORTS],
+    /// How far from rest, from 0 to 1, a stick presses the D-pad.
+    threshold: f32,
+}
+
+static DPAD_MAPPINGS: Mutex<DpadMappings> = Mutex::new(DpadMappings {
+    ports: [DpadMapping {
+        stick_to_dpad: None,
+        dpad_to_stick: false,
+    }; gamepad::PORTS],
+    threshold: 0.5,
+});
+
+/// Returns whether a stick is pushed past the threshold towards a D-pad button.
+fn stick_presses([x, y]: [i16; 2], id: u32, threshold: f32) -> bool {
+    let limit = (threshold * 32767.0) as i16;
+    match id {
+        DEVICE_ID_JOYPAD_LEFT => x < -limit,
+        DEVICE_ID_JOYPAD_RIGHT => x > limit,
+        DEVICE_ID_JOYPAD_UP => y < -limit,
+        DEVICE_ID_JOYPAD_DOWN => y > limit,
+        _ => false,
+    }
+}
+
+/// An axis that presses a joypad button, bound with `input_playerN_<button>_axis`
+/// as in RetroArch: the direction's sign then the axis, e.g. "+4". The axes are
+/// the left stick's x and y, the right stick's x and y, then the L2 and R2
+/// triggers, with y growing downwards.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct AxisBinding {
+    axis: usize,
+    positive: bool,
+}
+
+/// The number of axes bindings can name.
+const AXES: usize = 6;
+
+/// The axes bound to joypad buttons on each port, with the device ID each presses.
+static AXIS_BINDINGS: Mutex<Vec<Vec<(u32, AxisBinding)>>> = Mutex::new(Vec::new());
+
+/// Reads an axis binding, e.g. "+4" or "-1".
+fn parse_axis(binding: &str) -> Option<AxisBinding> {
+    let (positive, axis) = match binding.strip_prefix('+') {
+        Some(axis) => (true, axis),
+        None => (false, binding.strip_prefix('-')?),
+    };
+    let axis = axis.parse::<usize>().ok().filter(|&axis| axis < AXES)?;
+    Some(AxisBinding { axis, positive })
+}
+
+/// Returns whether an axis is pushed past the threshold in its binding's direction.
+fn axis_presses(analog: &AnalogState, binding: AxisBinding, threshold: f32) -> bool {
+    let value = match binding.axis {
+        axis @ 0..=3 => analog.sticks[axis / 2][axis % 2],
+        axis => analog.triggers[axis - 4],
+    } as f32
+        / 32767.0;
+    if binding.positive {
+        value > threshold
+    } else {
+        value < -threshold
+    }
+}
+
+/// Pushes a stick all the way along each axis the D-pad is held on, leaving the
+/// other axes as they are.
+fn dpad_stick(port: usize, stick: [i16; 2]) -> [i16; 2] {
+    let axis = |value: i16, negative: u32, positive: u32| match held(port, positive)
+        - held(port, negative)
+    {
+        0 => value,
+        direction => direction * 0x7fff,
+    };
+    [
+        axis(stick[0], DEVICE_ID_JOYPAD_LEFT, DEVICE_ID_JOYPAD_RIGHT),
+        axis(stick[1], DEVICE_ID_JOYPAD_UP, DEVICE_ID_JOYPAD_DOWN),
+    ]
+}
+
+/// How stick and trigger values are shaped before they reach the core.
+#[derive(Clone, Copy)]
+struct AnalogSettings {
+    /// How far from rest, from 0 to 1, is still taken as rest. Worn sticks never
+    /// quite settle on 0, which makes characters drift.
+    deadzone: f32,
+    /// What the value is multiplied by after the deadzone.
+    sensitivity: f32,
+    /// The power the value is raised to past the deadzone, 1 for a straight line
+    /// and more for finer control near the centre.
+    curve: f32,
+}
+
+static ANALOG_SETTINGS: Mutex<AnalogSettings> = Mutex::new(AnalogSettings {
+    deadzone: 0.0,
+    sensitivity: 1.0,
+    curve: 1.0,
+});
+
+/// Reads `input_analog_deadzone`, `input_analog_sensitivity` and
+/// `input_analog_curve`, which is linear, quadratic or cubic, and for each player
+/// `input_playerN_analog_dpad_mode`, 1 or 2 for the left or right stick to press
+/// the D-pad as in RetroArch, `input_playerN_dpad_to_analog` for the D-pad to
+/// push the left stick, and the axes bound to buttons.
+pub fn configure(config: &HashMap<String, String>) {
+    let number = |key: &str, default: f32| {
+        config
+            .get(key)
+            .and_then(|value| value.parse::<f32>().ok())
+            .unwrap_or(default)
+    };
+    let curve = match config.get("input_analog_curve").map(String::as_str) {
+        None | Some("linear") => 1.0,
+        Some("quadratic") => 2.0,
+        Some("cubic") => 3.0,
+        Some(other) => {
+            println!(
+                "Unknown input_analog_curve {}, expected linear, quadratic or cubic",
+                other
+            );
+            1.0
+        }
+    };
+    *ANALOG_SETTINGS.lock().unwrap() = AnalogSettings {
+        deadzone: number("input_analog_deadzone", 0.0).clamp(0.0, 0.99),
+        sensitivity: number("input_analog_sensitivity", 1.0).max(0.0),
+        curve,
+    };
+
+    let mut mappings = DPAD_MAPPINGS.lock().unwrap();
+    mappings.threshold = number("input_axis_threshold", 0.5).clamp(0.01, 1.0);
+    for (port, mapping) in mappings.ports.iter_mut().enumerate() {
+        let player = format!("input_player{}", port + 1);
+        let mode = format!("{}_analog_dpad_mode", player);
+        mapping.stick_to_dpad = match config.get(&mode).map(String::as_str) {
+            None | Some("0") => None,
+            Some("1") => Some(0),
+            Some("2") => Some(1),
+            Some(other) => {
+                println!("Unknown {} {}, expected 0, 1 or 2", mode, other);
+                None
+            }
+        };
+        mapping.dpad_to_stick = config
+            .get(&format!("{}_dpad_to_analog", player))
+            .is_some_and(|value| value == "true");
+    }
+
+    *AXIS_BINDINGS.lock().unwrap() = (0..gamepad::PORTS)
+        .map(|port| {
+            JOYPAD_BUTTONS
+                .iter()
+                .filter_map(|&(name, id)| {
+                    let key = format!("input_player{}_{}_axis", port + 1, name);
+                    let value = config
+                        .get(&key)
+                        .filter(|value| !value.is_empty() && *value != "nul")?;
+                    let binding = parse_axis(value);
+                    if binding.is_none() {
+                        println!("Unknown {} {}, expected e.g. +4 or -1", key, value);
+                    }
+                    binding.map(|binding| (id, binding))
+                })
+                .collect()
+        })
+        .collect();
+}
+
+/// Shapes how far a stick or trigger is pushed, from 0 to 1: nothing within the
+/// deadzone, then rising from 0 along the curve and scaled by the sensitivity.
+fn shape(magnitude: f32, settings: &AnalogSettings) -> f32 {
+    if magnitude <= settings.deadzone {
+        return 0.0;
+    }
+    let past_deadzone = (magnitude - settings.deadzone) / (1.0 - settings.deadzone);
+    (past_deadzone.min(1.0).powf(settings.curve) * settings.sensitivity).min(1.0)
+}
+
+/// Shapes a stick by how far it is pushed in any direction, keeping its direction,
+/// so the deadzone is a circle rather than a cross that snaps diagonals to the axes.
+fn shape_stick(x: f32, y: f32, settings: &AnalogSettings) -> [f32; 2] {
+    let magnitude = x.hypot(y);
+    if magnitude == 0.0 {
+        return [0.0, 0.0];
+    }
+    let scale = shape(magnitude, settings) / magnitude;
+    [x * scale, y * scale]
+}
+
+/// Converts a gilrs axis or button value to libretro's range.
+fn to_analog(value: f32) -> i16 {
+    (value * 32767.0).round().clamp(-32768.0, 32767.0) as i16
+}
+
+/// Samples the sticks and triggers of the gamepad on `port`, or puts them at rest
+/// if it has none.
+pub fn handle_gamepad_analog(gilrs: &Gilrs, gamepad: Option<GamepadId>, port: usize) {
+    let analog = match gamepad.map(|id| gilrs.gamepad(id)) {
+        Some(gamepad) => {
+            let settings = *ANALOG_SETTINGS.lock().unwrap();
+            let stick = |x: Axis, y: Axis| {
+                let [x, y] = shape_stick(gamepad.value(x), gamepad.value(y), &settings);
+                // gilrs has y grow upwards, libretro downwards
+                [to_analog(x), to_analog(-y)]
+            };
+            let trigger = |button: Button| {
+                let value = gamepad.button_data(button).map_or(0.0, |data| data.value());
+                to_analog(shape(value, &settings))
+            };
+            AnalogState {
+                sticks: [
+                    stick(Axis::LeftStickX, Axis::LeftStickY),
+                    stick(Axis::RightStickX, Axis::RightStickY),
+                ],
+                triggers: [
+                    trigger(Button::LeftTrigger2),
+                    trigger(Button::RightTrigger2),
+                ],
+            }
+        }
+        None => ANALOG_AT_REST,
+    };
+    ANALOG_STATE.lock().unwrap()[port] = analog;
 }
 
 /// Converts a libretro device ID to the corresponding gilrs Button.
@@ -197,11 +570,55 @@ fn libretro_to_button(libretro_button: u32) -> Option<Button> {
         DEVICE_ID_JOYPAD_LEFT => Some(Button::DPadLeft),
         DEVICE_ID_JOYPAD_START => Some(Button::Start),
         DEVICE_ID_JOYPAD_SELECT => Some(Button::Select),
+        DEVICE_ID_JOYPAD_L2 => Some(Button::LeftTrigger2),
+        DEVICE_ID_JOYPAD_R2 => Some(Button::RightTrigger2),
+        DEVICE_ID_JOYPAD_L3 => Some(Button::LeftThumb),
+        DEVICE_ID_JOYPAD_R3 => Some(Button::RightThumb),
         _ => None,
     }
 }
 
-/// Processes gamepad inputs and updates button states.
+/// The gamepad buttons bindings can name.
+const GAMEPAD_BUTTONS: [Button; 19] = [
+    Button::South,
+    Button::East,
+    Button::North,
+    Button::West,
+    Button::C,
+    Button::Z,
+    Button::LeftTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::Mode,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+/// Returns the name a gamepad button goes by in `input_player1_a_btn` and the like,
+/// e.g. "south" or "dpadup".
+pub fn button_name(button: Button) -> String {
+    format!("{:?}", button).to_ascii_lowercase()
+}
+
+/// Reads a gamepad button binding, either a button's name or, as the defaults are,
+/// the libretro device ID whose usual button it is.
+pub fn parse_button(binding: &str) -> Option<Button> {
+    match binding.parse::<u32>() {
+        Ok(id) => libretro_to_button(id),
+        Err(_) => GAMEPAD_BUTTONS
+            .into_iter()
+            .find(|&button| button_name(button) == binding),
+    }
+}
+
+/// Processes gamepad inputs, pressing the buttons held on the gamepad.
 pub fn handle_gamepad_input(
     joypad_device_map: &HashMap<String, usize>,
     gilrs: &Gilrs,
@@ -210,96 +627,204 @@ pub fn handle_gamepad_input(
 ) {
     if let Some(gamepad) = active_gamepad.map(|id| gilrs.gamepad(id)) {
         for (button, libretro_button) in joypad_device_map {
-            if let Some(gilrs_button) = libretro_to_button(*libretro_button as u32) {
-                buttons_pressed[*libretro_button as usize] =
-                    gamepad.is_pressed(gilrs_button) as i16;
+            if let Some(gilrs_button) = parse_button(button) {
+                if gamepad.is_pressed(gilrs_button) {
+                    buttons_pressed[*libretro_button as usize] = 1;
+                }
             }
         }
     }
 }
 
-/// Processes keyboard inputs, updates button states, and handles special input actions.
+/// Releases every button but the one mapped from `kept`, which is held.
+pub fn release_all_but(
+    joypad_device_map: &HashMap<String, usize>,
+    kept: Button,
+    buttons_pressed: &mut [i16],
+) {
+    for (button, &libretro_button) in joypad_device_map {
+        if parse_button(button) != Some(kept) {
+            buttons_pressed[libretro_button] = 0;
+        }
+    }
+    for (button, &libretro_button) in joypad_device_map {
+        if parse_button(button) == Some(kept) {
+            buttons_pressed[libretro_button] = 1;
+        }
+    }
+}
+
+/// Presses the buttons held on the keyboard into those held on the gamepads.
+pub fn merge_buttons(buttons_pressed: &mut [Vec<i16>], keyboard_buttons: &[Vec<i16>]) {
+    for (buttons, keys) in buttons_pressed.iter_mut().zip(keyboard_buttons) {
+        for (button, key) in buttons.iter_mut().zip(keys) {
+            *button |= key;
+        }
+    }
+}
+
+/// Processes keyboard inputs, updates the buttons held through the keyboard, and
+/// handles special input actions. The buttons are read from the keys held down
+/// every frame rather than kept from presses and releases, which can be missed,
+/// e.g. when a key is let go while the window is out of focus.
 pub fn handle_keyboard_input(
     core_api: &CoreAPI,
-    window: &Window,
+    window: &mut dyn Window,
     current_state: &mut EmulatorState,
-    buttons_pressed: &mut Vec<i16>,
-    key_device_map: &HashMap<String, usize>,
+    keyboard_buttons: &mut [Vec<i16>],
+    key_device_map: &HashMap<String, (usize, usize)>,
     config: &HashMap<String, String>,
-    game_pad_active: bool,
 ) {
     let mini_fb_keys_pressed = window.get_keys_pressed(KeyRepeat::No);
     for key in mini_fb_keys_pressed {
         let key_as_string = format!("{:?}", key).to_ascii_lowercase();
 
-        if !game_pad_active {
-            if let Some(&device_id) = key_device_map.get(&key_as_string) {
-                buttons_pressed[device_id as usize] = 1;
-            }
+        // While rebinding, keys are taken as bindings rather than pressed
+        if remap::is_capturing() {
+            remap::capture_key(&key_as_string, &config["input_remap"]);
+            continue;
         }
 
-        if &key_as_string == &config["input_save_state"] {
-            unsafe {
-                libretro::save_state(
-                    &core_api,
-                    &config["savestate_directory"],
-                    &current_state.rom_name,
-                    &current_state.current_save_slot,
-                );
-            } // f2
+        // Alt+Enter also toggles fullscreen, and doesn't press the button Enter is bound to
+        if key == Key::Enter
+            && (window.is_key_down(Key::LeftAlt) || window.is_key_down(Key::RightAlt))
+        {
+            window::toggle_fullscreen(window);
             continue;
         }
-        if &key_as_string == &config["input_load_state"] {
-            unsafe {
-                libretro::load_state(
-                    &core_api,
-                    &config["savestate_directory"],
-                    &current_state.rom_name,
-                    &current_state.current_save_slot,
-                );
-            } // f4
-            continue;
+
+        match hotkeys::find(config, &key_as_string) {
+            Some(hotkey) => hotkeys::run(hotkey, core_api, window, current_state, config),
+            None => println!("Unhandled Key Pressed: {} ", key_as_string),
         }
-        if &key_as_string == &config["input_state_slot_increase"] {
-            if current_state.current_save_slot != 255 {
-                current_state.current_save_slot += 1;
-                println!(
-                    "Current save slot increased to: {}",
-                    current_state.current_save_slot
-                );
-            }
+    }
 
+    for buttons in keyboard_buttons.iter_mut() {
+        buttons.fill(0);
+    }
+    // Keys being taken as bindings don't press anything
+    if remap::is_capturing() {
+        return;
+    }
+    let alt_held = window.is_key_down(Key::LeftAlt) || window.is_key_down(Key::RightAlt);
+    for key in window.get_keys() {
+        if key == Key::Enter && alt_held {
             continue;
         }
+        let key_as_string = format!("{:?}", key).to_ascii_lowercase();
+        if let Some(&(port, device_id)) = key_device_map.get(&key_as_string) {
+            keyboard_buttons[port][device_id] = 1;
+        }
+    }
+}
 
-        if &key_as_string == &config["input_state_slot_decrease"] {
-            if current_state.current_save_slot != 0 {
-                current_state.current_save_slot -= 1;
-                println!(
-                    "Current save slot decreased to: {}",
-                    current_state.current_save_slot
-                );
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            continue;
-        }
+    #[test]
+    fn reads_gamepad_button_bindings() {
+        assert_eq!(parse_button("dpadleft"), Some(Button::DPadLeft));
+        assert_eq!(
+            parse_button(&button_name(Button::LeftTrigger2)),
+            Some(Button::LeftTrigger2)
+        );
+        assert_eq!(
+            parse_button(&DEVICE_ID_JOYPAD_A.to_string()),
+            Some(Button::East)
+        );
+        assert_eq!(parse_button("turbo"), None);
+    }
+
+    #[test]
+    fn axes_press_buttons_past_the_threshold() {
+        assert_eq!(
+            parse_axis("+4"),
+            Some(AxisBinding {
+                axis: 4,
+                positive: true
+            })
+        );
+        assert_eq!(
+            parse_axis("-1"),
+            Some(AxisBinding {
+                axis: 1,
+                positive: false
+            })
+        );
+        assert_eq!(parse_axis("4"), None);
+        assert_eq!(parse_axis("+6"), None);
 
-        println!("Unhandled Key Pressed: {} ", key_as_string);
+        let analog = AnalogState {
+            sticks: [[0, -20000], [0, 0]],
+            triggers: [30000, 10000],
+        };
+        assert!(axis_presses(&analog, parse_axis("+4").unwrap(), 0.5));
+        assert!(!axis_presses(&analog, parse_axis("+5").unwrap(), 0.5));
+        assert!(axis_presses(&analog, parse_axis("-1").unwrap(), 0.5));
+        assert!(!axis_presses(&analog, parse_axis("+1").unwrap(), 0.5));
     }
 
-    if !game_pad_active {
-        let mini_fb_keys_released = window.get_keys_released();
-        for key in &mini_fb_keys_released {
-            let key_as_string = format!("{:?}", key).to_ascii_lowercase();
-
-            if let Some(&device_id) = key_device_map.get(&key_as_string) {
-                buttons_pressed[device_id as usize] = 0;
-            } else {
-                println!(
-                    "Unhandled Key Pressed: {} input_player1_a: {}",
-                    key_as_string, config["input_player1_a"]
-                );
-            }
-        }
+    #[test]
+    fn merges_keyboard_and_gamepad_buttons() {
+        let mut buttons = vec![vec![1, 0, 0], vec![0, 0, 0]];
+        merge_buttons(&mut buttons, &[vec![0, 1, 0], vec![0, 0, 1]]);
+        assert_eq!(buttons, [[1, 1, 0], [0, 0, 1]]);
+    }
+
+    #[test]
+    fn keeps_only_the_enable_button_held() {
+        let map = HashMap::from([
+            ("select".to_string(), 2),
+            ("start".to_string(), 3),
+            ("south".to_string(), 0),
+        ]);
+        let mut buttons = vec![1, 0, 1, 1];
+        release_all_but(&map, Button::Select, &mut buttons);
+        assert_eq!(buttons, [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn shapes_analog_values() {
+        let settings = AnalogSettings {
+            deadzone: 0.2,
+            sensitivity: 1.0,
+            curve: 2.0,
+        };
+        // Within the deadzone is rest, and past it the curve starts from 0
+        assert_eq!(shape_stick(0.1, -0.1, &settings), [0.0, 0.0]);
+        assert!((shape(0.6, &settings) - 0.25).abs() < 1e-6);
+        assert_eq!(shape(1.0, &settings), 1.0);
+
+        // A stick keeps its direction
+        let [x, y] = shape_stick(0.6, 0.8, &settings);
+        assert!((x - 0.6).abs() < 1e-6 && (y - 0.8).abs() < 1e-6);
+        let [x, y] = shape_stick(0.36, 0.48, &settings);
+        assert!((x / y - 0.75).abs() < 1e-6 && (x.hypot(y) - 0.25).abs() < 1e-6);
+
+        // Sensitivity scales but never goes past fully pushed
+        let sensitive = AnalogSettings {
+            sensitivity: 2.0,
+            ..settings
+        };
+        assert!((shape(0.6, &sensitive) - 0.5).abs() < 1e-6);
+        assert_eq!(shape(0.9, &sensitive), 1.0);
+    }
+
+    #[test]
+    fn sticks_and_d_pads_stand_in_for_each_other() {
+        assert!(stick_presses([-20000, 0], DEVICE_ID_JOYPAD_LEFT, 0.5));
+        assert!(!stick_presses([-10000, 0], DEVICE_ID_JOYPAD_LEFT, 0.5));
+        assert!(stick_presses([20000, 20000], DEVICE_ID_JOYPAD_DOWN, 0.5));
+        assert!(!stick_presses([20000, 20000], DEVICE_ID_JOYPAD_UP, 0.5));
+        assert!(!stick_presses([32767, 0], DEVICE_ID_JOYPAD_A, 0.5));
+
+        // Port 3 is left alone by the other tests
+        let port = 3;
+        BUTTONS_PRESSED.lock().unwrap()[port][DEVICE_ID_JOYPAD_LEFT as usize] = 1;
+        assert_eq!(dpad_stick(port, [100, 200]), [-0x7fff, 200]);
+        BUTTONS_PRESSED.lock().unwrap()[port][DEVICE_ID_JOYPAD_RIGHT as usize] = 1;
+        assert_eq!(dpad_stick(port, [100, 200]), [100, 200]);
+        BUTTONS_PRESSED.lock().unwrap()[port].fill(0);
     }
 }
@@ -0,0 +1,244 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// keyboard.rs
+//
+// This module implements ENVIRONMENT_SET_KEYBOARD_CALLBACK. Computer cores such as
+// DOSBox, VICE or ScummVM want raw keyboard events rather than a joypad, so key
+// presses and releases from the window are translated to RETROK codes and sent to
+// the core along with the character typed and the modifiers held.
+
+use libretro_sys::{Key as RetroKey, KeyboardCallback, KeyboardEventFn, Mod};
+use minifb::{Key, KeyRepeat};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+use crate::window::Window;
+
+// Keyboard callback registered by the core.
+static KEYBOARD_CALLBACK: Lazy<Mutex<Option<KeyboardEventFn>>> = Lazy::new(|| Mutex::new(None));
+
+// Handles ENVIRONMENT_SET_KEYBOARD_CALLBACK.
+pub unsafe fn set_keyboard_callback(callback: *const KeyboardCallback) -> bool {
+    let Some(callback) = callback.as_ref() else {
+        return false;
+    };
+    *KEYBOARD_CALLBACK.lock().unwrap() = Some(callback.callback);
+    true
+}
+
+// Sends the key presses and releases since the last frame to the core. Must be
+// called before retro_run.
+pub fn update(window: &dyn Window) {
+    let Some(callback) = *KEYBOARD_CALLBACK.lock().unwrap() else {
+        return;
+    };
+    let held = window.get_keys();
+    let modifiers = modifiers(&held);
+    let shift = modifiers & Mod::Shift as u16 != 0;
+    let caps_lock = modifiers & Mod::Capslock as u16 != 0;
+
+    for key in window.get_keys_pressed(KeyRepeat::Yes) {
+        let character = character(key, shift, caps_lock).map_or(0, |c| c as u32);
+        unsafe { callback(true, retro_key(key) as libc::c_uint, character, modifiers) };
+    }
+    for key in window.get_keys_released() {
+        unsafe { callback(false, retro_key(key) as libc::c_uint, 0, modifiers) };
+    }
+}
+
+// Works out the RETROKMOD flags from the keys being held. minifb doesn't report
+// whether the lock keys are on, so those flags are only set while the key is held.
+fn modifiers(held: &[Key]) -> u16 {
+    held.iter().fold(Mod::None as u16, |modifiers, key| {
+        modifiers
+            | match key {
+                Key::LeftShift | Key::RightShift => Mod::Shift as u16,
+                Key::LeftCtrl | Key::RightCtrl => Mod::Ctrl as u16,
+                Key::LeftAlt | Key::RightAlt => Mod::Alt as u16,
+                Key::LeftSuper | Key::RightSuper => Mod::Meta as u16,
+                Key::NumLock => Mod::Numlock as u16,
+                Key::CapsLock => Mod::Capslock as u16,
+                Key::ScrollLock => Mod::Scrollock as u16,
+                _ => Mod::None as u16,
+            }
+    })
+}
+
+// Returns the character a key types on a US layout, if any.
+fn character(key: Key, shift: bool, caps_lock: bool) -> Option<char> {
+    let (normal, shifted) = match key {
+        Key::Key0 => ('0', ')'),
+        Key::Key1 => ('1', '!'),
+        Key::Key2 => ('2', '@'),
+        Key::Key3 => ('3', '#'),
+        Key::Key4 => ('4', '$'),
+        Key::Key5 => ('5', '%'),
+        Key::Key6 => ('6', '^'),
+        Key::Key7 => ('7', '&'),
+        Key::Key8 => ('8', '*'),
+        Key::Key9 => ('9', '('),
+        Key::Apostrophe => ('\'', '"'),
+        Key::Backquote => ('`', '~'),
+        Key::Backslash => ('\\', '|'),
+        Key::Comma => (',', '<'),
+        Key::Equal => ('=', '+'),
+        Key::LeftBracket => ('[', '{'),
+        Key::Minus => ('-', '_'),
+        Key::Period => ('.', '>'),
+        Key::RightBracket => (']', '}'),
+        Key::Semicolon => (';', ':'),
+        Key::Slash => ('/', '?'),
+        Key::Space => (' ', ' '),
+        Key::Tab => ('\t', '\t'),
+        Key::Enter | Key::NumPadEnter => ('\r', '\r'),
+        Key::Backspace => ('\u{8}', '\u{8}'),
+        Key::NumPad0 => ('0', '0'),
+        Key::NumPad1 => ('1', '1'),
+        Key::NumPad2 => ('2', '2'),
+        Key::NumPad3 => ('3', '3'),
+        Key::NumPad4 => ('4', '4'),
+        Key::NumPad5 => ('5', '5'),
+        Key::NumPad6 => ('6', '6'),
+        Key::NumPad7 => ('7', '7'),
+        Key::NumPad8 => ('8', '8'),
+        Key::NumPad9 => ('9', '9'),
+        Key::NumPadDot => ('.', '.'),
+        Key::NumPadSlash => ('/', '/'),
+        Key::NumPadAsterisk => ('*', '*'),
+        Key::NumPadMinus => ('-', '-'),
+        Key::NumPadPlus => ('+', '+'),
+        _ => {
+            // Letters follow Caps Lock as well as Shift
+            let letter = letter(key)?;
+            return Some(if shift != caps_lock {
+                letter.to_ascii_uppercase()
+            } else {
+                letter
+            });
+        }
+    };
+    Some(if shift { shifted } else { normal })
+}
+
+// Returns the lowercase letter for a letter key.
+fn letter(key: Key) -> Option<char> {
+    let index = key as u8;
+    (Key::A as u8..=Key::Z as u8)
+        .contains(&index)
+        .then(|| (b'a' + index - Key::A as u8) as char)
+}
+
+// Translates a minifb key to its RETROK code.
+fn retro_key(key: Key) -> RetroKey {
+    match key {
+        Key::Key0 => RetroKey::Number_0,
+        Key::Key1 => RetroKey::Number_1,
+        Key::Key2 => RetroKey::Number_2,
+        Key::Key3 => RetroKey::Number_3,
+        Key::Key4 => RetroKey::Number_4,
+        Key::Key5 => RetroKey::Number_5,
+        Key::Key6 => RetroKey::Number_6,
+        Key::Key7 => RetroKey::Number_7,
+        Key::Key8 => RetroKey::Number_8,
+        Key::Key9 => RetroKey::Number_9,
+        Key::A => RetroKey::A,
+        Key::B => RetroKey::B,
+        Key::C => RetroKey::C,
+        Key::D => RetroKey::D,
+        Key::E => RetroKey::E,
+        Key::F => RetroKey::F,
+        Key::G => RetroKey::G,
+        Key::H => RetroKey::H,
+        Key::I => RetroKey::I,
+        Key::J => RetroKey::J,
+        Key::K => RetroKey::K,
+        Key::L => RetroKey::L,
+        Key::M => RetroKey::M,
+        Key::N => RetroKey::N,
+        Key::O => RetroKey::O,
+        Key::P => RetroKey::P,
+        Key::Q => RetroKey::Q,
+        Key::R => RetroKey::R,
+        Key::S => RetroKey::S,
+        Key::T => RetroKey::T,
+        Key::U => RetroKey::U,
+        Key::V => RetroKey::V,
+        Key::W => RetroKey::W,
+        Key::X => RetroKey::X,
+        Key::Y => RetroKey::Y,
+        Key::Z => RetroKey::Z,
+        Key::F1 => RetroKey::F1,
+        Key::F2 => RetroKey::F2,
+        Key::F3 => RetroKey::F3,
+        Key::F4 => RetroKey::F4,
+        Key::F5 => RetroKey::F5,
+        Key::F6 => RetroKey::F6,
+        Key::F7 => RetroKey::F7,
+        Key::F8 => RetroKey::F8,
+        Key::F9 => RetroKey::F9,
+        Key::F10 => RetroKey::F10,
+        Key::F11 => RetroKey::F11,
+        Key::F12 => RetroKey::F12,
+        Key::F13 => RetroKey::F13,
+        Key::F14 => RetroKey::F14,
+        Key::F15 => RetroKey::F15,
+        Key::Down => RetroKey::Down,
+        Key::Left => RetroKey::Left,
+        Key::Right => RetroKey::Right,
+        Key::Up => RetroKey::Up,
+        Key::Apostrophe => RetroKey::Quote,
+        Key::Backquote => RetroKey::Backquote,
+        Key::Backslash => RetroKey::Backslash,
+        Key::Comma => RetroKey::Comma,
+        Key::Equal => RetroKey::Equals,
+        Key::LeftBracket => RetroKey::LeftBracket,
+        Key::Minus => RetroKey::Minus,
+        Key::Period => RetroKey::Period,
+        Key::RightBracket => RetroKey::RightBracket,
+        Key::Semicolon => RetroKey::Semicolon,
+        Key::Slash => RetroKey::Slash,
+        Key::Backspace => RetroKey::Backspace,
+        Key::Delete => RetroKey::Delete,
+        Key::End => RetroKey::End,
+        Key::Enter => RetroKey::Return,
+        Key::Escape => RetroKey::Escape,
+        Key::Home => RetroKey::Home,
+        Key::Insert => RetroKey::Insert,
+        Key::Menu => RetroKey::Menu,
+        Key::PageDown => RetroKey::PageDown,
+        Key::PageUp => RetroKey::PageUp,
+        Key::Pause => RetroKey::Pause,
+        Key::Space => RetroKey::Space,
+        Key::Tab => RetroKey::Tab,
+        Key::NumLock => RetroKey::Numlock,
+        Key::CapsLock => RetroKey::Capslock,
+        Key::ScrollLock => RetroKey::Scrollock,
+        Key::LeftShift => RetroKey::LShift,
+        Key::RightShift => RetroKey::RShift,
+        Key::LeftCtrl => RetroKey::LCtrl,
+        Key::RightCtrl => RetroKey::RCtrl,
+        Key::NumPad0 => RetroKey::Kp0,
+        Key::NumPad1 => RetroKey::Kp1,
+        Key::NumPad2 => RetroKey::Kp2,
+        Key::NumPad3 => RetroKey::Kp3,
+        Key::NumPad4 => RetroKey::Kp4,
+        Key::NumPad5 => RetroKey::Kp5,
+        Key::NumPad6 => RetroKey::Kp6,
+        Key::NumPad7 => RetroKey::Kp7,
+        Key::NumPad8 => RetroKey::Kp8,
+        Key::NumPad9 => RetroKey::Kp9,
+        Key::NumPadDot => RetroKey::KpPeriod,
+        Key::NumPadSlash => RetroKey::KpDivide,
+        Key::NumPadAsterisk => RetroKey::KpMultiply,
+        Key::NumPadMinus => RetroKey::KpMinus,
+        Key::NumPadPlus => RetroKey::KpPlus,
+        Key::NumPadEnter => RetroKey::KpEnter,
+        Key::LeftAlt => RetroKey::LAlt,
+        Key::RightAlt => RetroKey::RAlt,
+        Key::LeftSuper => RetroKey::LSuper,
+        Key::RightSuper => RetroKey::RSuper,
+        _ => RetroKey::Unknown,
+    }
+}
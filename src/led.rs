@@ -0,0 +1,129 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// led.rs
+//
+// This module implements the libretro LED interface, which cores like MAME use for
+// power and disk activity lights. Lit LEDs are drawn as small squares in the top
+// right corner of the window, and on Linux the first three can also drive the
+// keyboard's Num Lock, Caps Lock and Scroll Lock lights.
+
+use libc::c_int;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+// Number of LEDs tracked; cores with more than this have the rest ignored.
+const MAX_LEDS: c_int = 32;
+
+// Size of an indicator and the gap around it, before scaling with the window.
+const INDICATOR_SIZE: usize = 4;
+const INDICATOR_GAP: usize = 2;
+const INDICATOR_COLOR: u32 = 0x20E020;
+
+// struct retro_led_interface, which libretro-sys predates.
+#[repr(C)]
+pub struct LedInterface {
+    pub set_led_state: unsafe extern "C" fn(led: c_int, state: c_int),
+}
+
+// One bit per LED, set while it is lit, and how many LEDs the core has used.
+static LED_STATE: AtomicU32 = AtomicU32::new(0);
+static LED_COUNT: AtomicU32 = AtomicU32::new(0);
+
+// Whether LED changes are mirrored to the keyboard, from `led_keyboard_enable`.
+static KEYBOARD_LEDS: AtomicBool = AtomicBool::new(false);
+
+// Builds the interface returned for ENVIRONMENT_GET_LED_INTERFACE.
+pub fn led_interface() -> LedInterface {
+    LedInterface { set_led_state }
+}
+
+// Reads `led_keyboard_enable` from the config.
+pub fn configure(config: &HashMap<String, String>) {
+    let enabled = config
+        .get("led_keyboard_enable")
+        .is_some_and(|value| value == "true");
+    KEYBOARD_LEDS.store(enabled, Ordering::SeqCst);
+}
+
+unsafe extern "C" fn set_led_state(led: c_int, state: c_int) {
+    if !(0..MAX_LEDS).contains(&led) {
+        return;
+    }
+    let bit = 1 << led;
+    let previous = if state != 0 {
+        LED_STATE.fetch_or(bit, Ordering::SeqCst)
+    } else {
+        LED_STATE.fetch_and(!bit, Ordering::SeqCst)
+    };
+    LED_COUNT.fetch_max(led as u32 + 1, Ordering::SeqCst);
+
+    if KEYBOARD_LEDS.load(Ordering::SeqCst) && (previous & bit != 0) != (state != 0) {
+        keyboard::set_led(led as usize, state != 0);
+    }
+}
+
+// Draws one square per LED the core has used in the top right corner of the
+// window buffer, filled while the LED is lit.
+pub fn draw(buffer: &mut [u32], width: usize, height: usize) {
+    let count = LED_COUNT.load(Ordering::SeqCst) as usize;
+    let state = LED_STATE.load(Ordering::SeqCst);
+    let scale = (height / 240).max(1);
+    let size = INDICATOR_SIZE * scale;
+    let step = size + INDICATOR_GAP * scale;
+    let top = INDICATOR_GAP * scale;
+    for led in 0..count {
+        let Some(left) = width.checked_sub(step * (count - led)) else {
+            continue;
+        };
+        let lit = state & (1 << led) != 0;
+        for y in 0..size.min(height.saturating_sub(top)) {
+            for x in 0..size {
+                // Unlit LEDs are drawn as an outline so it's clear they exist
+                let edge = x == 0 || y == 0 || x == size - 1 || y == size - 1;
+                if lit || edge {
+                    buffer[(top + y) * width + left + x] = INDICATOR_COLOR;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod keyboard {
+    use std::fs;
+
+    // Keyboard lights driven by LEDs 0, 1 and 2, as named in /sys/class/leds.
+    const KEYBOARD_LEDS: [&str; 3] = ["numlock", "capslock", "scrolllock"];
+
+    // Switches the matching light on every keyboard. Writing to sysfs usually needs
+    // extra permissions, so failures are reported but otherwise ignored.
+    pub fn set_led(led: usize, lit: bool) {
+        let Some(name) = KEYBOARD_LEDS.get(led) else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir("/sys/class/leds") else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(&format!("::{}", name))
+            {
+                continue;
+            }
+            let brightness = entry.path().join("brightness");
+            if let Err(err) = fs::write(&brightness, if lit { "1" } else { "0" }) {
+                println!("Failed to set {}: {}", brightness.display(), err);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod keyboard {
+    // Keyboard lights can only be driven on Linux.
+    pub fn set_led(_led: usize, _lit: bool) {}
+}
@@ -7,16 +7,23 @@
 // This module provides the interface to the libretro core, including functions for
 // loading ROMs, managing save states, and handling configurations.
 
-use crate::PIXEL_FORMAT_CHANNEL;
+use crate::core_options::CORE_OPTIONS;
+use crate::environment;
+use crate::input::InputDescriptor;
+use crate::osd;
 use crate::video;
+use crate::AV_INFO_CHANNEL;
 use clap::Parser;
 use libc::c_void;
 use libloading::Library;
-use libretro_sys::GameInfo;
-use libretro_sys::{CoreAPI, GameGeometry, PixelFormat, SystemAvInfo, SystemTiming};
-use std::ffi::CString;
+use libretro_sys::{CoreAPI, GameGeometry, GameInfo, SystemAvInfo, SystemInfo, SystemTiming};
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, CString};
 use std::fs;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
 use std::{
     collections::HashMap,
     env,
@@ -28,15 +35,110 @@ use std::{
 // Expected version of the libretro API.
 const EXPECTED_LIB_RETRO_VERSION: u32 = 1;
 
+// Names of the retro_language values, indexed by ID. libretro-sys predates Portuguese
+// being split in two, so its Language enum is out of step after Dutch.
+const LANGUAGES: &[&str] = &[
+    "english",
+    "japanese",
+    "french",
+    "spanish",
+    "german",
+    "italian",
+    "dutch",
+    "portuguese_brazil",
+    "portuguese_portugal",
+    "russian",
+    "korean",
+    "chinese_traditional",
+    "chinese_simplified",
+    "esperanto",
+    "polish",
+    "vietnamese",
+    "arabic",
+    "greek",
+    "turkish",
+    "slovak",
+    "persian",
+    "hebrew",
+    "asturian",
+    "finnish",
+    "indonesian",
+    "swedish",
+    "ukrainian",
+    "czech",
+    "catalan_valencia",
+    "catalan",
+    "british_english",
+    "hungarian",
+    "belarusian",
+    "galician",
+    "norwegian",
+];
+
+// Location of the frontend's own configuration file.
+const RUSTROARCH_CONFIG_PATH: &str = "./rustroarch.cfg";
+
+// Frontend settings the environment callback hands out to the core. Strings are kept
+// as CStrings here so the pointers given to the core stay valid after the call returns.
+pub struct EnvironmentSettings {
+    pub system_directory: CString,
+    pub core_assets_directory: CString,
+    pub username: Option<CString>,
+    pub language: u32,
+}
+
+pub static ENVIRONMENT_SETTINGS: Lazy<Mutex<EnvironmentSettings>> = Lazy::new(|| {
+    Mutex::new(EnvironmentSettings {
+        system_directory: CString::default(),
+        core_assets_directory: CString::default(),
+        username: None,
+        language: 0,
+    })
+});
+
 // Represents the emulator state and configuration.
 #[derive(Parser)]
 pub struct EmulatorState {
     // Path to the ROM file to be loaded.
-    #[arg(help = "Sets the path to the ROM file to load", index = 1)]
+    #[arg(
+        help = "Sets the path to the ROM file to load, if the core needs one",
+        index = 1,
+        default_value = ""
+    )]
     pub rom_name: String,
     #[arg(short = 'L', default_value = "default_library")]
     // Name of the core library to be loaded.
     pub library_name: String,
+    // Core option values to apply at launch, given as key=value.
+    #[arg(long = "core-option", value_name = "KEY=VALUE")]
+    pub core_option_overrides: Vec<String>,
+    // Persist changed core options to a per-game override file instead of the per-core one.
+    #[arg(long)]
+    pub save_game_options: bool,
+    // Dump the core's performance counters on exit.
+    #[arg(long)]
+    pub perf: bool,
+    // Load several content files through one of the core's subsystems, e.g. sgb.
+    #[arg(long, requires = "content")]
+    pub subsystem: Option<String>,
+    // Content files for the subsystem, in the order the core expects them.
+    #[arg(long, value_name = "PATH")]
+    pub content: Vec<String>,
+    // Run this many frames as fast as possible with audio and video off, report the
+    // speed and exit.
+    #[arg(long, value_name = "FRAMES")]
+    pub benchmark: Option<u64>,
+    // Emulated addresses, in hex, whose byte is printed whenever it changes.
+    #[arg(long = "watch", value_name = "ADDRESS", value_parser = parse_hex_address)]
+    pub memory_watches: Vec<usize>,
+    // A movie recorded with the movie hotkey, to replay from its save state.
+    #[arg(long, value_name = "FILE")]
+    pub play_movie: Option<String>,
+    #[arg(skip)]
+    pub core_name: String,
+    // Set when neither a ROM nor subsystem content was given, for cores that run without content.
+    #[arg(skip)]
+    pub contentless: bool,
     #[arg(skip)]
     pub frame_buffer: Option<Vec<u32>>,
     #[arg(skip)]
@@ -53,16 +155,51 @@ pub struct EmulatorState {
     pub pixel_format: video::EmulatorPixelFormat,
     #[arg(skip)]
     pub bytes_per_pixel: u8,
+    #[arg(skip)]
+    pub input_descriptors: Vec<InputDescriptor>,
+    // Counter-clockwise rotation of the picture in steps of 90 degrees, from ENVIRONMENT_SET_ROTATION.
+    #[arg(skip)]
+    pub rotation: u32,
+    #[arg(skip)]
+    pub retry_state_action: Option<StateAction>,
+}
+
+// Stores AV info the core renegotiated with ENVIRONMENT_SET_SYSTEM_AV_INFO and
+// returns it, so audio and frame pacing can be retargeted.
+pub fn update_system_av_info(state: &mut EmulatorState) -> Option<SystemAvInfo> {
+    let av_info = AV_INFO_CHANNEL.1.lock().unwrap().try_iter().last()?;
+    println!(
+        "Core changed AV info: {}x{}, {} fps, {} Hz",
+        av_info.geometry.base_width,
+        av_info.geometry.base_height,
+        av_info.timing.fps,
+        av_info.timing.sample_rate
+    );
+    state.av_info = Some(av_info.clone());
+    Some(av_info)
+}
+
+// Parses an address given on the command line as hex, with or without 0x.
+fn parse_hex_address(address: &str) -> Result<usize, String> {
+    let digits = address.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).map_err(|err| err.to_string())
 }
 
-// Parses command-line arguments to obtain the ROM name and core library name.
-pub fn parse_command_line_arguments() -> (String, String) {
-    let emulator_state = EmulatorState::parse();
+// Parses command-line arguments into the initial emulator state.
+pub fn parse_command_line_arguments() -> EmulatorState {
+    let mut emulator_state = EmulatorState::parse();
+
+    // Save states and per-game files are named after the first subsystem content.
+    if emulator_state.subsystem.is_some() && emulator_state.rom_name.is_empty() {
+        emulator_state.rom_name = emulator_state.content[0].clone();
+    }
+    emulator_state.contentless =
+        emulator_state.subsystem.is_none() && emulator_state.rom_name.is_empty();
 
     println!("ROM name: {}", emulator_state.rom_name);
     println!("Core Library name: {}", emulator_state.library_name);
 
-    (emulator_state.rom_name, emulator_state.library_name)
+    emulator_state
 }
 
 // Loads the specified ROM file using the provided Core API.
@@ -85,27 +222,112 @@ pub unsafe fn load_rom_file(core_api: &CoreAPI, rom_name: &String) -> bool {
     return was_load_successful;
 }
 
-// Callback function for the libretro environment.
-unsafe extern "C" fn libretro_environment_callback(command: u32, return_data: *mut c_void) -> bool {
-    match command {
-        libretro_sys::ENVIRONMENT_GET_CAN_DUPE => {
-            *(return_data as *mut bool) = true; // Set the return_data to the value true
-            println!("ENVIRONMENT_GET_CAN_DUPE");
-        }
-        libretro_sys::ENVIRONMENT_SET_PIXEL_FORMAT => {
-            let pixel_format = *(return_data as *const u32);
-            let sender = &PIXEL_FORMAT_CHANNEL.0; // Use the global sender
-            sender
-                .send(PixelFormat::from_uint(pixel_format).unwrap())
-                .expect("Failed to send pixel format");
-            return true;
-        }
-        _ => println!(
-            "libretro_environment_callback Called with command: {}",
-            command
-        ),
+// Set when the core announces through ENVIRONMENT_SET_SUPPORT_NO_GAME that it can
+// run without content, e.g. 2048 or a test core.
+static SUPPORTS_NO_GAME: AtomicBool = AtomicBool::new(false);
+
+// Handles ENVIRONMENT_SET_SUPPORT_NO_GAME.
+pub fn set_support_no_game(supported: bool) {
+    SUPPORTS_NO_GAME.store(supported, Ordering::SeqCst);
+}
+
+// Starts a core that runs without content by loading a NULL game.
+pub unsafe fn load_no_game(core_api: &CoreAPI) -> bool {
+    if !SUPPORTS_NO_GAME.load(Ordering::SeqCst) {
+        panic!("No ROM was given and the core can't run without content");
+    }
+    let was_load_successful = (core_api.retro_load_game)(ptr::null());
+    if !was_load_successful {
+        panic!("Starting the core without content was not successful");
+    }
+    was_load_successful
+}
+
+// struct retro_get_proc_address_interface. Mirrored because a core returns NULL for
+// symbols it doesn't have, which libretro-sys's non-nullable return type can't hold.
+#[repr(C)]
+pub struct GetProcAddressInterface {
+    pub get_proc_address:
+        Option<unsafe extern "C" fn(sym: *const libc::c_char) -> Option<unsafe extern "C" fn()>>,
+}
+
+// Lookup function the core registered with ENVIRONMENT_SET_PROC_ADDRESS_CALLBACK.
+static GET_PROC_ADDRESS: Lazy<
+    Mutex<Option<unsafe extern "C" fn(*const libc::c_char) -> Option<unsafe extern "C" fn()>>>,
+> = Lazy::new(|| Mutex::new(None));
+
+pub unsafe fn set_proc_address_callback(interface: *const GetProcAddressInterface) {
+    let get_proc_address = interface
+        .as_ref()
+        .and_then(|interface| interface.get_proc_address);
+    if get_proc_address.is_some() {
+        println!("Core exposes extension functions through get_proc_address");
+    }
+    *GET_PROC_ADDRESS.lock().unwrap() = get_proc_address;
+}
+
+// Looks up one of the core's libretro API extension functions by name. No
+// extensions are defined by the API yet, so nothing calls this so far.
+#[allow(dead_code)]
+pub fn proc_address(symbol: &str) -> Option<unsafe extern "C" fn()> {
+    let get_proc_address = (*GET_PROC_ADDRESS.lock().unwrap())?;
+    let symbol = CString::new(symbol).ok()?;
+    unsafe { get_proc_address(symbol.as_ptr()) }
+}
+
+// Serialization quirk flags, mirrored because libretro-sys predates them.
+pub const SERIALIZATION_QUIRK_INCOMPLETE: u64 = 1 << 0;
+const SERIALIZATION_QUIRK_MUST_INITIALIZE: u64 = 1 << 1;
+const SERIALIZATION_QUIRK_CORE_VARIABLE_SIZE: u64 = 1 << 2;
+const SERIALIZATION_QUIRK_FRONT_VARIABLE_SIZE: u64 = 1 << 3;
+pub const SERIALIZATION_QUIRK_SINGLE_SESSION: u64 = 1 << 4;
+const SERIALIZATION_QUIRK_ENDIAN_DEPENDENT: u64 = 1 << 5;
+const SERIALIZATION_QUIRK_PLATFORM_DEPENDENT: u64 = 1 << 6;
+
+// Quirks the core reported with ENVIRONMENT_SET_SERIALIZATION_QUIRKS, and when
+// the session they apply to started.
+static SERIALIZATION_QUIRKS: AtomicU64 = AtomicU64::new(0);
+static SESSION_START: Lazy<SystemTime> = Lazy::new(SystemTime::now);
+
+// Returns whether the core reported the given serialization quirk.
+pub fn has_serialization_quirk(quirk: u64) -> bool {
+    SERIALIZATION_QUIRKS.load(Ordering::SeqCst) & quirk != 0
+}
+
+// Handles ENVIRONMENT_SET_SERIALIZATION_QUIRKS and returns the flags to hand back
+// to the core. Save states are sized on every save, so a serialize size that
+// changes during the session is supported.
+pub fn set_serialization_quirks(mut flags: u64) -> u64 {
+    Lazy::force(&SESSION_START);
+    if flags & SERIALIZATION_QUIRK_CORE_VARIABLE_SIZE != 0 {
+        flags |= SERIALIZATION_QUIRK_FRONT_VARIABLE_SIZE;
     }
-    false
+    SERIALIZATION_QUIRKS.store(flags, Ordering::SeqCst);
+
+    let names = [
+        (
+            SERIALIZATION_QUIRK_INCOMPLETE,
+            "incomplete, rewind and recording disabled",
+        ),
+        (
+            SERIALIZATION_QUIRK_MUST_INITIALIZE,
+            "needs a frame to run first",
+        ),
+        (SERIALIZATION_QUIRK_CORE_VARIABLE_SIZE, "variable size"),
+        (
+            SERIALIZATION_QUIRK_SINGLE_SESSION,
+            "single session, rewind disabled",
+        ),
+        (SERIALIZATION_QUIRK_ENDIAN_DEPENDENT, "endian dependent"),
+        (SERIALIZATION_QUIRK_PLATFORM_DEPENDENT, "platform dependent"),
+    ];
+    let quirks: Vec<&str> = names
+        .iter()
+        .filter(|(quirk, _)| flags & quirk != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    println!("Core serialization quirks: {}", quirks.join(", "));
+    flags
 }
 
 // Represents a loaded libretro core with associated functions.
@@ -164,24 +386,28 @@ impl Core {
             if api_version != EXPECTED_LIB_RETRO_VERSION {
                 panic!("The Core has been compiled with a LibRetro API that is unexpected, we expected version to be: {} but it was: {}", EXPECTED_LIB_RETRO_VERSION, api_version)
             }
-            (core_api.retro_set_environment)(libretro_environment_callback);
-            (core_api.retro_init)();
-            let mut av_info = SystemAvInfo {
-                geometry: GameGeometry {
-                    base_width: 0,
-                    base_height: 0,
-                    max_width: 0,
-                    max_height: 0,
-                    aspect_ratio: 0.0,
-                },
-                timing: SystemTiming {
-                    fps: 0.0,
-                    sample_rate: 0.0,
-                },
+
+            // The system info is available before retro_init, which lets us find the
+            // core's option overrides before it starts asking for them.
+            let mut system_info = SystemInfo {
+                library_name: ptr::null(),
+                library_version: ptr::null(),
+                valid_extensions: ptr::null(),
+                need_fullpath: false,
+                block_extract: false,
             };
-            (core_api.retro_get_system_av_info)(&mut av_info);
-            println!("AV Info: {:?}", &av_info);
-            state.av_info = Some(av_info);
+            (core_api.retro_get_system_info)(&mut system_info);
+            if !system_info.library_name.is_null() {
+                state.core_name = CStr::from_ptr(system_info.library_name)
+                    .to_string_lossy()
+                    .into_owned();
+            }
+            println!("Core name: {}", state.core_name);
+
+            // Without content, save states and per-game files are named after the core
+            if state.contentless {
+                state.rom_name = state.core_name.clone();
+            }
 
             // Construct and return a Core instance
             (
@@ -193,6 +419,39 @@ impl Core {
             )
         }
     }
+
+    // Registers the environment callback, initializes the core and queries its AV info.
+    pub fn init(&self, mut state: EmulatorState) -> EmulatorState {
+        unsafe {
+            let core_api = &self.api;
+            (core_api.retro_set_environment)(environment::environment_callback);
+            (core_api.retro_init)();
+            state.av_info = Some(self.system_av_info());
+            state
+        }
+    }
+
+    // Queries the core's current geometry and timing.
+    pub fn system_av_info(&self) -> SystemAvInfo {
+        let mut av_info = SystemAvInfo {
+            geometry: GameGeometry {
+                base_width: 0,
+                base_height: 0,
+                max_width: 0,
+                max_height: 0,
+                aspect_ratio: 0.0,
+            },
+            timing: SystemTiming {
+                fps: 0.0,
+                sample_rate: 0.0,
+            },
+        };
+        unsafe {
+            (self.api.retro_get_system_av_info)(&mut av_info);
+        }
+        println!("AV Info: {:?}", &av_info);
+        av_info
+    }
 }
 
 // Handles dropping of the Core, which could include cleanup tasks.
@@ -204,11 +463,12 @@ impl Drop for Core {
 
 // Utility functions for managing save states and configuration files follow.
 
-// `get_save_state_path` computes the path for a save state file.
+// `get_save_state_path` computes the path for a save state file, given the slot's
+// number or "auto" for the state kept between sessions.
 fn get_save_state_path(
     save_directory: &String,
     game_file_name: &str,
-    save_state_index: &u8,
+    slot: &str,
 ) -> Option<PathBuf> {
     // Expand the tilde to the home directory
     let expanded_save_directory = shellexpand::tilde(save_directory);
@@ -231,7 +491,7 @@ fn get_save_state_path(
         .unwrap_or_default()
         .to_string_lossy()
         .replace(" ", "_");
-    let save_state_file_name = format!("{}_{}.state", game_name, save_state_index);
+    let save_state_file_name = format!("{}_{}.state", game_name, slot);
 
     // Combine the saves directory and the save state filename to create the full path
     let save_state_path = saves_dir.join(save_state_file_name);
@@ -239,29 +499,51 @@ fn get_save_state_path(
     Some(save_state_path)
 }
 
+// `serialize` returns the current state of the emulator as the core saves it.
+pub unsafe fn serialize(core_api: &CoreAPI) -> Result<Vec<u8>, String> {
+    let save_state_buffer_size = (core_api.retro_serialize_size)();
+    if save_state_buffer_size == 0 {
+        return Err("Save states are not supported by this core".to_string());
+    }
+    let mut state_buffer: Vec<u8> = vec![0; save_state_buffer_size];
+    // Call retro_serialize to create the save state. libretro-sys drops its bool
+    // result, which is the only way to tell the core refused.
+    let retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool =
+        std::mem::transmute(core_api.retro_serialize);
+    if !retro_serialize(
+        state_buffer.as_mut_ptr() as *mut c_void,
+        save_state_buffer_size,
+    ) {
+        return Err("The core failed to create a save state".to_string());
+    }
+    Ok(state_buffer)
+}
+
+// `unserialize` applies a save state, returning whether the core accepted it.
+pub unsafe fn unserialize(core_api: &CoreAPI, state_buffer: &mut [u8]) -> bool {
+    (core_api.retro_unserialize)(state_buffer.as_mut_ptr() as *mut c_void, state_buffer.len())
+}
+
 // `save_state` saves the current state of the emulator to a file.
 pub unsafe fn save_state(
     core_api: &CoreAPI,
     save_directory: &String,
     rom_name: &String,
-    save_index: &u8,
-) {
-    let save_state_buffer_size = (core_api.retro_serialize_size)();
-    let mut state_buffer: Vec<u8> = vec![0; save_state_buffer_size];
-    // Call retro_serialize to create the save state
-    (core_api.retro_serialize)(
-        state_buffer.as_mut_ptr() as *mut c_void,
-        save_state_buffer_size,
-    );
+    slot: &str,
+) -> Result<(), String> {
+    let state_buffer = serialize(core_api)?;
+    let save_state_buffer_size = state_buffer.len();
 
-    let file_path = get_save_state_path(save_directory, &rom_name, &save_index).unwrap();
+    let file_path = get_save_state_path(save_directory, &rom_name, slot).unwrap();
 
-    std::fs::write(&file_path, &state_buffer).unwrap();
+    std::fs::write(&file_path, &state_buffer)
+        .map_err(|err| format!("Failed to write {}: {}", file_path.display(), err))?;
     println!(
         "Save state saved to: {} with size: {}",
         file_path.display(),
         save_state_buffer_size
     );
+    Ok(())
 }
 
 // `load_state` loads the emulator state from a file.
@@ -269,31 +551,107 @@ pub unsafe fn load_state(
     core_api: &CoreAPI,
     save_directory: &String,
     rom_name: &String,
-    save_index: &u8,
-) {
-    let file_path = get_save_state_path(save_directory, &rom_name, &save_index).unwrap();
+    slot: &str,
+) -> Result<(), String> {
+    if (core_api.retro_serialize_size)() == 0 {
+        return Err("Save states are not supported by this core".to_string());
+    }
+    let file_path = get_save_state_path(save_directory, &rom_name, slot).unwrap();
 
     let mut state_buffer = Vec::new();
-    match File::open(&file_path) {
-        Ok(mut file) => {
-            // Read the save state file into a buffer
-            match file.read_to_end(&mut state_buffer) {
-                Ok(_) => {
-                    // Call retro_unserialize to apply the save state
-                    let result = (core_api.retro_unserialize)(
-                        state_buffer.as_mut_ptr() as *mut c_void,
-                        state_buffer.len() as usize,
-                    );
-                    if result {
-                        println!("Save state loaded from: {}", &file_path.display());
-                    } else {
-                        println!("Failed to load save state: error code {}", result);
-                    }
-                }
-                Err(err) => println!("Error reading save state file: {}", err),
-            }
+    let mut file = File::open(&file_path).map_err(|_| "Save state file not found".to_string())?;
+    if has_serialization_quirk(SERIALIZATION_QUIRK_SINGLE_SESSION) {
+        let written = file.metadata().and_then(|metadata| metadata.modified());
+        if written.map_or(true, |written| written < *SESSION_START) {
+            return Err(
+                "This core can only load save states made since it was started".to_string(),
+            );
+        }
+    }
+    // Read the save state file into a buffer
+    file.read_to_end(&mut state_buffer)
+        .map_err(|err| format!("Error reading save state file: {}", err))?;
+    if !unserialize(core_api, &mut state_buffer) {
+        return Err(format!("The core failed to load {}", file_path.display()));
+    }
+    println!("Save state loaded from: {}", &file_path.display());
+    Ok(())
+}
+
+// A save state operation, kept so it can be retried.
+#[derive(Clone, Copy)]
+pub enum StateAction {
+    Save,
+    Load,
+    // Saving and loading the state kept between sessions.
+    AutoSave,
+    AutoLoad,
+}
+
+// The slot the state kept between sessions is saved in.
+const AUTO_SLOT: &str = "auto";
+
+// `run_state_action` saves or loads the current slot. Cores with
+// SERIALIZATION_QUIRK_MUST_INITIALIZE can refuse until they have run a frame, so a
+// failed first attempt is retried once after the next retro_run.
+pub unsafe fn run_state_action(
+    core_api: &CoreAPI,
+    state: &mut EmulatorState,
+    config: &HashMap<String, String>,
+    action: StateAction,
+) {
+    let save_directory = &config["savestate_directory"];
+    let current_slot = state.current_save_slot.to_string();
+    let result = match action {
+        StateAction::Save => save_state(core_api, save_directory, &state.rom_name, &current_slot),
+        StateAction::Load => load_state(core_api, save_directory, &state.rom_name, &current_slot),
+        StateAction::AutoSave => save_state(core_api, save_directory, &state.rom_name, AUTO_SLOT),
+        StateAction::AutoLoad => load_state(core_api, save_directory, &state.rom_name, AUTO_SLOT),
+    };
+    let retrying = state.retry_state_action.take().is_some();
+    if let Err(err) = result {
+        if !retrying && has_serialization_quirk(SERIALIZATION_QUIRK_MUST_INITIALIZE) {
+            println!("{}, trying again after the next frame", err);
+            state.retry_state_action = Some(action);
+        } else {
+            println!("{}", err);
+            osd::show_message(&err);
         }
-        Err(_) => println!("Save state file not found"),
+    }
+}
+
+// `load_auto_state` resumes the game where it was left with `savestate_auto_load`,
+// from the state `savestate_auto_save` kept when it was last closed, if there is
+// one. Called once content is loaded.
+pub unsafe fn load_auto_state(
+    core_api: &CoreAPI,
+    state: &mut EmulatorState,
+    config: &HashMap<String, String>,
+) {
+    let save_directory = &config["savestate_directory"];
+    let exists = get_save_state_path(save_directory, &state.rom_name, AUTO_SLOT)
+        .is_some_and(|path| path.exists());
+    if exists
+        && config
+            .get("savestate_auto_load")
+            .is_some_and(|v| v == "true")
+    {
+        run_state_action(core_api, state, config, StateAction::AutoLoad);
+    }
+}
+
+// `save_auto_state` keeps the state of the game to resume from next time, with
+// `savestate_auto_save`. Called when the frontend closes.
+pub unsafe fn save_auto_state(
+    core_api: &CoreAPI,
+    state: &mut EmulatorState,
+    config: &HashMap<String, String>,
+) {
+    if config
+        .get("savestate_auto_save")
+        .is_some_and(|v| v == "true")
+    {
+        run_state_action(core_api, state, config, StateAction::AutoSave);
     }
 }
 
@@ -308,7 +666,7 @@ fn get_retroarch_config_path() -> PathBuf {
 }
 
 // `parse_retroarch_config` parses the RetroArch configuration file.
-fn parse_retroarch_config(config_file: &Path) -> Result<HashMap<String, String>, String> {
+pub fn parse_retroarch_config(config_file: &Path) -> Result<HashMap<String, String>, String> {
     let file = File::open(config_file).map_err(|e| format!("Failed to open file: {}", e))?;
     let reader = BufReader::new(file);
     let mut config_map = HashMap::new();
@@ -324,10 +682,188 @@ fn parse_retroarch_config(config_file: &Path) -> Result<HashMap<String, String>,
     Ok(config_map)
 }
 
-// `setup_config` merges various configuration sources into a single HashMap.
-pub fn setup_config() -> Result<HashMap<String, String>, String> {
+// `get_core_options_paths` returns the per-core and per-game option override files,
+// laid out as `<config dir>/<core>/<core>.opt` and `<config dir>/<core>/<game>.opt`.
+fn get_core_options_paths(
+    config_directory: &str,
+    core_name: &str,
+    rom_name: &str,
+) -> (PathBuf, PathBuf) {
+    let core_directory =
+        PathBuf::from(shellexpand::tilde(config_directory).into_owned()).join(core_name);
+    let game_name = Path::new(rom_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    (
+        core_directory.join(format!("{}.opt", core_name)),
+        core_directory.join(format!("{}.opt", game_name)),
+    )
+}
+
+// `apply_core_option_overrides` sets the option values passed on the command line.
+pub fn apply_core_option_overrides(state: &EmulatorState) {
+    let mut options = CORE_OPTIONS.lock().unwrap();
+    for core_option in &state.core_option_overrides {
+        match core_option.split_once('=') {
+            Some((key, value)) => {
+                options.set_value(key.trim(), value.trim());
+            }
+            None => println!("Ignoring malformed core option: {}", core_option),
+        }
+    }
+}
+
+// `config_modified_time` returns when our config file or option overrides were last written.
+pub fn config_modified_time(
+    config: &HashMap<String, String>,
+    state: &EmulatorState,
+) -> Option<SystemTime> {
+    let (core_options_path, game_options_path) = get_core_options_paths(
+        &config["rgui_config_directory"],
+        &state.core_name,
+        &state.rom_name,
+    );
+    [
+        PathBuf::from(RUSTROARCH_CONFIG_PATH),
+        core_options_path,
+        game_options_path,
+    ]
+    .iter()
+    .filter_map(|path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    })
+    .max()
+}
+
+// `save_core_options` persists the current core option values to the game override
+// file if one exists, otherwise to the per-core file.
+pub fn save_core_options(config: &HashMap<String, String>, state: &EmulatorState) {
+    let (core_options_path, game_options_path) = get_core_options_paths(
+        &config["rgui_config_directory"],
+        &state.core_name,
+        &state.rom_name,
+    );
+    let file_path = if state.save_game_options || game_options_path.exists() {
+        game_options_path
+    } else {
+        core_options_path
+    };
+    if let Some(parent) = file_path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            println!("Failed to create core options directory: {}", err);
+            return;
+        }
+    }
+
+    let contents: String = CORE_OPTIONS
+        .lock()
+        .unwrap()
+        .current_values()
+        .iter()
+        .map(|(key, value)| format!("{} = \"{}\"\n", key, value))
+        .collect();
+    match fs::write(&file_path, contents) {
+        Ok(_) => println!("Core options saved to: {}", file_path.display()),
+        Err(err) => println!("Failed to save core options: {}", err),
+    }
+}
+
+// `save_config_values` sets keys in our config file, replacing the lines they are
+// on and adding any that aren't there at the end, and leaves the rest as it was.
+pub fn save_config_values(values: &[(String, String)]) -> Result<(), String> {
+    let contents = fs::read_to_string(RUSTROARCH_CONFIG_PATH).unwrap_or_default();
+    fs::write(RUSTROARCH_CONFIG_PATH, set_config_values(&contents, values))
+        .map_err(|e| e.to_string())
+}
+
+// `set_config_values` returns a config file's contents with keys set.
+fn set_config_values(contents: &str, values: &[(String, String)]) -> String {
+    let line_for = |key: &str, value: &str| format!("{} = \"{}\"\n", key, value);
+    let mut output = String::new();
+    let mut written = vec![false; values.len()];
+    for line in contents.lines() {
+        let key = line.split_once('=').map(|(key, _)| key.trim());
+        match values
+            .iter()
+            .position(|(name, _)| Some(name.as_str()) == key)
+        {
+            Some(index) => {
+                output.push_str(&line_for(&values[index].0, &values[index].1));
+                written[index] = true;
+            }
+            None => output.push_str(&format!("{}\n", line)),
+        }
+    }
+    for ((key, value), written) in values.iter().zip(written) {
+        if !written {
+            output.push_str(&line_for(key, value));
+        }
+    }
+    output
+}
+
+// `update_environment_settings` refreshes the values the core can query through the
+// environment callback, creating the system and core assets directories on first run.
+pub fn update_environment_settings(config: &HashMap<String, String>) {
+    let system_directory = environment_directory(config, "system_directory");
+    let core_assets_directory = environment_directory(config, "core_assets_directory");
+
+    // Like RetroArch, the netplay nickname doubles as the username; without one the
+    // login name is used
+    let username = config
+        .get("netplay_nickname")
+        .filter(|nickname| !nickname.is_empty())
+        .cloned()
+        .or_else(|| env::var("USER").ok())
+        .or_else(|| env::var("USERNAME").ok());
+
+    let language = config.get("user_language").map_or(0, |language| {
+        parse_language(language).unwrap_or_else(|| {
+            println!("Unknown user_language {}, using english", language);
+            0
+        })
+    });
+
+    let mut settings = ENVIRONMENT_SETTINGS.lock().unwrap();
+    settings.system_directory = system_directory;
+    settings.core_assets_directory = core_assets_directory;
+    settings.username = username.and_then(|username| CString::new(username).ok());
+    settings.language = language;
+}
+
+// `environment_directory` expands a directory setting, creating the directory if needed.
+fn environment_directory(config: &HashMap<String, String>, key: &str) -> CString {
+    let directory = PathBuf::from(shellexpand::tilde(&config[key]).into_owned());
+    if !directory.exists() {
+        match fs::create_dir_all(&directory) {
+            Ok(_) => println!("Created {}: {}", key, directory.display()),
+            Err(err) => println!("Failed to create {}: {:?} Error: {}", key, &directory, err),
+        }
+    }
+    CString::new(directory.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// `parse_language` accepts a retro_language ID, as RetroArch writes it, or its name.
+fn parse_language(language: &str) -> Option<u32> {
+    let language = language.trim().to_ascii_lowercase();
+    match language.parse::<u32>() {
+        Ok(id) => ((id as usize) < LANGUAGES.len()).then_some(id),
+        Err(_) => LANGUAGES
+            .iter()
+            .position(|name| *name == language)
+            .map(|id| id as u32),
+    }
+}
+
+// `setup_config` merges various configuration sources into a single HashMap,
+// with per-core and per-game core option overrides taking precedence.
+pub fn setup_config(core_name: &str, rom_name: &str) -> Result<HashMap<String, String>, String> {
     let retro_arch_config_path = get_retroarch_config_path();
-    let our_config = parse_retroarch_config(Path::new("./rustroarch.cfg"));
+    let our_config = parse_retroarch_config(Path::new(RUSTROARCH_CONFIG_PATH));
     let retro_arch_config =
         parse_retroarch_config(&retro_arch_config_path.join("config/retroarch.cfg"));
     let mut merged_config: HashMap<String, String> = HashMap::from([
@@ -344,10 +880,106 @@ pub fn setup_config() -> Result<HashMap<String, String>, String> {
         ("input_player1_select", "space"),
         ("input_player1_start", "enter"),
         ("input_reset", "h"),
+        ("input_pause_toggle", "p"),
+        ("input_frame_advance", "n"),
+        ("input_rewind", "r"),
+        ("input_audio_mute", "m"),
+        ("input_menu_toggle", "backspace"),
+        ("input_exit_emulator", "escape"),
         ("input_save_state", "f2"),
         ("input_load_state", "f4"),
         ("input_screenshot", "f8"),
+        ("input_save_clip", "f6"),
+        ("input_toggle_perf_overlay", "f3"),
+        ("input_remap", "f1"),
+        ("input_toggle_audio_dump", "o"),
+        ("input_movie_record_toggle", "v"),
+        ("input_movie_take_over", "t"),
+        ("input_toggle_fast_forward", "tab"),
+        ("input_hold_fast_forward", "f"),
+        ("input_toggle_slowmotion", "e"),
+        ("input_toggle_smooth", "f7"),
+        ("input_toggle_crt", "f5"),
+        ("input_toggle_fullscreen", "f11"),
+        ("input_disk_eject_toggle", "f9"),
+        ("input_disk_next", "f10"),
+        ("input_disk_prev", "f12"),
+        ("input_enable_hotkey_btn", "select"),
+        ("input_save_state_btn", "righttrigger"),
+        ("input_load_state_btn", "lefttrigger"),
+        ("input_menu_toggle_btn", "start"),
+        ("fastforward_ratio", "0.0"),
+        ("fastforward_audio", "mute"),
+        ("slowmotion_ratio", "2.0"),
+        ("slowmotion_audio", "stretch"),
+        ("rewind_enable", "false"),
+        ("rewind_granularity", "1"),
+        ("rewind_buffer_size", "20"),
+        ("audio_device", ""),
+        ("audio_dump_format", "wav"),
+        ("audio_latency", "64"),
+        ("audio_rate_control", "true"),
+        ("audio_rate_control_delta", "0.005"),
+        ("audio_sync", "false"),
+        ("audio_dsp_chain", ""),
+        ("audio_dsp_lowpass_cutoff", "5000"),
+        ("audio_dsp_eq_bass", "0"),
+        ("audio_dsp_eq_treble", "0"),
+        ("audio_swap_channels", "false"),
+        ("audio_mono", "false"),
+        ("input_rumble_enable", "true"),
+        ("input_sensor_tilt_up", "i"),
+        ("input_sensor_tilt_down", "k"),
+        ("input_sensor_tilt_left", "j"),
+        ("input_sensor_tilt_right", "l"),
+        ("input_rumble_gain", "100"),
+        ("input_analog_deadzone", "0.0"),
+        ("input_analog_sensitivity", "1.0"),
+        ("input_analog_curve", "linear"),
+        ("input_axis_threshold", "0.5"),
+        ("led_keyboard_enable", "false"),
+        ("perf_overlay", "false"),
+        ("crop_overscan", "true"),
+        ("video_driver", "gl"),
+        ("video_backend", "minifb"),
+        ("video_fullscreen", "false"),
+        ("video_window_scale", "3"),
+        ("video_vsync", "false"),
+        ("video_adaptive_sync", "false"),
+        ("video_bfi", "false"),
+        ("video_threaded", "false"),
+        ("video_threads", "1"),
+        ("video_windowed_fullscreen", "true"),
+        ("video_monitor_index", "0"),
+        ("video_renderer", "software"),
+        ("video_scale_integer", "false"),
+        ("video_scale", "0"),
+        ("video_aspect_ratio", "core"),
+        ("video_aspect_ratio_custom", "4:3"),
+        ("video_smooth", "false"),
+        ("video_shader", ""),
+        ("video_bezel", ""),
+        ("video_gamma", "1.0"),
+        ("video_brightness", "1.0"),
+        ("video_saturation", "1.0"),
+        ("video_color_correction", "none"),
+        ("video_bezel_viewport", ""),
+        ("video_filters", ""),
+        ("netplay_nickname", ""),
+        ("user_language", "0"),
         ("savestate_directory", "./states"),
+        ("savefile_directory", "./saves"),
+        ("savestate_auto_save", "false"),
+        ("savestate_auto_load", "false"),
+        ("autosave_interval", "0"),
+        ("screenshot_directory", "./screenshots"),
+        ("joypad_autoconfig_dir", "./autoconfig"),
+        ("clip_length", "10"),
+        ("clip_downscale", "2"),
+        ("system_directory", "./system"),
+        ("core_assets_directory", "./downloads"),
+        ("libretro_log_level", "1"),
+        ("rgui_config_directory", "./config"),
     ])
     .iter()
     .map(|(k, v)| (k.to_string(), v.to_string()))
@@ -360,6 +992,14 @@ pub fn setup_config() -> Result<HashMap<String, String>, String> {
         Ok(config) => merged_config.extend(config),
         _ => println!("We don't have RustroArch config",),
     }
+    let (core_options_path, game_options_path) =
+        get_core_options_paths(&merged_config["rgui_config_directory"], core_name, rom_name);
+    for options_path in [core_options_path, game_options_path] {
+        if let Ok(options) = parse_retroarch_config(&options_path) {
+            println!("Loaded core options from: {}", options_path.display());
+            merged_config.extend(options);
+        }
+    }
     // println!("retro_arch_config_path: {} merged_config: {:?}", retro_arch_config_path.join("config/retroarch.cfg").display(), merged_config);
     Ok(merged_config)
 }
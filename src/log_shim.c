@@ -0,0 +1,24 @@
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// log_shim.c
+//
+// retro_log_printf_t is a C variadic function, which stable Rust cannot define.
+// This shim formats the message with vsnprintf and hands the finished string
+// to the Rust side in logging.rs.
+
+#include <stdarg.h>
+#include <stdio.h>
+
+void rustretro_log_message(int level, const char *message);
+
+void rustretro_log_printf(int level, const char *fmt, ...)
+{
+   char buffer[4096];
+   va_list args;
+
+   va_start(args, fmt);
+   vsnprintf(buffer, sizeof(buffer), fmt, args);
+   va_end(args);
+
+   rustretro_log_message(level, buffer);
+}
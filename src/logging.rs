@@ -0,0 +1,61 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// logging.rs
+//
+// This module implements the libretro log interface. Cores call the variadic
+// printf-style function from log_shim.c, which formats the message and passes it
+// back here to be filtered by level and printed.
+
+use libc::{c_char, c_int};
+use libretro_sys::{LogCallback, LogLevel};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// Messages below this level are dropped. Matches RetroArch's `libretro_log_level`.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+extern "C" {
+    // Defined in log_shim.c. Declared without the trailing varargs because we only
+    // ever hand its address to the core, which calls it from C.
+    fn rustretro_log_printf(level: LogLevel, fmt: *const c_char);
+}
+
+// Builds the interface returned for ENVIRONMENT_GET_LOG_INTERFACE.
+pub fn log_interface() -> LogCallback {
+    LogCallback {
+        log: rustretro_log_printf,
+    }
+}
+
+// Reads the minimum log level from the configuration.
+pub fn set_log_level(config: &HashMap<String, String>) {
+    let level = config["libretro_log_level"]
+        .parse::<u8>()
+        .unwrap_or(LogLevel::Info as u8)
+        .min(LogLevel::Error as u8);
+    LOG_LEVEL.store(level, Ordering::SeqCst);
+}
+
+// Called by log_shim.c with the formatted message.
+#[no_mangle]
+pub unsafe extern "C" fn rustretro_log_message(level: c_int, message: *const c_char) {
+    if message.is_null() || level < LOG_LEVEL.load(Ordering::SeqCst) as c_int {
+        return;
+    }
+    let label = match level {
+        0 => "DEBUG",
+        1 => "INFO",
+        2 => "WARN",
+        _ => "ERROR",
+    };
+    let message = CStr::from_ptr(message).to_string_lossy();
+    let message = message.trim_end();
+    if level >= LogLevel::Warn as c_int {
+        eprintln!("[libretro {}] {}", label, message);
+    } else {
+        println!("[libretro {}] {}", label, message);
+    }
+}
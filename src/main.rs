@@ -4,175 +4,474 @@
 
 // Import necessary modules from other files and crates
 mod audio;
+mod audio_dump;
+mod bezel;
+mod camera;
+mod clip;
+mod color;
+mod controllers;
+mod core_options;
+mod disk;
+mod dsp;
+mod environment;
+mod flac;
+mod frame_time;
+mod gamepad;
+#[cfg(feature = "wgpu")]
+mod gpu;
+mod hotkeys;
+mod hw_render;
 mod input;
+mod keyboard;
+mod led;
 mod libretro;
+mod logging;
+mod memory;
+mod movie;
+mod osd;
+mod perf;
+mod perf_overlay;
+mod pixels;
+mod remap;
+mod resampler;
+mod rewind;
+mod ring_buffer;
+mod rumble;
+mod screenshot;
+#[cfg(feature = "sdl2")]
+mod sdl;
+mod sensor;
+#[cfg(feature = "wgpu")]
+mod shader;
+mod speed;
+mod sram;
+mod subsystem;
 mod video;
-use audio::AudioBuffer;
-use gilrs::{GamepadId, Gilrs, Event};
-use libretro_sys::PixelFormat;
-use minifb::{Key, Window, WindowOptions};
+#[cfg(feature = "vulkan")]
+mod vulkan;
+mod window;
+#[cfg(feature = "winit")]
+mod winit_window;
+use core_options::CORE_OPTIONS;
+use libretro_sys::{GameGeometry, PixelFormat, SystemAvInfo};
 use once_cell::sync::Lazy;
-use rodio::{OutputStream, Sink};
+use rodio::Sink;
+use std::collections::HashMap;
 use std::sync::atomic::AtomicU8;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+
+// A channel shared between the libretro callbacks and the main loop
+type Channel<T> = (Sender<T>, Arc<Mutex<Receiver<T>>>);
 
 // Define global static variables for handling input, pixel format, video, and audio data
-static BUTTONS_PRESSED: Lazy<Mutex<(Vec<i16>, Vec<i16>)>> =
-    Lazy::new(|| Mutex::new((vec![0; 16], vec![0; 16])));
+// The joypad buttons held on each port
+static BUTTONS_PRESSED: Lazy<Mutex<Vec<Vec<i16>>>> =
+    Lazy::new(|| Mutex::new(vec![vec![0; 16]; gamepad::PORTS]));
 static BYTES_PER_PIXEL: AtomicU8 = AtomicU8::new(4); // Default value for bytes per pixel
 static PIXEL_FORMAT_CHANNEL: Lazy<(Sender<PixelFormat>, Arc<Mutex<Receiver<PixelFormat>>>)> =
     Lazy::new(|| {
         let (sender, receiver) = channel::<PixelFormat>();
         (sender, Arc::new(Mutex::new(receiver)))
     });
-static VIDEO_DATA_CHANNEL: Lazy<(Sender<VideoData>, Arc<Mutex<Receiver<VideoData>>>)> =
-    Lazy::new(|| {
-        let (sender, receiver) = channel::<VideoData>();
-        (sender, Arc::new(Mutex::new(receiver)))
-    });
-static AUDIO_DATA_CHANNEL: Lazy<(
-    Sender<Arc<Mutex<AudioBuffer>>>,
-    Arc<Mutex<Receiver<Arc<Mutex<AudioBuffer>>>>>,
-)> = Lazy::new(|| {
-    let (sender, receiver) = channel::<Arc<Mutex<AudioBuffer>>>();
+static INPUT_DESCRIPTORS_CHANNEL: Lazy<Channel<Vec<input::InputDescriptor>>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<Vec<input::InputDescriptor>>();
+    (sender, Arc::new(Mutex::new(receiver)))
+});
+static GEOMETRY_CHANNEL: Lazy<Channel<GameGeometry>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<GameGeometry>();
+    (sender, Arc::new(Mutex::new(receiver)))
+});
+static ROTATION_CHANNEL: Lazy<Channel<u32>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<u32>();
+    (sender, Arc::new(Mutex::new(receiver)))
+});
+static AV_INFO_CHANNEL: Lazy<Channel<SystemAvInfo>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<SystemAvInfo>();
+    (sender, Arc::new(Mutex::new(receiver)))
+});
+static RUMBLE_CHANNEL: Lazy<Channel<rumble::RumbleRequest>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<rumble::RumbleRequest>();
+    (sender, Arc::new(Mutex::new(receiver)))
+});
+// Frames from the core, or None when it dupes the previous one.
+static VIDEO_DATA_CHANNEL: Lazy<Channel<Option<VideoData>>> = Lazy::new(|| {
+    let (sender, receiver) = channel::<Option<VideoData>>();
     (sender, Arc::new(Mutex::new(receiver)))
 });
-
 // Structure to hold video data
 struct VideoData {
     frame_buffer: Vec<u32>,
     width: u32,
     height: u32,
-    pitch: u32,
+    // Pixels from the start of one line to the next
+    stride: u32,
 }
 
 // The main function, entry point of the application
 fn main() {
-    // Parse command line arguments to get ROM and library names
-    let (rom_name, library_name) = libretro::parse_command_line_arguments();
-    // Initialize emulator state with default values
-    let mut current_state = libretro::EmulatorState {
-        rom_name,
-        library_name,
-        frame_buffer: None,
-        screen_pitch: 0,
-        screen_width: 0,
-        screen_height: 0,
-        current_save_slot: 0,
-        av_info: None,
-        pixel_format: video::EmulatorPixelFormat(PixelFormat::ARGB8888),
-        bytes_per_pixel: 0,
-    };
-
-    // Create a new window with specific options
-    let mut window = Window::new(
-        "Test", // Window title
-        256,    // Window width
-        144,    // Window height
-        WindowOptions {
-            resize: true, // Allow window resizing
-            ..WindowOptions::default()
-        },
-    )
-    .expect("Unable to open Window");
-
-    // Limit window update rate to approximately 60 frames per second
-    window.limit_update_rate(Some(std::time::Duration::from_micros(16600)));
+    // Parse command line arguments to get the ROM and library names
+    let mut current_state = libretro::parse_command_line_arguments();
 
-    // Initialize the core of the emulator and update the emulator state
+    // Load the core of the emulator and update the emulator state
     let (core, updated_state) = libretro::Core::new(current_state);
     let core_api = &core.api; // Reference to the core API
     current_state = updated_state;
 
+    // Prepare the configuration before the core starts asking for its options
+    let mut config =
+        libretro::setup_config(&current_state.core_name, &current_state.rom_name).unwrap();
+    let mut config_modified = libretro::config_modified_time(&config, &current_state);
+    CORE_OPTIONS.lock().unwrap().apply_config(&config);
+    libretro::update_environment_settings(&config);
+    logging::set_log_level(&config);
+    audio::configure(&config);
+    dsp::configure(&config);
+    input::configure(&config);
+    led::configure(&config);
+    speed::configure(&config);
+    video::configure(&config);
+    bezel::configure(&config);
+    color::configure(&config);
+    clip::configure(&config);
+    perf_overlay::configure(&config);
+    rewind::configure(&config);
+    sram::configure(&config);
+    hw_render::configure(&config);
+
+    // Initialize the core and apply any options given on the command line
+    current_state = core.init(current_state);
+    libretro::apply_core_option_overrides(&current_state);
+
     // Extract the audio sample rate from the emulator state
-    let sample_rate = current_state
-        .av_info
-        .as_ref()
-        .map_or(0.0, |av_info| av_info.timing.sample_rate);
+    audio::set_sample_rate(
+        current_state
+            .av_info
+            .as_ref()
+            .map_or(0.0, |av_info| av_info.timing.sample_rate),
+    );
 
     // Spawn a new thread for audio handling
+    let audio_device = config["audio_device"].clone();
+    let (mut consumer, rate_changes) = audio::open_queue();
     let _audio_thread = thread::spawn(move || {
         println!("Audio Thread Started");
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let sink = Sink::try_new(&stream_handle).unwrap();
+        let (mut _stream, mut stream_handle, mut device_rate) =
+            audio::open_output(&audio_device).unwrap();
+        let mut sink = Sink::try_new(&stream_handle).unwrap();
+        let mut sample_rate = audio::sample_rate();
+        let mut rate_control = audio::RateControl::new();
+        let mut resampler = resampler::Resampler::new(sample_rate, device_rate);
+        let buffers = audio::OutputBuffers::default();
+        let mut rate_change = None;
+        let mut samples = Vec::new();
         loop {
-            let receiver = AUDIO_DATA_CHANNEL.1.lock().unwrap();
-            // Play audio in a loop
-            for buffer_arc in receiver.try_iter() {
-                let buffer = buffer_arc.lock().unwrap();
-                unsafe {
-                    audio::play_audio(&sink, &*buffer, sample_rate as u32);
+            // Once the audio sent at the old rate has finished playing, start over
+            // with a new output for the new one, so nothing plays at the wrong speed
+            if rate_change.is_none() {
+                rate_change = rate_changes.try_recv().ok();
+            }
+            let played = consumer.position();
+            let finished =
+                |change: &mut audio::RateChange| played >= change.position && sink.empty();
+            if let Some(change) = rate_change.take_if(finished) {
+                if change.sample_rate != sample_rate {
+                    sample_rate = change.sample_rate;
+                    println!("Reopening audio output for {} Hz", sample_rate);
+                    match audio::open_output(&audio_device) {
+                        Ok(output) => (_stream, stream_handle, device_rate) = output,
+                        Err(e) => {
+                            println!("Failed to reopen audio output, keeping the old one: {}", e)
+                        }
+                    }
+                    sink = Sink::try_new(&stream_handle).unwrap();
+                    rate_control = audio::RateControl::new();
+                    resampler = resampler::Resampler::new(sample_rate, device_rate);
                 }
+                continue;
+            }
+            // Pausing drops whatever is queued, by starting over with a new sink
+            if audio::take_pause() {
+                sink = Sink::try_new(&stream_handle).unwrap();
+                rate_control = audio::RateControl::new();
+            }
+            rate_control.update(&sink, sample_rate);
+            sink.set_volume(audio::volume());
+            // Play whatever the core has sent since last time
+            samples.clear();
+            audio::run_audio_callback(&sink, &mut samples);
+            let end = rate_change
+                .as_ref()
+                .map_or(usize::MAX, |change| change.position);
+            consumer.pop_until(&mut samples, end, 2);
+            if samples.is_empty() {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            audio_dump::write(&samples);
+            dsp::process(&mut samples, sample_rate);
+            if audio::is_enabled() {
+                audio::play_audio(&sink, &samples, &mut rate_control, &mut resampler, &buffers);
             }
         }
     });
 
     // Set up libretro callbacks for video, input, and audio
     unsafe {
-        (core_api.retro_init)();
         (core_api.retro_set_video_refresh)(video::libretro_set_video_refresh_callback);
         (core_api.retro_set_input_poll)(input::libretro_set_input_poll_callback);
         (core_api.retro_set_input_state)(input::libretro_set_input_state_callback);
         (core_api.retro_set_audio_sample)(audio::libretro_set_audio_sample_callback);
         (core_api.retro_set_audio_sample_batch)(audio::libretro_set_audio_sample_batch_callback);
-        println!("About to load ROM: {}", &current_state.rom_name);
-        // Load the ROM file
-        libretro::load_rom_file(core_api, &current_state.rom_name);
+        // Multi-disc games resume from the disc that was in use last time
+        disk::set_initial_image(&config, &current_state.rom_name);
+        // Load the content, either one ROM file or a subsystem's set of files
+        match &current_state.subsystem {
+            Some(subsystem) => {
+                println!("About to load subsystem content: {}", subsystem);
+                subsystem::load_subsystem_content(core_api, subsystem, &current_state.content);
+            }
+            None if current_state.contentless => {
+                println!("About to start the core without content");
+                libretro::load_no_game(core_api);
+            }
+            None => {
+                println!("About to load ROM: {}", &current_state.rom_name);
+                libretro::load_rom_file(core_api, &current_state.rom_name);
+            }
+        }
+    }
+    controllers::apply_port_devices(core_api, &config);
+    if let Some(path) = &current_state.play_movie {
+        if let Err(e) = movie::play(core_api, path, &current_state.rom_name) {
+            println!("Failed to play movie: {}", e);
+            osd::show_message("Movie playback failed");
+        }
     }
 
+    // The AV info is only reliable once content is loaded, and hardware rendered
+    // cores need their context sized to the content before they can draw.
+    let av_info = core.system_av_info();
+    if hw_render::is_active() {
+        hw_render::context_reset(&av_info.geometry);
+    }
+    audio::set_sample_rate(av_info.timing.sample_rate);
+    let fps = av_info.timing.fps;
+    speed::set_fps(fps);
+    video::reserve_frame_buffers(&av_info.geometry);
+    current_state.av_info = Some(av_info);
+    input::update_input_descriptors(&mut current_state);
+    camera::initialize();
+    audio::set_audio_callback_state(true);
+    disk::print_images();
+    let core_memory = memory::CoreMemory::new(core_api);
+    memory::print_regions(&core_memory);
+    sram::load(&core_memory, &config, &current_state.rom_name);
+    let mut watched_memory = HashMap::new();
+
+    // A benchmark runs headless, so it is done before any window is opened
+    if let Some(frames) = current_state.benchmark {
+        audio::set_enabled(false);
+        video::set_enabled(false);
+        speed::set_unthrottled(true);
+        perf::run_benchmark(core_api, frames, fps);
+        audio::set_audio_callback_state(false);
+        camera::deinitialize();
+        hw_render::context_destroy();
+        if current_state.perf {
+            perf::log_counters();
+        }
+        return;
+    }
+
+    // Pick up where the game was left, unless a movie is to start from its own state
+    if current_state.play_movie.is_none() {
+        unsafe { libretro::load_auto_state(core_api, &mut current_state, &config) };
+    }
+
+    let mut title = window::Title::new(
+        &current_state.rom_name,
+        &current_state.core_name,
+        current_state.contentless,
+    );
+    let mut window = window::open(
+        title.name(),
+        &config,
+        current_state.av_info.as_ref().unwrap(),
+    )
+    .expect("Unable to open Window");
+    let mut presenter = video::Presenter::new(window.as_ref(), &config);
+
     // Prepare configurations for input handling
-    let config = libretro::setup_config().unwrap();
-    let key_device_map = input::key_device_map(&config);
-    let joypad_device_map = input::setup_joypad_device_map(&config);
-    let mut gilrs = Gilrs::new().unwrap(); // Initialize gamepad handling
-    let mut active_gamepad: Option<GamepadId> = None;
+    let mut key_device_map = input::key_device_map(&config);
+    let mut joypad_device_map = input::setup_joypad_device_map(&config);
+    input::print_key_bindings(&key_device_map, &current_state);
+    // The joypad buttons held through the keyboard on each port
+    let mut keyboard_buttons = vec![vec![0; 16]; gamepad::PORTS];
+    hotkeys::print_bindings(&config);
+    let mut gilrs = gamepad::open(&config); // Initialize gamepad handling
+    let mut gamepads = gamepad::Gamepads::new(&gilrs, &config);
+    let mut rumble = rumble::Rumble::new(&config);
 
     // Main application loop
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        {
-            let mut buttons = BUTTONS_PRESSED.lock().unwrap();
-            let buttons_pressed = &mut buttons.0;
-            let mut game_pad_active: bool = false;
-
-            while let Some(Event { id, .. }) = gilrs.next_event() {
-                // println!("{:?} New event from {}: {:?}", time, id, event);
-                active_gamepad = Some(id);
+    while window.is_open() && !hotkeys::quit_requested() {
+        // Persist option changes, then pick up edits made to the config files while running
+        if CORE_OPTIONS.lock().unwrap().take_dirty() {
+            libretro::save_core_options(&config, &current_state);
+            config_modified = libretro::config_modified_time(&config, &current_state);
+        }
+        let modified = libretro::config_modified_time(&config, &current_state);
+        if modified != config_modified {
+            config_modified = modified;
+            if let Ok(new_config) =
+                libretro::setup_config(&current_state.core_name, &current_state.rom_name)
+            {
+                CORE_OPTIONS.lock().unwrap().apply_config(&new_config);
+                libretro::update_environment_settings(&new_config);
+                logging::set_log_level(&new_config);
+                audio::configure(&new_config);
+                dsp::configure(&new_config);
+                input::configure(&new_config);
+                led::configure(&new_config);
+                speed::configure(&new_config);
+                video::configure(&new_config);
+                bezel::configure(&new_config);
+                color::configure(&new_config);
+                clip::configure(&new_config);
+                perf_overlay::configure(&new_config);
+                rewind::configure(&new_config);
+                sram::configure(&new_config);
+                controllers::apply_port_devices(core_api, &new_config);
+                // Bindings may have changed, e.g. saved by remap
+                key_device_map = input::key_device_map(&new_config);
+                joypad_device_map = input::setup_joypad_device_map(&new_config);
+                config = new_config;
             }
+        }
+
+        // Wait for the frame to be due before reading input rather than after, so
+        // the core sees input as it is when the frame runs, not as it was before
+        // the wait
+        if !video::adaptive_sync() {
+            speed::wait_for_next_frame();
+        }
+        if !hotkeys::is_paused() {
+            audio::wait_for_room();
+        }
+        {
+            let mut buttons_pressed = BUTTONS_PRESSED.lock().unwrap();
 
             // Handle gamepad and keyboard input
-            if let Some(gamepad) = active_gamepad {
-                input::handle_gamepad_input(
-                    &joypad_device_map,
-                    &gilrs,
-                    &Some(gamepad),
-                    buttons_pressed,
-                );
-                game_pad_active = true;
+            for (port, button) in gamepads.update(&mut gilrs) {
+                if remap::is_capturing() {
+                    remap::capture_button(port, button);
+                } else if let Some(hotkey) =
+                    hotkeys::find_combo(&config, &gilrs, gamepads.port(port), button)
+                {
+                    hotkeys::run(
+                        hotkey,
+                        core_api,
+                        window.as_mut(),
+                        &mut current_state,
+                        &config,
+                    );
+                }
+            }
+            for (port, buttons) in buttons_pressed.iter_mut().enumerate() {
+                buttons.fill(0);
+                input::handle_gamepad_analog(&gilrs, gamepads.port(port), port);
+                if let Some(gamepad) = gamepads.port(port) {
+                    let device_map = gamepads.profile(port).unwrap_or(&joypad_device_map[port]);
+                    input::handle_gamepad_input(device_map, &gilrs, &Some(gamepad), buttons);
+                    // While the hotkey enable button is held, the other buttons are for combos
+                    if let Some(enable) = hotkeys::held_enable_button(&config, &gilrs, gamepad) {
+                        input::release_all_but(device_map, enable, buttons);
+                    }
+                }
             }
             input::handle_keyboard_input(
                 core_api,
-                &window,
+                window.as_mut(),
                 &mut current_state,
-                buttons_pressed,
+                &mut keyboard_buttons,
                 &key_device_map,
                 &config,
-                game_pad_active,
             );
+            // A button is held when either the player's gamepad or keys hold it, so
+            // someone on the keyboard can help out whoever is on the gamepad
+            input::merge_buttons(&mut buttons_pressed, &keyboard_buttons);
         }
+        hotkeys::update_held(&config, window.as_ref());
+        keyboard::update(window.as_ref());
+        sensor::update(window.as_ref(), &gilrs, gamepads.port(0), &config);
+        camera::poll();
         unsafe {
-            // Run one frame of the emulator
-            (core_api.retro_run)();
+            // Run one frame of the emulator, unless paused, from the state before
+            // the last one while rewinding
+            if hotkeys::runs_frame() {
+                let rewinding = rewind::is_rewinding();
+                if rewinding {
+                    rewind::step_back(core_api);
+                }
+                audio::report_buffer_status();
+                frame_time::notify();
+                (core_api.retro_run)();
+                audio::flush_single_samples();
+                if !rewinding {
+                    rewind::record(core_api);
+                }
+            }
+            // Retry a save or load the core wasn't ready for
+            if let Some(action) = current_state.retry_state_action {
+                libretro::run_state_action(core_api, &mut current_state, &config, action);
+            }
+            sram::update(&core_memory);
+            memory::print_watches(
+                &core_memory,
+                &current_state.memory_watches,
+                &mut watched_memory,
+            );
+            // Cores may send new descriptors, e.g. when the controller type changes
+            input::update_input_descriptors(&mut current_state);
+            video::update_geometry(&mut current_state);
+            video::update_rotation(&mut current_state);
+            rumble.update(&mut gilrs, gamepads.port(0));
+            // Retarget audio and frame pacing if the core renegotiated its timing
+            if let Some(av_info) = libretro::update_system_av_info(&mut current_state) {
+                audio::set_sample_rate(av_info.timing.sample_rate);
+                speed::set_fps(av_info.timing.fps);
+                video::reserve_frame_buffers(&av_info.geometry);
+            }
             // If needed, set up pixel format
             if current_state.bytes_per_pixel == 0 {
                 current_state = video::set_up_pixel_format(current_state);
             }
 
-            // Render the frame
-            let rendered_frame = video::render_frame(current_state, window);
+            // Render the frame, once it is due when presenting for adaptive sync
+            if video::adaptive_sync() {
+                speed::wait_for_next_frame();
+            }
+            let rendered_frame = video::render_frame(current_state, window, &mut presenter);
             current_state = rendered_frame.0;
             window = rendered_frame.1;
         }
+        title.frame(window.as_mut());
+        perf_overlay::frame();
+    }
+
+    audio::set_audio_callback_state(false);
+    audio::log_statistics();
+    audio_dump::stop();
+    movie::stop();
+    disk::save_image_index(&config, &current_state.rom_name);
+    unsafe { libretro::save_auto_state(core_api, &mut current_state, &config) };
+    sram::save(&core_memory);
+    camera::deinitialize();
+    hw_render::context_destroy();
+
+    if current_state.perf {
+        perf::log_counters();
     }
 }
@@ -0,0 +1,246 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// memory.rs
+//
+// This module keeps the memory map a core describes through
+// ENVIRONMENT_SET_MEMORY_MAPS: where each RAM or ROM chip sits in the emulated
+// address space and where it lives in the core's memory. `CoreMemory` reads
+// emulated memory through it for achievements, cheats and debugging.
+
+use libc::c_uint;
+use libretro_sys::{CoreAPI, MemoryMap, MEMDESC_BIGENDIAN, MEMDESC_CONST};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+// One mapping of emulated addresses onto a block of the core's memory.
+pub struct MemoryDescriptor {
+    pub flags: u64,
+    pub ptr: *mut u8,
+    pub offset: usize,
+    pub start: usize,
+    pub select: usize,
+    pub disconnect: usize,
+    pub len: usize,
+    pub address_space: String,
+}
+
+// The pointers stay valid while the game is loaded and are only read on the
+// emulation thread.
+unsafe impl Send for MemoryDescriptor {}
+
+impl MemoryDescriptor {
+    // Returns where an emulated address lands in this block, if it is mapped here.
+    // Blocks without a select mask cover `len` bytes from `start`; the rest match
+    // the address bits in `select`, and the `disconnect` bits are squeezed out.
+    fn offset_of(&self, address: usize) -> Option<usize> {
+        if self.ptr.is_null() {
+            return None;
+        }
+        let matches = if self.select == 0 {
+            address >= self.start && address - self.start < self.len
+        } else {
+            address & self.select == self.start & self.select
+        };
+        if !matches {
+            return None;
+        }
+        let offset = reduce(
+            address.wrapping_sub(self.start) & !self.disconnect,
+            self.disconnect,
+        );
+        (offset < self.len).then_some(self.offset + offset)
+    }
+}
+
+// Removes the bits set in `mask` from `address`, shifting the higher bits down.
+fn reduce(mut address: usize, mut mask: usize) -> usize {
+    while mask != 0 {
+        let low_bits = (mask - 1) & !mask;
+        address = (address & low_bits) | ((address >> 1) & !low_bits);
+        mask = (mask & (mask - 1)) >> 1;
+    }
+    address
+}
+
+pub static MEMORY_MAP: Lazy<Mutex<Vec<MemoryDescriptor>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+// Set when the core announces through ENVIRONMENT_SET_SUPPORT_ACHIEVEMENTS that its
+// memory can be used for achievements.
+static SUPPORTS_ACHIEVEMENTS: AtomicBool = AtomicBool::new(false);
+
+// Handles ENVIRONMENT_SET_SUPPORT_ACHIEVEMENTS.
+pub fn set_support_achievements(supported: bool) {
+    println!(
+        "Core {} achievements",
+        if supported {
+            "supports"
+        } else {
+            "does not support"
+        }
+    );
+    SUPPORTS_ACHIEVEMENTS.store(supported, Ordering::SeqCst);
+}
+
+// Returns whether the core announced achievement support.
+pub fn supports_achievements() -> bool {
+    SUPPORTS_ACHIEVEMENTS.load(Ordering::SeqCst)
+}
+
+// Handles ENVIRONMENT_SET_MEMORY_MAPS, replacing any map the core sent before.
+pub unsafe fn set_memory_maps(map: *const MemoryMap) {
+    let Some(map) = map.as_ref() else {
+        return;
+    };
+    let descriptors = if map.descriptors.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(map.descriptors, map.num_descriptors as usize)
+    };
+
+    let descriptors: Vec<MemoryDescriptor> = descriptors
+        .iter()
+        .map(|descriptor| MemoryDescriptor {
+            flags: descriptor.flags,
+            ptr: descriptor.ptr as *mut u8,
+            offset: descriptor.offset,
+            start: descriptor.start,
+            select: descriptor.select,
+            disconnect: descriptor.disconnect,
+            len: descriptor.len,
+            address_space: if descriptor.addrspace.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(descriptor.addrspace)
+                    .to_string_lossy()
+                    .into_owned()
+            },
+        })
+        .collect();
+
+    print_memory_map(&descriptors);
+    *MEMORY_MAP.lock().unwrap() = descriptors;
+}
+
+// Prints one line per descriptor, e.g. "  7E:000000 len 0x20000 select 0x0 disconnect 0x0".
+fn print_memory_map(descriptors: &[MemoryDescriptor]) {
+    println!("Core memory map with {} descriptor(s):", descriptors.len());
+    for descriptor in descriptors {
+        let mut flags = Vec::new();
+        if descriptor.flags & MEMDESC_CONST as u64 != 0 {
+            flags.push("const");
+        }
+        if descriptor.flags & MEMDESC_BIGENDIAN as u64 != 0 {
+            flags.push("big endian");
+        }
+        if descriptor.ptr.is_null() {
+            flags.push("unmapped");
+        }
+        println!(
+            "  {}:{:06X} len 0x{:X} select 0x{:X} disconnect 0x{:X} offset 0x{:X} {}",
+            descriptor.address_space,
+            descriptor.start,
+            descriptor.len,
+            descriptor.select,
+            descriptor.disconnect,
+            descriptor.offset,
+            flags.join(", ")
+        );
+    }
+}
+
+// Read access to the memory of the loaded game, either as whole regions from
+// retro_get_memory_data or byte by byte through the core's memory map, and write
+// access to whole regions. Slices and values are only valid until the next
+// retro_run.
+pub struct CoreMemory<'a> {
+    core_api: &'a CoreAPI,
+}
+
+impl<'a> CoreMemory<'a> {
+    pub fn new(core_api: &'a CoreAPI) -> Self {
+        CoreMemory { core_api }
+    }
+
+    // Returns one of the core's memory regions (MEMORY_SAVE_RAM, MEMORY_RTC,
+    // MEMORY_SYSTEM_RAM or MEMORY_VIDEO_RAM), if it has it.
+    pub fn region(&self, id: c_uint) -> Option<&[u8]> {
+        unsafe {
+            let data = (self.core_api.retro_get_memory_data)(id) as *const u8;
+            let size = (self.core_api.retro_get_memory_size)(id);
+            if data.is_null() || size == 0 {
+                return None;
+            }
+            Some(std::slice::from_raw_parts(data, size))
+        }
+    }
+
+    // Copies data over the start of a memory region, as much as fits, returning
+    // whether the core has the region.
+    pub fn write_region(&self, id: c_uint, data: &[u8]) -> bool {
+        unsafe {
+            let region = (self.core_api.retro_get_memory_data)(id) as *mut u8;
+            let size = (self.core_api.retro_get_memory_size)(id);
+            if region.is_null() || size == 0 {
+                return false;
+            }
+            std::ptr::copy_nonoverlapping(data.as_ptr(), region, data.len().min(size));
+            true
+        }
+    }
+
+    // Reads the byte at an emulated address through the memory map. Cores without
+    // a memory map have their system RAM treated as starting at address 0.
+    pub fn read_u8(&self, address: usize) -> Option<u8> {
+        let map = MEMORY_MAP.lock().unwrap();
+        if map.is_empty() {
+            return self
+                .region(libretro_sys::MEMORY_SYSTEM_RAM)
+                .and_then(|ram| ram.get(address).copied());
+        }
+        map.iter().find_map(|descriptor| {
+            let offset = descriptor.offset_of(address)?;
+            Some(unsafe { *descriptor.ptr.add(offset) })
+        })
+    }
+}
+
+// Prints which memory regions the core exposes and whether it supports achievements.
+pub fn print_regions(memory: &CoreMemory) {
+    let regions = [
+        (libretro_sys::MEMORY_SAVE_RAM, "save RAM"),
+        (libretro_sys::MEMORY_RTC, "RTC"),
+        (libretro_sys::MEMORY_SYSTEM_RAM, "system RAM"),
+        (libretro_sys::MEMORY_VIDEO_RAM, "video RAM"),
+    ];
+    for (id, name) in regions {
+        if let Some(region) = memory.region(id) {
+            println!("Core {}: {} bytes", name, region.len());
+        }
+    }
+    if supports_achievements() {
+        println!("Core memory can be used for achievements");
+    }
+}
+
+// Prints the emulated bytes given with --watch whenever they change, as a
+// lightweight way to follow game state while debugging.
+pub fn print_watches(
+    memory: &CoreMemory,
+    addresses: &[usize],
+    previous: &mut HashMap<usize, Option<u8>>,
+) {
+    for &address in addresses {
+        let value = memory.read_u8(address);
+        if previous.insert(address, value) != Some(value) {
+            match value {
+                Some(value) => println!("Memory {:06X}: {:02X}", address, value),
+                None => println!("Memory {:06X}: unmapped", address),
+            }
+        }
+    }
+}
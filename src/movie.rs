@@ -0,0 +1,236 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// movie.rs
+//
+// This module records the input the core reads to a movie file from the hotkey,
+// for tool-assisted runs and for reproducing bugs. Movies are laid out as
+// RetroArch's BSV files: a 16 byte header with the CRC32 of the content, a save
+// state the movie starts from, then the answer to every input query the core
+// made, in order, as little endian 16 bit values. The emulation is deterministic,
+// so loading the state and answering the queries the same way replays the
+// movie. They are saved in `screenshot_directory` with the other recordings.
+//
+// `--play-movie` replays one in place of the keyboard and gamepads, until it ends
+// or the take over hotkey hands control back to them part way through.
+
+use libretro_sys::CoreAPI;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::{libretro, osd, screenshot};
+
+// "BSV1", which begins every movie.
+const MAGIC: &[u8; 4] = b"BSV1";
+
+struct Recording {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+// A movie being replayed: the recorded answers, and how many have been given.
+struct Playback {
+    inputs: Vec<i16>,
+    position: usize,
+}
+
+static PLAYBACK: Mutex<Option<Playback>> = Mutex::new(None);
+
+// Starts recording from the current frame, or stops the recording in progress.
+pub fn toggle(core_api: &CoreAPI, config: &HashMap<String, String>, rom_name: &str) {
+    let mut recording = RECORDING.lock().unwrap();
+    if let Some(recording) = recording.take() {
+        finish(recording);
+        return;
+    }
+    match start(core_api, config, rom_name) {
+        Ok(started) => {
+            println!("Recording input to {}", started.path.display());
+            osd::show_message("Recording movie");
+            *recording = Some(started);
+        }
+        Err(e) => {
+            println!("Failed to record movie: {}", e);
+            osd::show_message("Movie recording failed");
+        }
+    }
+}
+
+// Opens a new movie starting from a save state of the current frame.
+fn start(
+    core_api: &CoreAPI,
+    config: &HashMap<String, String>,
+    rom_name: &str,
+) -> Result<Recording, String> {
+    let state = unsafe { libretro::serialize(core_api)? };
+    // Without content, e.g. for a core that runs on its own, the CRC is left 0
+    let content_crc = fs::read(rom_name).map_or(0, |content| crc32(&content));
+    let path = screenshot::new_path(config, rom_name, "bsv")?;
+    let mut writer = BufWriter::new(File::create(&path).map_err(|e| e.to_string())?);
+    writer
+        .write_all(&header(content_crc, state.len()))
+        .and_then(|_| writer.write_all(&state))
+        .map_err(|e| e.to_string())?;
+    Ok(Recording { writer, path })
+}
+
+// The header of a movie for content with the given CRC32, starting from a save
+// state of `state_size` bytes. The second word is reserved.
+fn header(content_crc: u32, state_size: usize) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&content_crc.to_le_bytes());
+    header[12..].copy_from_slice(&(state_size as u32).to_le_bytes());
+    header
+}
+
+// CRC-32 as zlib computes it, with the reflected polynomial 0xedb88320.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+// A movie read back: the CRC32 of its content, the save state it starts from and
+// the recorded answers to input queries.
+struct Movie {
+    content_crc: u32,
+    state: Vec<u8>,
+    inputs: Vec<i16>,
+}
+
+fn parse(movie: &[u8]) -> Result<Movie, String> {
+    if movie.len() < 16 || &movie[..4] != MAGIC {
+        return Err("not a BSV movie".to_string());
+    }
+    let word = |offset: usize| u32::from_le_bytes(movie[offset..offset + 4].try_into().unwrap());
+    let state_end = 16 + word(12) as usize;
+    if movie.len() < state_end {
+        return Err("the movie's save state is cut short".to_string());
+    }
+    Ok(Movie {
+        content_crc: word(8),
+        state: movie[16..state_end].to_vec(),
+        inputs: movie[state_end..]
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect(),
+    })
+}
+
+// Loads a movie's save state and starts replaying its input. Called once the
+// content is loaded.
+pub fn play(core_api: &CoreAPI, path: &str, rom_name: &str) -> Result<(), String> {
+    let movie = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut movie = parse(&movie)?;
+    let content_crc = fs::read(rom_name).map_or(0, |content| crc32(&content));
+    if movie.content_crc != content_crc {
+        // It may still play, e.g. with a differently headered dump of the game
+        println!("The movie was recorded with different content, it may not play back right");
+    }
+    if !unsafe { libretro::unserialize(core_api, &mut movie.state) } {
+        return Err("the core failed to load the movie's save state".to_string());
+    }
+    println!("Playing movie {}", path);
+    osd::show_message("Playing movie");
+    *PLAYBACK.lock().unwrap() = Some(Playback {
+        inputs: movie.inputs,
+        position: 0,
+    });
+    Ok(())
+}
+
+// Returns the recorded answer to the next input query while a movie is playing,
+// and hands control back once it has run out.
+pub fn play_input() -> Option<i16> {
+    let mut playback = PLAYBACK.lock().unwrap();
+    let current = playback.as_mut()?;
+    let Some(&input) = current.inputs.get(current.position) else {
+        *playback = None;
+        println!("The movie has finished");
+        osd::show_message("Movie finished");
+        return None;
+    };
+    current.position += 1;
+    Some(input)
+}
+
+// Stops replaying a movie part way through, handing control to the player.
+pub fn take_over() {
+    if PLAYBACK.lock().unwrap().take().is_some() {
+        println!("Took over from the movie");
+        osd::show_message("Took over from the movie");
+    }
+}
+
+// Closes a movie, writing out what is left of it.
+fn finish(mut recording: Recording) {
+    match recording.writer.flush() {
+        Ok(()) => {
+            println!("Saved movie to {}", recording.path.display());
+            osd::show_message("Movie saved");
+        }
+        Err(e) => {
+            println!("Failed to save movie: {}", e);
+            osd::show_message("Movie recording failed");
+        }
+    }
+}
+
+// Records the answer to an input query, if a movie is being recorded.
+pub fn record(state: i16) {
+    let mut recording = RECORDING.lock().unwrap();
+    let Some(current) = recording.as_mut() else {
+        return;
+    };
+    if let Err(e) = current.writer.write_all(&state.to_le_bytes()) {
+        println!("Failed to write movie: {}", e);
+        finish(recording.take().unwrap());
+    }
+}
+
+// Ends the recording in progress, e.g. when the frontend closes.
+pub fn stop() {
+    if let Some(recording) = RECORDING.lock().unwrap().take() {
+        finish(recording);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_bsv_headers() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(
+            header(0xcbf43926, 0x1234),
+            [b'B', b'S', b'V', b'1', 0, 0, 0, 0, 0x26, 0x39, 0xf4, 0xcb, 0x34, 0x12, 0, 0]
+        );
+    }
+
+    #[test]
+    fn reads_movies_back() {
+        let mut movie = header(7, 3).to_vec();
+        movie.extend([1, 2, 3, 0xff, 0xff, 1, 0]);
+        let movie = parse(&movie).unwrap();
+        assert_eq!(movie.content_crc, 7);
+        assert_eq!(movie.state, [1, 2, 3]);
+        assert_eq!(movie.inputs, [-1, 1]);
+
+        assert!(parse(&header(7, 3)).is_err());
+        assert!(parse(b"RIFF0000WAVEfmt ").is_err());
+    }
+}
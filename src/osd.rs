@@ -0,0 +1,302 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// osd.rs
+//
+// This module implements on-screen messages. Cores send them with
+// ENVIRONMENT_SET_MESSAGE and ENVIRONMENT_SET_MESSAGE_EXT (e.g. "Disk 2 inserted"),
+// and they are drawn over the window buffer with a built-in 5x7 bitmap font.
+
+use libc::{c_char, c_int, c_uint};
+use libretro_sys::Message;
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::logging;
+
+// Values of retro_message_target.
+const MESSAGE_TARGET_ALL: c_int = 0;
+const MESSAGE_TARGET_OSD: c_int = 1;
+
+// Values of retro_message_type.
+const MESSAGE_TYPE_PROGRESS: c_int = 3;
+
+// Priority given to messages sent with the original ENVIRONMENT_SET_MESSAGE.
+const DEFAULT_PRIORITY: u32 = 1;
+
+// Priority and duration of messages from the frontend itself.
+const FRONTEND_PRIORITY: u32 = 2;
+const FRONTEND_MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+// Number of messages shown at once; lower priority ones wait until these expire.
+const MAX_VISIBLE_MESSAGES: usize = 3;
+
+// Size of a glyph in font pixels, including one pixel of spacing.
+const GLYPH_WIDTH: usize = 6;
+pub const GLYPH_HEIGHT: usize = 8;
+
+const TEXT_COLOR: u32 = 0xFFFFFF;
+const SHADOW_COLOR: u32 = 0x000000;
+
+// struct retro_message_ext, which libretro-sys predates.
+#[repr(C)]
+pub struct MessageExt {
+    pub msg: *const c_char,
+    pub duration: c_uint,
+    pub priority: c_uint,
+    pub level: c_int,
+    pub target: c_int,
+    pub message_type: c_int,
+    pub progress: i8,
+}
+
+// How long a message stays on screen. SET_MESSAGE counts presented frames,
+// SET_MESSAGE_EXT gives a duration in milliseconds.
+enum Lifetime {
+    Frames(u32),
+    Until(Instant),
+}
+
+struct OsdMessage {
+    text: String,
+    priority: u32,
+    lifetime: Lifetime,
+}
+
+// Messages are queued from the environment callback and drawn by the main loop,
+// both on the emulation thread.
+static MESSAGES: Lazy<Mutex<Vec<OsdMessage>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+fn push(text: String, priority: u32, lifetime: Lifetime) {
+    let mut messages = MESSAGES.lock().unwrap();
+    // A newer message with the same text replaces the old one instead of stacking
+    messages.retain(|message| message.text != text);
+    messages.push(OsdMessage {
+        text,
+        priority,
+        lifetime,
+    });
+}
+
+// Shows a message from the frontend itself, e.g. why a save state failed.
+pub fn show_message(text: &str) {
+    let lifetime = Lifetime::Until(Instant::now() + FRONTEND_MESSAGE_DURATION);
+    push(text.to_string(), FRONTEND_PRIORITY, lifetime);
+}
+
+// Handles ENVIRONMENT_SET_MESSAGE.
+pub unsafe fn set_message(message: *const Message) -> bool {
+    let Some(message) = message.as_ref() else {
+        return false;
+    };
+    if message.msg.is_null() {
+        return false;
+    }
+    let text = CStr::from_ptr(message.msg).to_string_lossy().into_owned();
+    println!("Core message: {}", text);
+    push(text, DEFAULT_PRIORITY, Lifetime::Frames(message.frames));
+    true
+}
+
+// Handles ENVIRONMENT_SET_MESSAGE_EXT. Messages aimed at the log go through the
+// core log interface so they are filtered by `libretro_log_level` like the rest.
+pub unsafe fn set_message_ext(message: *const MessageExt) -> bool {
+    let Some(message) = message.as_ref() else {
+        return false;
+    };
+    if message.msg.is_null() {
+        return false;
+    }
+    if message.target != MESSAGE_TARGET_OSD {
+        logging::rustretro_log_message(message.level, message.msg);
+    }
+    if message.target == MESSAGE_TARGET_ALL || message.target == MESSAGE_TARGET_OSD {
+        let mut text = CStr::from_ptr(message.msg).to_string_lossy().into_owned();
+        if message.message_type == MESSAGE_TYPE_PROGRESS && message.progress >= 0 {
+            text = format!("{} ({}%)", text, message.progress);
+        }
+        let lifetime =
+            Lifetime::Until(Instant::now() + Duration::from_millis(message.duration as u64));
+        push(text, message.priority, lifetime);
+    }
+    true
+}
+
+// Draws the current messages in the bottom left corner of the window buffer and
+// counts down the frame-based ones. Called once per presented frame.
+pub fn draw(buffer: &mut [u32], width: usize, height: usize) {
+    let mut messages = MESSAGES.lock().unwrap();
+    let now = Instant::now();
+    messages.retain(|message| match message.lifetime {
+        Lifetime::Frames(frames) => frames > 0,
+        Lifetime::Until(deadline) => deadline > now,
+    });
+    if messages.is_empty() {
+        return;
+    }
+
+    // Highest priority first, newest first among equals
+    let mut order: Vec<usize> = (0..messages.len()).rev().collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(messages[index].priority));
+    order.truncate(MAX_VISIBLE_MESSAGES);
+
+    let scale = font_scale(height);
+    let line_height = GLYPH_HEIGHT * scale;
+    let mut y = height.saturating_sub(line_height * order.len() + scale * 2);
+    for &index in &order {
+        draw_text(
+            buffer,
+            width,
+            height,
+            &messages[index].text,
+            scale * 2,
+            y,
+            scale,
+        );
+        y += line_height;
+        if let Lifetime::Frames(frames) = &mut messages[index].lifetime {
+            *frames -= 1;
+        }
+    }
+}
+
+// Returns how many window pixels make up a font pixel, which grows with the window
+// so text stays readable at large sizes.
+pub fn font_scale(height: usize) -> usize {
+    (height / 240).max(1)
+}
+
+// Draws one line of text with a drop shadow, clipped to the buffer.
+pub fn draw_text(
+    buffer: &mut [u32],
+    width: usize,
+    height: usize,
+    text: &str,
+    x: usize,
+    y: usize,
+    scale: usize,
+) {
+    for (offset, color) in [(scale, SHADOW_COLOR), (0, TEXT_COLOR)] {
+        for (column, character) in text.chars().enumerate() {
+            let glyph = glyph(character);
+            let glyph_x = x + offset + column * GLYPH_WIDTH * scale;
+            if glyph_x >= width {
+                break;
+            }
+            for (row, bits) in glyph.iter().enumerate() {
+                for bit in 0..5 {
+                    if bits & (0x10 >> bit) == 0 {
+                        continue;
+                    }
+                    fill(
+                        buffer,
+                        width,
+                        height,
+                        glyph_x + bit * scale,
+                        y + offset + row * scale,
+                        scale,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Fills a square of `size` pixels, clipped to the buffer.
+pub fn fill(
+    buffer: &mut [u32],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    size: usize,
+    color: u32,
+) {
+    for row in y..(y + size).min(height) {
+        for column in x..(x + size).min(width) {
+            buffer[row * width + column] = color;
+        }
+    }
+}
+
+// Returns the rows of a character's glyph. Lowercase letters use the uppercase
+// glyphs and anything the font doesn't cover is drawn as '?'.
+fn glyph(character: char) -> &'static [u8; 7] {
+    let character = character.to_ascii_uppercase();
+    match character {
+        ' '..='_' => &FONT[character as usize - ' ' as usize],
+        _ => &FONT['?' as usize - ' ' as usize],
+    }
+}
+
+// 5x7 glyphs for ' ' through '_', one byte per row with the leftmost pixel in bit 4.
+const FONT: [[u8; 7]; 64] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04], // !
+    [0x0A, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x00], // "
+    [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A], // #
+    [0x04, 0x0F, 0x14, 0x0E, 0x05, 0x1E, 0x04], // $
+    [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03], // %
+    [0x0C, 0x12, 0x14, 0x08, 0x15, 0x12, 0x0D], // &
+    [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02], // (
+    [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08], // )
+    [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00], // *
+    [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08], // ,
+    [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C], // .
+    [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00], // /
+    [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E], // 0
+    [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E], // 1
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F], // 2
+    [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E], // 3
+    [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02], // 4
+    [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E], // 5
+    [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E], // 6
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08], // 7
+    [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E], // 8
+    [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x04, 0x08], // ;
+    [0x02, 0x04, 0x08, 0x10, 0x08, 0x04, 0x02], // <
+    [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00], // =
+    [0x08, 0x04, 0x02, 0x01, 0x02, 0x04, 0x08], // >
+    [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04], // ?
+    [0x0E, 0x11, 0x01, 0x0D, 0x15, 0x15, 0x0E], // @
+    [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11], // A
+    [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E], // B
+    [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E], // C
+    [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C], // D
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F], // E
+    [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10], // F
+    [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F], // G
+    [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11], // H
+    [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E], // I
+    [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C], // J
+    [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11], // K
+    [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F], // L
+    [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11], // M
+    [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11], // N
+    [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // O
+    [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10], // P
+    [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D], // Q
+    [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11], // R
+    [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E], // S
+    [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04], // T
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E], // U
+    [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04], // V
+    [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A], // W
+    [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11], // X
+    [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04], // Y
+    [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F], // Z
+    [0x0E, 0x08, 0x08, 0x08, 0x08, 0x08, 0x0E], // [
+    [0x00, 0x10, 0x08, 0x04, 0x02, 0x01, 0x00], // '\\'
+    [0x0E, 0x02, 0x02, 0x02, 0x02, 0x02, 0x0E], // ]
+    [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F], // _
+];
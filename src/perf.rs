@@ -0,0 +1,160 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// perf.rs
+//
+// This module implements the libretro performance interface: a microsecond clock,
+// CPU feature detection and the counters cores register to profile themselves.
+
+use libretro_sys::{CoreAPI, PerfCallback, PerfCounter, PerfTick, Time};
+use once_cell::sync::Lazy;
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{audio, frame_time};
+
+// Reference point for get_time_usec and get_perf_counter.
+static START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
+
+// Counters registered by the core. They live in the core's memory for as long as
+// the core is loaded, so we only keep their addresses.
+struct RegisteredCounter(*mut PerfCounter);
+
+// The counters are only dereferenced on the emulation thread or after it has stopped.
+unsafe impl Send for RegisteredCounter {}
+
+static REGISTERED_COUNTERS: Lazy<Mutex<Vec<RegisteredCounter>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+// Builds the interface returned for ENVIRONMENT_GET_PERF_INTERFACE.
+pub fn perf_interface() -> PerfCallback {
+    Lazy::force(&START_TIME);
+    PerfCallback {
+        get_time_usec,
+        get_cpu_features,
+        get_perf_counter,
+        perf_register,
+        perf_start,
+        perf_stop,
+        perf_log,
+    }
+}
+
+unsafe extern "C" fn get_time_usec() -> Time {
+    START_TIME.elapsed().as_micros() as Time
+}
+
+// Counter ticks are nanoseconds since the interface was first handed out.
+unsafe extern "C" fn get_perf_counter() -> PerfTick {
+    START_TIME.elapsed().as_nanos() as PerfTick
+}
+
+// Reports the SIMD extensions available on this CPU as SIMD_* flags.
+unsafe extern "C" fn get_cpu_features() -> u64 {
+    #[allow(unused_mut)]
+    let mut features: u64 = 0;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        let detected = [
+            (is_x86_feature_detected!("mmx"), libretro_sys::SIMD_MMX),
+            (is_x86_feature_detected!("sse"), libretro_sys::SIMD_SSE),
+            (is_x86_feature_detected!("sse2"), libretro_sys::SIMD_SSE2),
+            (is_x86_feature_detected!("sse3"), libretro_sys::SIMD_SSE3),
+            (is_x86_feature_detected!("ssse3"), libretro_sys::SIMD_SSSE3),
+            (is_x86_feature_detected!("sse4.1"), libretro_sys::SIMD_SSE4),
+            (is_x86_feature_detected!("sse4.2"), libretro_sys::SIMD_SSE42),
+            (is_x86_feature_detected!("avx"), libretro_sys::SIMD_AVX),
+            (is_x86_feature_detected!("avx2"), libretro_sys::SIMD_AVX2),
+            (is_x86_feature_detected!("aes"), libretro_sys::SIMD_AES),
+            (
+                is_x86_feature_detected!("popcnt"),
+                libretro_sys::SIMD_POPCNT,
+            ),
+        ];
+        for (available, flag) in detected {
+            if available {
+                features |= flag as u64;
+            }
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // NEON is mandatory on AArch64.
+        features |= libretro_sys::SIMD_NEON as u64;
+    }
+    features
+}
+
+unsafe extern "C" fn perf_register(counter: *mut PerfCounter) {
+    if counter.is_null() || (*counter).registered {
+        return;
+    }
+    (*counter).registered = true;
+    REGISTERED_COUNTERS
+        .lock()
+        .unwrap()
+        .push(RegisteredCounter(counter));
+}
+
+unsafe extern "C" fn perf_start(counter: *mut PerfCounter) {
+    if counter.is_null() {
+        return;
+    }
+    (*counter).call_cnt += 1;
+    (*counter).start = get_perf_counter();
+}
+
+unsafe extern "C" fn perf_stop(counter: *mut PerfCounter) {
+    if counter.is_null() {
+        return;
+    }
+    (*counter).total += get_perf_counter() - (*counter).start;
+}
+
+unsafe extern "C" fn perf_log() {
+    log_counters();
+}
+
+// Prints the accumulated time and call count of every registered counter.
+pub fn log_counters() {
+    let counters = REGISTERED_COUNTERS.lock().unwrap();
+    println!("Performance counters ({} registered):", counters.len());
+    for RegisteredCounter(counter) in counters.iter() {
+        unsafe {
+            let counter = &**counter;
+            let ident = if counter.ident.is_null() {
+                "<unnamed>".into()
+            } else {
+                CStr::from_ptr(counter.ident).to_string_lossy()
+            };
+            let average = counter.total / counter.call_cnt.max(1);
+            println!(
+                "  {}: {} calls, {} ns total, {} ns/call",
+                ident, counter.call_cnt, counter.total, average
+            );
+        }
+    }
+}
+
+// Runs the given number of frames as fast as the core can go and prints how that
+// compares to the core's own frame rate. Audio and video should be disabled first.
+pub fn run_benchmark(core_api: &CoreAPI, frames: u64, fps: f64) {
+    println!("Benchmarking {} frames", frames);
+    let start = Instant::now();
+    for _ in 0..frames {
+        frame_time::notify();
+        unsafe { (core_api.retro_run)() };
+        audio::flush_single_samples();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let measured_fps = frames as f64 / elapsed;
+    println!(
+        "Ran {} frames in {:.3} s: {:.1} fps, {:.2}x real time",
+        frames,
+        elapsed,
+        measured_fps,
+        if fps > 0.0 { measured_fps / fps } else { 0.0 }
+    );
+}
@@ -0,0 +1,167 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// perf_overlay.rs
+//
+// This module draws a performance overlay in the top left corner of the window,
+// shown with `perf_overlay` or toggled with its hotkey: the frame rate, a graph of
+// recent frame times against the core's frame duration, how much audio is queued
+// and how often it ran out or overflowed, and how many frames ran late.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::{audio, osd, speed, video};
+
+// Frames shown in the graph, one column each.
+const GRAPH_FRAMES: usize = 120;
+// Height of the graph in font pixels. The top is twice the core's frame duration.
+const GRAPH_HEIGHT: usize = 32;
+
+const GRAPH_COLOR: u32 = 0x40FF40;
+const SLOW_FRAME_COLOR: u32 = 0xFF4040;
+const TARGET_COLOR: u32 = 0xFFFF40;
+
+struct Overlay {
+    enabled: bool,
+    // How long each of the last GRAPH_FRAMES frames took, oldest first.
+    frame_times: VecDeque<Duration>,
+    last_frame: Option<Instant>,
+}
+
+static OVERLAY: Mutex<Overlay> = Mutex::new(Overlay {
+    enabled: false,
+    frame_times: VecDeque::new(),
+    last_frame: None,
+});
+
+// Reads `perf_overlay` from the config.
+pub fn configure(config: &HashMap<String, String>) {
+    OVERLAY.lock().unwrap().enabled = config
+        .get("perf_overlay")
+        .is_some_and(|value| value == "true");
+}
+
+// Shows or hides the overlay from the hotkey.
+pub fn toggle() {
+    let mut overlay = OVERLAY.lock().unwrap();
+    overlay.enabled = !overlay.enabled;
+    osd::show_message(if overlay.enabled {
+        "Performance overlay on"
+    } else {
+        "Performance overlay off"
+    });
+}
+
+// Counts a frame of the main loop. Frame times are kept while the overlay is
+// hidden, so the graph is full as soon as it shows.
+pub fn frame() {
+    let mut overlay = OVERLAY.lock().unwrap();
+    let now = Instant::now();
+    if let Some(last_frame) = overlay.last_frame {
+        if overlay.frame_times.len() == GRAPH_FRAMES {
+            overlay.frame_times.pop_front();
+        }
+        overlay.frame_times.push_back(now - last_frame);
+    }
+    overlay.last_frame = Some(now);
+}
+
+// Returns the average frame rate over the frame times.
+fn average_fps(frame_times: &VecDeque<Duration>) -> f64 {
+    let total: Duration = frame_times.iter().sum();
+    if total.is_zero() {
+        return 0.0;
+    }
+    frame_times.len() as f64 / total.as_secs_f64()
+}
+
+// Returns how many font pixels tall a frame time's bar is, with the core's frame
+// duration halfway up the graph.
+fn bar_height(frame_time: Duration, target: Duration) -> usize {
+    let height = frame_time.as_secs_f64() / target.as_secs_f64() * (GRAPH_HEIGHT / 2) as f64;
+    (height.round() as usize).min(GRAPH_HEIGHT)
+}
+
+// Draws the overlay over the window buffer, if it is on.
+pub fn draw(buffer: &mut [u32], width: usize, height: usize) {
+    let overlay = OVERLAY.lock().unwrap();
+    if !overlay.enabled {
+        return;
+    }
+    let scale = osd::font_scale(height);
+    let x = scale * 2;
+    let mut y = scale * 2;
+    let fps = average_fps(&overlay.frame_times);
+    let lines = [
+        format!("{:.1} fps", fps),
+        format!(
+            "Audio queue: {} ms ({}%)",
+            audio::queued_ms(),
+            audio::occupancy()
+        ),
+        format!(
+            "Audio underruns: {}, dropped: {}",
+            audio::underruns(),
+            audio::dropped_samples()
+        ),
+        format!("Late frames: {}", speed::late_frames()),
+    ];
+    for line in &lines {
+        osd::draw_text(buffer, width, height, line, x, y, scale);
+        y += osd::GLYPH_HEIGHT * scale;
+    }
+
+    // One column per frame, bottom up, red past the core's frame duration
+    let target = video::frame_duration(speed::fps());
+    let bottom = y + GRAPH_HEIGHT * scale;
+    for (column, &frame_time) in overlay.frame_times.iter().enumerate() {
+        let color = if frame_time > target + target / 10 {
+            SLOW_FRAME_COLOR
+        } else {
+            GRAPH_COLOR
+        };
+        for row in 0..bar_height(frame_time, target) {
+            let pixel_y = bottom - (row + 1) * scale;
+            osd::fill(
+                buffer,
+                width,
+                height,
+                x + column * scale,
+                pixel_y,
+                scale,
+                color,
+            );
+        }
+    }
+    let target_y = bottom - (GRAPH_HEIGHT / 2) * scale;
+    for column in 0..GRAPH_FRAMES {
+        osd::fill(
+            buffer,
+            width,
+            height,
+            x + column * scale,
+            target_y,
+            scale,
+            TARGET_COLOR,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_bars_to_the_frame_duration() {
+        let target = Duration::from_micros(16_667);
+        assert_eq!(bar_height(target, target), GRAPH_HEIGHT / 2);
+        assert_eq!(bar_height(target * 5, target), GRAPH_HEIGHT);
+        assert_eq!(bar_height(Duration::ZERO, target), 0);
+        let frame_times = VecDeque::from([Duration::from_millis(20); 4]);
+        assert_eq!(average_fps(&frame_times), 50.0);
+        assert_eq!(average_fps(&VecDeque::new()), 0.0);
+    }
+}
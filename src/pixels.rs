@@ -0,0 +1,465 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// pixels.rs
+//
+// This module holds the per-pixel work of the video path: converting the core's
+// frames to XRGB8888 and scaling them up to the window. The loops run over whole
+// lines with chunked iterators and lookup tables so the compiler can vectorize
+// them, and the module has no frontend state so benches/video.rs can build it on
+// its own.
+
+use libretro_sys::PixelFormat;
+use std::ops::Range;
+
+// Returns the size of one pixel in the given format.
+pub fn bytes_per_pixel(pixel_format: PixelFormat) -> u8 {
+    match pixel_format {
+        PixelFormat::ARGB1555 | PixelFormat::RGB565 => 2,
+        PixelFormat::ARGB8888 => 4,
+    }
+}
+
+// Returns how many bytes a frame with lines `pitch` bytes apart spans. The last
+// line may end right after its last pixel, without the padding of the others.
+pub fn frame_length(pixel_format: PixelFormat, width: usize, height: usize, pitch: usize) -> usize {
+    let line_length = width * bytes_per_pixel(pixel_format) as usize;
+    match height {
+        0 => 0,
+        height => pitch.max(line_length) * (height - 1) + line_length,
+    }
+}
+
+// Converts a frame in the core's pixel format to XRGB8888, one line at a time so
+// the padding at the end of each line is dropped. `frame` is resized to fit it, so
+// buffers can be reused from frame to frame.
+pub fn convert_frame_into(
+    data: &[u8],
+    pixel_format: PixelFormat,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    frame: &mut Vec<u32>,
+) {
+    let line_length = width * bytes_per_pixel(pixel_format) as usize;
+    let convert_line = match pixel_format {
+        PixelFormat::ARGB8888 => convert_xrgb8888_line,
+        PixelFormat::RGB565 => convert_rgb565_line,
+        PixelFormat::ARGB1555 => convert_0rgb1555_line,
+    };
+    frame.clear();
+    frame.resize(width * height, 0);
+    for (line, dest) in data
+        .chunks(pitch.max(line_length))
+        .zip(frame.chunks_exact_mut(width.max(1)))
+    {
+        convert_line(&line[..line_length], dest);
+    }
+}
+
+// Copies a line of XRGB8888 pixels, clearing the unused top byte.
+pub fn convert_xrgb8888_line(line: &[u8], dest: &mut [u32]) {
+    for (pixel, bytes) in dest.iter_mut().zip(line.chunks_exact(4)) {
+        *pixel = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x00FF_FFFF;
+    }
+}
+
+// Converts a line of native-endian RGB565 pixels: 5 bits of red, 6 of green and 5
+// of blue. Each channel's high bits are repeated into the low bits it gains, so
+// full intensity stays full.
+pub fn convert_rgb565_line(line: &[u8], dest: &mut [u32]) {
+    for (pixel, bytes) in dest.iter_mut().zip(line.chunks_exact(2)) {
+        let value = u16::from_ne_bytes([bytes[0], bytes[1]]) as u32;
+        let red = (value >> 11) & 0b1_1111;
+        let green = (value >> 5) & 0b11_1111;
+        let blue = value & 0b1_1111;
+        let red = (red << 3) | (red >> 2);
+        let green = (green << 2) | (green >> 4);
+        let blue = (blue << 3) | (blue >> 2);
+        *pixel = (red << 16) | (green << 8) | blue;
+    }
+}
+
+// Converts a line of the legacy 0RGB1555 format, which cores use until they set
+// another. Each channel has 5 bits and the top bit is unused.
+pub fn convert_0rgb1555_line(line: &[u8], dest: &mut [u32]) {
+    for (pixel, bytes) in dest.iter_mut().zip(line.chunks_exact(2)) {
+        let value = u16::from_ne_bytes([bytes[0], bytes[1]]) as u32;
+        let red = (value >> 10) & 0b1_1111;
+        let green = (value >> 5) & 0b1_1111;
+        let blue = value & 0b1_1111;
+        let red = (red << 3) | (red >> 2);
+        let green = (green << 3) | (green >> 2);
+        let blue = (blue << 3) | (blue >> 2);
+        *pixel = (red << 16) | (green << 8) | blue;
+    }
+}
+
+// An XRGB8888 picture whose lines are `stride` pixels apart.
+pub struct Frame<'a> {
+    pub pixels: &'a [u32],
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+}
+
+impl Frame<'_> {
+    pub fn line(&self, y: usize) -> &[u32] {
+        &self.pixels[y * self.stride..][..self.width]
+    }
+}
+
+// Rotates a picture counter-clockwise in steps of 90 degrees, appending the packed
+// pixels of the rotated picture to `rotated`. Without rotation the lines are only
+// packed.
+pub fn rotate(frame: &Frame, rotation: u32, rotated: &mut Vec<u32>) {
+    let (width, height) = (frame.width, frame.height);
+    rotated.reserve(width * height);
+    match rotation % 4 {
+        1 => {
+            for x in (0..width).rev() {
+                rotated.extend((0..height).map(|y| frame.line(y)[x]));
+            }
+        }
+        2 => {
+            for y in (0..height).rev() {
+                rotated.extend(frame.line(y).iter().rev());
+            }
+        }
+        3 => {
+            for x in 0..width {
+                rotated.extend((0..height).rev().map(|y| frame.line(y)[x]));
+            }
+        }
+        _ => {
+            for y in 0..height {
+                rotated.extend_from_slice(frame.line(y));
+            }
+        }
+    }
+}
+
+// Scales the target lines in `rows` of a picture scaled to `width` x `height` by
+// repeating the nearest pixel. `dest` starts at the left of the first of them and
+// its lines are `dest_stride` apart, so bands of the target can be scaled on
+// different threads. Target lines showing the same source line are copied from the
+// one above.
+pub fn scale_nearest_rows(
+    frame: &Frame,
+    dest: &mut [u32],
+    dest_stride: usize,
+    width: usize,
+    height: usize,
+    rows: Range<usize>,
+) {
+    let columns: Vec<usize> = (0..width).map(|x| x * frame.width / width).collect();
+    let mut previous: Option<(usize, usize)> = None;
+    for y in rows.clone() {
+        let source_y = y * frame.height / height;
+        let start = (y - rows.start) * dest_stride;
+        match previous {
+            Some((previous_y, previous_start)) if previous_y == source_y => {
+                dest.copy_within(previous_start..previous_start + width, start);
+            }
+            _ => {
+                let source = frame.line(source_y);
+                for (pixel, &x) in dest[start..start + width].iter_mut().zip(&columns) {
+                    *pixel = source[x];
+                }
+                previous = Some((source_y, start));
+            }
+        }
+    }
+}
+
+// Scales a picture to `width` x `height` with bilinear filtering, laid out in
+// `dest` like scale_nearest_rows. Source lines are first stretched horizontally, and
+// kept while neighbouring target lines fall between the same two, so the work
+// per target line is one blend of two prepared lines.
+pub fn scale_bilinear(
+    frame: &Frame,
+    dest: &mut [u32],
+    dest_stride: usize,
+    width: usize,
+    height: usize,
+) {
+    scale_bilinear_rows(frame, dest, dest_stride, width, height, 0..height);
+}
+
+// Scales only the target lines in `rows` like scale_bilinear, with `dest` starting
+// at the first of them.
+pub fn scale_bilinear_rows(
+    frame: &Frame,
+    dest: &mut [u32],
+    dest_stride: usize,
+    width: usize,
+    height: usize,
+    rows: Range<usize>,
+) {
+    let columns: Vec<(usize, usize, u32)> = (0..width)
+        .map(|x| sample_position(x, width, frame.width))
+        .collect();
+    let stretch = |y: usize, line: &mut [u32]| {
+        let source = frame.line(y);
+        for (pixel, &(x0, x1, weight)) in line.iter_mut().zip(&columns) {
+            *pixel = blend(source[x0], source[x1], weight);
+        }
+    };
+
+    let mut top = vec![0; width];
+    let mut bottom = vec![0; width];
+    let mut stretched: Option<(usize, usize)> = None;
+    for y in rows.clone() {
+        let (y0, y1, weight) = sample_position(y, height, frame.height);
+        match stretched {
+            Some(lines) if lines == (y0, y1) => {}
+            Some((_, previous_y1)) if previous_y1 == y0 => {
+                std::mem::swap(&mut top, &mut bottom);
+                stretch(y1, &mut bottom);
+            }
+            _ => {
+                stretch(y0, &mut top);
+                stretch(y1, &mut bottom);
+            }
+        }
+        stretched = Some((y0, y1));
+
+        let start = (y - rows.start) * dest_stride;
+        for ((pixel, &upper), &lower) in
+            dest[start..start + width].iter_mut().zip(&top).zip(&bottom)
+        {
+            *pixel = blend(upper, lower, weight);
+        }
+    }
+}
+
+// Returns the two source pixels a target pixel lies between along one axis, and how
+// far it is from the first towards the second in 1/256ths.
+pub fn sample_position(
+    target: usize,
+    target_size: usize,
+    source_size: usize,
+) -> (usize, usize, u32) {
+    let position = ((target * 2 + 1) * source_size * 128 / target_size).saturating_sub(128);
+    let first = (position >> 8).min(source_size - 1);
+    let second = (first + 1).min(source_size - 1);
+    (first, second, (position & 0xFF) as u32)
+}
+
+// Mixes two XRGB8888 pixels, taking `weight`/256 of the second.
+pub fn blend(first: u32, second: u32, weight: u32) -> u32 {
+    let inverse = 256 - weight;
+    let red_blue = ((first & 0xFF00FF) * inverse + (second & 0xFF00FF) * weight) >> 8;
+    let green = ((first & 0x00FF00) * inverse + (second & 0x00FF00) * weight) >> 8;
+    (red_blue & 0xFF00FF) | (green & 0x00FF00)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels_16(pixels: &[u16]) -> Vec<u8> {
+        pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect()
+    }
+
+    fn convert(pixel_format: PixelFormat, data: &[u8]) -> Vec<u32> {
+        let width = data.len() / bytes_per_pixel(pixel_format) as usize;
+        let mut frame = Vec::new();
+        convert_frame_into(data, pixel_format, width, 1, data.len(), &mut frame);
+        frame
+    }
+
+    #[test]
+    fn converts_0rgb1555_primaries() {
+        let converted = convert(
+            PixelFormat::ARGB1555,
+            &pixels_16(&[
+                0b0_11111_00000_00000,
+                0b0_00000_11111_00000,
+                0b0_00000_00000_11111,
+                0b0_11111_11111_11111,
+                0,
+            ]),
+        );
+        assert_eq!(
+            converted,
+            vec![0xFF0000, 0x00FF00, 0x0000FF, 0xFFFFFF, 0x000000]
+        );
+    }
+
+    #[test]
+    fn ignores_0rgb1555_top_bit() {
+        let converted = convert(PixelFormat::ARGB1555, &pixels_16(&[0b1_10000_01000_00100]));
+        assert_eq!(converted, vec![0x844221]);
+    }
+
+    #[test]
+    fn converts_rgb565_primaries() {
+        let converted = convert(
+            PixelFormat::RGB565,
+            &pixels_16(&[0xF800, 0x07E0, 0x001F, 0xFFFF]),
+        );
+        assert_eq!(converted, vec![0xFF0000, 0x00FF00, 0x0000FF, 0xFFFFFF]);
+    }
+
+    #[test]
+    fn tells_0rgb1555_from_rgb565() {
+        // Pure green in 0RGB1555 would come out as a mix of red and green through
+        // the RGB565 converter
+        let green = pixels_16(&[0b0_00000_11111_00000]);
+        assert_eq!(convert(PixelFormat::ARGB1555, &green), vec![0x00FF00]);
+        assert_ne!(convert(PixelFormat::RGB565, &green), vec![0x00FF00]);
+    }
+
+    #[test]
+    fn clears_xrgb8888_top_byte() {
+        let data = 0xAB123456u32.to_ne_bytes();
+        assert_eq!(convert(PixelFormat::ARGB8888, &data), vec![0x123456]);
+    }
+
+    #[test]
+    fn convert_frame_drops_line_padding() {
+        // Two 1 pixel lines with a pitch of 4 bytes
+        let mut data = pixels_16(&[0b0_11111_00000_00000, 0xFFFF]);
+        data.extend(pixels_16(&[0b0_00000_00000_11111]));
+        let mut frame = Vec::new();
+        convert_frame_into(&data, PixelFormat::ARGB1555, 1, 2, 4, &mut frame);
+        assert_eq!(frame, vec![0xFF0000, 0x0000FF]);
+    }
+
+    #[test]
+    fn convert_frame_reads_padded_xrgb8888_lines() {
+        // Two 3 pixel lines 16 bytes apart, the last ending right after its pixels
+        let lines = [
+            [0x010101, 0x020202, 0x030303, 0xDEAD],
+            [0x040404, 0x050505, 0x060606, 0],
+        ];
+        let mut data: Vec<u8> = lines
+            .iter()
+            .flatten()
+            .flat_map(|pixel: &u32| pixel.to_ne_bytes())
+            .collect();
+        data.truncate(frame_length(PixelFormat::ARGB8888, 3, 2, 16));
+        assert_eq!(data.len(), 28);
+        let mut frame = Vec::new();
+        convert_frame_into(&data, PixelFormat::ARGB8888, 3, 2, 16, &mut frame);
+        assert_eq!(
+            frame,
+            vec![0x010101, 0x020202, 0x030303, 0x040404, 0x050505, 0x060606]
+        );
+    }
+
+    #[test]
+    fn measures_padded_frames() {
+        assert_eq!(
+            frame_length(PixelFormat::RGB565, 320, 240, 1024),
+            1024 * 239 + 640
+        );
+        assert_eq!(frame_length(PixelFormat::ARGB8888, 3, 1, 16), 12);
+        // A pitch shorter than a line can't be right, so lines are taken as packed
+        assert_eq!(frame_length(PixelFormat::ARGB8888, 3, 2, 4), 24);
+        assert_eq!(frame_length(PixelFormat::ARGB8888, 3, 0, 16), 0);
+    }
+
+    #[test]
+    fn rotates_counter_clockwise() {
+        // 1 2 3
+        // 4 5 6
+        let pixels = [1, 2, 3, 4, 5, 6];
+        let frame = Frame {
+            pixels: &pixels,
+            width: 3,
+            height: 2,
+            stride: 3,
+        };
+        let rotated = |rotation| {
+            let mut rotated = Vec::new();
+            rotate(&frame, rotation, &mut rotated);
+            rotated
+        };
+        assert_eq!(rotated(0), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(rotated(1), vec![3, 6, 2, 5, 1, 4]);
+        assert_eq!(rotated(2), vec![6, 5, 4, 3, 2, 1]);
+        assert_eq!(rotated(3), vec![4, 1, 5, 2, 6, 3]);
+    }
+
+    #[test]
+    fn scales_nearest_into_target_area() {
+        // A padded 2x2 picture doubled into the middle of a 6 pixel wide buffer
+        let pixels = [1, 2, 0, 3, 4, 0];
+        let frame = Frame {
+            pixels: &pixels,
+            width: 2,
+            height: 2,
+            stride: 3,
+        };
+        let mut dest = vec![0; 6 * 4];
+        scale_nearest_rows(&frame, &mut dest[1..], 6, 4, 4, 0..4);
+        assert_eq!(
+            dest,
+            vec![
+                0, 1, 1, 2, 2, 0, //
+                0, 1, 1, 2, 2, 0, //
+                0, 3, 3, 4, 4, 0, //
+                0, 3, 3, 4, 4, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn scales_bands_like_the_whole_picture() {
+        let pixels: Vec<u32> = (0..5 * 3).map(|pixel| pixel * 0x0F0F0F).collect();
+        let frame = Frame {
+            pixels: &pixels,
+            width: 5,
+            height: 3,
+            stride: 5,
+        };
+        type Scale = fn(&Frame, &mut [u32], usize, usize, usize, Range<usize>);
+        for scale in [scale_nearest_rows as Scale, scale_bilinear_rows] {
+            let mut whole = vec![0; 13 * 7];
+            scale(&frame, &mut whole, 13, 13, 7, 0..7);
+            let mut bands = vec![0; 13 * 7];
+            for (band, dest) in bands.chunks_mut(13 * 3).enumerate() {
+                let rows = band * 3..(band * 3 + 3).min(7);
+                scale(&frame, dest, 13, 13, 7, rows);
+            }
+            assert_eq!(bands, whole);
+        }
+    }
+
+    #[test]
+    fn scales_bilinear_between_lines() {
+        let pixels = [0x000000, 0x000000, 0xFFFFFF, 0xFFFFFF];
+        let frame = Frame {
+            pixels: &pixels,
+            width: 2,
+            height: 2,
+            stride: 2,
+        };
+        let mut dest = vec![0; 2 * 4];
+        scale_bilinear(&frame, &mut dest, 2, 2, 4);
+        let first_column: Vec<u32> = dest.iter().step_by(2).copied().collect();
+        assert_eq!(first_column, vec![0x000000, 0x3F3F3F, 0xBFBFBF, 0xFFFFFF]);
+    }
+
+    #[test]
+    fn blends_halfway() {
+        assert_eq!(blend(0x000000, 0xFEFEFE, 128), 0x7F7F7F);
+        assert_eq!(blend(0xFF0000, 0x0000FF, 0), 0xFF0000);
+    }
+
+    #[test]
+    fn samples_between_source_pixels() {
+        // Doubling a 2 pixel line puts target pixels a quarter of the way between
+        // source pixels, clamped at the edges
+        assert_eq!(sample_position(0, 4, 2), (0, 1, 0));
+        assert_eq!(sample_position(1, 4, 2), (0, 1, 64));
+        assert_eq!(sample_position(2, 4, 2), (0, 1, 192));
+        assert_eq!(sample_position(3, 4, 2), (1, 1, 64));
+    }
+}
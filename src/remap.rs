@@ -0,0 +1,109 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// remap.rs
+//
+// This module rebinds the joypad buttons while playing. The `input_remap` hotkey
+// asks for each button in turn; the next key pressed becomes player 1's key for
+// it, and the next gamepad button becomes the button for it of the player whose
+// gamepad that is. Pressing the hotkey again keeps a button's binding and moves
+// on. Once every button has been asked for, the new bindings are written to
+// rustroarch.cfg, and take effect when the frontend picks the edit up.
+
+use gilrs::Button;
+use std::sync::Mutex;
+
+use crate::input::{self, JOYPAD_BUTTONS};
+use crate::{libretro, osd};
+
+struct Capture {
+    // Index in JOYPAD_BUTTONS of the button being asked for.
+    button: usize,
+    // Config keys and values bound so far.
+    bindings: Vec<(String, String)>,
+}
+
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+// Returns whether key and gamepad presses are being captured as bindings.
+pub fn is_capturing() -> bool {
+    CAPTURE.lock().unwrap().is_some()
+}
+
+// Starts asking for bindings from the first button.
+pub fn start() {
+    let capture = Capture {
+        button: 0,
+        bindings: Vec::new(),
+    };
+    prompt(&capture);
+    *CAPTURE.lock().unwrap() = Some(capture);
+}
+
+// What was pressed for a button.
+enum Binding {
+    // A key, named as in the config.
+    Key(String),
+    // A gamepad button, and the port of the gamepad counting from 0.
+    Button(usize, Button),
+}
+
+// Binds a key pressed while capturing to player 1's current button, or skips the
+// button if it is the remap hotkey.
+pub fn capture_key(key: &str, remap_key: &str) {
+    if key == remap_key {
+        advance(None);
+    } else {
+        advance(Some(Binding::Key(key.to_string())));
+    }
+}
+
+// Binds a gamepad button pressed while capturing to the current button of the
+// player on `port`, counting from 0.
+pub fn capture_button(port: usize, button: Button) {
+    advance(Some(Binding::Button(port, button)));
+}
+
+// Records a binding, if there is one, and asks for the next button, saving the
+// bindings after the last.
+fn advance(binding: Option<Binding>) {
+    let mut capture = CAPTURE.lock().unwrap();
+    let Some(current) = capture.as_mut() else {
+        return;
+    };
+    let (name, _) = JOYPAD_BUTTONS[current.button];
+    let binding = binding.map(|binding| match binding {
+        Binding::Key(key) => (format!("input_player1_{}", name), key),
+        Binding::Button(port, button) => (
+            format!("input_player{}_{}_btn", port + 1, name),
+            input::button_name(button),
+        ),
+    });
+    if let Some((key, value)) = binding {
+        println!("Bound {} to {}", key, value);
+        current.bindings.push((key, value));
+    }
+    current.button += 1;
+    if current.button < JOYPAD_BUTTONS.len() {
+        prompt(current);
+        return;
+    }
+
+    let finished = capture.take().unwrap();
+    match libretro::save_config_values(&finished.bindings) {
+        Ok(()) => osd::show_message("Bindings saved"),
+        Err(e) => {
+            println!("Failed to save bindings: {}", e);
+            osd::show_message("Failed to save bindings");
+        }
+    }
+}
+
+// Asks for the key or gamepad button for the current button.
+fn prompt(capture: &Capture) {
+    let (name, _) = JOYPAD_BUTTONS[capture.button];
+    let message = format!("Press a key or gamepad button for {}", name.to_uppercase());
+    println!("{}", message);
+    osd::show_message(&message);
+}
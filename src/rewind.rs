@@ -0,0 +1,292 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// rewind.rs
+//
+// This module lets the game be run backwards while the rewind hotkey is held,
+// with `rewind_enable = "true"`. A save state is taken every `rewind_granularity`
+// frames, but only the newest is kept whole: each older one is kept as the bytes
+// that differ from the one after it, which for most games is a small part of the
+// state. Rewinding undoes them one at a time, loading each state and running a
+// frame from it so the picture goes back too. The oldest are forgotten once they
+// take up more than `rewind_buffer_size` megabytes.
+
+use libretro_sys::CoreAPI;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::{audio, libretro, osd};
+
+struct Rewind {
+    enabled: bool,
+    // Frames between save states.
+    granularity: u32,
+    // Frames run since the last save state.
+    frames: u32,
+    history: History,
+    // Set once the core turns out unable to rewind, so it is only said once.
+    unsupported: bool,
+}
+
+// Save states, newest last, as the newest whole and deltas back from it.
+struct History {
+    current: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    // Bytes taken by the current state and the deltas together, and the most they
+    // may take.
+    size: usize,
+    capacity: usize,
+}
+
+impl History {
+    fn new(capacity: usize) -> Self {
+        History {
+            current: None,
+            deltas: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    // Adds a save state, forgetting the oldest ones if that takes it over capacity.
+    // States of a different size can't be compared, so they start over.
+    fn push(&mut self, state: Vec<u8>) {
+        if let Some(current) = self.current.take() {
+            self.size -= current.len();
+            if current.len() == state.len() {
+                let delta = encode(&current, &state);
+                self.size += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                self.deltas.clear();
+                self.size = 0;
+            }
+        }
+        self.size += state.len();
+        self.current = Some(state);
+        while self.size > self.capacity {
+            let Some(oldest) = self.deltas.pop_front() else {
+                break;
+            };
+            self.size -= oldest.len();
+        }
+    }
+
+    // Goes back to the save state before the newest, returning it, or the oldest
+    // there is once there are no more.
+    fn pop(&mut self) -> Option<&[u8]> {
+        let current = self.current.as_mut()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            apply(&delta, current);
+            self.size -= delta.len();
+        }
+        Some(current)
+    }
+
+    fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+        self.size = 0;
+    }
+}
+
+// The bytes that differ between two states of the same size, as runs of: how far
+// the run starts past the end of the last one and its length, as little endian
+// 32 bit values, then the XOR of the run's bytes in both. XOR works both ways, so
+// the same delta turns either state into the other.
+fn encode(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut last_end = 0;
+    let mut position = 0;
+    while position < new.len() {
+        if old[position] == new[position] {
+            position += 1;
+            continue;
+        }
+        let start = position;
+        while position < new.len() && old[position] != new[position] {
+            position += 1;
+        }
+        delta.extend_from_slice(&((start - last_end) as u32).to_le_bytes());
+        delta.extend_from_slice(&((position - start) as u32).to_le_bytes());
+        delta.extend(
+            old[start..position]
+                .iter()
+                .zip(&new[start..position])
+                .map(|(old, new)| old ^ new),
+        );
+        last_end = position;
+    }
+    delta
+}
+
+// Turns a state into the one on the other side of a delta.
+fn apply(delta: &[u8], state: &mut [u8]) {
+    let word =
+        |offset: usize| u32::from_le_bytes(delta[offset..offset + 4].try_into().unwrap()) as usize;
+    let mut offset = 0;
+    let mut position = 0;
+    while offset < delta.len() {
+        let start = position + word(offset);
+        let length = word(offset + 4);
+        offset += 8;
+        for (byte, change) in state[start..start + length]
+            .iter_mut()
+            .zip(&delta[offset..offset + length])
+        {
+            *byte ^= change;
+        }
+        offset += length;
+        position = start + length;
+    }
+}
+
+static REWIND: Mutex<Option<Rewind>> = Mutex::new(None);
+
+// Whether the rewind hotkey is held down.
+static HELD: AtomicBool = AtomicBool::new(false);
+
+// Reads `rewind_enable`, `rewind_granularity` and `rewind_buffer_size`, starting
+// the history over if they changed.
+pub fn configure(config: &HashMap<String, String>) {
+    let enabled = config
+        .get("rewind_enable")
+        .is_some_and(|value| value == "true");
+    let number = |key: &str, default: usize| {
+        config
+            .get(key)
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(default)
+    };
+    let granularity = number("rewind_granularity", 1).max(1) as u32;
+    let capacity = number("rewind_buffer_size", 20) << 20;
+    let mut rewind = REWIND.lock().unwrap();
+    let unchanged = rewind.as_ref().is_some_and(|rewind| {
+        rewind.enabled == enabled
+            && rewind.granularity == granularity
+            && rewind.history.capacity == capacity
+    });
+    if !unchanged {
+        *rewind = Some(Rewind {
+            enabled,
+            granularity,
+            frames: 0,
+            history: History::new(capacity),
+            unsupported: false,
+        });
+    }
+}
+
+// Returns whether rewind is turned on in the config.
+pub fn is_enabled() -> bool {
+    REWIND
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|rewind| rewind.enabled)
+}
+
+// Sets whether the rewind hotkey is held down. Called every frame.
+pub fn set_held(held: bool) {
+    if HELD.swap(held, Ordering::SeqCst) != held {
+        audio::set_rewinding(held && is_enabled());
+    }
+}
+
+// Returns whether the game is being run backwards.
+pub fn is_rewinding() -> bool {
+    HELD.load(Ordering::SeqCst) && is_enabled()
+}
+
+// Takes a save state every `rewind_granularity` frames. Called after each frame
+// that runs forwards.
+pub fn record(core_api: &CoreAPI) {
+    let mut rewind = REWIND.lock().unwrap();
+    let Some(rewind) = rewind
+        .as_mut()
+        .filter(|rewind| rewind.enabled && !rewind.unsupported)
+    else {
+        return;
+    };
+    rewind.frames += 1;
+    if rewind.frames < rewind.granularity {
+        return;
+    }
+    rewind.frames = 0;
+    if libretro::has_serialization_quirk(libretro::SERIALIZATION_QUIRK_INCOMPLETE) {
+        unsupported(rewind, "the core's save states are incomplete");
+        return;
+    }
+    if libretro::has_serialization_quirk(libretro::SERIALIZATION_QUIRK_SINGLE_SESSION) {
+        unsupported(rewind, "the core can't load save states made earlier");
+        return;
+    }
+    match unsafe { libretro::serialize(core_api) } {
+        Ok(state) => rewind.history.push(state),
+        Err(e) => unsupported(rewind, &e),
+    }
+}
+
+fn unsupported(rewind: &mut Rewind, reason: &str) {
+    println!("Rewind is unavailable: {}", reason);
+    osd::show_message("Rewind is unavailable");
+    rewind.unsupported = true;
+    rewind.history.clear();
+}
+
+// Loads the save state before the last one, for the next frame to run from.
+// Called before each frame while rewinding; at the oldest state there is, it is
+// loaded again each time, holding the game there.
+pub fn step_back(core_api: &CoreAPI) {
+    let mut rewind = REWIND.lock().unwrap();
+    let Some(rewind) = rewind.as_mut() else {
+        return;
+    };
+    rewind.frames = 0;
+    let Some(state) = rewind.history.pop() else {
+        return;
+    };
+    let mut state = state.to_vec();
+    if !unsafe { libretro::unserialize(core_api, &mut state) } {
+        println!("The core failed to load a rewind state");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_turn_states_into_each_other() {
+        let old = [1, 2, 3, 4, 5, 6, 7, 8];
+        let new = [1, 9, 9, 4, 5, 6, 0, 8];
+        let delta = encode(&old, &new);
+        assert_eq!(delta.len(), 2 * 8 + 3);
+        let mut state = new;
+        apply(&delta, &mut state);
+        assert_eq!(state, old);
+        apply(&delta, &mut state);
+        assert_eq!(state, new);
+        assert!(encode(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn goes_back_through_the_history_it_has_room_for() {
+        let mut history = History::new(16 + 8 + 16);
+        for frame in 0..4 {
+            history.push(vec![frame; 16]);
+        }
+        // Each delta is one run of 16 bytes after 8 of offset and length, so there
+        // is only room for the last
+        assert_eq!(history.pop(), Some(&[2; 16][..]));
+        assert_eq!(history.pop(), Some(&[2; 16][..]));
+
+        // A state of a different size starts over
+        history.push(vec![0; 4]);
+        history.push(vec![1, 0, 0, 0]);
+        assert_eq!(history.pop(), Some(&[0; 4][..]));
+        assert_eq!(history.size, 4);
+    }
+}
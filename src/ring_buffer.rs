@@ -0,0 +1,153 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// ring_buffer.rs
+//
+// This module is a fixed size single-producer, single-consumer queue of audio
+// samples, which carries the core's audio to the audio thread without allocating
+// or taking a lock. The two ends are separate values, so only one thread can
+// hold each. Samples are atomics, which keeps it free of unsafe code; relaxed
+// loads and stores of them cost the same as plain ones.
+
+use std::sync::atomic::{AtomicI16, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Shared {
+    samples: Box<[AtomicI16]>,
+    // Samples written and read since the start. Only the producer moves `written`
+    // and only the consumer moves `read`, and their difference is what is queued.
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+// The end samples are pushed into.
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+// The end samples are popped from.
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+// Creates a queue with room for `capacity` samples.
+pub fn new(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+impl Producer {
+    // Returns how many samples have been pushed since the start.
+    pub fn position(&self) -> usize {
+        self.shared.written.load(Ordering::Relaxed)
+    }
+
+    // Queues as many of the samples as there is room for and returns how many that was.
+    pub fn push(&mut self, samples: &[i16]) -> usize {
+        let shared = &self.shared;
+        let capacity = shared.samples.len();
+        let written = shared.written.load(Ordering::Relaxed);
+        let free = capacity - (written - shared.read.load(Ordering::Acquire));
+        let count = samples.len().min(free);
+        for (offset, &sample) in samples[..count].iter().enumerate() {
+            shared.samples[(written + offset) % capacity].store(sample, Ordering::Relaxed);
+        }
+        // Publishes the samples to the consumer
+        shared.written.store(written + count, Ordering::Release);
+        count
+    }
+}
+
+impl Consumer {
+    // Returns how many samples have been popped since the start.
+    pub fn position(&self) -> usize {
+        self.shared.read.load(Ordering::Relaxed)
+    }
+
+    // Appends the queued samples to `output`, stopping short of the `end`th sample
+    // pushed since the start and at a whole number of `multiple` samples, e.g. 2 to
+    // keep stereo pairs together.
+    pub fn pop_until(&mut self, output: &mut Vec<i16>, end: usize, multiple: usize) {
+        let shared = &self.shared;
+        let capacity = shared.samples.len();
+        let read = shared.read.load(Ordering::Relaxed);
+        let queued = shared
+            .written
+            .load(Ordering::Acquire)
+            .min(end)
+            .saturating_sub(read);
+        let count = queued - queued % multiple;
+        output.extend(
+            (read..read + count)
+                .map(|index| shared.samples[index % capacity].load(Ordering::Relaxed)),
+        );
+        // Hands the space back to the producer
+        shared.read.store(read + count, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wraps_around_and_stops_when_full() {
+        let (mut producer, mut consumer) = new(8);
+        let mut output = Vec::new();
+        assert_eq!(producer.push(&[1, 2, 3, 4, 5, 6]), 6);
+        consumer.pop_until(&mut output, usize::MAX, 4);
+        assert_eq!(output, [1, 2, 3, 4]);
+        assert_eq!(producer.push(&[7, 8, 9, 10, 11, 12, 13]), 6);
+        output.clear();
+        consumer.pop_until(&mut output, usize::MAX, 1);
+        assert_eq!(output, [5, 6, 7, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn stops_at_the_end_given() {
+        let (mut producer, mut consumer) = new(8);
+        let mut output = Vec::new();
+        producer.push(&[1, 2, 3]);
+        let end = producer.position();
+        producer.push(&[4, 5]);
+        consumer.pop_until(&mut output, end, 1);
+        assert_eq!(
+            (output.as_slice(), consumer.position()),
+            (&[1, 2, 3][..], 3)
+        );
+        consumer.pop_until(&mut output, usize::MAX, 1);
+        assert_eq!(output, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn carries_samples_between_threads_in_order() {
+        let (mut producer, mut consumer) = new(64);
+        let sender = thread::spawn(move || {
+            let samples: Vec<i16> = (0..10_000).map(|sample| sample as i16).collect();
+            let mut sent = 0;
+            while sent < samples.len() {
+                sent += producer.push(&samples[sent..(sent + 37).min(samples.len())]);
+            }
+        });
+        let mut received = Vec::new();
+        while received.len() < 10_000 {
+            consumer.pop_until(&mut received, usize::MAX, 1);
+        }
+        sender.join().unwrap();
+        assert!(received
+            .iter()
+            .enumerate()
+            .all(|(index, &sample)| sample == index as i16));
+    }
+}
@@ -0,0 +1,134 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// rumble.rs
+//
+// This module implements the libretro rumble interface. The core reports the
+// strength of the strong and weak motors, and the main loop turns that into a
+// gilrs force feedback effect on the active gamepad.
+
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+use gilrs::{GamepadId, Gilrs};
+use libretro_sys::{RumbleEffect, RumbleInterface};
+use std::collections::HashMap;
+
+use crate::RUMBLE_CHANNEL;
+
+// A motor strength change requested by the core.
+pub struct RumbleRequest {
+    pub port: u32,
+    pub effect: RumbleEffect,
+    pub strength: u16,
+}
+
+// Builds the interface returned for ENVIRONMENT_GET_RUMBLE_INTERFACE.
+pub fn rumble_interface() -> RumbleInterface {
+    RumbleInterface { set_rumble_state }
+}
+
+unsafe extern "C" fn set_rumble_state(
+    port: libc::c_uint,
+    effect: RumbleEffect,
+    strength: u16,
+) -> bool {
+    RUMBLE_CHANNEL
+        .0
+        .send(RumbleRequest {
+            port,
+            effect,
+            strength,
+        })
+        .is_ok()
+}
+
+// Force feedback state for the active gamepad, which plays port 0.
+pub struct Rumble {
+    enabled: bool,
+    gain: f32,
+    strong: u16,
+    weak: u16,
+    effect: Option<Effect>,
+    gamepad: Option<GamepadId>,
+}
+
+impl Rumble {
+    // Reads `input_rumble_enable` and `input_rumble_gain` (a percentage) from the config.
+    pub fn new(config: &HashMap<String, String>) -> Self {
+        let enabled = config
+            .get("input_rumble_enable")
+            .is_none_or(|value| value == "true");
+        let gain = config
+            .get("input_rumble_gain")
+            .and_then(|value| value.parse::<f32>().ok())
+            .map_or(1.0, |gain| gain.clamp(0.0, 100.0) / 100.0);
+        Rumble {
+            enabled,
+            gain,
+            strong: 0,
+            weak: 0,
+            effect: None,
+            gamepad: None,
+        }
+    }
+
+    // Applies the motor changes the core requested since the last frame.
+    pub fn update(&mut self, gilrs: &mut Gilrs, gamepad: Option<GamepadId>) {
+        let mut changed = gamepad != self.gamepad;
+        for request in RUMBLE_CHANNEL.1.lock().unwrap().try_iter() {
+            if request.port != 0 {
+                continue;
+            }
+            let strength = (request.strength as f32 * self.gain) as u16;
+            let motor = match request.effect {
+                RumbleEffect::Strong => &mut self.strong,
+                RumbleEffect::Weak => &mut self.weak,
+            };
+            changed |= *motor != strength;
+            *motor = strength;
+        }
+        if !changed || !self.enabled {
+            return;
+        }
+
+        self.gamepad = gamepad;
+        // Dropping the old effect stops it
+        self.effect = None;
+        let gamepad = match gamepad {
+            Some(gamepad) if gilrs.gamepad(gamepad).is_ff_supported() => gamepad,
+            _ => return,
+        };
+        if self.strong == 0 && self.weak == 0 {
+            return;
+        }
+
+        // The motors keep running at this strength until the core changes it
+        let scheduling = Replay {
+            play_for: Ticks::from_ms(50),
+            ..Default::default()
+        };
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong {
+                    magnitude: self.strong,
+                },
+                scheduling,
+                ..Default::default()
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak {
+                    magnitude: self.weak,
+                },
+                scheduling,
+                ..Default::default()
+            })
+            .gamepads(&[gamepad])
+            .repeat(Repeat::Infinitely)
+            .finish(gilrs)
+            .and_then(|effect| effect.play().map(|_| effect));
+        match effect {
+            Ok(effect) => self.effect = Some(effect),
+            Err(err) => println!("Failed to start rumble: {}", err),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// screenshot.rs
+//
+// This module saves the last frame the core sent as a PNG in `screenshot_directory`,
+// named after the game and the time it was taken. Frames are saved at the core's
+// resolution, before overscan cropping, scaling and filters.
+
+use chrono::{DateTime, Local, TimeZone};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::pixels::Frame;
+use crate::{osd, video};
+
+// Saves a screenshot from the hotkey and reports how it went on the OSD.
+pub fn take(config: &HashMap<String, String>, rom_name: &str) {
+    match save(config, rom_name) {
+        Ok(path) => {
+            println!("Saved screenshot to {}", path.display());
+            osd::show_message("Screenshot saved");
+        }
+        Err(e) => {
+            println!("Failed to save screenshot: {}", e);
+            osd::show_message("Screenshot failed");
+        }
+    }
+}
+
+fn save(config: &HashMap<String, String>, rom_name: &str) -> Result<PathBuf, String> {
+    let png = video::with_last_frame(encode).ok_or("The core hasn't sent a frame yet")??;
+    let path = new_path(config, rom_name, "png")?;
+    fs::write(&path, png).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+// Returns where to save a capture taken now with the given file extension, in
+// `screenshot_directory`, creating it if needed. Clips and audio recordings are
+// saved there too.
+pub fn new_path(
+    config: &HashMap<String, String>,
+    rom_name: &str,
+    extension: &str,
+) -> Result<PathBuf, String> {
+    let directory = config
+        .get("screenshot_directory")
+        .map_or("./screenshots", String::as_str);
+    let directory = PathBuf::from(shellexpand::tilde(directory).into_owned());
+    fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+    Ok(directory.join(file_name(rom_name, &Local::now(), extension)))
+}
+
+// `<game>-<yymmdd>-<hhmmss>-<milliseconds>.<extension>`, so captures sort by when
+// they were taken and several in a second don't overwrite each other.
+fn file_name<Tz: TimeZone>(rom_name: &str, time: &DateTime<Tz>, extension: &str) -> String
+where
+    Tz::Offset: Display,
+{
+    let game_name = Path::new(rom_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .replace(' ', "_");
+    format!(
+        "{}-{}.{}",
+        game_name,
+        time.format("%y%m%d-%H%M%S-%3f"),
+        extension
+    )
+}
+
+// Encodes an XRGB8888 frame as an 8 bit RGB PNG.
+fn encode(frame: &Frame) -> Result<Vec<u8>, String> {
+    let mut rgb = Vec::with_capacity(frame.width * frame.height * 3);
+    for y in 0..frame.height {
+        for &pixel in frame.line(y) {
+            rgb.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+        }
+    }
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&rgb).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn names_screenshots_after_the_game_and_time() {
+        let time = Utc.with_ymd_and_hms(2024, 3, 9, 14, 5, 30).unwrap();
+        assert_eq!(
+            file_name("roms/Super Game (USA).sfc", &time, "png"),
+            "Super_Game_(USA)-240309-140530-000.png"
+        );
+    }
+
+    #[test]
+    fn encodes_padded_frames() {
+        let pixels = [0x112233, 0xFF0000, 0xDEAD, 0x00FF00, 0x0000FF, 0xDEAD];
+        let frame = Frame {
+            pixels: &pixels,
+            width: 2,
+            height: 2,
+            stride: 3,
+        };
+        let png = encode(&frame).unwrap();
+
+        let mut reader = png::Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(
+            decoded,
+            [0x11, 0x22, 0x33, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF]
+        );
+    }
+}
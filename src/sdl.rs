@@ -0,0 +1,389 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// sdl.rs
+//
+// This module implements the window with SDL2 when the `sdl2` feature is enabled
+// and `video_backend` is "sdl2", for platforms where minifb misbehaves. Pictures
+// are streamed to a texture the SDL renderer stretches over the window, and key
+// events are translated to minifb keys so bindings work the same.
+
+use minifb::{Key, KeyRepeat};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext, WindowPos};
+use sdl2::{EventPump, Sdl};
+
+use crate::window::{self, FullscreenOptions, Window};
+
+// The renderer drawing software pictures. It is only created once the first one
+// arrives, since a GPU presenter needs the window to itself.
+struct Renderer {
+    canvas: WindowCanvas,
+    texture_creator: TextureCreator<WindowContext>,
+    texture: Option<(Texture, usize, usize)>,
+}
+
+pub struct SdlWindow {
+    // SDL shuts down when the context is dropped, so it lives as long as the window.
+    _context: Sdl,
+    event_pump: EventPump,
+    window: Option<sdl2::video::Window>,
+    renderer: Option<Renderer>,
+    fullscreen: FullscreenOptions,
+    vsync: bool,
+    open: bool,
+    held: Vec<Key>,
+    pressed: Vec<Key>,
+    repeated: Vec<Key>,
+    released: Vec<Key>,
+}
+
+pub fn open(
+    title: &str,
+    width: usize,
+    height: usize,
+    fullscreen: FullscreenOptions,
+    vsync: bool,
+) -> Result<Box<dyn Window>, String> {
+    let context = sdl2::init()?;
+    let window = context
+        .video()?
+        .window(title, width as u32, height as u32)
+        .resizable()
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let event_pump = context.event_pump()?;
+    Ok(Box::new(SdlWindow {
+        _context: context,
+        event_pump,
+        window: Some(window),
+        renderer: None,
+        fullscreen,
+        vsync,
+        open: true,
+        held: Vec::new(),
+        pressed: Vec::new(),
+        repeated: Vec::new(),
+        released: Vec::new(),
+    }))
+}
+
+impl SdlWindow {
+    fn sdl_window(&self) -> &sdl2::video::Window {
+        match &self.renderer {
+            Some(renderer) => renderer.canvas.window(),
+            None => self.window.as_ref().unwrap(),
+        }
+    }
+
+    fn sdl_window_mut(&mut self) -> &mut sdl2::video::Window {
+        match &mut self.renderer {
+            Some(renderer) => renderer.canvas.window_mut(),
+            None => self.window.as_mut().unwrap(),
+        }
+    }
+
+    fn renderer(&mut self) -> Result<&mut Renderer, String> {
+        if let Some(window) = self.window.take() {
+            let mut builder = window.into_canvas();
+            if self.vsync {
+                builder = builder.present_vsync();
+            }
+            let canvas = builder.build().map_err(|e| e.to_string())?;
+            self.renderer = Some(Renderer {
+                texture_creator: canvas.texture_creator(),
+                canvas,
+                texture: None,
+            });
+        }
+        Ok(self.renderer.as_mut().unwrap())
+    }
+}
+
+// SAFETY: the handle is the SDL window's own, which lives as long as this
+unsafe impl HasRawWindowHandle for SdlWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.sdl_window().raw_window_handle()
+    }
+}
+
+impl Window for SdlWindow {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.held.contains(&key)
+    }
+
+    fn get_keys(&self) -> Vec<Key> {
+        self.held.clone()
+    }
+
+    fn get_keys_pressed(&self, repeat: KeyRepeat) -> Vec<Key> {
+        match repeat {
+            KeyRepeat::Yes => self.repeated.clone(),
+            KeyRepeat::No => self.pressed.clone(),
+        }
+    }
+
+    fn get_keys_released(&self) -> Vec<Key> {
+        self.released.clone()
+    }
+
+    fn get_size(&self) -> (usize, usize) {
+        let (width, height) = self.sdl_window().size();
+        (width as usize, height as usize)
+    }
+
+    fn update_with_buffer(
+        &mut self,
+        buffer: &[u32],
+        width: usize,
+        height: usize,
+    ) -> Result<(), String> {
+        let renderer = self.renderer()?;
+        if !matches!(renderer.texture, Some((_, w, h)) if (w, h) == (width, height)) {
+            // XRGB8888 is what SDL calls RGB888
+            let texture = renderer
+                .texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB888, width as u32, height as u32)
+                .map_err(|e| e.to_string())?;
+            if let Some((old, _, _)) = renderer.texture.replace((texture, width, height)) {
+                unsafe { old.destroy() };
+            }
+        }
+        let (texture, _, _) = renderer.texture.as_mut().unwrap();
+        // SAFETY: any u32 is four valid bytes
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, width * height * 4) };
+        texture
+            .update(None, bytes, width * 4)
+            .map_err(|e| e.to_string())?;
+        renderer.canvas.copy(texture, None, None)?;
+        renderer.canvas.present();
+        self.update();
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        self.pressed.clear();
+        self.repeated.clear();
+        self.released.clear();
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => self.open = false,
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat,
+                    ..
+                } => {
+                    let key = key(scancode);
+                    self.repeated.push(key);
+                    if !repeat {
+                        self.pressed.push(key);
+                        self.held.push(key);
+                    }
+                }
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    let key = key(scancode);
+                    self.released.push(key);
+                    self.held.retain(|&held| held != key);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.sdl_window_mut().set_title(title) {
+            println!("Failed to set the window title: {}", e);
+        }
+    }
+
+    fn has_vsync(&self) -> bool {
+        self.vsync
+    }
+
+    fn refresh_rate(&self) -> Option<f64> {
+        let sdl_window = self.sdl_window();
+        let display = sdl_window.display_index().ok()?;
+        let mode = sdl_window.subsystem().current_display_mode(display).ok()?;
+        (mode.refresh_rate > 0).then_some(mode.refresh_rate as f64)
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.sdl_window().fullscreen_state() != FullscreenType::Off
+    }
+
+    // Desktop fullscreen keeps the display mode, so switching is instant.
+    // Exclusive fullscreen switches to the mode with the desktop's resolution and
+    // the refresh rate closest to the core's.
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let options = self.fullscreen;
+        let sdl_window = self.sdl_window_mut();
+        if !fullscreen {
+            return sdl_window.set_fullscreen(FullscreenType::Off);
+        }
+        let video = sdl_window.subsystem().clone();
+        let display = match options.monitor {
+            0 => sdl_window.display_index()?,
+            monitor => monitor as i32 - 1,
+        };
+        if display >= video.num_video_displays()? {
+            return Err(format!("There is no monitor {}", options.monitor));
+        }
+
+        // Fullscreen covers the monitor the window is on, so move it there first
+        let bounds = video.display_bounds(display)?;
+        sdl_window.set_position(
+            WindowPos::Positioned(bounds.x()),
+            WindowPos::Positioned(bounds.y()),
+        );
+        if !options.exclusive {
+            return sdl_window.set_fullscreen(FullscreenType::Desktop);
+        }
+
+        let desktop = video.desktop_display_mode(display)?;
+        let modes: Vec<_> = (0..video.num_display_modes(display)?)
+            .filter_map(|index| video.display_mode(display, index).ok())
+            .filter(|mode| (mode.w, mode.h) == (desktop.w, desktop.h))
+            .collect();
+        let mode = window::closest_refresh_rate(
+            modes.iter().map(|mode| mode.refresh_rate as f64),
+            options.refresh_rate,
+        )
+        .map_or(desktop, |index| modes[index]);
+        println!(
+            "Switching monitor {} to {}x{} at {} Hz",
+            display + 1,
+            mode.w,
+            mode.h,
+            mode.refresh_rate
+        );
+        sdl_window.set_display_mode(mode)?;
+        sdl_window.set_fullscreen(FullscreenType::True)
+    }
+}
+
+// Translates the physical key SDL reports to the minifb key in the same place.
+fn key(scancode: Scancode) -> Key {
+    match scancode {
+        Scancode::A => Key::A,
+        Scancode::B => Key::B,
+        Scancode::C => Key::C,
+        Scancode::D => Key::D,
+        Scancode::E => Key::E,
+        Scancode::F => Key::F,
+        Scancode::G => Key::G,
+        Scancode::H => Key::H,
+        Scancode::I => Key::I,
+        Scancode::J => Key::J,
+        Scancode::K => Key::K,
+        Scancode::L => Key::L,
+        Scancode::M => Key::M,
+        Scancode::N => Key::N,
+        Scancode::O => Key::O,
+        Scancode::P => Key::P,
+        Scancode::Q => Key::Q,
+        Scancode::R => Key::R,
+        Scancode::S => Key::S,
+        Scancode::T => Key::T,
+        Scancode::U => Key::U,
+        Scancode::V => Key::V,
+        Scancode::W => Key::W,
+        Scancode::X => Key::X,
+        Scancode::Y => Key::Y,
+        Scancode::Z => Key::Z,
+        Scancode::Num0 => Key::Key0,
+        Scancode::Num1 => Key::Key1,
+        Scancode::Num2 => Key::Key2,
+        Scancode::Num3 => Key::Key3,
+        Scancode::Num4 => Key::Key4,
+        Scancode::Num5 => Key::Key5,
+        Scancode::Num6 => Key::Key6,
+        Scancode::Num7 => Key::Key7,
+        Scancode::Num8 => Key::Key8,
+        Scancode::Num9 => Key::Key9,
+        Scancode::F1 => Key::F1,
+        Scancode::F2 => Key::F2,
+        Scancode::F3 => Key::F3,
+        Scancode::F4 => Key::F4,
+        Scancode::F5 => Key::F5,
+        Scancode::F6 => Key::F6,
+        Scancode::F7 => Key::F7,
+        Scancode::F8 => Key::F8,
+        Scancode::F9 => Key::F9,
+        Scancode::F10 => Key::F10,
+        Scancode::F11 => Key::F11,
+        Scancode::F12 => Key::F12,
+        Scancode::F13 => Key::F13,
+        Scancode::F14 => Key::F14,
+        Scancode::F15 => Key::F15,
+        Scancode::Down => Key::Down,
+        Scancode::Left => Key::Left,
+        Scancode::Right => Key::Right,
+        Scancode::Up => Key::Up,
+        Scancode::Apostrophe => Key::Apostrophe,
+        Scancode::Grave => Key::Backquote,
+        Scancode::Backslash => Key::Backslash,
+        Scancode::Comma => Key::Comma,
+        Scancode::Equals => Key::Equal,
+        Scancode::LeftBracket => Key::LeftBracket,
+        Scancode::Minus => Key::Minus,
+        Scancode::Period => Key::Period,
+        Scancode::RightBracket => Key::RightBracket,
+        Scancode::Semicolon => Key::Semicolon,
+        Scancode::Slash => Key::Slash,
+        Scancode::Backspace => Key::Backspace,
+        Scancode::Delete => Key::Delete,
+        Scancode::End => Key::End,
+        Scancode::Return => Key::Enter,
+        Scancode::Escape => Key::Escape,
+        Scancode::Home => Key::Home,
+        Scancode::Insert => Key::Insert,
+        Scancode::Application => Key::Menu,
+        Scancode::PageDown => Key::PageDown,
+        Scancode::PageUp => Key::PageUp,
+        Scancode::Pause => Key::Pause,
+        Scancode::Space => Key::Space,
+        Scancode::Tab => Key::Tab,
+        Scancode::NumLockClear => Key::NumLock,
+        Scancode::CapsLock => Key::CapsLock,
+        Scancode::ScrollLock => Key::ScrollLock,
+        Scancode::LShift => Key::LeftShift,
+        Scancode::RShift => Key::RightShift,
+        Scancode::LCtrl => Key::LeftCtrl,
+        Scancode::RCtrl => Key::RightCtrl,
+        Scancode::Kp0 => Key::NumPad0,
+        Scancode::Kp1 => Key::NumPad1,
+        Scancode::Kp2 => Key::NumPad2,
+        Scancode::Kp3 => Key::NumPad3,
+        Scancode::Kp4 => Key::NumPad4,
+        Scancode::Kp5 => Key::NumPad5,
+        Scancode::Kp6 => Key::NumPad6,
+        Scancode::Kp7 => Key::NumPad7,
+        Scancode::Kp8 => Key::NumPad8,
+        Scancode::Kp9 => Key::NumPad9,
+        Scancode::KpPeriod => Key::NumPadDot,
+        Scancode::KpDivide => Key::NumPadSlash,
+        Scancode::KpMultiply => Key::NumPadAsterisk,
+        Scancode::KpMinus => Key::NumPadMinus,
+        Scancode::KpPlus => Key::NumPadPlus,
+        Scancode::KpEnter => Key::NumPadEnter,
+        Scancode::LAlt => Key::LeftAlt,
+        Scancode::RAlt => Key::RightAlt,
+        Scancode::LGui => Key::LeftSuper,
+        Scancode::RGui => Key::RightSuper,
+        _ => Key::Unknown,
+    }
+}
@@ -0,0 +1,137 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// sensor.rs
+//
+// This module implements the libretro sensor interface for cores that read tilt or
+// rotation (e.g. WarioWare: Twisted!, Kirby Tilt 'n' Tumble). gilrs does not expose
+// motion sensors, so tilt comes from the gamepad's right stick, or from the
+// keyboard when no gamepad is active.
+
+use gilrs::{Axis, GamepadId, Gilrs};
+use libc::c_uint;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::window::Window;
+
+// Sensor actions and IDs, mirrored because libretro-sys only knows the accelerometer.
+const SENSOR_ACCELEROMETER_ENABLE: c_uint = 0;
+const SENSOR_ACCELEROMETER_DISABLE: c_uint = 1;
+const SENSOR_GYROSCOPE_ENABLE: c_uint = 2;
+const SENSOR_GYROSCOPE_DISABLE: c_uint = 3;
+const SENSOR_ACCELEROMETER_X: c_uint = 0;
+const SENSOR_ACCELEROMETER_Y: c_uint = 1;
+const SENSOR_ACCELEROMETER_Z: c_uint = 2;
+const SENSOR_GYROSCOPE_X: c_uint = 3;
+const SENSOR_GYROSCOPE_Y: c_uint = 4;
+const SENSOR_GYROSCOPE_Z: c_uint = 5;
+
+// Rotation rate reported for a full stick or key deflection, in radians per second.
+const MAX_ROTATION_RATE: f32 = std::f32::consts::PI;
+
+// struct retro_sensor_interface, with the action passed as a plain integer so
+// actions libretro-sys doesn't know about can't produce an invalid enum.
+#[repr(C)]
+pub struct SensorInterface {
+    pub set_sensor_state: unsafe extern "C" fn(port: c_uint, action: c_uint, rate: c_uint) -> bool,
+    pub get_sensor_input: unsafe extern "C" fn(port: c_uint, id: c_uint) -> f32,
+}
+
+// Readings for port 0, in g for the accelerometer and rad/s for the gyroscope.
+#[derive(Default)]
+struct SensorState {
+    accelerometer_enabled: bool,
+    gyroscope_enabled: bool,
+    accelerometer: [f32; 3],
+    gyroscope: [f32; 3],
+}
+
+// The core reads sensors synchronously from retro_run, so the latest readings are
+// kept here rather than sent over a channel.
+static SENSOR_STATE: Lazy<Mutex<SensorState>> = Lazy::new(|| {
+    Mutex::new(SensorState {
+        accelerometer: [0.0, 0.0, 1.0],
+        ..Default::default()
+    })
+});
+
+// Builds the interface returned for ENVIRONMENT_GET_SENSOR_INTERFACE.
+pub fn sensor_interface() -> SensorInterface {
+    SensorInterface {
+        set_sensor_state,
+        get_sensor_input,
+    }
+}
+
+unsafe extern "C" fn set_sensor_state(port: c_uint, action: c_uint, _rate: c_uint) -> bool {
+    if port != 0 {
+        return false;
+    }
+    let mut state = SENSOR_STATE.lock().unwrap();
+    match action {
+        SENSOR_ACCELEROMETER_ENABLE => state.accelerometer_enabled = true,
+        SENSOR_ACCELEROMETER_DISABLE => state.accelerometer_enabled = false,
+        SENSOR_GYROSCOPE_ENABLE => state.gyroscope_enabled = true,
+        SENSOR_GYROSCOPE_DISABLE => state.gyroscope_enabled = false,
+        _ => return false,
+    }
+    true
+}
+
+unsafe extern "C" fn get_sensor_input(port: c_uint, id: c_uint) -> f32 {
+    let state = SENSOR_STATE.lock().unwrap();
+    if port != 0 {
+        return 0.0;
+    }
+    let accelerometer = state.accelerometer_enabled;
+    let gyroscope = state.gyroscope_enabled;
+    match id {
+        SENSOR_ACCELEROMETER_X if accelerometer => state.accelerometer[0],
+        SENSOR_ACCELEROMETER_Y if accelerometer => state.accelerometer[1],
+        SENSOR_ACCELEROMETER_Z if accelerometer => state.accelerometer[2],
+        SENSOR_GYROSCOPE_X if gyroscope => state.gyroscope[0],
+        SENSOR_GYROSCOPE_Y if gyroscope => state.gyroscope[1],
+        SENSOR_GYROSCOPE_Z if gyroscope => state.gyroscope[2],
+        _ => 0.0,
+    }
+}
+
+// Updates the readings from the active gamepad's right stick, or from the
+// `input_sensor_tilt_*` keys when no gamepad is active.
+pub fn update(
+    window: &dyn Window,
+    gilrs: &Gilrs,
+    active_gamepad: Option<GamepadId>,
+    config: &HashMap<String, String>,
+) {
+    let (tilt_x, tilt_y) = match active_gamepad.map(|id| gilrs.gamepad(id)) {
+        Some(gamepad) => (
+            gamepad.value(Axis::RightStickX),
+            gamepad.value(Axis::RightStickY),
+        ),
+        None => {
+            let keys_down: Vec<String> = window
+                .get_keys()
+                .iter()
+                .map(|key| format!("{:?}", key).to_ascii_lowercase())
+                .collect();
+            let is_down = |name: &str| {
+                config.get(name).is_some_and(|key| keys_down.contains(key)) as i8 as f32
+            };
+            (
+                is_down("input_sensor_tilt_right") - is_down("input_sensor_tilt_left"),
+                is_down("input_sensor_tilt_up") - is_down("input_sensor_tilt_down"),
+            )
+        }
+    };
+
+    // Tilting moves gravity off the Z axis; turning left and right also spins the
+    // device around Z, which is what gyroscope games read.
+    let mut state = SENSOR_STATE.lock().unwrap();
+    let z = (1.0 - tilt_x * tilt_x - tilt_y * tilt_y).max(0.0).sqrt();
+    state.accelerometer = [tilt_x, tilt_y, z];
+    state.gyroscope = [0.0, 0.0, -tilt_x * MAX_ROTATION_RATE];
+}
@@ -0,0 +1,812 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// shader.rs
+//
+// This module reads RetroArch shader presets (.slangp and .glslp) and the shaders
+// they chain for the wgpu renderer. Shader sources are GLSL written for Vulkan, so
+// they go through naga's GLSL front end after two rewrites it needs: combined
+// `sampler2D` uniforms become a texture and a sampler, and push constant blocks
+// become uniform blocks. Which uniforms and textures a shader uses is read back
+// from the compiled module and filled in by name every frame.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use wgpu::naga;
+
+// Binding the push constant block is moved to.
+const PUSH_BINDING: u32 = 64;
+
+// How far above its texture the sampler split off a combined sampler is bound.
+const SAMPLER_BINDING_OFFSET: u32 = 32;
+
+// How deeply `#include`s may nest before a shader is assumed to include itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// How a pass's output size follows on one axis from the size of its input, the
+// final viewport, or a fixed number of pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Source(f32),
+    Viewport(f32),
+    Absolute(u32),
+}
+
+// One pass of a preset, as its `shaderN` and related keys describe it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassConfig {
+    pub shader: PathBuf,
+    // Whether inputs are sampled bilinearly, or None to follow `video_smooth`.
+    pub filter_linear: Option<bool>,
+    // Output size on each axis, or None for the input size, or the viewport for the
+    // last pass.
+    pub scale: Option<(Scale, Scale)>,
+    pub wrap_mode: wgpu::AddressMode,
+    // What FrameCount is taken modulo, or 0 to leave it counting.
+    pub frame_count_mod: u32,
+    // Name later passes can sample the output by, besides PassOutputN.
+    pub alias: Option<String>,
+    pub float_framebuffer: bool,
+    pub srgb_framebuffer: bool,
+}
+
+// The passes of a preset and the parameter values it overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub passes: Vec<PassConfig>,
+    pub parameters: HashMap<String, f32>,
+}
+
+// A value shaders read from a `#pragma parameter` line, with the range and step
+// menus would offer for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub description: String,
+    pub default: f32,
+    pub minimum: f32,
+    pub maximum: f32,
+    pub step: f32,
+}
+
+// A shader split into its stages and rewritten for naga, with what its pragmas say.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub vertex: String,
+    pub fragment: String,
+    pub parameters: Vec<Parameter>,
+    // From `#pragma name`, an alias for the pass's output.
+    pub name: Option<String>,
+    // From `#pragma format`, the Vulkan format of the pass's output.
+    pub format: Option<String>,
+}
+
+// A resource a shader uses, by the binding the renderer has to fill.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    // A uniform block of `size` bytes, with the offset of each member by name.
+    Uniforms {
+        binding: u32,
+        size: u32,
+        members: Vec<(String, u32)>,
+    },
+    // A texture, by the name it was sampled as before the combined sampler rewrite.
+    Texture {
+        binding: u32,
+        name: String,
+    },
+    Sampler {
+        binding: u32,
+    },
+}
+
+impl Binding {
+    pub fn binding(&self) -> u32 {
+        match self {
+            Binding::Uniforms { binding, .. }
+            | Binding::Texture { binding, .. }
+            | Binding::Sampler { binding } => *binding,
+        }
+    }
+}
+
+// What the uniforms of a pass are filled with: the sizes of the textures by their
+// names (Source, Original, Output, FinalViewport, PassOutputN and aliases), the
+// frame count and parameter values.
+pub struct Semantics<'a> {
+    pub sizes: &'a HashMap<String, (u32, u32)>,
+    pub frame_count: u32,
+    pub parameters: &'a HashMap<String, f32>,
+}
+
+// Loads a preset, or a single .slang or .glsl shader as a preset with one pass.
+pub fn load_preset(path: &Path) -> Result<Preset, String> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    if matches!(extension, Some("slang" | "glsl")) {
+        return Ok(Preset {
+            passes: vec![pass_config(&HashMap::new(), 0, path.to_path_buf())?],
+            parameters: HashMap::new(),
+        });
+    }
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read shader preset {}: {}", path.display(), e))?;
+    parse_preset(&text, path.parent().unwrap_or(Path::new("")))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Parses the `key = value` lines of a preset, with shader paths relative to
+// `directory`.
+pub fn parse_preset(text: &str, directory: &Path) -> Result<Preset, String> {
+    let mut values = HashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("#reference") {
+            return Err("Presets referencing other presets are not supported".to_string());
+        }
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .map_or(value, |value| value.split('"').next().unwrap_or(value));
+        values.insert(key.trim().to_string(), value.to_string());
+    }
+
+    let count: usize = values
+        .get("shaders")
+        .ok_or("The preset has no shaders key")?
+        .parse()
+        .map_err(|_| "The shaders key isn't a number")?;
+    if count == 0 {
+        return Err("The preset has no passes".to_string());
+    }
+    let passes = (0..count)
+        .map(|index| {
+            let shader = values
+                .get(&format!("shader{}", index))
+                .ok_or(format!("Pass {} has no shader", index))?;
+            pass_config(&values, index, directory.join(shader))
+        })
+        .collect::<Result<_, String>>()?;
+    if values
+        .get("textures")
+        .is_some_and(|textures| !textures.is_empty())
+    {
+        println!(
+            "Shader preset lookup textures are not supported, passes sampling them won't load"
+        );
+    }
+
+    let mut parameters = HashMap::new();
+    for name in values
+        .get("parameters")
+        .into_iter()
+        .flat_map(|names| names.split(';'))
+    {
+        let name = name.trim();
+        if let Some(value) = values.get(name).and_then(|value| value.parse().ok()) {
+            parameters.insert(name.to_string(), value);
+        }
+    }
+    Ok(Preset { passes, parameters })
+}
+
+// Reads the keys of pass `index` from the values of a preset.
+fn pass_config(
+    values: &HashMap<String, String>,
+    index: usize,
+    shader: PathBuf,
+) -> Result<PassConfig, String> {
+    let value = |key: &str| values.get(&format!("{}{}", key, index)).map(String::as_str);
+    let flag = |key: &str| value(key).map(|value| value == "true" || value == "1");
+
+    let scale_type = value("scale_type");
+    let scale_types = (
+        value("scale_type_x").or(scale_type),
+        value("scale_type_y").or(scale_type),
+    );
+    let scale = value("scale");
+    let axis = |scale_type: Option<&str>, factor: Option<&str>| -> Result<Option<Scale>, String> {
+        let factor = factor.or(scale);
+        let Some(scale_type) = scale_type.or(factor.map(|_| "source")) else {
+            return Ok(None);
+        };
+        let invalid = || format!("Pass {} has an invalid scale {:?}", index, factor);
+        let factor =
+            |default| factor.map_or(Ok(default), |factor| factor.parse().map_err(|_| invalid()));
+        Ok(Some(match scale_type {
+            "source" => Scale::Source(factor(1.0)?),
+            "viewport" => Scale::Viewport(factor(1.0)?),
+            "absolute" => Scale::Absolute(factor(0.0)? as u32),
+            scale_type => {
+                return Err(format!(
+                    "Pass {} has an unknown scale type {}",
+                    index, scale_type
+                ))
+            }
+        }))
+    };
+    let scale = match (
+        axis(scale_types.0, value("scale_x"))?,
+        axis(scale_types.1, value("scale_y"))?,
+    ) {
+        (None, None) => None,
+        (x, y) => Some((
+            x.unwrap_or(Scale::Source(1.0)),
+            y.unwrap_or(Scale::Source(1.0)),
+        )),
+    };
+
+    let wrap_mode = match value("wrap_mode") {
+        None | Some("clamp_to_edge" | "clamp_to_border") => wgpu::AddressMode::ClampToEdge,
+        Some("repeat") => wgpu::AddressMode::Repeat,
+        Some("mirrored_repeat") => wgpu::AddressMode::MirrorRepeat,
+        Some(wrap_mode) => {
+            return Err(format!(
+                "Pass {} has an unknown wrap mode {}",
+                index, wrap_mode
+            ))
+        }
+    };
+    Ok(PassConfig {
+        shader,
+        filter_linear: flag("filter_linear"),
+        scale,
+        wrap_mode,
+        frame_count_mod: value("frame_count_mod")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0),
+        alias: value("alias")
+            .filter(|alias| !alias.is_empty())
+            .map(str::to_string),
+        float_framebuffer: flag("float_framebuffer").unwrap_or(false),
+        srgb_framebuffer: flag("srgb_framebuffer").unwrap_or(false),
+    })
+}
+
+// Loads a shader with its includes and splits it into stages.
+pub fn load_source(path: &Path) -> Result<Source, String> {
+    let text = read_with_includes(path, 0)?;
+    let legacy = path
+        .extension()
+        .is_some_and(|extension| extension == "glsl");
+    parse_source(&text, legacy).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// Reads a shader, replacing its `#include "file"` lines with the files' contents.
+fn read_with_includes(path: &Path, depth: usize) -> Result<String, String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{} includes itself", path.display()));
+    }
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read shader {}: {}", path.display(), e))?;
+    let mut output = String::with_capacity(text.len());
+    for line in text.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(include) => {
+                let include = include.trim().trim_matches('"');
+                let include = path.parent().unwrap_or(Path::new("")).join(include);
+                output.push_str(&read_with_includes(&include, depth + 1)?);
+            }
+            None => output.push_str(line),
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+// Splits a shader into its stages at `#pragma stage` lines, reading the other
+// pragmas on the way. Legacy .glsl shaders have both stages in one body chosen
+// between with VERTEX and FRAGMENT defines instead. Pragma lines are blanked
+// rather than removed so errors still point at the right line.
+pub fn parse_source(text: &str, legacy: bool) -> Result<Source, String> {
+    let mut stages = [String::new(), String::new()];
+    // Which stages the current line goes to, both until the first stage pragma
+    let mut current = [true, true];
+    let mut parameters: Vec<Parameter> = Vec::new();
+    let mut name = None;
+    let mut format = None;
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let pragma = match (words.next(), words.next()) {
+            (Some("#pragma"), Some(pragma)) => Some(pragma),
+            _ => None,
+        };
+        let output = match pragma {
+            Some("stage") => {
+                current = match words.next() {
+                    Some("vertex") => [true, false],
+                    Some("fragment") => [false, true],
+                    stage => return Err(format!("Unknown shader stage {:?}", stage)),
+                };
+                String::new()
+            }
+            Some("parameter") => {
+                let parameter = parse_parameter(line)?;
+                if !parameters
+                    .iter()
+                    .any(|existing| existing.name == parameter.name)
+                {
+                    parameters.push(parameter);
+                }
+                String::new()
+            }
+            Some("name") => {
+                name = words.next().map(str::to_string);
+                String::new()
+            }
+            Some("format") => {
+                format = words.next().map(str::to_string);
+                String::new()
+            }
+            _ => rewrite_line(line),
+        };
+        for (stage, source) in stages.iter_mut().enumerate() {
+            if current[stage] {
+                source.push_str(&output);
+            }
+            source.push('\n');
+        }
+    }
+    let [mut vertex, mut fragment] = stages;
+    if legacy {
+        vertex = define_after_version(&vertex, "VERTEX");
+        fragment = define_after_version(&fragment, "FRAGMENT");
+    } else if current == [true, true] {
+        return Err("The shader has no #pragma stage lines".to_string());
+    }
+    Ok(Source {
+        vertex,
+        fragment,
+        parameters,
+        name,
+        format,
+    })
+}
+
+// Parses `#pragma parameter NAME "Description" default minimum maximum [step]`.
+fn parse_parameter(line: &str) -> Result<Parameter, String> {
+    let invalid = || format!("Invalid parameter line {}", line.trim());
+    let rest = line.trim().strip_prefix("#pragma").ok_or_else(invalid)?;
+    let rest = rest
+        .trim_start()
+        .strip_prefix("parameter")
+        .ok_or_else(invalid)?;
+    let (name, rest) = rest.trim_start().split_once('"').ok_or_else(invalid)?;
+    let (description, rest) = rest.split_once('"').ok_or_else(invalid)?;
+    let numbers = rest
+        .split_whitespace()
+        .map(|number| number.parse::<f32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let [default, minimum, maximum, ..] = numbers[..] else {
+        return Err(invalid());
+    };
+    Ok(Parameter {
+        name: name.trim().to_string(),
+        description: description.to_string(),
+        default,
+        minimum,
+        maximum,
+        step: numbers.get(3).copied().unwrap_or(0.0),
+    })
+}
+
+// Adds `#define name` after the `#version` line, which has to stay first.
+fn define_after_version(source: &str, name: &str) -> String {
+    match source.find("#version") {
+        Some(start) => {
+            let end = source[start..]
+                .find('\n')
+                .map_or(source.len(), |end| start + end);
+            format!("{}\n#define {}{}", &source[..end], name, &source[end..])
+        }
+        None => format!("#define {}\n{}", name, source),
+    }
+}
+
+// Rewrites a line into GLSL naga accepts. A push constant block is bound as a
+// uniform block, and a combined `uniform sampler2D Name;` becomes a texture and a
+// sampler with a define so `Name` still works everywhere a sampler2D would.
+fn rewrite_line(line: &str) -> String {
+    let Some((layout, declaration)) = split_layout(line) else {
+        return line.to_string();
+    };
+    let qualifiers: Vec<&str> = layout.split(',').map(str::trim).collect();
+    if qualifiers.contains(&"push_constant") {
+        return format!(
+            "layout(std140, set = 0, binding = {}) {}",
+            PUSH_BINDING, declaration
+        );
+    }
+    let words: Vec<&str> = declaration
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let (["uniform", "sampler2D", name] | ["uniform", _, "sampler2D", name]) = words[..] else {
+        return line.to_string();
+    };
+    let binding = qualifiers.iter().find_map(|qualifier| {
+        let (key, value) = qualifier.split_once('=')?;
+        (key.trim() == "binding").then(|| value.trim().parse::<u32>().ok())?
+    });
+    let Some(binding) = binding else {
+        return line.to_string();
+    };
+    let set = qualifiers
+        .iter()
+        .filter(|qualifier| qualifier.starts_with("set"))
+        .map(|qualifier| format!("{}, ", qualifier))
+        .collect::<String>();
+    format!(
+        "layout({set}binding = {binding}) uniform texture2D {name}_texture; \
+         layout({set}binding = {sampler}) uniform sampler {name}_sampler;\n\
+         #define {name} sampler2D({name}_texture, {name}_sampler)",
+        sampler = binding + SAMPLER_BINDING_OFFSET,
+    )
+}
+
+// Splits `layout(qualifiers) rest` into the qualifiers and the rest.
+fn split_layout(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix("layout")?;
+    let rest = rest.trim_start().strip_prefix('(')?;
+    let (layout, declaration) = rest.split_once(')')?;
+    Some((layout, declaration.trim_start()))
+}
+
+// Compiles a stage of a rewritten shader into a validated naga module.
+pub fn compile(source: &str, stage: naga::ShaderStage) -> Result<naga::Module, String> {
+    let module = naga::front::glsl::Frontend::default()
+        .parse(&stage.into(), source)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(|error| {
+                    format!(
+                        "line {}: {}",
+                        error.meta.location(source).line_number,
+                        error.kind
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        })?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::empty(),
+    )
+    .validate(&module)
+    .map_err(|error| error.emit_to_string(source))?;
+    Ok(module)
+}
+
+// Returns the resources the modules of a pass use, each binding once.
+pub fn bindings(modules: &[&naga::Module]) -> Vec<Binding> {
+    let mut bindings: Vec<Binding> = Vec::new();
+    for module in modules {
+        for (_, variable) in module.global_variables.iter() {
+            let Some(resource) = &variable.binding else {
+                continue;
+            };
+            let binding = resource.binding;
+            if bindings
+                .iter()
+                .any(|existing| existing.binding() == binding)
+            {
+                continue;
+            }
+            match &module.types[variable.ty].inner {
+                naga::TypeInner::Struct { members, span }
+                    if variable.space == naga::AddressSpace::Uniform =>
+                {
+                    bindings.push(Binding::Uniforms {
+                        binding,
+                        size: span.next_multiple_of(16),
+                        members: members
+                            .iter()
+                            .filter_map(|member| Some((member.name.clone()?, member.offset)))
+                            .collect(),
+                    });
+                }
+                naga::TypeInner::Image { .. } => {
+                    let name = variable.name.as_deref().unwrap_or_default();
+                    bindings.push(Binding::Texture {
+                        binding,
+                        name: name.strip_suffix("_texture").unwrap_or(name).to_string(),
+                    });
+                }
+                naga::TypeInner::Sampler { .. } => bindings.push(Binding::Sampler { binding }),
+                _ => {}
+            }
+        }
+    }
+    bindings.sort_by_key(Binding::binding);
+    bindings
+}
+
+// Matrix from the [0, 1] quad the passes draw to clip space, with the top left
+// corner at the top of the target.
+const MVP: [f32; 16] = [
+    2.0, 0.0, 0.0, 0.0, //
+    0.0, -2.0, 0.0, 0.0, //
+    0.0, 0.0, 1.0, 0.0, //
+    -1.0, 1.0, 0.0, 1.0,
+];
+
+// Returns the contents of a uniform block, with each member the semantics know by
+// name written at its offset and the rest left zero.
+pub fn uniform_data(size: u32, members: &[(String, u32)], semantics: &Semantics) -> Vec<u8> {
+    let mut data = vec![0u8; size as usize];
+    for (name, offset) in members {
+        let mut write = |bytes: &[u8]| {
+            if let Some(range) = data.get_mut(*offset as usize..*offset as usize + bytes.len()) {
+                range.copy_from_slice(bytes);
+            }
+        };
+        let size = name
+            .strip_suffix("Size")
+            .and_then(|texture| semantics.sizes.get(texture));
+        if let Some(&(width, height)) = size {
+            let (width, height) = (width as f32, height as f32);
+            let vector = [width, height, 1.0 / width, 1.0 / height];
+            write(&vector.map(f32::to_ne_bytes).concat());
+        } else if name == "MVP" {
+            write(&MVP.map(f32::to_ne_bytes).concat());
+        } else if name == "FrameCount" {
+            write(&semantics.frame_count.to_ne_bytes());
+        } else if name == "FrameDirection" {
+            write(&1i32.to_ne_bytes());
+        } else if let Some(value) = semantics.parameters.get(name) {
+            write(&value.to_ne_bytes());
+        }
+    }
+    data
+}
+
+// Returns the size a pass renders at given its input size and the final viewport.
+pub fn output_size(
+    scale: Option<(Scale, Scale)>,
+    source: (u32, u32),
+    viewport: (u32, u32),
+) -> (u32, u32) {
+    let axis = |scale, source: u32, viewport: u32| {
+        let size = match scale {
+            Scale::Source(factor) => (source as f32 * factor).round() as u32,
+            Scale::Viewport(factor) => (viewport as f32 * factor).round() as u32,
+            Scale::Absolute(size) => size,
+        };
+        size.max(1)
+    };
+    match scale {
+        Some((x, y)) => (axis(x, source.0, viewport.0), axis(y, source.1, viewport.1)),
+        None => source,
+    }
+}
+
+// Returns the format a pass renders into, from its `#pragma format` or the
+// preset's framebuffer flags.
+pub fn texture_format(pass: &PassConfig, format: Option<&str>) -> wgpu::TextureFormat {
+    match format {
+        Some("R8G8B8A8_SRGB") => wgpu::TextureFormat::Rgba8UnormSrgb,
+        // 32 bit floats can't be filtered without an optional feature
+        Some("R16G16B16A16_SFLOAT" | "R32G32B32A32_SFLOAT") => wgpu::TextureFormat::Rgba16Float,
+        Some("A2B10G10R10_UNORM_PACK32") => wgpu::TextureFormat::Rgb10a2Unorm,
+        _ if pass.float_framebuffer => wgpu::TextureFormat::Rgba16Float,
+        _ if pass.srgb_framebuffer => wgpu::TextureFormat::Rgba8UnormSrgb,
+        _ => wgpu::TextureFormat::Rgba8Unorm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSTHROUGH: &str = r#"#version 450
+
+layout(push_constant) uniform Push
+{
+    vec4 SourceSize;
+    float BRIGHTNESS;
+} params;
+
+#pragma parameter BRIGHTNESS "Brightness" 1.0 0.0 2.0 0.05
+
+layout(std140, set = 0, binding = 0) uniform UBO
+{
+    mat4 MVP;
+    uint FrameCount;
+} global;
+
+#pragma stage vertex
+layout(location = 0) in vec4 Position;
+layout(location = 1) in vec2 TexCoord;
+layout(location = 0) out vec2 vTexCoord;
+
+void main()
+{
+    gl_Position = global.MVP * Position;
+    vTexCoord = TexCoord;
+}
+
+#pragma stage fragment
+#pragma name Bright
+layout(location = 0) in vec2 vTexCoord;
+layout(location = 0) out vec4 FragColor;
+layout(set = 0, binding = 2) uniform sampler2D Source;
+
+void main()
+{
+    FragColor = vec4(texture(Source, vTexCoord).rgb * params.BRIGHTNESS, 1.0);
+}
+"#;
+
+    #[test]
+    fn parses_multi_pass_presets() {
+        let preset = parse_preset(
+            r#"
+            # Two passes
+            shaders = 2
+            shader0 = "shaders/first.slang"
+            filter_linear0 = false
+            scale_type0 = source
+            scale0 = 2.0
+            alias0 = First
+            shader1 = shaders/second.slang
+            scale_type_x1 = viewport
+            scale_y1 = 240
+            scale_type_y1 = absolute
+            wrap_mode1 = repeat
+            parameters = "BRIGHTNESS;UNUSED"
+            BRIGHTNESS = 1.5
+            "#,
+            Path::new("presets"),
+        )
+        .unwrap();
+        assert_eq!(preset.passes.len(), 2);
+        let (first, second) = (&preset.passes[0], &preset.passes[1]);
+        assert_eq!(first.shader, Path::new("presets/shaders/first.slang"));
+        assert_eq!(first.filter_linear, Some(false));
+        assert_eq!(first.scale, Some((Scale::Source(2.0), Scale::Source(2.0))));
+        assert_eq!(first.alias.as_deref(), Some("First"));
+        assert_eq!(second.filter_linear, None);
+        assert_eq!(
+            second.scale,
+            Some((Scale::Viewport(1.0), Scale::Absolute(240)))
+        );
+        assert_eq!(second.wrap_mode, wgpu::AddressMode::Repeat);
+        assert_eq!(
+            preset.parameters,
+            HashMap::from([("BRIGHTNESS".to_string(), 1.5)])
+        );
+
+        assert!(parse_preset("shaders = 1", Path::new("")).is_err());
+        assert!(parse_preset(
+            "shaders = 1\nshader0 = a.slang\nscale_type0 = huge",
+            Path::new("")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn splits_stages_and_reads_pragmas() {
+        let source = parse_source(PASSTHROUGH, false).unwrap();
+        assert_eq!(source.name.as_deref(), Some("Bright"));
+        assert_eq!(
+            source.parameters,
+            vec![Parameter {
+                name: "BRIGHTNESS".to_string(),
+                description: "Brightness".to_string(),
+                default: 1.0,
+                minimum: 0.0,
+                maximum: 2.0,
+                step: 0.05,
+            }]
+        );
+        assert!(source.vertex.contains("gl_Position"));
+        assert!(!source.vertex.contains("FragColor"));
+        assert!(source.fragment.contains("FragColor"));
+        assert!(!source.fragment.contains("gl_Position"));
+        // Both stages keep every line so errors point at the right one
+        assert_eq!(source.vertex.lines().count(), PASSTHROUGH.lines().count());
+        assert!(parse_source("#version 450\nvoid main() {}", false).is_err());
+    }
+
+    #[test]
+    fn rewrites_what_naga_doesnt_accept() {
+        assert_eq!(
+            rewrite_line("layout(push_constant) uniform Push"),
+            "layout(std140, set = 0, binding = 64) uniform Push"
+        );
+        assert_eq!(
+            rewrite_line("layout(set = 0, binding = 3) uniform sampler2D Original;"),
+            "layout(set = 0, binding = 3) uniform texture2D Original_texture; \
+             layout(set = 0, binding = 35) uniform sampler Original_sampler;\n\
+             #define Original sampler2D(Original_texture, Original_sampler)"
+        );
+        let line = "layout(location = 0) in vec2 vTexCoord;";
+        assert_eq!(rewrite_line(line), line);
+    }
+
+    #[test]
+    fn compiles_and_reflects_slang_shaders() {
+        let source = parse_source(PASSTHROUGH, false).unwrap();
+        let vertex = compile(&source.vertex, naga::ShaderStage::Vertex).unwrap();
+        let fragment = compile(&source.fragment, naga::ShaderStage::Fragment).unwrap();
+        let bindings = bindings(&[&vertex, &fragment]);
+        assert_eq!(
+            bindings,
+            vec![
+                Binding::Uniforms {
+                    binding: 0,
+                    size: 80,
+                    members: vec![("MVP".to_string(), 0), ("FrameCount".to_string(), 64)],
+                },
+                Binding::Texture {
+                    binding: 2,
+                    name: "Source".to_string(),
+                },
+                Binding::Sampler { binding: 34 },
+                Binding::Uniforms {
+                    binding: 64,
+                    size: 32,
+                    members: vec![
+                        ("SourceSize".to_string(), 0),
+                        ("BRIGHTNESS".to_string(), 16)
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fills_uniforms_by_name() {
+        let sizes = HashMap::from([("Source".to_string(), (256, 224))]);
+        let parameters = HashMap::from([("BRIGHTNESS".to_string(), 1.5)]);
+        let semantics = Semantics {
+            sizes: &sizes,
+            frame_count: 7,
+            parameters: &parameters,
+        };
+        let members = [
+            ("SourceSize".to_string(), 0),
+            ("BRIGHTNESS".to_string(), 16),
+            ("FrameCount".to_string(), 20),
+            ("Unknown".to_string(), 24),
+        ];
+        let data = uniform_data(32, &members, &semantics);
+        let float =
+            |offset: usize| f32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+        assert_eq!(
+            [float(0), float(4), float(8), float(12)],
+            [256.0, 224.0, 1.0 / 256.0, 1.0 / 224.0]
+        );
+        assert_eq!(float(16), 1.5);
+        assert_eq!(u32::from_ne_bytes(data[20..24].try_into().unwrap()), 7);
+        assert_eq!(&data[24..], &[0; 8]);
+    }
+
+    #[test]
+    fn sizes_passes_from_their_scale() {
+        assert_eq!(output_size(None, (256, 224), (1024, 768)), (256, 224));
+        assert_eq!(
+            output_size(
+                Some((Scale::Source(2.0), Scale::Viewport(0.5))),
+                (256, 224),
+                (1024, 768)
+            ),
+            (512, 384)
+        );
+        assert_eq!(
+            output_size(
+                Some((Scale::Absolute(320), Scale::Absolute(0))),
+                (256, 224),
+                (1024, 768)
+            ),
+            (320, 1)
+        );
+    }
+}
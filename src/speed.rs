@@ -0,0 +1,320 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// speed.rs
+//
+// This module controls how fast emulation runs. It paces frames to the core's
+// frame rate itself instead of relying on the minifb limiter, so fast-forward can
+// run at a chosen multiple of normal speed and slow motion at a fraction of it,
+// e.g. for practising a hard part of a game. Cores can ask whether the frontend is
+// fast-forwarding and force it on themselves, e.g. to skip disc load times.
+
+use libc::c_uint;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{audio, hotkeys, osd, video};
+
+// struct retro_fastforwarding_override, which libretro-sys predates.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FastForwardingOverride {
+    pub ratio: f32,
+    pub fastforward: bool,
+    pub notification: bool,
+    pub inhibit_toggle: bool,
+}
+
+// struct retro_throttle_state, which libretro-sys predates.
+#[repr(C)]
+pub struct ThrottleState {
+    pub mode: c_uint,
+    pub rate: f32,
+}
+
+// Values of ThrottleState.mode for the speeds this frontend can run at.
+const THROTTLE_NONE: c_uint = 0;
+const THROTTLE_FAST_FORWARD: c_uint = 2;
+const THROTTLE_SLOW_MOTION: c_uint = 3;
+const THROTTLE_UNBLOCKED: c_uint = 6;
+
+struct Speed {
+    // Run as fast as possible regardless of fast-forward, e.g. for --benchmark
+    unthrottled: bool,
+    // Frame rate of the core, used for the length of a frame at normal speed
+    fps: f64,
+    // Speed multiplier while fast-forwarding, from `fastforward_ratio`; 0 is unlimited
+    fast_forward_ratio: f32,
+    // Fast-forward toggled with the hotkey
+    user_fast_forward: bool,
+    // The hold fast-forward hotkey is held down
+    held_fast_forward: bool,
+    // How many times slower slow motion runs, from `slowmotion_ratio`
+    slow_motion_ratio: f32,
+    // Slow motion toggled with the hotkey
+    slow_motion: bool,
+    // Fast-forward requested by the core with ENVIRONMENT_SET_FASTFORWARDING_OVERRIDE
+    core_override: Option<FastForwardingOverride>,
+    // When the next frame is due
+    next_frame: Instant,
+    // Frames that weren't ready by the time they were due
+    late_frames: u64,
+}
+
+impl Speed {
+    fn is_fast_forwarding(&self) -> bool {
+        match &self.core_override {
+            Some(core_override) if core_override.fastforward => true,
+            Some(core_override) if core_override.inhibit_toggle => false,
+            _ => self.user_fast_forward || self.held_fast_forward,
+        }
+    }
+
+    // The speed multiplier in slow motion, while it is on and not overridden by
+    // fast-forward.
+    fn slow_motion_speed(&self) -> Option<f32> {
+        (self.slow_motion && !self.is_fast_forwarding()).then(|| 1.0 / self.slow_motion_ratio)
+    }
+
+    // The speed multiplier to run at; 0 means as fast as possible.
+    fn ratio(&self) -> f32 {
+        if self.unthrottled {
+            return 0.0;
+        }
+        if !self.is_fast_forwarding() {
+            return self.slow_motion_speed().unwrap_or(1.0);
+        }
+        match &self.core_override {
+            Some(core_override) if core_override.fastforward && core_override.ratio >= 0.0 => {
+                core_override.ratio
+            }
+            _ => self.fast_forward_ratio,
+        }
+    }
+}
+
+// The environment callback and the main loop both run on the emulation thread.
+static SPEED: Lazy<Mutex<Speed>> = Lazy::new(|| {
+    Mutex::new(Speed {
+        unthrottled: false,
+        fps: 0.0,
+        fast_forward_ratio: 0.0,
+        user_fast_forward: false,
+        held_fast_forward: false,
+        slow_motion_ratio: 2.0,
+        slow_motion: false,
+        core_override: None,
+        next_frame: Instant::now(),
+        late_frames: 0,
+    })
+});
+
+// Reads `fastforward_ratio` and `slowmotion_ratio` from the config.
+pub fn configure(config: &HashMap<String, String>) {
+    let ratio = |key: &str, default: f32| {
+        config
+            .get(key)
+            .and_then(|ratio| ratio.parse::<f32>().ok())
+            .unwrap_or(default)
+    };
+    let mut speed = SPEED.lock().unwrap();
+    speed.fast_forward_ratio = ratio("fastforward_ratio", 0.0).max(0.0);
+    speed.slow_motion_ratio = ratio("slowmotion_ratio", 2.0).max(1.0);
+    let slow_motion_speed = speed.slow_motion_speed();
+    drop(speed);
+    audio::set_slow_motion(slow_motion_speed.map(f64::from));
+}
+
+// Sets the frame rate frames are paced to at normal speed. Pacing starts over from
+// now when it changes, e.g. when a core switches between PAL and NTSC timing.
+pub fn set_fps(fps: f64) {
+    let mut speed = SPEED.lock().unwrap();
+    if speed.fps != fps {
+        speed.fps = fps;
+        speed.next_frame = Instant::now();
+    }
+}
+
+// Returns the core's frame rate.
+pub fn fps() -> f64 {
+    SPEED.lock().unwrap().fps
+}
+
+// Returns how many frames have run late since the start, for the performance overlay.
+pub fn late_frames() -> u64 {
+    SPEED.lock().unwrap().late_frames
+}
+
+// Lets emulation run as fast as possible, ignoring frame pacing altogether.
+pub fn set_unthrottled(unthrottled: bool) {
+    SPEED.lock().unwrap().unthrottled = unthrottled;
+}
+
+// Returns whether emulation is currently fast-forwarding, for ENVIRONMENT_GET_FASTFORWARDING.
+pub fn is_fast_forwarding() -> bool {
+    SPEED.lock().unwrap().is_fast_forwarding()
+}
+
+// Handles ENVIRONMENT_GET_THROTTLE_STATE: how frames are being paced and the frame
+// rate that works out to, or 0 when it is unbounded.
+pub unsafe fn get_throttle_state(state: *mut ThrottleState) -> bool {
+    let Some(state) = state.as_mut() else {
+        return false;
+    };
+    let speed = SPEED.lock().unwrap();
+    let ratio = speed.ratio();
+    let mode = if ratio <= 0.0 {
+        THROTTLE_UNBLOCKED
+    } else if speed.is_fast_forwarding() {
+        THROTTLE_FAST_FORWARD
+    } else if speed.slow_motion {
+        THROTTLE_SLOW_MOTION
+    } else {
+        THROTTLE_NONE
+    };
+    *state = ThrottleState {
+        mode,
+        rate: speed.fps as f32 * ratio,
+    };
+    true
+}
+
+// Turns fast-forward on or off from the hotkey, unless the core has inhibited it.
+pub fn toggle_fast_forward() {
+    let mut speed = SPEED.lock().unwrap();
+    if speed
+        .core_override
+        .as_ref()
+        .is_some_and(|core_override| core_override.inhibit_toggle)
+    {
+        println!("Fast-forward is controlled by the core");
+        return;
+    }
+    speed.user_fast_forward = !speed.user_fast_forward;
+    let fast_forwarding = speed.is_fast_forwarding();
+    drop(speed);
+    fast_forward_changed(fast_forwarding, true);
+}
+
+// Fast-forwards for as long as the hold hotkey is down. Called every frame with
+// whether it is.
+pub fn set_fast_forward_held(held: bool) {
+    let mut speed = SPEED.lock().unwrap();
+    if speed.held_fast_forward == held {
+        return;
+    }
+    let was_fast_forwarding = speed.is_fast_forwarding();
+    speed.held_fast_forward = held;
+    let fast_forwarding = speed.is_fast_forwarding();
+    drop(speed);
+    // Held fast-forward is plain to see, so it goes without a message
+    if fast_forwarding != was_fast_forwarding {
+        fast_forward_changed(fast_forwarding, false);
+    }
+}
+
+// Handles ENVIRONMENT_SET_FASTFORWARDING_OVERRIDE. A NULL override only asks
+// whether the frontend supports it.
+pub unsafe fn set_fastforwarding_override(core_override: *const FastForwardingOverride) -> bool {
+    if core_override.is_null() {
+        return true;
+    }
+    let core_override = &*core_override;
+    let mut speed = SPEED.lock().unwrap();
+    let was_fast_forwarding = speed.is_fast_forwarding();
+    speed.core_override = Some(*core_override);
+    let fast_forwarding = speed.is_fast_forwarding();
+    drop(speed);
+    if fast_forwarding != was_fast_forwarding {
+        fast_forward_changed(fast_forwarding, core_override.notification);
+    }
+    true
+}
+
+// Turns slow motion on or off from the hotkey. Fast-forward takes over from it
+// while on.
+pub fn toggle_slow_motion() {
+    let mut speed = SPEED.lock().unwrap();
+    speed.slow_motion = !speed.slow_motion;
+    let message = if speed.slow_motion {
+        format!("Slow motion on, {}x speed", 1.0 / speed.slow_motion_ratio)
+    } else {
+        "Slow motion off".to_string()
+    };
+    let slow_motion_speed = speed.slow_motion_speed();
+    drop(speed);
+    audio::set_slow_motion(slow_motion_speed.map(f64::from));
+    println!("{}", message);
+    osd::show_message(&message);
+}
+
+// Audio can't keep up with a core running faster than normal, so the audio thread
+// is told to mute or speed it up while fast-forwarding.
+fn fast_forward_changed(fast_forwarding: bool, notify: bool) {
+    audio::set_fast_forwarding(fast_forwarding);
+    let slow_motion_speed = SPEED.lock().unwrap().slow_motion_speed();
+    audio::set_slow_motion(slow_motion_speed.map(f64::from));
+    let message = if fast_forwarding {
+        "Fast-forward on"
+    } else {
+        "Fast-forward off"
+    };
+    println!("{}", message);
+    if notify {
+        osd::show_message(message);
+    }
+}
+
+// Sleeps until the next frame is due at the current speed. Called once per frame.
+pub fn wait_for_next_frame() {
+    let mut speed = SPEED.lock().unwrap();
+    // While paused the loop only redraws, at normal speed even when fast-forwarding
+    let ratio = if hotkeys::is_paused() {
+        1.0
+    } else {
+        speed.ratio()
+    };
+    let now = Instant::now();
+    if ratio <= 0.0 {
+        speed.next_frame = now;
+        return;
+    }
+    // With audio_sync, waiting for room in the audio queue paces frames instead
+    if ratio == 1.0 && audio::syncs() {
+        speed.next_frame = now;
+        return;
+    }
+    let frame = video::frame_duration(speed.fps).div_f32(ratio);
+    // After a stall, start pacing again from now instead of racing to catch up
+    if now > speed.next_frame + frame {
+        speed.next_frame = now;
+        speed.late_frames += 1;
+    }
+    speed.next_frame += frame;
+    if now > speed.next_frame {
+        speed.late_frames += 1;
+    }
+    let next_frame = speed.next_frame.min(now + Duration::from_secs(1));
+    drop(speed);
+    sleep_until(next_frame);
+}
+
+// How long before a deadline to stop sleeping, since the OS can wake a sleeping
+// thread up to a millisecond or more late.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(1500);
+
+// Waits until `deadline`, sleeping through most of the wait and spinning through
+// the end of it, so frames start on time without keeping a core busy.
+pub fn sleep_until(deadline: Instant) {
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        if remaining > SPIN_THRESHOLD {
+            thread::sleep(remaining - SPIN_THRESHOLD);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
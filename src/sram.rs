@@ -0,0 +1,160 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// sram.rs
+//
+// This module keeps the game's save RAM, where cartridges kept their battery
+// backed saves, in `<savefile_directory>/<game>.srm` as RetroArch does. It is
+// loaded once the game is, and written when the frontend closes. With
+// `autosave_interval` set to a number of seconds, it is also written that often
+// whenever it has changed, so a crash or power cut loses little progress. Files
+// are written on a thread of their own, so a slow disk doesn't hold up the game.
+
+use libretro_sys::MEMORY_SAVE_RAM;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::memory::CoreMemory;
+
+struct SaveRam {
+    path: PathBuf,
+    // The save RAM as last written, to tell when it has changed.
+    saved: Vec<u8>,
+    last_check: Instant,
+    // Sends save RAM to the thread writing it out.
+    writer: Sender<Vec<u8>>,
+    thread: JoinHandle<()>,
+}
+
+static SAVE_RAM: Mutex<Option<SaveRam>> = Mutex::new(None);
+
+// How often to check the save RAM for changes, or None to only save it on exit.
+static AUTOSAVE_INTERVAL: Mutex<Option<Duration>> = Mutex::new(None);
+
+// Reads `autosave_interval`, in seconds, 0 to turn autosaving off.
+pub fn configure(config: &HashMap<String, String>) {
+    let seconds = config
+        .get("autosave_interval")
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(0);
+    *AUTOSAVE_INTERVAL.lock().unwrap() = (seconds > 0).then(|| Duration::from_secs(seconds));
+}
+
+fn save_ram_path(config: &HashMap<String, String>, rom_name: &str) -> PathBuf {
+    let game_name = Path::new(rom_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    PathBuf::from(shellexpand::tilde(&config["savefile_directory"]).into_owned())
+        .join(format!("{}.srm", game_name))
+}
+
+// Writes save RAM through a temporary file, so a crash part way through leaves
+// the last save whole.
+fn write(path: &Path, save_ram: &[u8]) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|e| e.to_string())?;
+    }
+    let temporary = path.with_extension("srm.tmp");
+    fs::write(&temporary, save_ram)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| e.to_string())
+}
+
+// Loads the game's save RAM from its file, if it has some, and starts the thread
+// that saves it. Called once the game is loaded.
+pub fn load(memory: &CoreMemory, config: &HashMap<String, String>, rom_name: &str) {
+    let path = save_ram_path(config, rom_name);
+    if let Ok(saved) = fs::read(&path) {
+        if memory.write_region(MEMORY_SAVE_RAM, &saved) {
+            println!("Loaded save RAM from {}", path.display());
+        }
+    }
+    let Some(region) = memory.region(MEMORY_SAVE_RAM) else {
+        return;
+    };
+    let (writer, saves) = channel::<Vec<u8>>();
+    let thread_path = path.clone();
+    let thread = thread::spawn(move || {
+        for save_ram in saves {
+            if let Err(e) = write(&thread_path, &save_ram) {
+                println!(
+                    "Failed to save save RAM to {}: {}",
+                    thread_path.display(),
+                    e
+                );
+            }
+        }
+    });
+    *SAVE_RAM.lock().unwrap() = Some(SaveRam {
+        path,
+        saved: region.to_vec(),
+        last_check: Instant::now(),
+        writer,
+        thread,
+    });
+}
+
+// Hands the save RAM to the writing thread if it has changed since it was last
+// written, returning whether it had.
+fn flush(save_ram: &mut SaveRam, memory: &CoreMemory) -> bool {
+    let Some(region) = memory.region(MEMORY_SAVE_RAM) else {
+        return false;
+    };
+    if region == save_ram.saved.as_slice() {
+        return false;
+    }
+    save_ram.saved = region.to_vec();
+    save_ram.writer.send(save_ram.saved.clone()).is_ok()
+}
+
+// Saves the save RAM if it has changed and `autosave_interval` has passed since
+// it was last checked. Called every frame.
+pub fn update(memory: &CoreMemory) {
+    let Some(interval) = *AUTOSAVE_INTERVAL.lock().unwrap() else {
+        return;
+    };
+    let mut save_ram = SAVE_RAM.lock().unwrap();
+    let Some(save_ram) = save_ram.as_mut() else {
+        return;
+    };
+    if save_ram.last_check.elapsed() >= interval {
+        save_ram.last_check = Instant::now();
+        flush(save_ram, memory);
+    }
+}
+
+// Saves the save RAM if it has changed and waits for it to be written. Called
+// when the frontend closes.
+pub fn save(memory: &CoreMemory) {
+    let Some(mut save_ram) = SAVE_RAM.lock().unwrap().take() else {
+        return;
+    };
+    let changed = flush(&mut save_ram, memory);
+    drop(save_ram.writer);
+    let _ = save_ram.thread.join();
+    if changed {
+        println!("Saved save RAM to {}", save_ram.path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_save_ram_with_the_other_saves() {
+        let config = HashMap::from([("savefile_directory".to_string(), "./saves".to_string())]);
+        assert_eq!(
+            save_ram_path(&config, "roms/Super Metroid.sfc"),
+            Path::new("./saves/Super Metroid.srm")
+        );
+    }
+}
@@ -0,0 +1,159 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// subsystem.rs
+//
+// This module keeps the subsystems a core announces through
+// ENVIRONMENT_SET_SUBSYSTEM_INFO and loads several content files at once with
+// retro_load_game_special, e.g. a Game Boy ROM together with a Super Game Boy BIOS.
+
+use libc::{c_char, c_void};
+use libretro_sys::{CoreAPI, GameInfo, SubsystemInfo};
+use once_cell::sync::Lazy;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::ptr;
+use std::sync::Mutex;
+
+// One content slot of a subsystem, e.g. "GameBoy ROM".
+pub struct SubsystemRom {
+    pub description: String,
+    pub valid_extensions: String,
+    pub need_fullpath: bool,
+    pub required: bool,
+}
+
+// A way of loading content the core offers besides retro_load_game.
+pub struct Subsystem {
+    pub description: String,
+    pub ident: String,
+    pub id: u32,
+    pub roms: Vec<SubsystemRom>,
+}
+
+// Subsystems the core registered. The core sends them from retro_set_environment,
+// before the frontend knows which content it will load.
+pub static SUBSYSTEMS: Lazy<Mutex<Vec<Subsystem>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+fn string_from_ptr(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(ptr).to_string_lossy().into_owned() }
+    }
+}
+
+// Handles ENVIRONMENT_SET_SUBSYSTEM_INFO. The array ends with a zeroed entry.
+pub unsafe fn set_subsystem_info(info: *const SubsystemInfo) {
+    let mut subsystems = Vec::new();
+    let mut current = info;
+    while !current.is_null() && !(*current).ident.is_null() {
+        let info = &*current;
+        let roms = if info.roms.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(info.roms, info.num_roms as usize)
+                .iter()
+                .map(|rom| SubsystemRom {
+                    description: string_from_ptr(rom.desc),
+                    valid_extensions: string_from_ptr(rom.valid_extensions),
+                    need_fullpath: rom.need_fullpath,
+                    required: rom.required,
+                })
+                .collect()
+        };
+        subsystems.push(Subsystem {
+            description: string_from_ptr(info.desc),
+            ident: string_from_ptr(info.ident),
+            id: info.id,
+            roms,
+        });
+        current = current.add(1);
+    }
+
+    for subsystem in &subsystems {
+        println!(
+            "Core subsystem: {} ({}), {} content file(s)",
+            subsystem.ident,
+            subsystem.description,
+            subsystem.roms.len()
+        );
+        for rom in &subsystem.roms {
+            println!("  {} ({})", rom.description, rom.valid_extensions);
+        }
+    }
+    *SUBSYSTEMS.lock().unwrap() = subsystems;
+}
+
+// Loads the content files for the subsystem named `ident`, in the order the core
+// listed its slots.
+pub unsafe fn load_subsystem_content(core_api: &CoreAPI, ident: &str, content: &[String]) -> bool {
+    let subsystems = SUBSYSTEMS.lock().unwrap();
+    let subsystem = match subsystems.iter().find(|subsystem| subsystem.ident == ident) {
+        Some(subsystem) => subsystem,
+        None => {
+            let available: Vec<&str> = subsystems
+                .iter()
+                .map(|subsystem| subsystem.ident.as_str())
+                .collect();
+            panic!(
+                "Core has no subsystem '{}', available: {}",
+                ident,
+                available.join(", ")
+            );
+        }
+    };
+    if content.len() > subsystem.roms.len() {
+        panic!(
+            "Subsystem '{}' takes at most {} content file(s), got {}",
+            ident,
+            subsystem.roms.len(),
+            content.len()
+        );
+    }
+
+    // Keep the paths and file contents alive until the core has loaded them.
+    let mut paths = Vec::new();
+    let mut contents = Vec::new();
+    for (index, rom) in subsystem.roms.iter().enumerate() {
+        match content.get(index) {
+            Some(path) => {
+                println!("Loading {}: {}", rom.description, path);
+                paths.push(Some(
+                    CString::new(path.clone()).expect("Failed to create CString"),
+                ));
+                contents.push(if rom.need_fullpath {
+                    None
+                } else {
+                    Some(fs::read(path).expect("Failed to read file"))
+                });
+            }
+            None if rom.required => panic!("Subsystem '{}' requires {}", ident, rom.description),
+            None => {
+                paths.push(None);
+                contents.push(None);
+            }
+        }
+    }
+
+    let game_infos: Vec<GameInfo> = paths
+        .iter()
+        .zip(&contents)
+        .map(|(path, data)| GameInfo {
+            path: path.as_ref().map_or(ptr::null(), |path| path.as_ptr()),
+            data: data
+                .as_ref()
+                .map_or(ptr::null(), |data| data.as_ptr() as *const c_void),
+            size: data.as_ref().map_or(0, |data| data.len()),
+            meta: ptr::null(),
+        })
+        .collect();
+
+    let was_load_successful =
+        (core_api.retro_load_game_special)(subsystem.id, game_infos.as_ptr(), game_infos.len());
+    if !was_load_successful {
+        panic!("Subsystem content load was not successful");
+    }
+    was_load_successful
+}
@@ -7,14 +7,92 @@
 // This module handles video output for the emulator, including pixel format conversions,
 // rendering frames, and interfacing with the libretro video callbacks.
 
-use libretro_sys::PixelFormat;
-use minifb::Window;
-use std::sync::atomic::Ordering;
+use libc::c_void;
+use libretro_sys::{Framebuffer, GameGeometry, PixelFormat, SystemAvInfo, MEMORY_TYPE_CACHED};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "wgpu")]
+use crate::gpu;
+use crate::pixels::{self, Frame};
+use crate::window::Window;
 use crate::{
-    libretro::EmulatorState, VideoData, BYTES_PER_PIXEL, PIXEL_FORMAT_CHANNEL, VIDEO_DATA_CHANNEL,
+    bezel, clip, color, hotkeys, hw_render, led, libretro::EmulatorState, osd, perf_overlay, speed,
+    VideoData, BYTES_PER_PIXEL, GEOMETRY_CHANNEL, PIXEL_FORMAT_CHANNEL, ROTATION_CHANNEL,
+    VIDEO_DATA_CHANNEL,
 };
 
+// The last frame the core sent, kept for screenshots.
+static LAST_FRAME: Mutex<Option<VideoData>> = Mutex::new(None);
+
+// Pixel buffers that are done with, kept to be filled again instead of allocating
+// new ones every frame: converted frames from the core, colour corrected and
+// rotated frames, and window sized pictures. Only a few are in use at once, one
+// on its way from the core, the last frame and the picture being shown, and
+// with `video_threaded` one more composed ahead.
+static SPARE_BUFFERS: Mutex<Vec<Vec<u32>>> = Mutex::new(Vec::new());
+const MAX_SPARE_BUFFERS: usize = 8;
+
+// Returns an empty buffer, the smallest spare that holds `capacity` pixels if
+// there is one.
+fn take_buffer(capacity: usize) -> Vec<u32> {
+    let mut spares = SPARE_BUFFERS.lock().unwrap();
+    let fitting = (0..spares.len())
+        .filter(|&index| spares[index].capacity() >= capacity)
+        .min_by_key(|&index| spares[index].capacity());
+    match fitting.or(spares.len().checked_sub(1)) {
+        Some(index) => {
+            let mut buffer = spares.swap_remove(index);
+            buffer.clear();
+            buffer
+        }
+        None => Vec::with_capacity(capacity),
+    }
+}
+
+// Keeps a buffer that is done with for take_buffer to hand out again.
+fn recycle(buffer: Vec<u32>) {
+    let mut spares = SPARE_BUFFERS.lock().unwrap();
+    if buffer.capacity() > 0 && spares.len() < MAX_SPARE_BUFFERS {
+        spares.push(buffer);
+    }
+}
+
+// Calls `f` with the last frame the core sent, as it was sent, or returns None if
+// there is none yet.
+pub fn with_last_frame<T>(f: impl FnOnce(&Frame) -> T) -> Option<T> {
+    let last_frame = LAST_FRAME.lock().unwrap();
+    let video_data = last_frame
+        .as_ref()
+        .filter(|video_data| is_complete(video_data))?;
+    Some(f(&whole_frame(video_data)))
+}
+
+// The frame as the core sent it, before cropping or rotating.
+fn whole_frame(video_data: &VideoData) -> Frame<'_> {
+    Frame {
+        pixels: &video_data.frame_buffer,
+        width: video_data.width as usize,
+        height: video_data.height as usize,
+        stride: video_data.stride as usize,
+    }
+}
+
+// Returns whether a frame has pixels, and all of them. Empty frames, or ones from
+// the hardware renderer or a misbehaving core that come up short, are not shown.
+fn is_complete(video_data: &VideoData) -> bool {
+    video_data.width > 0
+        && video_data.height > 0
+        && video_data.frame_buffer.len()
+            >= video_data.stride as usize * (video_data.height as usize - 1)
+                + video_data.width as usize
+}
+
 // Represents the pixel format used by the emulator.
 pub struct EmulatorPixelFormat(pub PixelFormat);
 
@@ -25,6 +103,424 @@ impl Default for EmulatorPixelFormat {
     }
 }
 
+// Whether frames are converted and shown. Benchmark runs turn it off, and cores can
+// skip rendering when they see it through ENVIRONMENT_GET_AUDIO_VIDEO_ENABLE.
+static VIDEO_ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Returns whether the frames the core produces are shown.
+pub fn is_enabled() -> bool {
+    VIDEO_ENABLED.load(Ordering::SeqCst)
+}
+
+// Turns video output on or off. Frames sent while it is off are dropped.
+pub fn set_enabled(enabled: bool) {
+    VIDEO_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+// Lines of overscan cropped from the top and bottom of full NTSC (240 line) and
+// PAL (288 line) frames.
+const OVERSCAN_LINES: usize = 8;
+
+// Whether overscan is cropped, from `crop_overscan`. Cores are asked to crop it
+// themselves through ENVIRONMENT_GET_OVERSCAN, and frames that still have full
+// height are cropped here.
+static CROP_OVERSCAN: AtomicBool = AtomicBool::new(true);
+
+// Whether frames are presented for an adaptive sync (G-Sync, FreeSync) display,
+// from `video_adaptive_sync`.
+static ADAPTIVE_SYNC: AtomicBool = AtomicBool::new(false);
+
+// Returns whether frames are presented for an adaptive sync display. Those refresh
+// when a frame is shown, so frames are held until they are due and then shown
+// straight away, which keeps each one on screen for the same time however long
+// the core took to make it.
+pub fn adaptive_sync() -> bool {
+    ADAPTIVE_SYNC.load(Ordering::SeqCst)
+}
+
+// Reads `video_vsync`. Waiting for a vertical blank would only add latency on an
+// adaptive sync display, so vsync is off with `video_adaptive_sync`.
+pub fn vsync(config: &HashMap<String, String>) -> bool {
+    let vsync = config
+        .get("video_vsync")
+        .is_some_and(|value| value == "true");
+    let adaptive_sync = config
+        .get("video_adaptive_sync")
+        .is_some_and(|value| value == "true");
+    if vsync && adaptive_sync {
+        println!("Presenting for adaptive sync, video_vsync is ignored");
+    }
+    vsync && !adaptive_sync
+}
+
+// The shape the picture is given, from `video_aspect_ratio`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum AspectRatio {
+    // The display aspect ratio from the core's geometry.
+    Core,
+    // Square pixels, for systems whose pixels already have the intended shape.
+    Square,
+    // Fills the whole window.
+    Stretch,
+    // A fixed width to height ratio, e.g. 4:3 or `video_aspect_ratio_custom`.
+    Fixed(f64),
+}
+
+// Parses an aspect ratio given as "W:H", e.g. "4:3" or "8:7".
+fn parse_ratio(ratio: &str) -> Option<f64> {
+    let (width, height) = ratio.split_once(':')?;
+    let width: f64 = width.trim().parse().ok()?;
+    let height: f64 = height.trim().parse().ok()?;
+    (width > 0.0 && height > 0.0).then(|| width / height)
+}
+
+// Reads `video_aspect_ratio`: "core", "square", "stretch", "custom" for
+// `video_aspect_ratio_custom`, or a ratio such as "4:3".
+fn parse_aspect_ratio(config: &HashMap<String, String>) -> AspectRatio {
+    let mode = config
+        .get("video_aspect_ratio")
+        .map_or("core", String::as_str);
+    match mode {
+        "core" => AspectRatio::Core,
+        "square" => AspectRatio::Square,
+        "stretch" => AspectRatio::Stretch,
+        "custom" => config
+            .get("video_aspect_ratio_custom")
+            .and_then(|ratio| parse_ratio(ratio))
+            .map_or(AspectRatio::Core, AspectRatio::Fixed),
+        ratio => match parse_ratio(ratio) {
+            Some(ratio) => AspectRatio::Fixed(ratio),
+            None => {
+                println!("Unknown video_aspect_ratio {}, using the core's", ratio);
+                AspectRatio::Core
+            }
+        },
+    }
+}
+
+// How the picture is scaled up to the window.
+#[derive(Clone, Copy)]
+struct Scaling {
+    // Scale width and height by the same whole number, so every source pixel
+    // becomes an exact square block.
+    integer: bool,
+    // Fixed scale factor, or 0 for the largest that fits the window.
+    scale: usize,
+    aspect_ratio: AspectRatio,
+    // Bilinear filtering instead of repeating the nearest pixel.
+    smooth: bool,
+}
+
+static SCALING: Mutex<Scaling> = Mutex::new(Scaling {
+    integer: false,
+    scale: 0,
+    aspect_ratio: AspectRatio::Core,
+    smooth: false,
+});
+
+// Threads that scale bands of the picture at the same time, from `video_threads`,
+// and how many there are. None scales on the composing thread alone.
+static SCALING_POOL: Mutex<Option<(usize, rayon::ThreadPool)>> = Mutex::new(None);
+
+// How many bands the picture is split into per scaling thread, so threads that
+// finish early can take another.
+const BANDS_PER_THREAD: usize = 4;
+
+// Reads `video_threads`, where 0 means one per CPU core, and starts that many
+// scaling threads unless it is 1 or they are already running.
+fn configure_scaling_pool(config: &HashMap<String, String>) {
+    let threads = match config
+        .get("video_threads")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1)
+    {
+        0 => thread::available_parallelism().map_or(1, |threads| threads.get()),
+        threads => threads,
+    };
+    let mut pool = SCALING_POOL.lock().unwrap();
+    if pool.as_ref().map_or(1, |(current, _)| *current) == threads {
+        return;
+    }
+    *pool = if threads > 1 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("scaling {}", index))
+            .build()
+            .map_err(|e| println!("Failed to start scaling threads: {}", e))
+            .ok()
+            .map(|new_pool| {
+                println!("Scaling with {} threads", threads);
+                (threads, new_pool)
+            })
+    } else {
+        None
+    };
+}
+
+// Scales a frame to `width` x `height` into `dest` like pixels::scale_nearest_rows
+// and pixels::scale_bilinear_rows, split into bands of lines across the scaling
+// threads if there are any. The output is the same either way.
+fn scale(
+    frame: &Frame,
+    dest: &mut [u32],
+    stride: usize,
+    width: usize,
+    height: usize,
+    smooth: bool,
+) {
+    let scale_rows = if smooth {
+        pixels::scale_bilinear_rows
+    } else {
+        pixels::scale_nearest_rows
+    };
+    let pool = SCALING_POOL.lock().unwrap();
+    let Some((threads, pool)) = pool.as_ref().filter(|_| height > 1) else {
+        scale_rows(frame, dest, stride, width, height, 0..height);
+        return;
+    };
+    let band_height = height.div_ceil(threads * BANDS_PER_THREAD);
+    let dest = &mut dest[..(height - 1) * stride + width];
+    pool.install(|| {
+        dest.par_chunks_mut(band_height * stride)
+            .enumerate()
+            .for_each(|(band, dest)| {
+                let start = band * band_height;
+                let rows = start..(start + band_height).min(height);
+                scale_rows(frame, dest, stride, width, height, rows);
+            });
+    });
+}
+
+// The scaled picture in the window buffer, which filters change in place.
+struct Picture<'a> {
+    // The window buffer from the picture's top left corner on.
+    pixels: &'a mut [u32],
+    width: usize,
+    height: usize,
+    // Distance between the starts of two lines, which is the window width.
+    stride: usize,
+    // Size of the frame the picture was scaled from, as displayed.
+    source_width: usize,
+    source_height: usize,
+}
+
+impl Picture<'_> {
+    fn line_mut(&mut self, y: usize) -> &mut [u32] {
+        let start = y * self.stride;
+        &mut self.pixels[start..start + self.width]
+    }
+}
+
+// A software post-processing stage. Filters run after scaling and before the LED
+// and OSD are drawn, in the order `video_filters` lists them, each on the output of
+// the one before.
+trait Filter: Send {
+    fn name(&self) -> &'static str;
+    fn apply(&self, picture: &mut Picture);
+}
+
+// Brightness of the gaps between scanlines, and of the two dimmed colours in each
+// aperture grille stripe, out of 256.
+const SCANLINE_BRIGHTNESS: u32 = 128;
+const GRILLE_BRIGHTNESS: u32 = 192;
+
+// Looks like a CRT: a dark gap under every line the core drew, and the red, green
+// and blue stripes of an aperture grille across the picture.
+struct Crt;
+
+impl Filter for Crt {
+    fn name(&self) -> &'static str {
+        "crt"
+    }
+
+    fn apply(&self, picture: &mut Picture) {
+        // Below twice the source height a gap would cover whole lines, and below
+        // three times the width a stripe would be a whole pixel
+        let scanlines = picture.height >= picture.source_height * 2;
+        let grille = picture.width >= picture.source_width * 3;
+        for y in 0..picture.height {
+            // The bottom half of each source line is the gap
+            let phase = (y * picture.source_height * 2 / picture.height) % 2;
+            let brightness = if scanlines && phase == 1 {
+                SCANLINE_BRIGHTNESS
+            } else {
+                256
+            };
+            let dimmed = if grille {
+                brightness * GRILLE_BRIGHTNESS / 256
+            } else {
+                brightness
+            };
+            let stripes = [
+                [brightness, dimmed, dimmed],
+                [dimmed, brightness, dimmed],
+                [dimmed, dimmed, brightness],
+            ];
+            for (x, pixel) in picture.line_mut(y).iter_mut().enumerate() {
+                *pixel = shade(*pixel, stripes[x % 3]);
+            }
+        }
+    }
+}
+
+// Scales the red, green and blue channels of an XRGB8888 pixel by out-of-256 weights.
+fn shade(pixel: u32, [red, green, blue]: [u32; 3]) -> u32 {
+    let red = (((pixel >> 16) & 0xFF) * red) >> 8;
+    let green = (((pixel >> 8) & 0xFF) * green) >> 8;
+    let blue = ((pixel & 0xFF) * blue) >> 8;
+    (red.min(0xFF) << 16) | (green.min(0xFF) << 8) | blue.min(0xFF)
+}
+
+// Creates the filter with the given name.
+fn make_filter(name: &str) -> Option<Box<dyn Filter>> {
+    match name {
+        "crt" => Some(Box::new(Crt)),
+        _ => None,
+    }
+}
+
+// The filters applied to every frame, in order.
+static FILTERS: Mutex<Vec<Box<dyn Filter>>> = Mutex::new(Vec::new());
+
+// Reads `video_filters`, a comma separated list of filter names such as "crt".
+fn parse_filters(config: &HashMap<String, String>) -> Vec<Box<dyn Filter>> {
+    config
+        .get("video_filters")
+        .map_or("", String::as_str)
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let filter = make_filter(name);
+            if filter.is_none() {
+                println!("Unknown video filter {}", name);
+            }
+            filter
+        })
+        .collect()
+}
+
+// Reads `crop_overscan`, `video_adaptive_sync`, `video_smooth`, `video_filters`,
+// `video_threads` and the `video_scale*` and `video_aspect_ratio*` settings from
+// the config.
+pub fn configure(config: &HashMap<String, String>) {
+    let crop = config
+        .get("crop_overscan")
+        .is_none_or(|value| value == "true");
+    CROP_OVERSCAN.store(crop, Ordering::SeqCst);
+    let adaptive_sync = config
+        .get("video_adaptive_sync")
+        .is_some_and(|value| value == "true");
+    ADAPTIVE_SYNC.store(adaptive_sync, Ordering::SeqCst);
+
+    *SCALING.lock().unwrap() = Scaling {
+        integer: config
+            .get("video_scale_integer")
+            .is_some_and(|value| value == "true"),
+        scale: config
+            .get("video_scale")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0),
+        aspect_ratio: parse_aspect_ratio(config),
+        smooth: config
+            .get("video_smooth")
+            .is_some_and(|value| value == "true"),
+    };
+    *FILTERS.lock().unwrap() = parse_filters(config);
+    configure_scaling_pool(config);
+}
+
+// Switches bilinear filtering on or off from the hotkey.
+pub fn toggle_smooth() {
+    let mut scaling = SCALING.lock().unwrap();
+    scaling.smooth = !scaling.smooth;
+    osd::show_message(if scaling.smooth {
+        "Bilinear filtering on"
+    } else {
+        "Bilinear filtering off"
+    });
+}
+
+// Adds the CRT filter to the end of the chain, or takes it out, from the hotkey.
+pub fn toggle_crt() {
+    let mut filters = FILTERS.lock().unwrap();
+    let count = filters.len();
+    filters.retain(|filter| filter.name() != "crt");
+    if filters.len() == count {
+        filters.push(Box::new(Crt));
+        osd::show_message("CRT filter on");
+    } else {
+        osd::show_message("CRT filter off");
+    }
+}
+
+// Returns whether overscan is cropped.
+pub fn crop_overscan() -> bool {
+    CROP_OVERSCAN.load(Ordering::SeqCst)
+}
+
+// Returns how many lines to crop from the top and bottom of a frame.
+fn overscan_lines(height: usize) -> usize {
+    match height {
+        240 | 288 if crop_overscan() => OVERSCAN_LINES,
+        _ => 0,
+    }
+}
+
+// The pixel format the core set with ENVIRONMENT_SET_PIXEL_FORMAT, which is
+// 0RGB1555 until it sets one.
+static CORE_PIXEL_FORMAT: AtomicU32 = AtomicU32::new(PixelFormat::ARGB1555 as u32);
+
+// Records the core's pixel format as soon as it is set, so buffers handed to the
+// core during the same call to retro_run already use it.
+pub fn set_core_pixel_format(pixel_format: PixelFormat) {
+    CORE_PIXEL_FORMAT.store(pixel_format as u32, Ordering::SeqCst);
+}
+
+// Frame memory lent to the core through ENVIRONMENT_GET_CURRENT_SOFTWARE_FRAMEBUFFER.
+// It is only touched from retro_run and the video refresh callback, both on the
+// emulation thread.
+thread_local! {
+    static SOFTWARE_FRAMEBUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// Makes room in the software framebuffer for the core's largest frames, so it
+// isn't moved when the core changes resolution. Called whenever the AV info changes.
+pub fn reserve_frame_buffers(geometry: &GameGeometry) {
+    let length = geometry.max_width as usize * geometry.max_height as usize * 4;
+    SOFTWARE_FRAMEBUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        let additional = length.saturating_sub(buffer.len());
+        buffer.reserve(additional);
+    });
+}
+
+// Handles ENVIRONMENT_GET_CURRENT_SOFTWARE_FRAMEBUFFER. The core renders the next
+// frame straight into a buffer owned here instead of its own, which saves it a copy
+// per frame, and passes it back to the video refresh callback as usual. The buffer
+// keeps the previous frame, so cores asking for read access get it too.
+pub fn get_current_software_framebuffer(framebuffer: &mut Framebuffer) -> bool {
+    if hw_render::is_active() || framebuffer.width == 0 || framebuffer.height == 0 {
+        return false;
+    }
+    let format = CORE_PIXEL_FORMAT.load(Ordering::SeqCst);
+    let bpp = match PixelFormat::from_uint(format) {
+        Some(PixelFormat::ARGB8888) => 4,
+        _ => 2,
+    };
+    let pitch = framebuffer.width as usize * bpp;
+    let length = pitch * framebuffer.height as usize;
+    SOFTWARE_FRAMEBUFFER.with(|buffer| {
+        let mut buffer = buffer.borrow_mut();
+        buffer.resize(length, 0);
+        framebuffer.data = buffer.as_mut_ptr() as *mut c_void;
+    });
+    framebuffer.pitch = pitch;
+    framebuffer.format = format;
+    framebuffer.memory_flags = MEMORY_TYPE_CACHED;
+    true
+}
+
 // Callback function that the libretro core will use to pass video frame data.
 pub unsafe extern "C" fn libretro_set_video_refresh_callback(
     frame_buffer_data: *const libc::c_void,
@@ -32,27 +528,64 @@ pub unsafe extern "C" fn libretro_set_video_refresh_callback(
     height: libc::c_uint,
     pitch: libc::size_t,
 ) {
+    if !is_enabled() {
+        return;
+    }
+    // Cores may dupe the previous frame, since GET_CAN_DUPE says they can, and it is
+    // shown again so the window, OSD and clip keep up
     if frame_buffer_data.is_null() {
-        println!("frame_buffer_data was null");
+        if let Err(e) = VIDEO_DATA_CHANNEL.0.send(None) {
+            eprintln!("Failed to send video data: {:?}", e);
+        }
+        return;
+    }
+    // Hardware rendered frames have to be read back from the core's framebuffer
+    if frame_buffer_data == hw_render::HW_FRAME_BUFFER_VALID {
+        if let Some(frame_buffer) = hw_render::read_frame(width, height) {
+            let video_data = VideoData {
+                frame_buffer,
+                width,
+                height,
+                stride: width,
+            };
+            if let Err(e) = VIDEO_DATA_CHANNEL.0.send(Some(video_data)) {
+                eprintln!("Failed to send video data: {:?}", e);
+            }
+        }
+        return;
+    }
+    if width == 0 || height == 0 {
         return;
     }
-    let bpp = BYTES_PER_PIXEL.load(Ordering::SeqCst) as u32;
-    let length_of_frame_buffer = ((pitch as u32) * height) * bpp;
 
-    let buffer_slice = std::slice::from_raw_parts(
-        frame_buffer_data as *const u8,
-        length_of_frame_buffer as usize,
+    // The pitch is in bytes, and the frame is converted to packed lines of pixels.
+    // Frames rendered into the shared software framebuffer are read where they are,
+    // like any other.
+    let pixel_format = PixelFormat::from_uint(CORE_PIXEL_FORMAT.load(Ordering::SeqCst))
+        .unwrap_or(PixelFormat::ARGB1555);
+    let length_of_frame_buffer =
+        pixels::frame_length(pixel_format, width as usize, height as usize, pitch);
+
+    let buffer_slice =
+        std::slice::from_raw_parts(frame_buffer_data as *const u8, length_of_frame_buffer);
+    let mut frame_buffer = take_buffer(width as usize * height as usize);
+    pixels::convert_frame_into(
+        buffer_slice,
+        pixel_format,
+        width as usize,
+        height as usize,
+        pitch,
+        &mut frame_buffer,
     );
-    let result = convert_pixel_array_from_rgb565_to_xrgb8888(buffer_slice);
 
     let video_data = VideoData {
-        frame_buffer: Vec::from(result),
+        frame_buffer,
         width: width as u32,
         height: height as u32,
-        pitch: pitch as u32,
+        stride: width,
     };
 
-    if let Err(e) = VIDEO_DATA_CHANNEL.0.send(video_data) {
+    if let Err(e) = VIDEO_DATA_CHANNEL.0.send(Some(video_data)) {
         eprintln!("Failed to send video data: {:?}", e);
         // Handle error appropriately
     }
@@ -64,10 +597,7 @@ pub fn set_up_pixel_format(mut current_state: EmulatorState) -> EmulatorState {
 
     for pixel_format in pixel_format_receiver.try_iter() {
         current_state.pixel_format.0 = pixel_format;
-        let bpp = match pixel_format {
-            PixelFormat::ARGB1555 | PixelFormat::RGB565 => 2,
-            PixelFormat::ARGB8888 => 4,
-        };
+        let bpp = pixels::bytes_per_pixel(pixel_format);
         println!("Core will send us pixel data in format {:?}", pixel_format);
         BYTES_PER_PIXEL.store(bpp, Ordering::SeqCst);
         current_state.bytes_per_pixel = bpp;
@@ -76,100 +606,783 @@ pub fn set_up_pixel_format(mut current_state: EmulatorState) -> EmulatorState {
     return current_state;
 }
 
-// Converts a pixel array from RGB565 format to XRGB8888 format.
-fn convert_pixel_array_from_rgb565_to_xrgb8888(color_array: &[u8]) -> Box<[u32]> {
-    let bytes_per_pixel = 2;
-    assert_eq!(
-        color_array.len() % bytes_per_pixel,
-        0,
-        "color_array length must be a multiple of 2 (16-bits per pixel)"
-    );
+// Applies geometry changes the core made with ENVIRONMENT_SET_GEOMETRY.
+pub fn update_geometry(current_state: &mut EmulatorState) {
+    let geometry_receiver = GEOMETRY_CHANNEL.1.lock().unwrap();
+    for geometry in geometry_receiver.try_iter() {
+        println!(
+            "Core changed geometry to {}x{} (aspect ratio {})",
+            geometry.base_width, geometry.base_height, geometry.aspect_ratio
+        );
+        if let Some(av_info) = current_state.av_info.as_mut() {
+            // The maximum size is fixed at load time, only the base size and aspect change
+            av_info.geometry.base_width = geometry.base_width;
+            av_info.geometry.base_height = geometry.base_height;
+            av_info.geometry.aspect_ratio = geometry.aspect_ratio;
+        }
+    }
+}
+
+// Applies rotation the core requested with ENVIRONMENT_SET_ROTATION, e.g. for
+// vertical arcade games.
+pub fn update_rotation(current_state: &mut EmulatorState) {
+    if let Some(rotation) = ROTATION_CHANNEL.1.lock().unwrap().try_iter().last() {
+        println!("Core set rotation to {} degrees", rotation * 90);
+        current_state.rotation = rotation;
+    }
+}
 
-    let num_pixels = color_array.len() / bytes_per_pixel;
-    let mut result = vec![0u32; num_pixels];
-
-    for i in 0..num_pixels {
-        // This Rust code is decoding a 16-bit color value, represented by two bytes of data, into its corresponding red, green, and blue components.
-        let first_byte = color_array[bytes_per_pixel * i];
-        let second_byte = color_array[(bytes_per_pixel * i) + 1];
-
-        // First extract the red component from the first byte. The first byte contains the most significant 8 bits of the 16-bit color value. The & operator performs a bitwise AND operation on first_byte and 0b1111_1000, which extracts the 5 most significant bits of the byte. The >> operator then shifts the extracted bits to the right by 3 positions, effectively dividing by 8, to get the value of the red component on a scale of 0-31.
-        let red = (first_byte & 0b1111_1000) >> 3;
-        // Next extract the green component from both bytes. The first part of the expression ((first_byte & 0b0000_0111) << 3) extracts the 3 least significant bits of first_byte and shifts them to the left by 3 positions, effectively multiplying by 8. The second part of the expression ((second_byte & 0b1110_0000) >> 5) extracts the 3 most significant bits of second_byte and shifts them to the right by 5 positions, effectively dividing by 32. The two parts are then added together to get the value of the green component on a scale of 0-63.
-        let green = ((first_byte & 0b0000_0111) << 3) + ((second_byte & 0b1110_0000) >> 5);
-        // Next extract the blue component from the second byte. The & operator performs a bitwise AND operation on second_byte and 0b0001_1111, which extracts the 5 least significant bits of the byte. This gives the value of the blue component on a scale of 0-31.
-        let blue = second_byte & 0b0001_1111;
-
-        // Use high bits for empty low bits as we have more bits available in XRGB8888
-        let red = (red << 3) | (red >> 2);
-        let green = (green << 2) | (green >> 3);
-        let blue = (blue << 3) | (blue >> 2);
-
-        // Finally save the pixel data in the result array as an XRGB8888 value
-        result[i] = ((red as u32) << 16) | ((green as u32) << 8) | (blue as u32);
-    }
-
-    result.into_boxed_slice()
-}
-
-// Renders the frame received from the libretro core to the window.
-pub fn render_frame(current_state: EmulatorState, mut window: Window) -> (EmulatorState, Window) {
-    // Lock the video data channel to prevent data races
-    let video_data_receiver = VIDEO_DATA_CHANNEL.1.lock().unwrap();
-
-    // Iterate over the video data received from the core
-    for video_data in video_data_receiver.try_iter() {
-        // Extract the video data dimensions
-        let source_width = video_data.width as usize;
-        let source_height = video_data.height as usize;
-        let pitch = video_data.pitch as usize; // number of bytes per row
-
-        // Calculate the window size
-        let window_size = window.get_size();
-        let scale_x = window_size.0 / source_width;
-        let scale_y = window_size.1 / source_height;
-        let scale = scale_y.min(scale_x); // maintain aspect ratio
-
-        // Calculate the target dimensions
-        let target_width = source_width * scale;
-        let target_height = source_height * scale;
-
-        // Calculate padding for centering the image
-        let bpp = BYTES_PER_PIXEL.load(Ordering::SeqCst) as usize;
-        let padding_x = (window_size.0 - target_width) / bpp;
-        let padding_y = (window_size.1 - target_height) / bpp;
-
-        // Prepare the buffer that will be sent to the window
-        let mut window_buffer = vec![0; window_size.0 * window_size.1];
-        for y in 0..source_height {
-            let source_start = y * pitch / bpp; // divide by 2 because the pitch is based on 2 bytes per pixel
-            let dest_start = (y * scale + padding_y) * window_size.0 + padding_x;
-
-            // Copy each row, taking into account the pitch and scaling
-            for x in 0..source_width {
-                let dest_index = dest_start + x * scale;
-                let source_index = source_start + x;
-
-                // Copy the pixel `scale` times in both X and Y dimensions
-                for dx in 0..scale {
-                    for dy in 0..scale {
-                        let window_index = (dest_index + dy * window_size.0 + dx) as usize;
-                        let source_pixel = video_data
-                            .frame_buffer
-                            .get(source_index)
-                            .copied()
-                            .unwrap_or(0);
-                        window_buffer[window_index] = source_pixel;
-                    }
+// Works out the size of the picture in the window. The picture is the largest
+// integer multiple of the core's base height that fits, or the configured scale,
+// and its width follows the aspect ratio, so frames that change resolution
+// mid-game (e.g. SNES hi-res modes) keep the same footprint on screen. In integer
+// mode both sides are the same multiple of the frame itself instead. The frame
+// size is given as displayed, after rotation.
+fn target_size(
+    av_info: Option<&SystemAvInfo>,
+    rotation: u32,
+    frame_width: usize,
+    frame_height: usize,
+    window_size: (usize, usize),
+    scaling: Scaling,
+) -> (usize, usize) {
+    if scaling.integer {
+        let (frame_width, frame_height) = (frame_width.max(1), frame_height.max(1));
+        let scale = match scaling.scale {
+            0 => (window_size.0 / frame_width).min(window_size.1 / frame_height),
+            scale => scale,
+        };
+        let scale = scale.max(1);
+        return (
+            (frame_width * scale).min(window_size.0),
+            (frame_height * scale).min(window_size.1),
+        );
+    }
+
+    let (base_width, base_height, aspect_ratio) = match av_info {
+        Some(av_info) if av_info.geometry.base_width > 0 && av_info.geometry.base_height > 0 => (
+            av_info.geometry.base_width as usize,
+            av_info.geometry.base_height as usize,
+            av_info.geometry.aspect_ratio as f64,
+        ),
+        _ if rotation % 2 == 1 => (frame_height.max(1), frame_width.max(1), 0.0),
+        _ => (frame_width.max(1), frame_height.max(1), 0.0),
+    };
+    let aspect_ratio = match scaling.aspect_ratio {
+        AspectRatio::Core if aspect_ratio > 0.0 => aspect_ratio,
+        AspectRatio::Core | AspectRatio::Square => base_width as f64 / base_height as f64,
+        AspectRatio::Fixed(ratio) => ratio,
+        AspectRatio::Stretch => return window_size,
+    };
+    // The aspect ratio describes the unrotated picture
+    let (base_height, aspect_ratio) = if rotation % 2 == 1 {
+        (base_width, 1.0 / aspect_ratio)
+    } else {
+        (base_height, aspect_ratio)
+    };
+
+    let scale_x = (window_size.0 as f64 / (base_height as f64 * aspect_ratio)) as usize;
+    let scale_y = window_size.1 / base_height;
+    let scale = match scaling.scale {
+        0 => scale_x.min(scale_y).max(1),
+        scale => scale,
+    };
+
+    let target_height = (base_height * scale).min(window_size.1);
+    let target_width = ((target_height as f64 * aspect_ratio).round() as usize).min(window_size.0);
+    (target_width, target_height)
+}
+
+// Shows finished pictures in the window. With the `wgpu` feature and
+// `video_renderer` set to "wgpu", frames are scaled on the GPU unless software
+// filters need the scaled picture, and the CPU composes it into the window buffer
+// otherwise.
+pub struct Presenter {
+    #[cfg(feature = "wgpu")]
+    gpu: Option<gpu::Renderer>,
+    // Window sized buffer for the LED and OSD, drawn over GPU scaled frames.
+    #[cfg(feature = "wgpu")]
+    overlay: Vec<u32>,
+    // Whether presenting waits for the display's vertical blank.
+    vsync: bool,
+    last_present: Instant,
+    // Whether `video_bfi` asks for black frame insertion.
+    bfi: bool,
+    // Frame rate and refresh rate black frame insertion was last set up for, with
+    // 0 for an unknown refresh rate, and how many black frames follow each picture.
+    bfi_rates: (f64, f64),
+    black_frames: usize,
+    // Whether frames are composed on the video thread, from `video_threaded`.
+    threaded: bool,
+}
+
+// How often pictures are shown while fast-forwarding. With vsync every present
+// waits for the display, so showing them all would cap fast-forward at its refresh
+// rate, and without it scaling and showing frames nobody can see still slows it.
+const FAST_FORWARD_PRESENT_INTERVAL: Duration = Duration::from_micros(16_667);
+
+// How far the refresh rate may be from a multiple of the frame rate for black frame
+// insertion, relative to it, so e.g. 59.94 fps still counts at 120 Hz.
+const BFI_TOLERANCE: f64 = 0.01;
+
+// Returns how many black frames to show after each picture so pictures and black
+// frames together fill every refresh, or None if the refresh rate isn't at least
+// twice the frame rate and a multiple of it.
+pub fn black_frames(fps: f64, refresh_rate: f64) -> Option<usize> {
+    if fps <= 0.0 {
+        return None;
+    }
+    let ratio = refresh_rate / fps;
+    let refreshes = ratio.round();
+    (refreshes >= 2.0 && (ratio - refreshes).abs() < BFI_TOLERANCE * refreshes)
+        .then(|| refreshes as usize - 1)
+}
+
+impl Presenter {
+    // Reads `video_renderer`, `video_shader`, `video_vsync`, `video_bfi` and
+    // `video_threaded` from the config, and starts the video thread if asked to.
+    // The window must outlive the presenter.
+    pub fn new(window: &dyn Window, config: &HashMap<String, String>) -> Self {
+        let renderer = config
+            .get("video_renderer")
+            .map_or("software", String::as_str);
+        let vsync = vsync(config);
+        #[cfg(feature = "wgpu")]
+        let mut gpu = if renderer == "wgpu" {
+            gpu::Renderer::new(window, vsync)
+                .map_err(|e| println!("{}, scaling on the CPU instead", e))
+                .ok()
+        } else {
+            None
+        };
+        let shader = config.get("video_shader").filter(|path| !path.is_empty());
+        #[cfg(feature = "wgpu")]
+        match (gpu.as_mut(), shader) {
+            (Some(renderer), Some(path)) => {
+                let path = shellexpand::tilde(path);
+                if let Err(e) = renderer.load_shader(std::path::Path::new(path.as_ref())) {
+                    println!("{}, drawing without it", e);
                 }
             }
+            (None, Some(_)) => println!("video_shader needs video_renderer wgpu"),
+            _ => {}
+        }
+        #[cfg(not(feature = "wgpu"))]
+        if shader.is_some() {
+            println!("video_shader needs the wgpu feature");
+        }
+        #[cfg(feature = "wgpu")]
+        let gpu_vsync = vsync && gpu.is_some();
+        #[cfg(not(feature = "wgpu"))]
+        let gpu_vsync = false;
+        #[cfg(not(feature = "wgpu"))]
+        if renderer == "wgpu" {
+            println!("Built without the wgpu feature, scaling on the CPU instead");
         }
+        let vsync_active = gpu_vsync || window.has_vsync();
+        if vsync && !vsync_active {
+            println!("video_vsync needs video_renderer wgpu or video_backend sdl2");
+        }
+        let threaded = config
+            .get("video_threaded")
+            .is_some_and(|value| value == "true");
+        if threaded {
+            spawn_video_thread();
+        }
+        Presenter {
+            #[cfg(feature = "wgpu")]
+            gpu,
+            #[cfg(feature = "wgpu")]
+            overlay: Vec::new(),
+            vsync: vsync_active,
+            last_present: Instant::now(),
+            bfi: config.get("video_bfi").is_some_and(|value| value == "true"),
+            bfi_rates: (0.0, -1.0),
+            black_frames: 0,
+            threaded,
+        }
+    }
 
-        // Update the window
+    // Returns whether frames are scaled on the GPU, as long as no software filters
+    // need the scaled picture.
+    fn scales_on_gpu(&self) -> bool {
+        #[cfg(feature = "wgpu")]
+        return self.gpu.is_some();
+        #[cfg(not(feature = "wgpu"))]
+        false
+    }
+
+    // Shows a composed frame with the bezel, LED, performance overlay and OSD on
+    // top, then any black frames.
+    fn show(&mut self, window: &mut dyn Window, mut composed: Composed, fps: f64) {
+        self.last_present = Instant::now();
+        let window_size = composed.window_size;
+        if composed.on_gpu {
+            let frame = composed.frame();
+            if self.draw_on_gpu(&frame, composed.target, window_size, composed.smooth) {
+                recycle(composed.pixels);
+                window.update();
+                self.insert_black_frames(window, fps, window_size);
+                return;
+            }
+            let window_buffer =
+                scale_into_window(&frame, composed.target, window_size, composed.smooth);
+            recycle(std::mem::replace(&mut composed.pixels, window_buffer));
+        }
+
+        let window_buffer = &mut composed.pixels;
+        bezel::draw(window_buffer, window_size.0, window_size.1);
+        led::draw(window_buffer, window_size.0, window_size.1);
+        perf_overlay::draw(window_buffer, window_size.0, window_size.1);
+        hotkeys::draw(window_buffer, window_size.0, window_size.1);
+        osd::draw(window_buffer, window_size.0, window_size.1);
+        self.present(window, window_buffer, window_size);
+        recycle(composed.pixels);
+        self.insert_black_frames(window, fps, window_size);
+    }
+
+    // Follows the picture just shown with black frames for black frame insertion,
+    // each lasting one refresh. Whether they fit is checked again whenever the
+    // core's frame rate or the display's refresh rate changes.
+    fn insert_black_frames(
+        &mut self,
+        window: &mut dyn Window,
+        fps: f64,
+        window_size: (usize, usize),
+    ) {
+        if !self.bfi || speed::is_fast_forwarding() {
+            return;
+        }
+        let refresh_rate = window.refresh_rate().unwrap_or(0.0);
+        if (fps, refresh_rate) != self.bfi_rates {
+            self.bfi_rates = (fps, refresh_rate);
+            self.black_frames = match black_frames(fps, refresh_rate) {
+                Some(black_frames) => {
+                    println!(
+                        "Black frame insertion on, {} black frames per picture at {} Hz",
+                        black_frames, refresh_rate
+                    );
+                    black_frames
+                }
+                None if refresh_rate == 0.0 => {
+                    println!("Black frame insertion is off, the display's refresh rate is unknown");
+                    0
+                }
+                None => {
+                    println!(
+                        "Black frame insertion is off, {} Hz is not a multiple of {} fps",
+                        refresh_rate, fps
+                    );
+                    0
+                }
+            };
+        }
+        if self.black_frames == 0 {
+            return;
+        }
+
+        // Presenting with vsync waits for the next refresh by itself
+        let mut black = take_buffer(window_size.0 * window_size.1);
+        black.resize(window_size.0 * window_size.1, 0);
+        let refresh = Duration::from_secs_f64(1.0 / refresh_rate);
+        let mut shown = Instant::now();
+        for _ in 0..self.black_frames {
+            if !self.vsync {
+                shown += refresh;
+                speed::sleep_until(shown);
+            }
+            self.present(window, &black, window_size);
+        }
+        recycle(black);
+    }
+
+    // Returns whether to drop frames instead of showing them, which only happens
+    // while fast-forwarding.
+    fn skip_frame(&self) -> bool {
+        speed::is_fast_forwarding()
+            && Instant::now() < self.last_present + FAST_FORWARD_PRESENT_INTERVAL
+    }
+
+    // Scales the frame into the target area on the GPU with the bezel, LED and OSD
+    // on top. Returns false if there is no GPU renderer or software filters are on,
+    // and the picture has to be composed on the CPU instead.
+    #[cfg(feature = "wgpu")]
+    fn draw_on_gpu(
+        &mut self,
+        frame: &Frame,
+        target: (usize, usize, usize, usize),
+        window_size: (usize, usize),
+        smooth: bool,
+    ) -> bool {
+        let Some(renderer) = self.gpu.as_mut() else {
+            return false;
+        };
+        if !FILTERS.lock().unwrap().is_empty() {
+            return false;
+        }
+        self.overlay.clear();
+        self.overlay
+            .resize(window_size.0 * window_size.1, gpu::OVERLAY_CLEAR);
+        bezel::draw_overlay(&mut self.overlay, window_size.0, window_size.1);
+        led::draw(&mut self.overlay, window_size.0, window_size.1);
+        perf_overlay::draw(&mut self.overlay, window_size.0, window_size.1);
+        hotkeys::draw(&mut self.overlay, window_size.0, window_size.1);
+        osd::draw(&mut self.overlay, window_size.0, window_size.1);
+        let drawn = self
+            .overlay
+            .iter()
+            .any(|&pixel| pixel != gpu::OVERLAY_CLEAR);
+        let overlay = drawn.then_some(self.overlay.as_slice());
+        if let Err(e) = renderer.draw(frame, target, window_size, smooth, overlay) {
+            println!("{}, scaling on the CPU from now on", e);
+            self.gpu = None;
+            return false;
+        }
+        true
+    }
+
+    #[cfg(not(feature = "wgpu"))]
+    fn draw_on_gpu(
+        &mut self,
+        _frame: &Frame,
+        _target: (usize, usize, usize, usize),
+        _window_size: (usize, usize),
+        _smooth: bool,
+    ) -> bool {
+        false
+    }
+
+    // Shows a picture composed on the CPU, which covers the whole window.
+    fn present(
+        &mut self,
+        window: &mut dyn Window,
+        window_buffer: &[u32],
+        window_size: (usize, usize),
+    ) {
+        #[cfg(feature = "wgpu")]
+        if let Some(renderer) = self.gpu.as_mut() {
+            let frame = Frame {
+                pixels: window_buffer,
+                width: window_size.0,
+                height: window_size.1,
+                stride: window_size.0,
+            };
+            let target = (0, 0, window_size.0, window_size.1);
+            match renderer.draw(&frame, target, window_size, false, None) {
+                Ok(()) => {
+                    window.update();
+                    return;
+                }
+                Err(e) => {
+                    println!("{}, scaling on the CPU from now on", e);
+                    self.gpu = None;
+                }
+            }
+        }
         window
-            .update_with_buffer(&window_buffer, window_size.0, window_size.1)
+            .update_with_buffer(window_buffer, window_size.0, window_size.1)
             .unwrap();
     }
+}
+
+// Returns how long one frame lasts at the core's frame rate, falling back to 60 fps.
+pub fn frame_duration(fps: f64) -> Duration {
+    if fps > 0.0 {
+        Duration::from_secs_f64(1.0 / fps)
+    } else {
+        Duration::from_micros(16600)
+    }
+}
+
+// How frames are to be shown, from the main loop, for composing them.
+#[derive(Clone)]
+struct View {
+    av_info: Option<SystemAvInfo>,
+    rotation: u32,
+    window_size: (usize, usize),
+    // Whether frames are scaled on the GPU, so composing only crops and rotates them
+    scale_on_gpu: bool,
+}
+
+impl View {
+    fn fps(&self) -> f64 {
+        self.av_info
+            .as_ref()
+            .map_or(0.0, |av_info| av_info.timing.fps)
+    }
+}
+
+// A frame ready to be shown in a window of `window_size`. Composed on the CPU it
+// covers the whole window, and for the GPU it is the cropped and rotated frame,
+// still to be scaled into `target`.
+struct Composed {
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    target: (usize, usize, usize, usize),
+    window_size: (usize, usize),
+    smooth: bool,
+    on_gpu: bool,
+}
+
+impl Composed {
+    fn frame(&self) -> Frame<'_> {
+        Frame {
+            pixels: &self.pixels,
+            width: self.width,
+            height: self.height,
+            stride: self.width,
+        }
+    }
+}
 
-    return (current_state, window);
+// With `video_threaded`, the main loop's view and the latest frame composed on
+// the video thread.
+static VIEW: Mutex<Option<View>> = Mutex::new(None);
+static COMPOSED: Mutex<Option<Composed>> = Mutex::new(None);
+
+// Takes the frames the core sent, keeping each as the last frame to show again
+// when the core dupes it. Returns whether any arrived.
+fn receive(frames: impl Iterator<Item = Option<VideoData>>, fps: f64) -> bool {
+    let mut last_frame = LAST_FRAME.lock().unwrap();
+    let mut received = false;
+    for video_data in frames {
+        let video_data = match video_data {
+            Some(video_data) => {
+                if let Some(replaced) = last_frame.take() {
+                    recycle(replaced.frame_buffer);
+                }
+                last_frame.insert(video_data)
+            }
+            None => match last_frame.as_mut() {
+                Some(video_data) => video_data,
+                None => continue,
+            },
+        };
+        received = true;
+        if is_complete(video_data) {
+            clip::record(&whole_frame(video_data), fps);
+        }
+    }
+    received
+}
+
+// Composes the last frame the core sent.
+fn compose_last(view: &View) -> Option<Composed> {
+    let last_frame = LAST_FRAME.lock().unwrap();
+    compose(last_frame.as_ref()?, view)
+}
+
+// Crops, colour corrects and rotates a frame and, unless the GPU scales it, scales
+// and filters it into a window sized picture. Returns None for minimized windows and frames that
+// can't be shown.
+fn compose(video_data: &VideoData, view: &View) -> Option<Composed> {
+    let window_size = view.window_size;
+    if window_size.0 == 0 || window_size.1 == 0 {
+        return None;
+    }
+
+    // Extract the video data dimensions
+    let source_width = video_data.width as usize;
+    let source_height = video_data.height as usize;
+    let stride = video_data.stride as usize;
+
+    // Crop overscan before anything else, and widen the aspect ratio to match so
+    // the remaining lines keep their shape
+    let overscan = overscan_lines(source_height);
+    let source_height = source_height - overscan * 2;
+    let av_info = view.av_info.clone().map(|mut av_info| {
+        if overscan > 0 && av_info.geometry.base_height as usize > overscan * 2 {
+            let base_height = av_info.geometry.base_height as f32;
+            let cropped_height = base_height - (overscan * 2) as f32;
+            av_info.geometry.aspect_ratio *= base_height / cropped_height;
+            av_info.geometry.base_height = cropped_height as u32;
+        }
+        av_info
+    });
+
+    // A picture rotated by 90 or 270 degrees is displayed with its sides swapped
+    let rotation = view.rotation;
+    let (display_width, display_height) = if rotation % 2 == 1 {
+        (source_height, source_width)
+    } else {
+        (source_width, source_height)
+    };
+
+    // Calculate the target dimensions from the core's geometry, within the bezel's
+    // viewport if there is one
+    let (viewport_x, viewport_y, viewport_width, viewport_height) = bezel::viewport(window_size);
+    let (target_width, target_height) = target_size(
+        av_info.as_ref(),
+        rotation,
+        display_width,
+        display_height,
+        (viewport_width, viewport_height),
+        *SCALING.lock().unwrap(),
+    );
+
+    // Calculate padding for centering the image
+    let padding_x = viewport_x + (viewport_width - target_width) / 2;
+    let padding_y = viewport_y + (viewport_height - target_height) / 2;
+
+    // Incomplete frames, and ones cropped to nothing, are skipped
+    if source_height == 0 || !is_complete(video_data) {
+        println!(
+            "Dropping a {}x{} frame with too few pixels",
+            source_width, video_data.height
+        );
+        return None;
+    }
+    let frame = Frame {
+        pixels: &video_data.frame_buffer[overscan * stride..],
+        width: source_width,
+        height: source_height,
+        stride,
+    };
+
+    // Correct colours at the core's resolution, where there are fewest pixels
+    let mut corrected = take_buffer(source_width * source_height);
+    let frame = if color::correct(&frame, &mut corrected) {
+        Frame {
+            pixels: &corrected,
+            width: source_width,
+            height: source_height,
+            stride: source_width,
+        }
+    } else {
+        frame
+    };
+
+    // Rotate counter-clockwise as the core asked before scaling. Frames for the GPU
+    // are packed even without rotation, since that is all that's left to do here.
+    let smooth = SCALING.lock().unwrap().smooth;
+    let target = (padding_x, padding_y, target_width, target_height);
+    if view.scale_on_gpu && FILTERS.lock().unwrap().is_empty() {
+        let mut pixels = take_buffer(display_width * display_height);
+        pixels::rotate(&frame, rotation, &mut pixels);
+        recycle(corrected);
+        return Some(Composed {
+            pixels,
+            width: display_width,
+            height: display_height,
+            target,
+            window_size,
+            smooth,
+            on_gpu: true,
+        });
+    }
+    let mut rotated = Vec::new();
+    let frame = if rotation != 0 {
+        rotated = take_buffer(display_width * display_height);
+        pixels::rotate(&frame, rotation, &mut rotated);
+        Frame {
+            pixels: &rotated,
+            width: display_width,
+            height: display_height,
+            stride: display_width,
+        }
+    } else {
+        frame
+    };
+    let pixels = scale_into_window(&frame, target, window_size, smooth);
+    recycle(rotated);
+    recycle(corrected);
+    Some(Composed {
+        pixels,
+        width: window_size.0,
+        height: window_size.1,
+        target: (0, 0, window_size.0, window_size.1),
+        window_size,
+        smooth,
+        on_gpu: false,
+    })
+}
+
+// Returns a window sized buffer with the frame scaled into the centered target
+// area and the software filters applied.
+fn scale_into_window(
+    frame: &Frame,
+    (padding_x, padding_y, target_width, target_height): (usize, usize, usize, usize),
+    window_size: (usize, usize),
+    smooth: bool,
+) -> Vec<u32> {
+    let mut window_buffer = take_buffer(window_size.0 * window_size.1);
+    window_buffer.resize(window_size.0 * window_size.1, 0);
+    let dest = &mut window_buffer[padding_y * window_size.0 + padding_x..];
+    scale(
+        frame,
+        dest,
+        window_size.0,
+        target_width,
+        target_height,
+        smooth,
+    );
+    let mut picture = Picture {
+        pixels: dest,
+        width: target_width,
+        height: target_height,
+        stride: window_size.0,
+        source_width: frame.width,
+        source_height: frame.height,
+    };
+    for filter in FILTERS.lock().unwrap().iter() {
+        filter.apply(&mut picture);
+    }
+    window_buffer
+}
+
+// Composes frames on their own thread as the core sends them, so scaling and
+// filters don't hold up the core. The window has to stay on the main thread, so
+// only the latest composed frame is kept there for the main loop to show.
+fn spawn_video_thread() {
+    thread::spawn(|| {
+        let receiver = VIDEO_DATA_CHANNEL.1.lock().unwrap();
+        while let Ok(video_data) = receiver.recv() {
+            let view = VIEW.lock().unwrap().clone();
+            let fps = view.as_ref().map_or(0.0, View::fps);
+            receive(std::iter::once(video_data).chain(receiver.try_iter()), fps);
+            if let Some(composed) = view.and_then(|view| compose_last(&view)) {
+                // A frame the main loop never got to is dropped for the newer one
+                if let Some(dropped) = COMPOSED.lock().unwrap().replace(composed) {
+                    recycle(dropped.pixels);
+                }
+            }
+        }
+    });
+}
+
+// Shows the latest frame from the core in the window, composing it here unless
+// the video thread already has.
+pub fn render_frame(
+    current_state: EmulatorState,
+    mut window: Box<dyn Window>,
+    presenter: &mut Presenter,
+) -> (EmulatorState, Box<dyn Window>) {
+    let window_size = window.get_size();
+    let view = View {
+        av_info: current_state.av_info.clone(),
+        rotation: current_state.rotation,
+        window_size,
+        scale_on_gpu: presenter.scales_on_gpu(),
+    };
+    let fps = view.fps();
+    // No frames come while paused, so the last one is composed again for the OSD
+    // and menu to keep up and the window to be redrawn after a resize
+    let paused = hotkeys::is_paused();
+    let composed = if presenter.threaded {
+        *VIEW.lock().unwrap() = Some(view.clone());
+        let composed = COMPOSED.lock().unwrap().take();
+        match composed {
+            None if paused => compose_last(&view),
+            composed => composed,
+        }
+    } else {
+        let receiver = VIDEO_DATA_CHANNEL.1.lock().unwrap();
+        let received = receive(receiver.try_iter(), fps);
+        if (received || paused) && !presenter.skip_frame() {
+            compose_last(&view)
+        } else {
+            None
+        }
+    };
+
+    // Frames composed for a window size that has since changed, and frames skipped
+    // during fast-forward, only need the window's events handled
+    match composed {
+        Some(composed) if composed.window_size == window_size && !presenter.skip_frame() => {
+            presenter.show(window.as_mut(), composed, fps)
+        }
+        Some(composed) => {
+            recycle(composed.pixels);
+            window.update();
+        }
+        None => window.update(),
+    }
+
+    (current_state, window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_aspect_ratio_modes() {
+        let config = |mode: &str| {
+            HashMap::from([
+                ("video_aspect_ratio".to_string(), mode.to_string()),
+                ("video_aspect_ratio_custom".to_string(), "8:7".to_string()),
+            ])
+        };
+        assert_eq!(parse_aspect_ratio(&config("core")), AspectRatio::Core);
+        assert_eq!(parse_aspect_ratio(&config("stretch")), AspectRatio::Stretch);
+        assert_eq!(
+            parse_aspect_ratio(&config("4:3")),
+            AspectRatio::Fixed(4.0 / 3.0)
+        );
+        assert_eq!(
+            parse_aspect_ratio(&config("custom")),
+            AspectRatio::Fixed(8.0 / 7.0)
+        );
+        assert_eq!(parse_aspect_ratio(&config("4:0")), AspectRatio::Core);
+    }
+
+    #[test]
+    fn composes_padded_frames_for_the_gpu_and_cpu() {
+        let video_data = VideoData {
+            frame_buffer: vec![1, 2, 0, 3, 4],
+            width: 2,
+            height: 2,
+            stride: 3,
+        };
+        let view = View {
+            av_info: None,
+            rotation: 0,
+            window_size: (4, 4),
+            scale_on_gpu: true,
+        };
+        let composed = compose(&video_data, &view).unwrap();
+        assert!(composed.on_gpu);
+        assert_eq!(composed.pixels, vec![1, 2, 3, 4]);
+        assert_eq!(composed.target, (0, 0, 4, 4));
+
+        let view = View {
+            scale_on_gpu: false,
+            ..view
+        };
+        let composed = compose(&video_data, &view).unwrap();
+        assert!(!composed.on_gpu);
+        assert_eq!(
+            composed.pixels,
+            vec![1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]
+        );
+    }
+
+    #[test]
+    fn inserts_black_frames_at_multiples_of_the_frame_rate() {
+        assert_eq!(black_frames(60.0, 120.0), Some(1));
+        assert_eq!(black_frames(59.94, 120.0), Some(1));
+        assert_eq!(black_frames(60.0, 240.0), Some(3));
+        assert_eq!(black_frames(50.0, 120.0), None);
+        assert_eq!(black_frames(60.0, 144.0), None);
+        assert_eq!(black_frames(60.0, 60.0), None);
+        assert_eq!(black_frames(60.0, 0.0), None);
+    }
+
+    #[test]
+    fn crt_filter_darkens_scanlines_and_stripes() {
+        // A white pixel scaled to 3x2, so the second line is a scanline gap
+        let mut pixels = vec![0x00FF_FFFF; 6];
+        let mut picture = Picture {
+            pixels: &mut pixels,
+            width: 3,
+            height: 2,
+            stride: 3,
+            source_width: 1,
+            source_height: 1,
+        };
+        Crt.apply(&mut picture);
+        assert_eq!(&pixels[..3], &[0x00FF_BFBF, 0x00BF_FFBF, 0x00BF_BFFF]);
+        assert_eq!(&pixels[3..], &[0x007F_5F5F, 0x005F_7F5F, 0x005F_5F7F]);
+    }
 }
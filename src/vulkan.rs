@@ -0,0 +1,687 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// vulkan.rs
+//
+// This module provides the Vulkan side of hardware rendering. It negotiates the
+// instance and device with the core through the context negotiation interface,
+// exposes retro_hw_render_interface_vulkan, and copies the image the core hands
+// over with set_image into host memory so the frame can be presented by video.rs.
+
+use ash::vk;
+use libc::{c_char, c_uint, c_void};
+
+use crate::hw_render;
+
+// Versions of the libretro Vulkan interfaces implemented here.
+const HW_RENDER_INTERFACE_VULKAN_VERSION: c_uint = 5;
+const HW_RENDER_INTERFACE_VULKAN: c_uint = 0;
+const HW_RENDER_CONTEXT_NEGOTIATION_INTERFACE_VULKAN: c_uint = 0;
+
+// Some cores reject a NULL handle, so they get the address of this marker instead.
+static INTERFACE_HANDLE: u8 = 0;
+
+// struct retro_vulkan_image
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VulkanImage {
+    pub image_view: vk::ImageView,
+    pub image_layout: vk::ImageLayout,
+    pub create_info: vk::ImageViewCreateInfo,
+}
+
+// struct retro_vulkan_context
+#[repr(C)]
+pub struct VulkanContextInfo {
+    pub gpu: vk::PhysicalDevice,
+    pub device: vk::Device,
+    pub queue: vk::Queue,
+    pub queue_family_index: u32,
+    pub presentation_queue: vk::Queue,
+    pub presentation_queue_family_index: u32,
+}
+
+type CreateInstanceWrapperFn = unsafe extern "C" fn(
+    opaque: *mut c_void,
+    create_info: *const vk::InstanceCreateInfo,
+) -> vk::Instance;
+type CreateDeviceWrapperFn = unsafe extern "C" fn(
+    gpu: vk::PhysicalDevice,
+    opaque: *mut c_void,
+    create_info: *const vk::DeviceCreateInfo,
+) -> vk::Device;
+
+// struct retro_hw_render_context_negotiation_interface_vulkan
+#[repr(C)]
+pub struct NegotiationInterfaceVulkan {
+    pub interface_type: c_uint,
+    pub interface_version: c_uint,
+    pub get_application_info: Option<unsafe extern "C" fn() -> *const vk::ApplicationInfo>,
+    pub create_device: Option<
+        unsafe extern "C" fn(
+            context: *mut VulkanContextInfo,
+            instance: vk::Instance,
+            gpu: vk::PhysicalDevice,
+            surface: vk::SurfaceKHR,
+            get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+            required_device_extensions: *const *const c_char,
+            num_required_device_extensions: c_uint,
+            required_device_layers: *const *const c_char,
+            num_required_device_layers: c_uint,
+            required_features: *const vk::PhysicalDeviceFeatures,
+        ) -> bool,
+    >,
+    pub destroy_device: Option<unsafe extern "C" fn()>,
+    // Version 2 additions.
+    pub create_instance: Option<
+        unsafe extern "C" fn(
+            get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+            app: *const vk::ApplicationInfo,
+            create_instance_wrapper: CreateInstanceWrapperFn,
+            opaque: *mut c_void,
+        ) -> vk::Instance,
+    >,
+    pub create_device2: Option<
+        unsafe extern "C" fn(
+            context: *mut VulkanContextInfo,
+            instance: vk::Instance,
+            gpu: vk::PhysicalDevice,
+            surface: vk::SurfaceKHR,
+            get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+            create_device_wrapper: CreateDeviceWrapperFn,
+            opaque: *mut c_void,
+        ) -> bool,
+    >,
+}
+
+// struct retro_hw_render_interface_vulkan
+#[repr(C)]
+pub struct HwRenderInterfaceVulkan {
+    pub interface_type: c_uint,
+    pub interface_version: c_uint,
+    pub handle: *const c_void,
+    pub instance: vk::Instance,
+    pub gpu: vk::PhysicalDevice,
+    pub device: vk::Device,
+    pub get_device_proc_addr: vk::PFN_vkGetDeviceProcAddr,
+    pub get_instance_proc_addr: vk::PFN_vkGetInstanceProcAddr,
+    pub queue: vk::Queue,
+    pub queue_index: c_uint,
+    pub set_image: unsafe extern "C" fn(
+        handle: *mut c_void,
+        image: *const VulkanImage,
+        num_semaphores: u32,
+        semaphores: *const vk::Semaphore,
+        src_queue_family: u32,
+    ),
+    pub get_sync_index: unsafe extern "C" fn(handle: *mut c_void) -> u32,
+    pub get_sync_index_mask: unsafe extern "C" fn(handle: *mut c_void) -> u32,
+    pub set_command_buffers:
+        unsafe extern "C" fn(handle: *mut c_void, num_cmd: u32, cmd: *const vk::CommandBuffer),
+    pub wait_sync_index: unsafe extern "C" fn(handle: *mut c_void),
+    pub lock_queue: unsafe extern "C" fn(handle: *mut c_void),
+    pub unlock_queue: unsafe extern "C" fn(handle: *mut c_void),
+    pub set_signal_semaphore: unsafe extern "C" fn(handle: *mut c_void, semaphore: vk::Semaphore),
+}
+
+// Everything the core handed us for the frame it is about to present.
+#[derive(Default)]
+pub struct PendingFrame {
+    image: Option<VulkanImage>,
+    wait_semaphores: Vec<vk::Semaphore>,
+    src_queue_family: u32,
+    command_buffers: Vec<vk::CommandBuffer>,
+    signal_semaphore: vk::Semaphore,
+}
+
+// Host visible buffer the presented image is copied into.
+struct ReadbackBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+}
+
+// The instance, device and readback resources shared with the core.
+pub struct VulkanContext {
+    _entry: ash::Entry,
+    instance: ash::Instance,
+    device: ash::Device,
+    queue: vk::Queue,
+    queue_family_index: u32,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    readback: Option<ReadbackBuffer>,
+    destroy_device: Option<unsafe extern "C" fn()>,
+    interface: Box<HwRenderInterfaceVulkan>,
+    pub pending: PendingFrame,
+}
+
+impl VulkanContext {
+    // Creates the instance and device, letting the core take part in their creation
+    // if it provided a negotiation interface.
+    pub unsafe fn new(negotiation: *const NegotiationInterfaceVulkan) -> Result<Self, String> {
+        let entry = ash::Entry::load().map_err(|err| err.to_string())?;
+        let negotiation = negotiation.as_ref().filter(|negotiation| {
+            negotiation.interface_type == HW_RENDER_CONTEXT_NEGOTIATION_INTERFACE_VULKAN
+        });
+
+        let default_application_info = vk::ApplicationInfo::builder()
+            .application_name(c"rustretro")
+            .api_version(vk::API_VERSION_1_1)
+            .build();
+        let application_info = negotiation
+            .and_then(|negotiation| negotiation.get_application_info)
+            .map(|get_application_info| get_application_info())
+            .filter(|info| !info.is_null())
+            .unwrap_or(&default_application_info);
+
+        let create_instance = negotiation
+            .filter(|negotiation| negotiation.interface_version >= 2)
+            .and_then(|negotiation| negotiation.create_instance);
+        let instance = match create_instance {
+            Some(create_instance) => {
+                let raw_instance = create_instance(
+                    entry.static_fn().get_instance_proc_addr,
+                    application_info,
+                    create_instance_wrapper,
+                    &entry as *const ash::Entry as *mut c_void,
+                );
+                if raw_instance == vk::Instance::null() {
+                    return Err("Core failed to create a Vulkan instance".to_string());
+                }
+                ash::Instance::load(entry.static_fn(), raw_instance)
+            }
+            None => {
+                let create_info =
+                    vk::InstanceCreateInfo::builder().application_info(&*application_info);
+                entry
+                    .create_instance(&create_info, None)
+                    .map_err(|err| err.to_string())?
+            }
+        };
+
+        // Pick the first GPU with a graphics queue.
+        let (gpu, queue_family_index) = instance
+            .enumerate_physical_devices()
+            .map_err(|err| err.to_string())?
+            .into_iter()
+            .find_map(|gpu| {
+                instance
+                    .get_physical_device_queue_family_properties(gpu)
+                    .iter()
+                    .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+                    .map(|index| (gpu, index as u32))
+            })
+            .ok_or("No Vulkan device with a graphics queue")?;
+
+        let mut context_info = VulkanContextInfo {
+            gpu,
+            device: vk::Device::null(),
+            queue: vk::Queue::null(),
+            queue_family_index,
+            presentation_queue: vk::Queue::null(),
+            presentation_queue_family_index: queue_family_index,
+        };
+        let get_instance_proc_addr = entry.static_fn().get_instance_proc_addr;
+        let negotiated = match negotiation {
+            Some(negotiation)
+                if negotiation.interface_version >= 2 && negotiation.create_device2.is_some() =>
+            {
+                (negotiation.create_device2.unwrap())(
+                    &mut context_info,
+                    instance.handle(),
+                    gpu,
+                    vk::SurfaceKHR::null(),
+                    get_instance_proc_addr,
+                    create_device_wrapper,
+                    &instance as *const ash::Instance as *mut c_void,
+                )
+            }
+            Some(negotiation) if negotiation.create_device.is_some() => {
+                (negotiation.create_device.unwrap())(
+                    &mut context_info,
+                    instance.handle(),
+                    gpu,
+                    vk::SurfaceKHR::null(),
+                    get_instance_proc_addr,
+                    std::ptr::null(),
+                    0,
+                    std::ptr::null(),
+                    0,
+                    std::ptr::null(),
+                )
+            }
+            _ => false,
+        };
+
+        let (device, queue_family_index) = if negotiated {
+            println!("Vulkan device negotiated with the core");
+            (
+                ash::Device::load(instance.fp_v1_0(), context_info.device),
+                context_info.queue_family_index,
+            )
+        } else {
+            let priorities = [1.0];
+            let queue_info = [vk::DeviceQueueCreateInfo::builder()
+                .queue_family_index(queue_family_index)
+                .queue_priorities(&priorities)
+                .build()];
+            let create_info = vk::DeviceCreateInfo::builder().queue_create_infos(&queue_info);
+            let device = instance
+                .create_device(gpu, &create_info, None)
+                .map_err(|err| err.to_string())?;
+            context_info.gpu = gpu;
+            (device, queue_family_index)
+        };
+        let queue = if negotiated && context_info.queue != vk::Queue::null() {
+            context_info.queue
+        } else {
+            device.get_device_queue(queue_family_index, 0)
+        };
+        let memory_properties = instance.get_physical_device_memory_properties(context_info.gpu);
+
+        let command_pool = device
+            .create_command_pool(
+                &vk::CommandPoolCreateInfo::builder()
+                    .queue_family_index(queue_family_index)
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+        let command_buffer = device
+            .allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1),
+            )
+            .map_err(|err| err.to_string())?[0];
+        let fence = device
+            .create_fence(&vk::FenceCreateInfo::default(), None)
+            .map_err(|err| err.to_string())?;
+
+        let interface = Box::new(HwRenderInterfaceVulkan {
+            interface_type: HW_RENDER_INTERFACE_VULKAN,
+            interface_version: HW_RENDER_INTERFACE_VULKAN_VERSION,
+            handle: &INTERFACE_HANDLE as *const u8 as *const c_void,
+            instance: instance.handle(),
+            gpu: context_info.gpu,
+            device: device.handle(),
+            get_device_proc_addr: instance.fp_v1_0().get_device_proc_addr,
+            get_instance_proc_addr,
+            queue,
+            queue_index: queue_family_index,
+            set_image,
+            get_sync_index,
+            get_sync_index_mask,
+            set_command_buffers,
+            wait_sync_index,
+            lock_queue,
+            unlock_queue,
+            set_signal_semaphore,
+        });
+
+        Ok(VulkanContext {
+            _entry: entry,
+            instance,
+            device,
+            queue,
+            queue_family_index,
+            memory_properties,
+            command_pool,
+            command_buffer,
+            fence,
+            readback: None,
+            destroy_device: negotiation.and_then(|negotiation| negotiation.destroy_device),
+            interface,
+            pending: PendingFrame::default(),
+        })
+    }
+
+    // Pointer handed out for ENVIRONMENT_GET_HW_RENDER_INTERFACE.
+    pub fn interface(&self) -> *const HwRenderInterfaceVulkan {
+        &*self.interface
+    }
+
+    // Waits for all work on the device to finish.
+    pub fn wait_idle(&self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+        }
+    }
+
+    // Copies the image set by the core into host memory and returns it as XRGB8888.
+    pub fn read_frame(&mut self, width: u32, height: u32) -> Result<Vec<u32>, String> {
+        let image = self.pending.image.ok_or("Core did not set an image")?;
+        let size = width as vk::DeviceSize * height as vk::DeviceSize * 4;
+        unsafe {
+            self.ensure_readback_buffer(size)?;
+            let readback = self.readback.as_ref().unwrap();
+            self.record_copy(&image, readback.buffer, width, height)?;
+
+            let mut command_buffers = std::mem::take(&mut self.pending.command_buffers);
+            command_buffers.push(self.command_buffer);
+            let wait_semaphores = std::mem::take(&mut self.pending.wait_semaphores);
+            let wait_stages = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+            let signal_semaphore =
+                std::mem::replace(&mut self.pending.signal_semaphore, vk::Semaphore::null());
+            let signal_semaphores: Vec<vk::Semaphore> = if signal_semaphore == vk::Semaphore::null()
+            {
+                Vec::new()
+            } else {
+                vec![signal_semaphore]
+            };
+            let submit_info = vk::SubmitInfo::builder()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&command_buffers)
+                .signal_semaphores(&signal_semaphores)
+                .build();
+            self.device
+                .queue_submit(self.queue, &[submit_info], self.fence)
+                .map_err(|err| err.to_string())?;
+            self.device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .map_err(|err| err.to_string())?;
+            self.device
+                .reset_fences(&[self.fence])
+                .map_err(|err| err.to_string())?;
+
+            let mapped = self
+                .device
+                .map_memory(readback.memory, 0, size, vk::MemoryMapFlags::empty())
+                .map_err(|err| err.to_string())? as *const u32;
+            let mut pixels = std::slice::from_raw_parts(mapped, (width * height) as usize).to_vec();
+            self.device.unmap_memory(readback.memory);
+
+            // The copy keeps the image's byte order; RGBA formats need red and blue swapped.
+            let swap_red_blue = matches!(
+                image.create_info.format,
+                vk::Format::R8G8B8A8_UNORM
+                    | vk::Format::R8G8B8A8_SRGB
+                    | vk::Format::A8B8G8R8_UNORM_PACK32
+            );
+            if swap_red_blue {
+                for pixel in pixels.iter_mut() {
+                    *pixel =
+                        (*pixel & 0xFF00FF00) | ((*pixel & 0xFF) << 16) | ((*pixel >> 16) & 0xFF);
+                }
+            }
+            Ok(pixels)
+        }
+    }
+
+    // Records the layout transitions and the image to buffer copy.
+    unsafe fn record_copy(
+        &self,
+        image: &VulkanImage,
+        buffer: vk::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Result<(), String> {
+        let device = &self.device;
+        device
+            .begin_command_buffer(
+                self.command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .map_err(|err| err.to_string())?;
+
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: image.create_info.subresource_range.base_mip_level,
+            level_count: 1,
+            base_array_layer: image.create_info.subresource_range.base_array_layer,
+            layer_count: 1,
+        };
+        let (src_family, dst_family) = if self.pending.src_queue_family == vk::QUEUE_FAMILY_IGNORED
+            || self.pending.src_queue_family == self.queue_family_index
+        {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        } else {
+            (self.pending.src_queue_family, self.queue_family_index)
+        };
+        let to_transfer = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(image.image_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(src_family)
+            .dst_queue_family_index(dst_family)
+            .image(image.create_info.image)
+            .subresource_range(range)
+            .build();
+        device.cmd_pipeline_barrier(
+            self.command_buffer,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[to_transfer],
+        );
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: range.base_mip_level,
+                base_array_layer: range.base_array_layer,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_image_to_buffer(
+            self.command_buffer,
+            image.create_info.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+
+        // Hand the image back in the layout and queue family the core left it in.
+        let to_core = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(image.image_layout)
+            .src_queue_family_index(dst_family)
+            .dst_queue_family_index(src_family)
+            .image(image.create_info.image)
+            .subresource_range(range)
+            .build();
+        let host_read = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build();
+        device.cmd_pipeline_barrier(
+            self.command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS | vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[host_read],
+            &[to_core],
+        );
+        device
+            .end_command_buffer(self.command_buffer)
+            .map_err(|err| err.to_string())
+    }
+
+    // (Re)creates the host visible buffer when the frame outgrows it.
+    unsafe fn ensure_readback_buffer(&mut self, size: vk::DeviceSize) -> Result<(), String> {
+        if self
+            .readback
+            .as_ref()
+            .is_some_and(|readback| readback.size >= size)
+        {
+            return Ok(());
+        }
+        self.destroy_readback_buffer();
+
+        let buffer = self
+            .device
+            .create_buffer(
+                &vk::BufferCreateInfo::builder()
+                    .size(size)
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+        let requirements = self.device.get_buffer_memory_requirements(buffer);
+        let wanted = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+        let memory_type_index = (0..self.memory_properties.memory_type_count)
+            .find(|&index| {
+                requirements.memory_type_bits & (1 << index) != 0
+                    && self.memory_properties.memory_types[index as usize]
+                        .property_flags
+                        .contains(wanted)
+            })
+            .ok_or("No host visible memory for the readback buffer")?;
+        let memory = self
+            .device
+            .allocate_memory(
+                &vk::MemoryAllocateInfo::builder()
+                    .allocation_size(requirements.size)
+                    .memory_type_index(memory_type_index),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+        self.device
+            .bind_buffer_memory(buffer, memory, 0)
+            .map_err(|err| err.to_string())?;
+        self.readback = Some(ReadbackBuffer {
+            buffer,
+            memory,
+            size,
+        });
+        Ok(())
+    }
+
+    unsafe fn destroy_readback_buffer(&mut self) {
+        if let Some(readback) = self.readback.take() {
+            self.device.destroy_buffer(readback.buffer, None);
+            self.device.free_memory(readback.memory, None);
+        }
+    }
+}
+
+impl Drop for VulkanContext {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            self.destroy_readback_buffer();
+            self.device.destroy_fence(self.fence, None);
+            self.device.destroy_command_pool(self.command_pool, None);
+            // The core frees what it attached to the device, then we destroy it.
+            if let Some(destroy_device) = self.destroy_device {
+                destroy_device();
+            }
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+// Lets a v2 core create the instance with extra extensions while we still own it.
+unsafe extern "C" fn create_instance_wrapper(
+    opaque: *mut c_void,
+    create_info: *const vk::InstanceCreateInfo,
+) -> vk::Instance {
+    let entry = &*(opaque as *const ash::Entry);
+    match entry.create_instance(&*create_info, None) {
+        Ok(instance) => instance.handle(),
+        Err(err) => {
+            println!("Failed to create Vulkan instance: {}", err);
+            vk::Instance::null()
+        }
+    }
+}
+
+// Lets a v2 core create the device with extra extensions and features.
+unsafe extern "C" fn create_device_wrapper(
+    gpu: vk::PhysicalDevice,
+    opaque: *mut c_void,
+    create_info: *const vk::DeviceCreateInfo,
+) -> vk::Device {
+    let instance = &*(opaque as *const ash::Instance);
+    match instance.create_device(gpu, &*create_info, None) {
+        Ok(device) => device.handle(),
+        Err(err) => {
+            println!("Failed to create Vulkan device: {}", err);
+            vk::Device::null()
+        }
+    }
+}
+
+unsafe extern "C" fn set_image(
+    _handle: *mut c_void,
+    image: *const VulkanImage,
+    num_semaphores: u32,
+    semaphores: *const vk::Semaphore,
+    src_queue_family: u32,
+) {
+    hw_render::with_vulkan(|context| {
+        let pending = &mut context.pending;
+        pending.image = image.as_ref().copied();
+        pending.wait_semaphores = if semaphores.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(semaphores, num_semaphores as usize).to_vec()
+        };
+        pending.src_queue_family = src_queue_family;
+    });
+}
+
+// We keep a single frame in flight, so there is only ever one sync index.
+unsafe extern "C" fn get_sync_index(_handle: *mut c_void) -> u32 {
+    0
+}
+
+unsafe extern "C" fn get_sync_index_mask(_handle: *mut c_void) -> u32 {
+    1
+}
+
+unsafe extern "C" fn set_command_buffers(
+    _handle: *mut c_void,
+    num_cmd: u32,
+    cmd: *const vk::CommandBuffer,
+) {
+    hw_render::with_vulkan(|context| {
+        context.pending.command_buffers = if cmd.is_null() {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(cmd, num_cmd as usize).to_vec()
+        };
+    });
+}
+
+unsafe extern "C" fn wait_sync_index(_handle: *mut c_void) {
+    hw_render::with_vulkan(|context| context.wait_idle());
+}
+
+// The queue is only ever used from the emulation thread, so locking is a no-op.
+unsafe extern "C" fn lock_queue(_handle: *mut c_void) {}
+
+unsafe extern "C" fn unlock_queue(_handle: *mut c_void) {}
+
+unsafe extern "C" fn set_signal_semaphore(_handle: *mut c_void, semaphore: vk::Semaphore) {
+    hw_render::with_vulkan(|context| context.pending.signal_semaphore = semaphore);
+}
@@ -0,0 +1,362 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// window.rs
+//
+// This module defines the window the emulator runs in. minifb is the default, and
+// with the `sdl2` or `winit` features `video_backend` can pick an SDL2 or winit
+// window instead, see sdl.rs and winit_window.rs. Keys are minifb's everywhere,
+// so key bindings in the config read the same whichever backend is used.
+
+use libretro_sys::{GameGeometry, SystemAvInfo};
+use minifb::{HasRawWindowHandle, Key, KeyRepeat, WindowOptions};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::{osd, video};
+
+// A window that shows frames and reports keyboard state. Key state changes when
+// the window is updated, with or without a new picture.
+pub trait Window: HasRawWindowHandle {
+    fn is_open(&self) -> bool;
+    fn is_key_down(&self, key: Key) -> bool;
+    // Keys held down.
+    fn get_keys(&self) -> Vec<Key>;
+    // Keys pressed during the last update, including repeats with KeyRepeat::Yes.
+    fn get_keys_pressed(&self, repeat: KeyRepeat) -> Vec<Key>;
+    // Keys released during the last update.
+    fn get_keys_released(&self) -> Vec<Key>;
+    // Size of the area pictures are drawn to.
+    fn get_size(&self) -> (usize, usize);
+    // Shows a picture covering the whole window and processes window events.
+    fn update_with_buffer(
+        &mut self,
+        buffer: &[u32],
+        width: usize,
+        height: usize,
+    ) -> Result<(), String>;
+    // Processes window events without drawing, for pictures presented elsewhere.
+    fn update(&mut self);
+    fn set_title(&mut self, title: &str);
+    fn is_fullscreen(&self) -> bool;
+    // Whether showing a picture waits for the display's vertical blank.
+    fn has_vsync(&self) -> bool {
+        false
+    }
+    // Refresh rate of the display the window is on, if the window can tell.
+    fn refresh_rate(&self) -> Option<f64> {
+        None
+    }
+    // Switches between windowed and borderless fullscreen.
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String>;
+}
+
+impl Window for minifb::Window {
+    fn is_open(&self) -> bool {
+        minifb::Window::is_open(self)
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        minifb::Window::is_key_down(self, key)
+    }
+
+    fn get_keys(&self) -> Vec<Key> {
+        minifb::Window::get_keys(self)
+    }
+
+    fn get_keys_pressed(&self, repeat: KeyRepeat) -> Vec<Key> {
+        minifb::Window::get_keys_pressed(self, repeat)
+    }
+
+    fn get_keys_released(&self) -> Vec<Key> {
+        minifb::Window::get_keys_released(self)
+    }
+
+    fn get_size(&self) -> (usize, usize) {
+        minifb::Window::get_size(self)
+    }
+
+    fn update_with_buffer(
+        &mut self,
+        buffer: &[u32],
+        width: usize,
+        height: usize,
+    ) -> Result<(), String> {
+        minifb::Window::update_with_buffer(self, buffer, width, height).map_err(|e| e.to_string())
+    }
+
+    fn update(&mut self) {
+        minifb::Window::update(self)
+    }
+
+    fn set_title(&mut self, title: &str) {
+        minifb::Window::set_title(self, title)
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        false
+    }
+
+    // minifb can only make a window borderless when it is created, and doesn't
+    // know the size of the screen
+    fn set_fullscreen(&mut self, _fullscreen: bool) -> Result<(), String> {
+        Err("minifb windows can't go fullscreen, try video_backend sdl2 or winit".to_string())
+    }
+}
+
+// How the window goes fullscreen, from `video_windowed_fullscreen` and
+// `video_monitor_index`. Only windows that can go fullscreen read it.
+#[cfg_attr(not(any(feature = "sdl2", feature = "winit")), allow(dead_code))]
+#[derive(Clone, Copy, Debug)]
+pub struct FullscreenOptions {
+    // Take the display over and switch its mode, instead of covering it with a
+    // borderless window. Lower latency, but switching takes a moment.
+    pub exclusive: bool,
+    // Monitor to go fullscreen on counting from 1, or 0 for the one the window is on.
+    pub monitor: usize,
+    // Refresh rate an exclusive display mode should be as close to as possible, the
+    // core's frame rate.
+    pub refresh_rate: f64,
+}
+
+fn fullscreen_options(config: &HashMap<String, String>, fps: f64) -> FullscreenOptions {
+    FullscreenOptions {
+        exclusive: config
+            .get("video_windowed_fullscreen")
+            .is_some_and(|value| value == "false"),
+        monitor: config
+            .get("video_monitor_index")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0),
+        refresh_rate: fps,
+    }
+}
+
+// Returns the index of the refresh rate closest to `target`.
+#[cfg_attr(not(any(feature = "sdl2", feature = "winit")), allow(dead_code))]
+pub fn closest_refresh_rate(rates: impl IntoIterator<Item = f64>, target: f64) -> Option<usize> {
+    rates
+        .into_iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| (a - target).abs().total_cmp(&(b - target).abs()))
+        .map(|(index, _)| index)
+}
+
+// Size of the window when the core doesn't report its geometry.
+const WIDTH: usize = 256;
+const HEIGHT: usize = 144;
+
+// Returns the window size for the core's base geometry times `scale`, with the
+// width following the core's aspect ratio when it gives one.
+fn initial_size(geometry: &GameGeometry, scale: usize) -> (usize, usize) {
+    if geometry.base_width == 0 || geometry.base_height == 0 {
+        return (WIDTH, HEIGHT);
+    }
+    let height = geometry.base_height as usize * scale;
+    let width = if geometry.aspect_ratio > 0.0 {
+        (height as f32 * geometry.aspect_ratio).round() as usize
+    } else {
+        geometry.base_width as usize * scale
+    };
+    (width, height)
+}
+
+fn open_minifb(title: &str, (width, height): (usize, usize)) -> Result<Box<dyn Window>, String> {
+    let mut window = minifb::Window::new(
+        title,
+        width,
+        height,
+        WindowOptions {
+            resize: true, // Allow window resizing
+            ..WindowOptions::default()
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    // Frames are paced by the speed module, so the window must not throttle updates itself
+    window.limit_update_rate(None);
+    Ok(Box::new(window))
+}
+
+// Opens the window `video_backend` asks for, "minifb", "sdl2" or "winit", sized to
+// the core's geometry times `video_window_scale`, and in fullscreen if
+// `video_fullscreen` is set.
+pub fn open(
+    title: &str,
+    config: &HashMap<String, String>,
+    av_info: &SystemAvInfo,
+) -> Result<Box<dyn Window>, String> {
+    let vsync = video::vsync(config);
+    let scale = config
+        .get("video_window_scale")
+        .and_then(|scale| scale.parse::<usize>().ok())
+        .map_or(3, |scale| scale.max(1));
+    let size = initial_size(&av_info.geometry, scale);
+    let fullscreen = fullscreen_options(config, av_info.timing.fps);
+    let mut window = open_backend(title, config, size, fullscreen, vsync)?;
+    if config
+        .get("video_fullscreen")
+        .is_some_and(|value| value == "true")
+    {
+        if let Err(e) = window.set_fullscreen(true) {
+            println!("{}", e);
+        }
+    }
+    Ok(window)
+}
+
+#[cfg_attr(not(any(feature = "sdl2", feature = "winit")), allow(unused_variables))]
+fn open_backend(
+    title: &str,
+    config: &HashMap<String, String>,
+    size: (usize, usize),
+    fullscreen: FullscreenOptions,
+    vsync: bool,
+) -> Result<Box<dyn Window>, String> {
+    match config.get("video_backend").map_or("minifb", String::as_str) {
+        #[cfg(feature = "sdl2")]
+        "sdl2" => crate::sdl::open(title, size.0, size.1, fullscreen, vsync),
+        #[cfg(not(feature = "sdl2"))]
+        "sdl2" => {
+            println!("Built without the sdl2 feature, using minifb instead");
+            open_minifb(title, size)
+        }
+        #[cfg(feature = "winit")]
+        "winit" => {
+            // softbuffer has no way to wait for the vertical blank
+            if vsync && config.get("video_renderer").map(String::as_str) != Some("wgpu") {
+                println!("The winit backend only has vsync with video_renderer = \"wgpu\"");
+            }
+            crate::winit_window::open(title, size.0, size.1, fullscreen)
+        }
+        #[cfg(not(feature = "winit"))]
+        "winit" => {
+            println!("Built without the winit feature, using minifb instead");
+            open_minifb(title, size)
+        }
+        "minifb" => open_minifb(title, size),
+        backend => {
+            println!("Unknown video_backend {}, using minifb", backend);
+            open_minifb(title, size)
+        }
+    }
+}
+
+// Keeps the window title showing the game, the core, and the frame rate and
+// slowest frame over the last second.
+pub struct Title {
+    name: String,
+    frames: u32,
+    slowest: Duration,
+    since: Instant,
+    last_frame: Instant,
+}
+
+// How often the frame rate in the title is updated.
+const TITLE_INTERVAL: Duration = Duration::from_secs(1);
+
+impl Title {
+    // `<game> - <core>`, or just the core when it runs without content.
+    pub fn new(rom_name: &str, core_name: &str, contentless: bool) -> Self {
+        let name = if contentless {
+            core_name.to_string()
+        } else {
+            let game = Path::new(rom_name).file_stem().unwrap_or_default();
+            format!("{} - {}", game.to_string_lossy(), core_name)
+        };
+        let now = Instant::now();
+        Title {
+            name,
+            frames: 0,
+            slowest: Duration::ZERO,
+            since: now,
+            last_frame: now,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Counts a frame of the main loop, and updates the title once a second.
+    pub fn frame(&mut self, window: &mut dyn Window) {
+        let now = Instant::now();
+        self.frames += 1;
+        self.slowest = self.slowest.max(now - self.last_frame);
+        self.last_frame = now;
+        let elapsed = now - self.since;
+        if elapsed < TITLE_INTERVAL {
+            return;
+        }
+        let fps = self.frames as f64 / elapsed.as_secs_f64();
+        window.set_title(&format_title(&self.name, fps, self.slowest));
+        self.frames = 0;
+        self.slowest = Duration::ZERO;
+        self.since = now;
+    }
+}
+
+fn format_title(name: &str, fps: f64, slowest: Duration) -> String {
+    format!(
+        "{} - {:.1} fps, slowest frame {:.1} ms",
+        name,
+        fps,
+        slowest.as_secs_f64() * 1000.0
+    )
+}
+
+// Switches between windowed and fullscreen from the hotkey. The picture is scaled
+// to the new size from the next frame on.
+pub fn toggle_fullscreen(window: &mut dyn Window) {
+    let fullscreen = !window.is_fullscreen();
+    match window.set_fullscreen(fullscreen) {
+        Ok(()) if fullscreen => osd::show_message("Fullscreen"),
+        Ok(()) => osd::show_message("Windowed"),
+        Err(e) => {
+            println!("{}", e);
+            osd::show_message("Fullscreen is not supported by this window");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_the_window_after_the_game_and_core() {
+        let title = Title::new("roms/Super Game (USA).sfc", "Snes9x", false);
+        assert_eq!(title.name(), "Super Game (USA) - Snes9x");
+        assert_eq!(Title::new("2048", "2048", true).name(), "2048");
+        assert_eq!(
+            format_title(
+                "Super Game (USA) - Snes9x",
+                59.94,
+                Duration::from_micros(18_260)
+            ),
+            "Super Game (USA) - Snes9x - 59.9 fps, slowest frame 18.3 ms"
+        );
+    }
+
+    #[test]
+    fn sizes_the_window_to_the_core_geometry() {
+        let geometry = |base_width, base_height, aspect_ratio| GameGeometry {
+            base_width,
+            base_height,
+            max_width: 512,
+            max_height: 512,
+            aspect_ratio,
+        };
+        assert_eq!(initial_size(&geometry(256, 224, 4.0 / 3.0), 3), (896, 672));
+        assert_eq!(initial_size(&geometry(160, 144, 0.0), 2), (320, 288));
+        assert_eq!(initial_size(&geometry(0, 0, 0.0), 3), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn picks_the_closest_refresh_rate() {
+        assert_eq!(closest_refresh_rate([60.0, 50.0, 144.0], 50.007), Some(1));
+        assert_eq!(closest_refresh_rate([60.0, 75.0], 59.94), Some(0));
+        assert_eq!(closest_refresh_rate([], 60.0), None);
+    }
+}
@@ -0,0 +1,363 @@
+// This implementation is based on the guide provided by [RetroGameDeveloper/RetroReversing].
+// Original guide can be found at [https://www.retroreversing.com/CreateALibRetroFrontEndInRust].
+// Copyright (c) 2023 Nicholas Ricciuti
+//
+// winit_window.rs
+//
+// This module implements the window with winit when the `winit` feature is enabled
+// and `video_backend` is "winit". The window opens at a size in logical pixels, so
+// it isn't tiny on HiDPI screens, and pictures are drawn in physical pixels with
+// softbuffer. The event loop is pumped once per update instead of taking over the
+// main loop, and key events are translated to minifb keys so bindings work the same.
+
+use minifb::{Key, KeyRepeat};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use std::num::NonZeroU32;
+use std::rc::Rc;
+use std::time::Duration;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Fullscreen, WindowBuilder};
+
+use crate::window::{self, FullscreenOptions, Window};
+
+type Surface = softbuffer::Surface<Rc<winit::window::Window>, Rc<winit::window::Window>>;
+
+pub struct WinitWindow {
+    event_loop: EventLoop<()>,
+    window: Rc<winit::window::Window>,
+    // Created with the first software picture, since a GPU presenter needs the
+    // window to itself.
+    surface: Option<(softbuffer::Context<Rc<winit::window::Window>>, Surface)>,
+    fullscreen: FullscreenOptions,
+    open: bool,
+    held: Vec<Key>,
+    pressed: Vec<Key>,
+    repeated: Vec<Key>,
+    released: Vec<Key>,
+}
+
+pub fn open(
+    title: &str,
+    width: usize,
+    height: usize,
+    fullscreen: FullscreenOptions,
+) -> Result<Box<dyn Window>, String> {
+    let event_loop = EventLoop::new().map_err(|e| e.to_string())?;
+    let window = WindowBuilder::new()
+        .with_title(title)
+        .with_inner_size(LogicalSize::new(width as f64, height as f64))
+        .build(&event_loop)
+        .map_err(|e| e.to_string())?;
+    Ok(Box::new(WinitWindow {
+        event_loop,
+        window: Rc::new(window),
+        surface: None,
+        fullscreen,
+        open: true,
+        held: Vec::new(),
+        pressed: Vec::new(),
+        repeated: Vec::new(),
+        released: Vec::new(),
+    }))
+}
+
+// SAFETY: the handle is the winit window's own, which lives as long as this
+unsafe impl HasRawWindowHandle for WinitWindow {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        self.window.raw_window_handle()
+    }
+}
+
+impl Window for WinitWindow {
+    fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.held.contains(&key)
+    }
+
+    fn get_keys(&self) -> Vec<Key> {
+        self.held.clone()
+    }
+
+    fn get_keys_pressed(&self, repeat: KeyRepeat) -> Vec<Key> {
+        match repeat {
+            KeyRepeat::Yes => self.repeated.clone(),
+            KeyRepeat::No => self.pressed.clone(),
+        }
+    }
+
+    fn get_keys_released(&self) -> Vec<Key> {
+        self.released.clone()
+    }
+
+    // Minimized windows have nothing to draw to.
+    fn get_size(&self) -> (usize, usize) {
+        if self.window.is_minimized() == Some(true) {
+            return (0, 0);
+        }
+        let size = self.window.inner_size();
+        (size.width as usize, size.height as usize)
+    }
+
+    fn update_with_buffer(
+        &mut self,
+        buffer: &[u32],
+        width: usize,
+        height: usize,
+    ) -> Result<(), String> {
+        if let (Some(width), Some(height)) = (
+            NonZeroU32::new(width as u32),
+            NonZeroU32::new(height as u32),
+        ) {
+            if self.surface.is_none() {
+                let context =
+                    softbuffer::Context::new(self.window.clone()).map_err(|e| e.to_string())?;
+                let surface =
+                    Surface::new(&context, self.window.clone()).map_err(|e| e.to_string())?;
+                self.surface = Some((context, surface));
+            }
+            let (_, surface) = self.surface.as_mut().unwrap();
+            surface.resize(width, height).map_err(|e| e.to_string())?;
+            let mut surface_buffer = surface.buffer_mut().map_err(|e| e.to_string())?;
+            // softbuffer takes the same 0RGB pixels minifb does
+            let length = surface_buffer.len();
+            surface_buffer.copy_from_slice(&buffer[..length]);
+            surface_buffer.present().map_err(|e| e.to_string())?;
+        }
+        self.update();
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        self.pressed.clear();
+        self.repeated.clear();
+        self.released.clear();
+        let WinitWindow {
+            event_loop,
+            open,
+            held,
+            pressed,
+            repeated,
+            released,
+            ..
+        } = self;
+        let status = event_loop.pump_events(Some(Duration::ZERO), |event, _| {
+            let Event::WindowEvent { event, .. } = event else {
+                return;
+            };
+            match event {
+                WindowEvent::CloseRequested => *open = false,
+                // Keys let go of in another window would otherwise stay held
+                WindowEvent::Focused(false) => released.append(held),
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(code),
+                            state,
+                            repeat,
+                            ..
+                        },
+                    ..
+                } => {
+                    let key = key(code);
+                    match state {
+                        ElementState::Pressed => {
+                            repeated.push(key);
+                            if !repeat {
+                                pressed.push(key);
+                                held.push(key);
+                            }
+                        }
+                        ElementState::Released => {
+                            released.push(key);
+                            held.retain(|&held| held != key);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        });
+        if let PumpStatus::Exit(_) = status {
+            self.open = false;
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        self.window.fullscreen().is_some()
+    }
+
+    fn refresh_rate(&self) -> Option<f64> {
+        let millihertz = self.window.current_monitor()?.refresh_rate_millihertz()?;
+        Some(millihertz as f64 / 1000.0)
+    }
+
+    // Exclusive fullscreen switches to the mode with the monitor's resolution and
+    // the refresh rate closest to the core's. Platforms that can't tell which
+    // monitor the window is on, like Wayland, only get borderless fullscreen.
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        if !fullscreen {
+            self.window.set_fullscreen(None);
+            return Ok(());
+        }
+        let options = self.fullscreen;
+        let monitor = match options.monitor {
+            0 => self.window.current_monitor(),
+            index => Some(
+                self.window
+                    .available_monitors()
+                    .nth(index - 1)
+                    .ok_or_else(|| format!("There is no monitor {}", index))?,
+            ),
+        };
+        let fullscreen = match monitor {
+            Some(monitor) if options.exclusive => {
+                let size = monitor.size();
+                let mut modes: Vec<_> = monitor
+                    .video_modes()
+                    .filter(|mode| mode.size() == size)
+                    .collect();
+                let index = window::closest_refresh_rate(
+                    modes
+                        .iter()
+                        .map(|mode| mode.refresh_rate_millihertz() as f64 / 1000.0),
+                    options.refresh_rate,
+                )
+                .ok_or("The monitor has no video modes at its resolution")?;
+                let mode = modes.swap_remove(index);
+                println!(
+                    "Switching to {}x{} at {} Hz",
+                    size.width,
+                    size.height,
+                    mode.refresh_rate_millihertz() as f64 / 1000.0
+                );
+                Fullscreen::Exclusive(mode)
+            }
+            monitor => Fullscreen::Borderless(monitor),
+        };
+        self.window.set_fullscreen(Some(fullscreen));
+        Ok(())
+    }
+}
+
+// Translates the physical key winit reports to the minifb key in the same place.
+fn key(code: KeyCode) -> Key {
+    match code {
+        KeyCode::KeyA => Key::A,
+        KeyCode::KeyB => Key::B,
+        KeyCode::KeyC => Key::C,
+        KeyCode::KeyD => Key::D,
+        KeyCode::KeyE => Key::E,
+        KeyCode::KeyF => Key::F,
+        KeyCode::KeyG => Key::G,
+        KeyCode::KeyH => Key::H,
+        KeyCode::KeyI => Key::I,
+        KeyCode::KeyJ => Key::J,
+        KeyCode::KeyK => Key::K,
+        KeyCode::KeyL => Key::L,
+        KeyCode::KeyM => Key::M,
+        KeyCode::KeyN => Key::N,
+        KeyCode::KeyO => Key::O,
+        KeyCode::KeyP => Key::P,
+        KeyCode::KeyQ => Key::Q,
+        KeyCode::KeyR => Key::R,
+        KeyCode::KeyS => Key::S,
+        KeyCode::KeyT => Key::T,
+        KeyCode::KeyU => Key::U,
+        KeyCode::KeyV => Key::V,
+        KeyCode::KeyW => Key::W,
+        KeyCode::KeyX => Key::X,
+        KeyCode::KeyY => Key::Y,
+        KeyCode::KeyZ => Key::Z,
+        KeyCode::Digit0 => Key::Key0,
+        KeyCode::Digit1 => Key::Key1,
+        KeyCode::Digit2 => Key::Key2,
+        KeyCode::Digit3 => Key::Key3,
+        KeyCode::Digit4 => Key::Key4,
+        KeyCode::Digit5 => Key::Key5,
+        KeyCode::Digit6 => Key::Key6,
+        KeyCode::Digit7 => Key::Key7,
+        KeyCode::Digit8 => Key::Key8,
+        KeyCode::Digit9 => Key::Key9,
+        KeyCode::F1 => Key::F1,
+        KeyCode::F2 => Key::F2,
+        KeyCode::F3 => Key::F3,
+        KeyCode::F4 => Key::F4,
+        KeyCode::F5 => Key::F5,
+        KeyCode::F6 => Key::F6,
+        KeyCode::F7 => Key::F7,
+        KeyCode::F8 => Key::F8,
+        KeyCode::F9 => Key::F9,
+        KeyCode::F10 => Key::F10,
+        KeyCode::F11 => Key::F11,
+        KeyCode::F12 => Key::F12,
+        KeyCode::F13 => Key::F13,
+        KeyCode::F14 => Key::F14,
+        KeyCode::F15 => Key::F15,
+        KeyCode::ArrowDown => Key::Down,
+        KeyCode::ArrowLeft => Key::Left,
+        KeyCode::ArrowRight => Key::Right,
+        KeyCode::ArrowUp => Key::Up,
+        KeyCode::Quote => Key::Apostrophe,
+        KeyCode::Backquote => Key::Backquote,
+        KeyCode::Backslash => Key::Backslash,
+        KeyCode::Comma => Key::Comma,
+        KeyCode::Equal => Key::Equal,
+        KeyCode::BracketLeft => Key::LeftBracket,
+        KeyCode::Minus => Key::Minus,
+        KeyCode::Period => Key::Period,
+        KeyCode::BracketRight => Key::RightBracket,
+        KeyCode::Semicolon => Key::Semicolon,
+        KeyCode::Slash => Key::Slash,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Delete,
+        KeyCode::End => Key::End,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Escape => Key::Escape,
+        KeyCode::Home => Key::Home,
+        KeyCode::Insert => Key::Insert,
+        KeyCode::ContextMenu => Key::Menu,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::Pause => Key::Pause,
+        KeyCode::Space => Key::Space,
+        KeyCode::Tab => Key::Tab,
+        KeyCode::NumLock => Key::NumLock,
+        KeyCode::CapsLock => Key::CapsLock,
+        KeyCode::ScrollLock => Key::ScrollLock,
+        KeyCode::ShiftLeft => Key::LeftShift,
+        KeyCode::ShiftRight => Key::RightShift,
+        KeyCode::ControlLeft => Key::LeftCtrl,
+        KeyCode::ControlRight => Key::RightCtrl,
+        KeyCode::Numpad0 => Key::NumPad0,
+        KeyCode::Numpad1 => Key::NumPad1,
+        KeyCode::Numpad2 => Key::NumPad2,
+        KeyCode::Numpad3 => Key::NumPad3,
+        KeyCode::Numpad4 => Key::NumPad4,
+        KeyCode::Numpad5 => Key::NumPad5,
+        KeyCode::Numpad6 => Key::NumPad6,
+        KeyCode::Numpad7 => Key::NumPad7,
+        KeyCode::Numpad8 => Key::NumPad8,
+        KeyCode::Numpad9 => Key::NumPad9,
+        KeyCode::NumpadDecimal => Key::NumPadDot,
+        KeyCode::NumpadDivide => Key::NumPadSlash,
+        KeyCode::NumpadMultiply => Key::NumPadAsterisk,
+        KeyCode::NumpadSubtract => Key::NumPadMinus,
+        KeyCode::NumpadAdd => Key::NumPadPlus,
+        KeyCode::NumpadEnter => Key::NumPadEnter,
+        KeyCode::AltLeft => Key::LeftAlt,
+        KeyCode::AltRight => Key::RightAlt,
+        KeyCode::SuperLeft => Key::LeftSuper,
+        KeyCode::SuperRight => Key::RightSuper,
+        _ => Key::Unknown,
+    }
+}